@@ -0,0 +1,244 @@
+//! Loopback benchmarks of the transports.
+//!
+//! Run with `cargo bench`. The crate has no in-memory transport, so stream
+//! throughput over a Unix socket pair serves as the baseline without any
+//! transport in between.
+
+use async_std::task;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use std::io;
+use std::time::{Duration, Instant};
+
+use hyperswarm::transport::combined::CombinedTransport;
+use hyperswarm::transport::tcp::TcpTransport;
+#[cfg(feature = "transport_utp")]
+use hyperswarm::transport::utp::UtpTransport;
+use hyperswarm::transport::Transport;
+
+const ACCEPTS: usize = 64;
+const LISTENERS: usize = 16;
+const PAYLOAD: usize = 1024 * 1024;
+const LARGE_TRANSFER: usize = 16 * 1024 * 1024;
+
+// Time to accept a batch of incoming TCP connections.
+fn accept_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("accept");
+    group.throughput(Throughput::Elements(ACCEPTS as u64));
+    group.bench_function("tcp", |b| {
+        b.iter_custom(|iters| {
+            task::block_on(async {
+                let mut transport = TcpTransport::bind("127.0.0.1:0").await.unwrap();
+                let addr = transport.local_addr();
+                let mut elapsed = Duration::from_secs(0);
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    let clients = task::spawn(async move {
+                        let mut streams = vec![];
+                        for _ in 0..ACCEPTS {
+                            streams.push(async_std::net::TcpStream::connect(addr).await.unwrap());
+                        }
+                        streams
+                    });
+                    for _ in 0..ACCEPTS {
+                        transport.next().await.unwrap().unwrap();
+                    }
+                    elapsed += start.elapsed();
+                    drop(clients.await);
+                }
+                elapsed
+            })
+        })
+    });
+    group.finish();
+}
+
+// Time from dialing until the connection is yielded, for a single transport
+// and for the combined one with and without dedup of outgoing connections.
+fn dial_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dial");
+    group.bench_function("tcp", |b| {
+        b.iter_custom(|iters| {
+            task::block_on(async {
+                let listener = TcpTransport::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr();
+                let server = task::spawn(drain(listener));
+                let mut transport = TcpTransport::dial_only();
+                let mut elapsed = Duration::from_secs(0);
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    transport.connect(addr);
+                    transport.next().await.unwrap().unwrap();
+                    elapsed += start.elapsed();
+                }
+                server.cancel().await;
+                elapsed
+            })
+        })
+    });
+    // Dedup keys on the address, so every iteration dials a batch of
+    // distinct listeners with a fresh transport.
+    for dedup in [true, false].iter().copied() {
+        let id = BenchmarkId::new("combined", if dedup { "dedup" } else { "no-dedup" });
+        group.bench_function(id, |b| {
+            b.iter_custom(|iters| {
+                task::block_on(async {
+                    let mut addrs = vec![];
+                    let mut servers = vec![];
+                    for _ in 0..LISTENERS {
+                        let listener = TcpTransport::bind("127.0.0.1:0").await.unwrap();
+                        addrs.push(listener.local_addr());
+                        servers.push(task::spawn(drain(listener)));
+                    }
+                    let mut elapsed = Duration::from_secs(0);
+                    for _ in 0..iters {
+                        let mut transport = CombinedTransport::dial_only();
+                        transport.set_dedup(dedup);
+                        let start = Instant::now();
+                        for addr in addrs.iter() {
+                            transport.connect(*addr);
+                        }
+                        for _ in 0..LISTENERS {
+                            transport.next().await.unwrap().unwrap();
+                        }
+                        elapsed += start.elapsed();
+                    }
+                    for server in servers {
+                        server.cancel().await;
+                    }
+                    elapsed / LISTENERS as u32
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+// Time to send a payload one way and have it read on the other end.
+fn stream_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Bytes(PAYLOAD as u64));
+    group.sample_size(20);
+    group.bench_function("tcp", |b| {
+        b.iter_custom(|iters| {
+            task::block_on(async {
+                let mut listener = TcpTransport::bind("127.0.0.1:0").await.unwrap();
+                let mut dialer = TcpTransport::dial_only();
+                dialer.connect(listener.local_addr());
+                let writer = dialer.next().await.unwrap().unwrap();
+                let reader = listener.next().await.unwrap().unwrap();
+                transfer(writer, reader, iters).await.unwrap()
+            })
+        })
+    });
+    #[cfg(feature = "transport_utp")]
+    group.bench_function("utp", |b| {
+        b.iter_custom(|iters| {
+            task::block_on(async {
+                // uTP does not report the address it is bound to.
+                let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+                    .and_then(|socket| socket.local_addr())
+                    .unwrap();
+                let mut listener = UtpTransport::bind(addr).await.unwrap();
+                let mut dialer = UtpTransport::bind("127.0.0.1:0").await.unwrap();
+                dialer.connect(addr);
+                let (writer, reader) = futures::join!(dialer.next(), listener.next());
+                transfer(writer.unwrap().unwrap(), reader.unwrap().unwrap(), iters)
+                    .await
+                    .unwrap()
+            })
+        })
+    });
+    #[cfg(unix)]
+    group.bench_function("socketpair", |b| {
+        b.iter_custom(|iters| {
+            task::block_on(async {
+                let (writer, reader) = async_std::os::unix::net::UnixStream::pair().unwrap();
+                transfer(writer, reader, iters).await.unwrap()
+            })
+        })
+    });
+    group.finish();
+}
+
+// Time to send a replication-sized transfer over a combined TCP connection,
+// by the size of the buffers handed to it. Large buffers go to the socket
+// without being copied on the way.
+fn large_writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_writes");
+    group.throughput(Throughput::Bytes(LARGE_TRANSFER as u64));
+    group.sample_size(10);
+    for size in [4 * 1024, 64 * 1024, 1024 * 1024].iter().copied() {
+        group.bench_function(BenchmarkId::new("combined_tcp", size), |b| {
+            b.iter_custom(|iters| {
+                task::block_on(async {
+                    let mut listener = CombinedTransport::bind("127.0.0.1:0").await.unwrap();
+                    let mut dialer = CombinedTransport::dial_only();
+                    dialer.connect(listener.local_addr());
+                    let (writer, reader) = futures::join!(dialer.next(), listener.next());
+                    let (mut writer, mut reader) =
+                        (writer.unwrap().unwrap(), reader.unwrap().unwrap());
+                    let chunk = vec![7u8; size];
+                    let mut buf = vec![0u8; LARGE_TRANSFER];
+                    let start = Instant::now();
+                    let write = task::spawn(async move {
+                        for _ in 0..iters * (LARGE_TRANSFER / size) as u64 {
+                            writer.write_all(&chunk).await?;
+                        }
+                        writer.flush().await?;
+                        Ok::<_, io::Error>(writer)
+                    });
+                    for _ in 0..iters {
+                        reader.read_exact(&mut buf).await.unwrap();
+                    }
+                    let elapsed = start.elapsed();
+                    write.await.unwrap();
+                    elapsed
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+async fn drain<T>(mut transport: T)
+where
+    T: Transport + Unpin + Send + 'static,
+{
+    let mut open = vec![];
+    while let Some(conn) = transport.next().await {
+        open.extend(conn.ok());
+    }
+}
+
+async fn transfer<W, R>(mut writer: W, mut reader: R, iters: u64) -> io::Result<Duration>
+where
+    W: futures::AsyncWrite + Unpin + Send + 'static,
+    R: futures::AsyncRead + Unpin,
+{
+    let payload = vec![7u8; PAYLOAD];
+    let mut buf = vec![0u8; PAYLOAD];
+    let start = Instant::now();
+    let write = task::spawn(async move {
+        for _ in 0..iters {
+            writer.write_all(&payload).await?;
+        }
+        writer.flush().await?;
+        Ok::<_, io::Error>(writer)
+    });
+    for _ in 0..iters {
+        reader.read_exact(&mut buf).await?;
+    }
+    let elapsed = start.elapsed();
+    write.await?;
+    Ok(elapsed)
+}
+
+criterion_group!(
+    benches,
+    accept_throughput,
+    dial_latency,
+    stream_throughput,
+    large_writes
+);
+criterion_main!(benches);
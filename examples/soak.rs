@@ -0,0 +1,172 @@
+//! Soak test: many in-process swarms on loopback, joining and leaving topics
+//! for a long time, while memory and file descriptors must stay bounded.
+//!
+//! Run with `cargo run --release --example soak --features soak -- --help`.
+//! Memory and descriptors are read from `/proc`, so they are only checked on
+//! Linux. The process exits with an error once usage grows past the allowed
+//! amount over the baseline taken after warmup.
+
+use async_std::stream::StreamExt;
+use async_std::task;
+use clap::Clap;
+use rand::Rng;
+use std::io;
+use std::time::{Duration, Instant};
+
+use hyperswarm::discovery::Backend;
+use hyperswarm::{run_bootstrap_node, Config, ConnectionLimits, Hyperswarm, TopicConfig};
+
+/// Options for the soak test
+#[derive(Clap, Debug)]
+struct Options {
+    /// Number of swarms to run.
+    #[clap(long, default_value = "200")]
+    swarms: usize,
+
+    /// Number of topics the swarms join and leave.
+    #[clap(long, default_value = "16")]
+    topics: u8,
+
+    /// How long to run, in seconds.
+    #[clap(long, default_value = "3600")]
+    duration: u64,
+
+    /// Seconds to run before taking the baseline.
+    #[clap(long, default_value = "60")]
+    warmup: u64,
+
+    /// Milliseconds between two joins or leaves.
+    #[clap(long, default_value = "50")]
+    churn: u64,
+
+    /// Connections per swarm.
+    #[clap(long, default_value = "8")]
+    max_connections: usize,
+
+    /// Allowed growth of the resident memory over the baseline, in MiB.
+    #[clap(long, default_value = "64")]
+    max_rss_growth: u64,
+
+    /// Allowed growth of open file descriptors over the baseline.
+    #[clap(long, default_value = "256")]
+    max_fd_growth: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    rss_kib: u64,
+    fds: u64,
+}
+
+fn main() -> io::Result<()> {
+    env_logger::init();
+    task::block_on(async_main())
+}
+
+async fn async_main() -> io::Result<()> {
+    let opts: Options = Options::parse();
+    let (bs_addr, _bs_task) = run_bootstrap_node::<&str>(None).await?;
+    let limits = ConnectionLimits::default().set_max_connections(Some(opts.max_connections));
+    let config = Config::default()
+        .set_bootstrap_nodes(Some(vec![bs_addr]))
+        .set_backend_enabled(Backend::Mdns, false)
+        .set_connection_limits(limits);
+
+    let mut handles = vec![];
+    for _ in 0..opts.swarms {
+        let mut swarm = Hyperswarm::bind(config.clone()).await?;
+        handles.push(swarm.handle());
+        task::spawn(async move {
+            while let Some(conn) = swarm.next().await {
+                // Hold connections until the remote closes them or the
+                // swarm evicts them.
+                if let Ok(mut conn) = conn {
+                    task::spawn(async move {
+                        let mut buf = [0u8; 1024];
+                        while let Ok(n) = futures::AsyncReadExt::read(&mut conn, &mut buf).await {
+                            if n == 0 {
+                                break;
+                            }
+                        }
+                    });
+                }
+            }
+        });
+    }
+    println!("started {} swarms, bootstrap node {}", opts.swarms, bs_addr);
+
+    let topics = opts.topics;
+    let churn = Duration::from_millis(opts.churn);
+    task::spawn(async move {
+        let mut joined = vec![vec![false; topics as usize]; handles.len()];
+        loop {
+            let (swarm, topic) = {
+                let mut rng = rand::thread_rng();
+                (rng.gen_range(0..handles.len()), rng.gen_range(0..topics))
+            };
+            let is_joined = &mut joined[swarm][topic as usize];
+            if *is_joined {
+                handles[swarm].leave([topic; 32]);
+            } else {
+                handles[swarm].configure([topic; 32], TopicConfig::both());
+            }
+            *is_joined = !*is_joined;
+            task::sleep(churn).await;
+        }
+    });
+
+    let start = Instant::now();
+    let warmup = Duration::from_secs(opts.warmup);
+    let duration = Duration::from_secs(opts.duration);
+    let mut baseline = None;
+    while start.elapsed() < duration {
+        task::sleep(Duration::from_secs(10)).await;
+        let usage = match usage() {
+            Some(usage) => usage,
+            None => continue,
+        };
+        println!(
+            "{:>6}s: {} KiB resident, {} fds",
+            start.elapsed().as_secs(),
+            usage.rss_kib,
+            usage.fds
+        );
+        if start.elapsed() < warmup {
+            continue;
+        }
+        let baseline = *baseline.get_or_insert(usage);
+        if usage.rss_kib > baseline.rss_kib + opts.max_rss_growth * 1024 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("memory grew from {:?} to {:?}", baseline, usage),
+            ));
+        }
+        if usage.fds > baseline.fds + opts.max_fd_growth {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("file descriptors grew from {:?} to {:?}", baseline, usage),
+            ));
+        }
+    }
+    println!("usage stayed bounded for {:?}", duration);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn usage() -> Option<Usage> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let rss_kib = status
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    let fds = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    Some(Usage { rss_kib, fds })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usage() -> Option<Usage> {
+    None
+}
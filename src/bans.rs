@@ -0,0 +1,222 @@
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A peer to ban or allow, by address or by public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerFilter {
+    /// A single address and port.
+    Addr(SocketAddr),
+    /// Every port of an address.
+    Ip(IpAddr),
+    /// A remote public key, matched once connections carry one.
+    Key([u8; 32]),
+}
+
+impl From<SocketAddr> for PeerFilter {
+    fn from(addr: SocketAddr) -> Self {
+        PeerFilter::Addr(addr)
+    }
+}
+
+impl From<IpAddr> for PeerFilter {
+    fn from(ip: IpAddr) -> Self {
+        PeerFilter::Ip(ip)
+    }
+}
+
+impl fmt::Display for PeerFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerFilter::Addr(addr) => write!(f, "addr {}", addr),
+            PeerFilter::Ip(ip) => write!(f, "ip {}", ip),
+            PeerFilter::Key(key) => write!(f, "key {}", hex::encode(key)),
+        }
+    }
+}
+
+impl PeerFilter {
+    fn matches(&self, addr: &SocketAddr, key: Option<&[u8; 32]>) -> bool {
+        match self {
+            PeerFilter::Addr(a) => a == addr,
+            PeerFilter::Ip(ip) => *ip == addr.ip(),
+            PeerFilter::Key(k) => Some(k) == key,
+        }
+    }
+
+    fn parse<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Option<Self> {
+        let filter = match parts.next()? {
+            "addr" => PeerFilter::Addr(parts.next()?.parse().ok()?),
+            "ip" => PeerFilter::Ip(parts.next()?.parse().ok()?),
+            "key" => {
+                let bytes = hex::decode(parts.next()?).ok()?;
+                if bytes.len() != 32 {
+                    return None;
+                }
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&bytes);
+                PeerFilter::Key(key)
+            }
+            _ => return None,
+        };
+        Some(filter)
+    }
+}
+
+/// Banned peers and an optional allowlist, persisted to a file if a path is
+/// set.
+///
+/// The file format is line based: `ban <filter> <expires>` with the expiry in
+/// seconds since the unix epoch, or 0 for permanent bans, and
+/// `allow <filter>`.
+#[derive(Debug, Default)]
+pub(crate) struct BanList {
+    path: Option<PathBuf>,
+    bans: HashMap<PeerFilter, u64>,
+    allow: Option<HashSet<PeerFilter>>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let mut list = Self {
+            path: Some(path.as_ref().to_path_buf()),
+            ..Self::default()
+        };
+        match fs::read_to_string(path.as_ref()) {
+            Ok(content) => {
+                for line in content.lines() {
+                    if list.parse_line(line).is_none() {
+                        warn!("skipping invalid ban list line: {}", line);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to read ban list {:?}: {}", path.as_ref(), e),
+        }
+        list
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let mut parts = line.split_whitespace();
+        match parts.next()? {
+            "ban" => {
+                let filter = PeerFilter::parse(&mut parts)?;
+                let expires = parts.next()?.parse().ok()?;
+                self.bans.insert(filter, expires);
+            }
+            "allow" => {
+                let filter = PeerFilter::parse(&mut parts)?;
+                self.allow.get_or_insert_with(HashSet::new).insert(filter);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn save(&mut self) {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut content = String::new();
+        for (filter, expires) in self.bans.iter() {
+            content.push_str(&format!("ban {} {}\n", filter, expires));
+        }
+        for filter in self.allow.iter().flatten() {
+            content.push_str(&format!("allow {}\n", filter));
+        }
+        let tmp_path = path.with_extension("tmp");
+        let res = fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, path));
+        if let Err(e) = res {
+            warn!("failed to write ban list {:?}: {}", path, e);
+        }
+    }
+
+    /// Ban a peer, for `duration` or until unbanned if None.
+    pub fn ban(&mut self, filter: PeerFilter, duration: Option<Duration>) {
+        let expires = match duration {
+            Some(duration) => now() + duration.as_secs().max(1),
+            None => 0,
+        };
+        self.bans.insert(filter, expires);
+        self.save();
+    }
+
+    pub fn unban(&mut self, filter: &PeerFilter) {
+        if self.bans.remove(filter).is_some() {
+            self.save();
+        }
+    }
+
+    /// Only allow peers matching one of the filters, or all peers if None.
+    pub fn allow_only(&mut self, allow: Option<HashSet<PeerFilter>>) {
+        self.allow = allow;
+        self.save();
+    }
+
+    /// Whether a peer may be dialed or accepted.
+    pub fn is_allowed(&mut self, addr: &SocketAddr, key: Option<&[u8; 32]>) -> bool {
+        let now = now();
+        let before = self.bans.len();
+        self.bans
+            .retain(|_, expires| *expires == 0 || *expires > now);
+        if self.bans.len() != before {
+            self.save();
+        }
+        if self.bans.keys().any(|filter| filter.matches(addr, key)) {
+            return false;
+        }
+        match self.allow.as_ref() {
+            Some(allow) => allow.iter().any(|filter| filter.matches(addr, key)),
+            None => true,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bans() {
+        let a: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let mut list = BanList::new();
+        list.ban(PeerFilter::Ip(a.ip()), None);
+        assert!(!list.is_allowed(&"10.0.0.1:5000".parse().unwrap(), None));
+        assert!(list.is_allowed(&b, None));
+
+        list.unban(&PeerFilter::Ip(a.ip()));
+        let allow = vec![PeerFilter::Addr(a)].into_iter().collect();
+        list.allow_only(Some(allow));
+        assert!(list.is_allowed(&a, None));
+        assert!(!list.is_allowed(&b, None));
+    }
+
+    #[test]
+    fn test_line_roundtrip() {
+        let mut list = BanList::new();
+        let key = PeerFilter::Key([7u8; 32]);
+        list.ban(key, None);
+        let line = format!("ban {} 0", key);
+        let mut parsed = BanList::new();
+        parsed.parse_line(&line).unwrap();
+        assert_eq!(parsed.bans, list.bans);
+    }
+}
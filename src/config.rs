@@ -1,34 +1,849 @@
+use async_std::channel;
+use std::collections::HashSet;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::discovery::instrument::{DhtMessage, DhtQueryEvent, DhtQueryHook};
+use crate::discovery::static_peers::StaticPeer;
+use crate::discovery::Backend;
+use crate::firewall::{Firewall, RemotePeer};
+use crate::keys::Keypair;
+use crate::runtime::TaskObserver;
+#[cfg(feature = "noise")]
+use crate::transport::handshake::Handshaker;
+#[cfg(feature = "sim")]
+use crate::transport::memory::MemoryNetwork;
 
 #[derive(Debug, Default, Clone)]
 pub struct Config {
+    pub name: Option<String>,
     pub bootstrap: Option<Vec<SocketAddr>>,
+    pub bootstrap_seeds: Vec<String>,
     pub ephemeral: bool,
+    pub cache_path: Option<PathBuf>,
+    pub peer_cache_ttl: Option<Duration>,
+    pub lookup_cache_ttl: Option<Duration>,
+    pub topic_salt: Option<Vec<u8>>,
+    pub dht_query_hook: Option<DhtQueryHook>,
+    pub dht_tap: Option<channel::Sender<DhtMessage>>,
+    pub mdns: MdnsConfig,
+    pub static_peers: Vec<StaticPeer>,
+    pub dns_discovery_domain: Option<String>,
+    pub pex: bool,
+    pub gossip: bool,
+    pub rendezvous_server: Option<SocketAddr>,
+    pub mainline: bool,
+    pub disabled_backends: HashSet<Backend>,
+    pub lan_only: bool,
+    pub connection_limits: ConnectionLimits,
+    pub resource_limits: ResourceLimits,
+    pub firewall: Option<Firewall>,
+    pub ban_list_path: Option<PathBuf>,
+    pub reconnect: ReconnectPolicy,
+    pub mode: SwarmMode,
+    pub dedup: DedupPolicy,
+    pub keepalive: Option<KeepalivePolicy>,
+    pub coalesce: Option<CoalescePolicy>,
+    pub reputation: ReputationPolicy,
+    pub keypair: Option<Keypair>,
+    pub handshake: HandshakePolicy,
+    pub plaintext: bool,
+    pub redact_logs: bool,
+    pub spawn_free: bool,
+    pub task_observer: Option<Arc<dyn TaskObserver>>,
+    #[cfg(feature = "noise")]
+    pub handshaker: Option<Arc<dyn Handshaker>>,
+    #[cfg(feature = "sim")]
+    pub memory_network: Option<MemoryNetwork>,
 }
 
 impl Config {
+    /// Name the swarm in logs and status, to tell apart several swarms in one
+    /// process.
+    pub fn set_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
     pub fn set_bootstrap_nodes(mut self, nodes: Option<Vec<SocketAddr>>) -> Self {
         self.bootstrap = nodes;
         self
     }
 
+    /// Resolve additional bootstrap nodes from DNS seeds, either `host:port`
+    /// (A/AAAA records) or a SRV record name.
+    pub fn set_bootstrap_seeds(mut self, seeds: Vec<String>) -> Self {
+        self.bootstrap_seeds = seeds;
+        self
+    }
+
     pub fn set_ephemeral(mut self, ephemeral: bool) -> Self {
         self.ephemeral = ephemeral;
         self
     }
+
+    /// Persist recently seen DHT nodes and topic peers to this file, and use
+    /// them to warm-start the next run. Cached nodes that answer a ping are
+    /// bootstrapped from instead of the bootstrap nodes.
+    pub fn set_cache_path(mut self, path: Option<PathBuf>) -> Self {
+        self.cache_path = path;
+        self
+    }
+
+    /// How long cached peers are dialed on rejoin, one hour by default.
+    pub fn set_peer_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.peer_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// How long the result of a DHT lookup answers further lookups of the
+    /// same topic, five seconds by default. Zero disables this, while
+    /// lookups of a topic that is being looked up still share the running
+    /// query.
+    pub fn set_lookup_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.lookup_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Mix a salt into every topic before it is announced or looked up, so that
+    /// applications using the same topic names do not find each other.
+    pub fn set_topic_salt(mut self, salt: Option<Vec<u8>>) -> Self {
+        self.topic_salt = salt;
+        self
+    }
+
+    /// Always report these peers for their topics, see
+    /// `discovery::static_peers::read_static_peers` to load them from a file.
+    pub fn set_static_peers(mut self, peers: Vec<StaticPeer>) -> Self {
+        self.static_peers = peers;
+        self
+    }
+
+    /// Look up peers in the TXT and SRV records of `<topic>.<domain>`
+    /// (requires the `dns` feature).
+    pub fn set_dns_discovery_domain(mut self, domain: Option<String>) -> Self {
+        self.dns_discovery_domain = domain;
+        self
+    }
+
+    /// Enable peer exchange, see `Hyperswarm::pex`.
+    pub fn set_pex(mut self, pex: bool) -> Self {
+        self.pex = pex;
+        self
+    }
+
+    /// Enable gossip of topic membership, see `Hyperswarm::gossip`.
+    pub fn set_gossip(mut self, gossip: bool) -> Self {
+        self.gossip = gossip;
+        self
+    }
+
+    /// Register with and discover peers through a rendezvous server.
+    pub fn set_rendezvous_server(mut self, server: Option<SocketAddr>) -> Self {
+        self.rendezvous_server = server;
+        self
+    }
+
+    /// Also announce and look up topics on the BitTorrent mainline DHT.
+    pub fn set_mainline(mut self, mainline: bool) -> Self {
+        self.mainline = mainline;
+        self
+    }
+
+    /// Start with a backend disabled. It can be enabled later with
+    /// `CombinedDiscovery::set_enabled`.
+    pub fn set_backend_enabled(mut self, backend: Backend, enabled: bool) -> Self {
+        if enabled {
+            self.disabled_backends.remove(&backend);
+        } else {
+            self.disabled_backends.insert(backend);
+        }
+        self
+    }
+
+    /// Never touch the internet: the DHT and every other backend that talks
+    /// to outside hosts stay disabled, leaving mDNS, static peers and the
+    /// exchanges over established connections. Peers outside the local network
+    /// are ignored.
+    pub fn set_lan_only(mut self, lan_only: bool) -> Self {
+        self.lan_only = lan_only;
+        self
+    }
+
+    pub fn set_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// Bound the memory of caches, queues and buffers, e.g. on embedded
+    /// targets.
+    pub fn set_resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
+    /// Check every new connection with an async callback before it is
+    /// handed to the application, rejecting it if the callback returns false.
+    /// The callback sees the remote public key and the topics of the peer.
+    pub fn set_firewall<F, Fut>(mut self, firewall: F) -> Self
+    where
+        F: Fn(RemotePeer) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.firewall = Some(Firewall::new(firewall));
+        self
+    }
+
+    /// Persist bans and the allowlist to this file, see `Hyperswarm::ban`.
+    pub fn set_ban_list_path(mut self, path: Option<PathBuf>) -> Self {
+        self.ban_list_path = path;
+        self
+    }
+
+    pub fn set_reconnect_policy(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    pub fn set_mode(mut self, mode: SwarmMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn set_dedup_policy(mut self, dedup: DedupPolicy) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Ping idle connections and close those whose peer stopped answering.
+    /// Changes the wire format, so all peers have to enable it.
+    pub fn set_keepalive(mut self, keepalive: Option<KeepalivePolicy>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Collect small writes to connections into fewer packets, which mostly
+    /// helps chatty protocols over uTP. Disabled by default.
+    pub fn set_write_coalescing(mut self, coalesce: Option<CoalescePolicy>) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    pub fn set_reputation_policy(mut self, reputation: ReputationPolicy) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    pub fn set_handshake_policy(mut self, handshake: HandshakePolicy) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    /// Skip the handshake and send everything unencrypted, e.g. inside an
+    /// already encrypted network or for benchmarks. Both ends have to agree,
+    /// and as peers are not authenticated, bans and dedup by key do not
+    /// apply. Without the `noise` feature connections are always plaintext.
+    pub fn set_plaintext(mut self, plaintext: bool) -> Self {
+        self.plaintext = plaintext;
+        self
+    }
+
+    /// Replace peer addresses and keys in all log and tracing output with
+    /// short hashes, so logs can be shared without revealing who is in the
+    /// swarm. Applies to the whole process once any swarm enables it.
+    pub fn set_redact_logs(mut self, redact_logs: bool) -> Self {
+        self.redact_logs = redact_logs;
+        self
+    }
+
+    /// Spawn no background tasks, and instead run them all, e.g. of mDNS,
+    /// lifecycle hooks, connection timers and `Hyperswarm::write_events_json`,
+    /// while the swarm stream is polled. For constrained executors and deterministic
+    /// simulations. The swarm then only makes progress while polled, and
+    /// channels of a `Mux` still run as tasks.
+    pub fn set_spawn_free(mut self, spawn_free: bool) -> Self {
+        self.spawn_free = spawn_free;
+        self
+    }
+
+    /// Report the tasks the swarm spawns as they start and stop, see
+    /// `TaskObserver`. Dialing and announcing are not tasks of their own but
+    /// run while the swarm is polled, the timers of all connections share
+    /// the `hyperswarm::timers` task, and the tasks of a `Mux` are only
+    /// named, as muxes are created outside swarms.
+    pub fn set_task_observer(mut self, observer: Option<Arc<dyn TaskObserver>>) -> Self {
+        self.task_observer = observer;
+        self
+    }
+
+    /// Bind on a `MemoryNetwork` instead of TCP and uTP sockets, and find
+    /// peers announced on it with a `MemoryDiscovery`. The DHT and mDNS
+    /// still run unless disabled, see `sim::Simulation::config`.
+    #[cfg(feature = "sim")]
+    pub fn set_memory_network(mut self, network: Option<MemoryNetwork>) -> Self {
+        self.memory_network = network;
+        self
+    }
+
+    /// Secure connections with another handshake than the Noise one, see
+    /// `Handshaker`. The policy's resumption and payload are then up to the
+    /// handshaker, and `Hyperswarm::public_key` is only correct if it
+    /// authenticates with the configured keypair.
+    #[cfg(feature = "noise")]
+    pub fn set_handshaker(mut self, handshaker: Option<Arc<dyn Handshaker>>) -> Self {
+        self.handshaker = handshaker;
+        self
+    }
+
+    /// Authenticate with this keypair instead of a newly generated one.
+    pub fn set_keypair(mut self, keypair: Option<Keypair>) -> Self {
+        self.keypair = keypair;
+        self
+    }
+
+    pub fn set_mdns_config(mut self, mdns: MdnsConfig) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Invoke a callback for every DHT query and response.
+    pub fn set_dht_query_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&DhtQueryEvent) + Send + Sync + 'static,
+    {
+        self.dht_query_hook = Some(DhtQueryHook::new(hook));
+        self
+    }
+
+    /// Send every DHT message sent and received to a channel, to debug
+    /// interop with other DHT implementations. Messages are dropped while the
+    /// channel is full.
+    pub fn set_dht_tap(mut self, tap: Option<channel::Sender<DhtMessage>>) -> Self {
+        self.dht_tap = tap;
+        self
+    }
+}
+
+/// Naming and timing of mDNS records.
+///
+/// The IPv4 backend uses a fixed record format and only honours
+/// `query_interval`. If `service`, `instance_name` or `ttl` are changed, it
+/// is not started, so that topics are only announced under the configured
+/// names over IPv6.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MdnsConfig {
+    /// Domain appended to topic names, e.g. `<topic>.hyperswarm.local`.
+    pub service: String,
+    /// Instance name used as the SRV target, defaults to a random id.
+    pub instance_name: Option<String>,
+    pub ttl: Duration,
+    pub query_interval: Duration,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self {
+            service: "hyperswarm.local".into(),
+            instance_name: None,
+            ttl: Duration::from_secs(120),
+            query_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl MdnsConfig {
+    pub fn set_service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    pub fn set_instance_name(mut self, name: Option<String>) -> Self {
+        self.instance_name = name;
+        self
+    }
+
+    pub fn set_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn set_query_interval(mut self, interval: Duration) -> Self {
+        self.query_interval = interval;
+        self
+    }
+}
+
+/// Caps on what the swarm keeps in memory, see `Config::set_resource_limits`.
+/// Whenever a cap makes the swarm drop something, it is counted in
+/// `Metrics::limit_hits` under the name of the cap, e.g. `dial_queue`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResourceLimits {
+    /// DHT nodes in the peer cache, see `Config::set_cache_path`. The least
+    /// recently seen ones are dropped (`cached_nodes`).
+    pub max_cached_nodes: usize,
+    /// Peers per topic in the peer cache (`cached_peers`).
+    pub max_cached_peers_per_topic: usize,
+    /// Peers waiting to be dialed. The least promising ones are dropped
+    /// (`dial_queue`).
+    pub max_queued_dials: usize,
+    /// Peers found by the DHT and other backends until the swarm takes them
+    /// (`discovery_queue`).
+    pub max_queued_peers: usize,
+    /// DHT nodes whose health the swarm tracks. The DHT's own routing table
+    /// is bounded by its bucket size. The worst nodes are dropped
+    /// (`dht_nodes`).
+    pub max_dht_nodes: usize,
+    /// Bytes a connection buffers for writing. Larger writes are split into
+    /// frames of at most this size. Reads are bounded by the frame size the
+    /// peer chose, at most 64 KiB.
+    pub max_write_buffer: usize,
+    /// Peers whose topics the swarm remembers, to attribute connections to
+    /// topics. Peers that are neither connected nor being dialed are
+    /// forgotten (`known_peers`).
+    pub max_known_peers: usize,
+    /// Recently discovered peers whose later reports, e.g. by another
+    /// backend, are merged into the first. Further peers are not recorded,
+    /// so their reports are yielded again (`merged_peers`).
+    pub max_merged_peers: usize,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_cached_nodes: 256,
+            max_cached_peers_per_topic: 64,
+            max_queued_dials: 256,
+            max_queued_peers: 1024,
+            max_dht_nodes: 1024,
+            max_write_buffer: 65535,
+            max_known_peers: 4096,
+            max_merged_peers: 4096,
+        }
+    }
+}
+
+impl ResourceLimits {
+    pub fn set_max_cached_nodes(mut self, max: usize) -> Self {
+        self.max_cached_nodes = max;
+        self
+    }
+
+    pub fn set_max_cached_peers_per_topic(mut self, max: usize) -> Self {
+        self.max_cached_peers_per_topic = max;
+        self
+    }
+
+    pub fn set_max_queued_dials(mut self, max: usize) -> Self {
+        self.max_queued_dials = max;
+        self
+    }
+
+    pub fn set_max_queued_peers(mut self, max: usize) -> Self {
+        self.max_queued_peers = max;
+        self
+    }
+
+    pub fn set_max_dht_nodes(mut self, max: usize) -> Self {
+        self.max_dht_nodes = max;
+        self
+    }
+
+    pub fn set_max_write_buffer(mut self, max: usize) -> Self {
+        self.max_write_buffer = max;
+        self
+    }
+
+    pub fn set_max_known_peers(mut self, max: usize) -> Self {
+        self.max_known_peers = max;
+        self
+    }
+
+    pub fn set_max_merged_peers(mut self, max: usize) -> Self {
+        self.max_merged_peers = max;
+        self
+    }
+}
+
+/// Caps on the number of open connections. When a cap is hit, the eviction
+/// policy decides whether a new connection is refused or an open one closed.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ConnectionLimits {
+    pub max_connections: Option<usize>,
+    pub max_inbound: Option<usize>,
+    pub max_outbound: Option<usize>,
+    /// Incoming connections from a single IP.
+    pub max_per_ip: Option<usize>,
+    /// At most this many incoming connections are accepted from a single IP
+    /// within the duration.
+    pub accept_rate: Option<(usize, Duration)>,
+    pub eviction: EvictionPolicy,
+}
+
+impl ConnectionLimits {
+    pub fn set_max_connections(mut self, max: Option<usize>) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    pub fn set_max_inbound(mut self, max: Option<usize>) -> Self {
+        self.max_inbound = max;
+        self
+    }
+
+    pub fn set_max_outbound(mut self, max: Option<usize>) -> Self {
+        self.max_outbound = max;
+        self
+    }
+
+    pub fn set_max_per_ip(mut self, max: Option<usize>) -> Self {
+        self.max_per_ip = max;
+        self
+    }
+
+    pub fn set_accept_rate(mut self, rate: Option<(usize, Duration)>) -> Self {
+        self.accept_rate = rate;
+        self
+    }
+
+    pub fn set_eviction(mut self, eviction: EvictionPolicy) -> Self {
+        self.eviction = eviction;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Refuse new connections, and stop dialing, while at a cap.
+    Reject,
+    /// Close the connection that has been idle the longest.
+    Idle,
+    /// Close an incoming connection before an outgoing one, as those were
+    /// dialed for a topic, then the one idle the longest.
+    LowestPriority,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Reject
+    }
+}
+
+/// Which directions of connections a swarm opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwarmMode {
+    /// Accept incoming connections and dial peers.
+    Full,
+    /// Announce topics and accept incoming connections, but never dial, e.g.
+    /// for servers with strict control over outbound traffic. Topics are
+    /// still looked up, and the peers found reported as events.
+    ListenOnly,
+    /// Bind no TCP or uTP listener and only look up topics and dial, e.g. for
+    /// short-lived clients or sandboxes that may not bind ports. Topics are
+    /// never announced, and only TCP is used.
+    DialOnly,
+}
+
+impl Default for SwarmMode {
+    fn default() -> Self {
+        SwarmMode::Full
+    }
+}
+
+/// How duplicate connections to the same peer are detected. Of two
+/// duplicates, only one is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Keep one outgoing connection per address, across transports.
+    Addr,
+    /// Keep one connection per remote public key, regardless of address,
+    /// transport and direction. Only applies to connections carrying a key.
+    Key,
+    /// Keep all connections, for applications that want parallel ones.
+    Disabled,
+}
+
+impl Default for DedupPolicy {
+    fn default() -> Self {
+        DedupPolicy::Addr
+    }
+}
+
+/// Pings on connections without incoming data, see `Config::set_keepalive`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeepalivePolicy {
+    /// A ping is sent after this long without data from the peer.
+    pub interval: Duration,
+    /// Close the connection after this many pings in a row went unanswered.
+    pub max_missed: u32,
+}
+
+impl Default for KeepalivePolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
+    }
+}
+
+impl KeepalivePolicy {
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn set_max_missed(mut self, max_missed: u32) -> Self {
+        self.max_missed = max_missed;
+        self
+    }
+}
+
+/// Buffering of small writes, see `Config::set_write_coalescing`. Buffered
+/// data is written once it would exceed `max_bytes`, on flush, or after
+/// `max_delay`, whichever comes first. Writes to connections that nobody
+/// reads from are not buffered, as nothing would write them out in time.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CoalescePolicy {
+    /// Writes at least this large are not buffered.
+    pub max_bytes: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for CoalescePolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1200,
+            max_delay: Duration::from_millis(5),
+        }
+    }
+}
+
+impl CoalescePolicy {
+    pub fn set_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn set_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+/// When encrypted connections switch to new keys, see
+/// `HandshakePolicy::rekey`. Keys are rekeyed once either threshold is hit.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RekeyPolicy {
+    pub interval: Duration,
+    /// Bytes sent under one key.
+    pub max_bytes: u64,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            max_bytes: 1 << 30,
+        }
+    }
+}
+
+impl RekeyPolicy {
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn set_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+}
+
+/// Limits on what a remote may consume before completing the handshake.
+/// Connections exceeding them are dropped as a failed handshake, which for
+/// incoming connections counts against the IP, see `ReputationPolicy`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HandshakePolicy {
+    pub timeout: Duration,
+    /// Bytes read from the remote, including framing.
+    pub max_bytes: usize,
+    /// Issue tickets valid this long after every handshake, so reconnects
+    /// to the same address can skip the full key exchange. Disabled if None.
+    /// Resumed sessions keep forward secrecy, but authenticate the peer by
+    /// its ticket instead of its static key.
+    pub resumption: Option<Duration>,
+    /// Drop incoming connections from IPs with failed handshakes before any
+    /// handshake work, for a time doubling with every failure up to this.
+    /// Disabled if None.
+    pub greylist: Option<Duration>,
+    /// Application data sent to every peer in the handshake, e.g. a version
+    /// or an auth token, see `PeerInfo::handshake_payload`. The responder
+    /// sends it before the initiator is authenticated, and it counts towards
+    /// the remote's `max_bytes`.
+    pub payload: Vec<u8>,
+    /// Rekey long-lived connections, so that a key leaked later does not
+    /// decrypt the data sent before. Disabled if None.
+    pub rekey: Option<RekeyPolicy>,
+}
+
+impl Default for HandshakePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_bytes: 1024,
+            resumption: None,
+            greylist: Some(Duration::from_secs(10 * 60)),
+            payload: vec![],
+            rekey: Some(RekeyPolicy::default()),
+        }
+    }
 }
 
+impl HandshakePolicy {
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn set_resumption(mut self, ttl: Option<Duration>) -> Self {
+        self.resumption = ttl;
+        self
+    }
+
+    pub fn set_greylist(mut self, max_delay: Option<Duration>) -> Self {
+        self.greylist = max_delay;
+        self
+    }
+
+    pub fn set_payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn set_rekey(mut self, rekey: Option<RekeyPolicy>) -> Self {
+        self.rekey = rekey;
+        self
+    }
+}
+
+/// What happens to misbehaving peers, see `Misbehavior`. They are always
+/// dialed after well-behaved ones.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReputationPolicy {
+    /// Ban peers whose misbehavior score reaches this, never if None.
+    pub ban_threshold: Option<u32>,
+    pub ban_duration: Duration,
+}
+
+impl Default for ReputationPolicy {
+    fn default() -> Self {
+        Self {
+            ban_threshold: None,
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl ReputationPolicy {
+    pub fn set_ban_threshold(mut self, threshold: Option<u32>) -> Self {
+        self.ban_threshold = threshold;
+        self
+    }
+
+    pub fn set_ban_duration(mut self, duration: Duration) -> Self {
+        self.ban_duration = duration;
+        self
+    }
+}
+
+/// Redialing of peers whose outgoing connection was lost while one of their
+/// topics is still joined. The delay doubles with every attempt.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReconnectPolicy {
+    /// Give up after this many attempts, 0 disables reconnecting.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn set_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub fn set_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn set_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+}
+
+/// How a topic is joined, following the server and client modes of
+/// hyperswarm: a server announces the topic and accepts connections, a
+/// client looks it up and dials the peers found.
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct TopicConfig {
+    /// Server mode.
     pub announce: bool,
+    /// Client mode.
     pub lookup: bool,
+    /// Stop dialing peers for the topic while this many are connected.
+    pub max_peers: Option<usize>,
 }
 
 impl TopicConfig {
+    pub fn server() -> Self {
+        Self::default().set_server(true)
+    }
+
+    pub fn client() -> Self {
+        Self::default().set_client(true)
+    }
+
+    pub fn set_server(mut self, server: bool) -> Self {
+        self.announce = server;
+        self
+    }
+
+    pub fn set_client(mut self, client: bool) -> Self {
+        self.lookup = client;
+        self
+    }
+
+    pub fn set_max_peers(mut self, max: Option<usize>) -> Self {
+        self.max_peers = max;
+        self
+    }
+
     pub fn both() -> Self {
         Self {
             announce: true,
             lookup: true,
+            max_peers: None,
         }
     }
 
@@ -0,0 +1,74 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+type AnyValue = Box<dyn Any + Send + Sync>;
+
+/// Values attached to a connection, one per type. Clones share the same
+/// values, so data set on a connection can be read back from later events.
+#[derive(Clone, Default)]
+pub struct ConnectionData(Arc<Mutex<HashMap<TypeId, AnyValue>>>);
+
+impl fmt::Debug for ConnectionData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ConnectionData")
+            .field(&self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl ConnectionData {
+    /// Attach a value, returning the previous value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&self, value: T) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Any + Send + Sync + Clone>(&self) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    /// Run a function on the value of a type, if set.
+    pub fn with<T: Any + Send + Sync, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.0
+            .lock()
+            .unwrap()
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut::<T>())
+            .map(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConnectionData;
+
+    #[test]
+    fn test_data() {
+        let data = ConnectionData::default();
+        assert_eq!(data.insert(1u32), None);
+        assert_eq!(data.insert(String::from("peer")), None);
+        let shared = data.clone();
+        shared.with(|n: &mut u32| *n += 1);
+        assert_eq!(data.get::<u32>(), Some(2));
+        assert_eq!(data.remove::<String>(), Some("peer".into()));
+        assert_eq!(shared.get::<String>(), None);
+    }
+}
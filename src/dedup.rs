@@ -0,0 +1,105 @@
+use log::*;
+use std::collections::HashMap;
+
+use crate::redact::redact;
+use crate::transport::ConnectionControl;
+
+pub(crate) type PublicKey = [u8; 32];
+
+/// Whether a new connection to a peer should replace an existing one.
+///
+/// Like JS hyperswarm, of two connections in opposite directions the one
+/// initiated by the peer with the larger key is kept, so both sides drop the
+/// same duplicate. Of two in the same direction the newer one is kept.
+pub(crate) fn keep_new(
+    local_key: &PublicKey,
+    remote_key: &PublicKey,
+    existing_is_initiator: bool,
+    new_is_initiator: bool,
+) -> bool {
+    if existing_is_initiator == new_is_initiator {
+        return true;
+    }
+    new_is_initiator == (local_key > remote_key)
+}
+
+/// Open connections by remote public key, keeping one per peer regardless of
+/// address or transport.
+#[derive(Debug, Default)]
+pub(crate) struct KeyDedup {
+    open: HashMap<PublicKey, (bool, ConnectionControl)>,
+}
+
+impl KeyDedup {
+    /// Register a connection. Returns false if it is a duplicate that should
+    /// be dropped; an existing connection that loses the tie-break is evicted.
+    pub fn insert(
+        &mut self,
+        local_key: &PublicKey,
+        remote_key: PublicKey,
+        is_initiator: bool,
+        control: ConnectionControl,
+    ) -> bool {
+        self.open.retain(|_, (_, control)| !control.is_closed());
+        if let Some((existing_is_initiator, existing)) = self.open.get(&remote_key) {
+            if !keep_new(local_key, &remote_key, *existing_is_initiator, is_initiator) {
+                debug!(
+                    "dropping duplicate connection to {}",
+                    redact(hex::encode(remote_key))
+                );
+                return false;
+            }
+            debug!(
+                "replacing connection to {}",
+                redact(hex::encode(remote_key))
+            );
+            existing.evict();
+        }
+        self.open.insert(remote_key, (is_initiator, control));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ConnectionLimits;
+    use crate::limits::ConnectionTracker;
+    use crate::transport::Connection;
+    use futures::io::Cursor;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_tie_break_agrees() {
+        let a = [1u8; 32];
+        let b = [2u8; 32];
+        // A dialed B and B dialed A. A sees its own connection as outgoing,
+        // B sees the same connection as incoming.
+        let a_keeps_own = !keep_new(&a, &b, true, false);
+        let b_keeps_a = keep_new(&b, &a, true, false);
+        assert_eq!(a_keeps_own, b_keeps_a);
+        assert!(!a_keeps_own);
+
+        let mut dedup = KeyDedup::default();
+        let first = ConnectionControl::default();
+        assert!(dedup.insert(&a, b, true, first.clone()));
+        assert!(dedup.insert(&a, b, false, ConnectionControl::default()));
+        assert!(first.is_closed());
+    }
+
+    #[test]
+    fn test_dropped_connection_is_freed() {
+        let (a, b) = ([1u8; 32], [2u8; 32]);
+        let addr = SocketAddr::from(([10, 0, 0, 2], 4000));
+        let conn = Connection::new(Cursor::new(vec![]), addr, false, "tcp".into());
+        let mut dedup = KeyDedup::default();
+        let mut tracker = ConnectionTracker::new(ConnectionLimits::default());
+        assert!(dedup.insert(&a, b, false, conn.control()));
+        tracker.insert(addr, false, "tcp", conn.control());
+        // Dropped without being read to the end or closed.
+        drop(conn);
+        assert_eq!(tracker.len(), 0);
+        // Would lose the tie-break against the first one if it were open.
+        assert!(dedup.insert(&a, b, true, ConnectionControl::default()));
+    }
+}
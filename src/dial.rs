@@ -0,0 +1,474 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::discovery::{DiscoveredPeer, DiscoveryMethod, Topic};
+use crate::runtime;
+use crate::status::DialDump;
+
+const MAX_QUEUED: usize = 256;
+/// Bounds of the number of dials in flight, see `Concurrency`.
+const MIN_IN_FLIGHT: usize = 4;
+const MAX_IN_FLIGHT: usize = 128;
+const INITIAL_IN_FLIGHT: usize = 16;
+/// Connects faster than this let the number of dials in flight grow.
+const QUICK_CONNECT: Duration = Duration::from_secs(1);
+/// Share of recent dials timing out above which dials are backed off.
+const TIMEOUT_SHARE: f64 = 0.5;
+/// Dials without a result after this long are counted as failed.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Connects faster than this get the full latency bonus.
+const FAST_CONNECT: Duration = Duration::from_millis(50);
+/// Added to the score of prioritized peers, ranking them above all others.
+const PRIORITY_BONUS: i32 = 1000;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct History {
+    successes: u32,
+    failures: u32,
+    connect_time: Option<Duration>,
+}
+
+/// How many dials may be in flight at once.
+///
+/// The limit grows by one with every quick connect, and shrinks by a quarter
+/// with every timeout while timeouts make up most of the recent dials. A
+/// good network thus gets many parallel dials soon, while a lossy one is not
+/// flooded with connects that only time out.
+#[derive(Debug, Clone, Copy)]
+struct Concurrency {
+    limit: usize,
+    // Moving average of the share of dials that timed out.
+    timeouts: f64,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self {
+            limit: INITIAL_IN_FLIGHT,
+            timeouts: 0.0,
+        }
+    }
+}
+
+impl Concurrency {
+    fn on_connected(&mut self, connect_time: Option<Duration>) {
+        self.record(false);
+        if connect_time.map_or(false, |time| time <= QUICK_CONNECT) {
+            self.limit = (self.limit + 1).min(MAX_IN_FLIGHT);
+        }
+    }
+
+    fn on_failed(&mut self, timed_out: bool) {
+        self.record(timed_out);
+        if timed_out && self.timeouts > TIMEOUT_SHARE {
+            self.limit = (self.limit - self.limit / 4).max(MIN_IN_FLIGHT);
+        }
+    }
+
+    fn record(&mut self, timed_out: bool) {
+        let sample = if timed_out { 1.0 } else { 0.0 };
+        self.timeouts = 0.9 * self.timeouts + 0.1 * sample;
+    }
+}
+
+/// Queue of peers to dial, ordered by how likely they are to be useful.
+#[derive(Debug)]
+pub(crate) struct DialQueue {
+    max_queued: usize,
+    queue: BTreeMap<(i32, Reverse<u64>), SocketAddr>,
+    queued: HashMap<SocketAddr, (i32, Reverse<u64>)>,
+    in_flight: HashMap<SocketAddr, Instant>,
+    /// The topic queued and in-flight peers were found for.
+    topics: HashMap<SocketAddr, Topic>,
+    history: HashMap<SocketAddr, History>,
+    priority_peers: HashSet<SocketAddr>,
+    penalties: HashMap<SocketAddr, u32>,
+    priority_topics: HashSet<Topic>,
+    concurrency: Concurrency,
+    seq: u64,
+    // Peers dropped from or refused by the full queue.
+    shed: u64,
+}
+
+impl Default for DialQueue {
+    fn default() -> Self {
+        Self::with_capacity(MAX_QUEUED)
+    }
+}
+
+impl DialQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(max_queued: usize) -> Self {
+        Self {
+            max_queued,
+            queue: BTreeMap::new(),
+            queued: HashMap::new(),
+            in_flight: HashMap::new(),
+            topics: HashMap::new(),
+            history: HashMap::new(),
+            priority_peers: HashSet::new(),
+            penalties: HashMap::new(),
+            priority_topics: HashSet::new(),
+            concurrency: Concurrency::default(),
+            seq: 0,
+            shed: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.max_queued
+    }
+
+    /// Queue a peer for dialing. Returns false if it is queued or being
+    /// dialed already, or if the queue is full of better peers.
+    pub fn push(&mut self, peer: &DiscoveredPeer) -> bool {
+        let addr = peer.addr();
+        if self.queued.contains_key(&addr) || self.in_flight.contains_key(&addr) {
+            return false;
+        }
+        let score = self.score(peer);
+        if self.queue.len() >= self.max_queued {
+            self.shed += 1;
+            match self.queue.keys().next().copied() {
+                Some(lowest) if lowest.0 < score => {
+                    let dropped = self.queue.remove(&lowest).unwrap();
+                    self.queued.remove(&dropped);
+                    self.topics.remove(&dropped);
+                }
+                _ => return false,
+            }
+        }
+        self.seq += 1;
+        let key = (score, Reverse(self.seq));
+        self.queue.insert(key, addr);
+        self.queued.insert(addr, key);
+        if let Some(topic) = peer.topic() {
+            self.topics.insert(addr, topic);
+        }
+        true
+    }
+
+    /// Dial a peer before all peers without priority. A queued peer moves to
+    /// the front right away.
+    pub fn set_peer_priority(&mut self, addr: SocketAddr, high: bool) {
+        let changed = if high {
+            self.priority_peers.insert(addr)
+        } else {
+            self.priority_peers.remove(&addr)
+        };
+        if !changed {
+            return;
+        }
+        if let Some(key) = self.queued.get_mut(&addr) {
+            self.queue.remove(key);
+            key.0 += if high {
+                PRIORITY_BONUS
+            } else {
+                -PRIORITY_BONUS
+            };
+            self.queue.insert(*key, addr);
+        }
+    }
+
+    /// Rank a peer lower by its misbehavior score, see `Reputation`.
+    pub fn set_penalty(&mut self, addr: SocketAddr, score: u32) {
+        if score == 0 {
+            self.penalties.remove(&addr);
+        } else {
+            self.penalties.insert(addr, score);
+        }
+    }
+
+    /// Dial peers found for a topic before all peers without priority. Only
+    /// applies to peers queued from now on.
+    pub fn set_topic_priority(&mut self, topic: Topic, high: bool) {
+        if high {
+            self.priority_topics.insert(topic);
+        } else {
+            self.priority_topics.remove(&topic);
+        }
+    }
+
+    /// The next peer to dial, if there is room for another dial.
+    pub fn next_dial(&mut self) -> Option<SocketAddr> {
+        self.expire();
+        if self.in_flight.len() >= self.concurrency.limit {
+            return None;
+        }
+        let key = *self.queue.keys().next_back()?;
+        let addr = self.queue.remove(&key).unwrap();
+        self.queued.remove(&addr);
+        self.in_flight.insert(addr, runtime::now());
+        Some(addr)
+    }
+
+    pub fn on_connected(&mut self, addr: SocketAddr) {
+        let started = self.in_flight.remove(&addr);
+        self.topics.remove(&addr);
+        let history = self.history.entry(addr).or_default();
+        history.successes += 1;
+        history.failures = 0;
+        let connect_time = started.map(runtime::elapsed);
+        if connect_time.is_some() {
+            history.connect_time = connect_time;
+            self.concurrency.on_connected(connect_time);
+        }
+    }
+
+    pub fn on_failed(&mut self, addr: SocketAddr) {
+        self.failed(addr, false);
+    }
+
+    /// Like `on_failed`, for a dial that timed out, which counts towards
+    /// backing off dials.
+    pub fn on_timed_out(&mut self, addr: SocketAddr) {
+        self.failed(addr, true);
+    }
+
+    fn failed(&mut self, addr: SocketAddr, timed_out: bool) {
+        if self.in_flight.remove(&addr).is_some() {
+            self.concurrency.on_failed(timed_out);
+        }
+        self.topics.remove(&addr);
+        self.history.entry(addr).or_default().failures += 1;
+    }
+
+    /// Whether a peer is queued or being dialed.
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.queued.contains_key(addr) || self.in_flight.contains_key(addr)
+    }
+
+    /// Number of peers waiting to be dialed.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of peers shed from or refused by the full queue since the last
+    /// call.
+    pub fn take_shed(&mut self) -> u64 {
+        std::mem::take(&mut self.shed)
+    }
+
+    /// Number of dials that are in progress.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Number of dials that may be in progress at once, see `Concurrency`.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.limit
+    }
+
+    /// Number of dials in progress for peers found for a topic.
+    pub fn in_flight_for(&self, topic: &Topic) -> usize {
+        self.in_flight
+            .keys()
+            .filter(|addr| self.topics.get(addr) == Some(topic))
+            .count()
+    }
+
+    /// In-flight dials, followed by queued ones in the order they will be
+    /// dialed.
+    pub fn dump(&self) -> Vec<DialDump> {
+        let topic = |addr: &SocketAddr| self.topics.get(addr).map(hex::encode);
+        let in_flight = self.in_flight.iter().map(|(addr, started)| DialDump {
+            addr: *addr,
+            topic: topic(addr),
+            dialing_for: Some(runtime::elapsed(*started)),
+        });
+        let queued = self.queue.values().rev().map(|addr| DialDump {
+            addr: *addr,
+            topic: topic(addr),
+            dialing_for: None,
+        });
+        in_flight.chain(queued).collect()
+    }
+
+    /// Forget all in-flight dials, keeping the queue.
+    pub fn cancel_all(&mut self) {
+        for addr in self.in_flight.drain().map(|(addr, _)| addr) {
+            self.topics.remove(&addr);
+        }
+    }
+
+    /// Forget an in-flight dial that was not started after all.
+    pub fn cancel(&mut self, addr: &SocketAddr) {
+        self.in_flight.remove(addr);
+        self.topics.remove(addr);
+    }
+
+    /// Drop queued peers found for a topic and forget their in-flight dials,
+    /// returning the addresses that were being dialed.
+    pub fn cancel_topic(&mut self, topic: &Topic) -> Vec<SocketAddr> {
+        let addrs: Vec<SocketAddr> = self
+            .topics
+            .iter()
+            .filter(|(_, t)| *t == topic)
+            .map(|(addr, _)| *addr)
+            .collect();
+        let mut dialing = vec![];
+        for addr in addrs {
+            self.topics.remove(&addr);
+            if let Some(key) = self.queued.remove(&addr) {
+                self.queue.remove(&key);
+            }
+            if self.in_flight.remove(&addr).is_some() {
+                dialing.push(addr);
+            }
+        }
+        dialing
+    }
+
+    fn expire(&mut self) {
+        let timed_out: Vec<SocketAddr> = self
+            .in_flight
+            .iter()
+            .filter(|(_, started)| runtime::elapsed(**started) > DIAL_TIMEOUT)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in timed_out {
+            self.on_timed_out(addr);
+        }
+    }
+
+    fn score(&self, peer: &DiscoveredPeer) -> i32 {
+        let mut score = match peer.source() {
+            DiscoveryMethod::Static => 40,
+            DiscoveryMethod::Mdns => 30,
+            DiscoveryMethod::Cache => 25,
+            DiscoveryMethod::Dht | DiscoveryMethod::Dns | DiscoveryMethod::Rendezvous => 20,
+            DiscoveryMethod::Mainline | DiscoveryMethod::Custom(_) => 15,
+            DiscoveryMethod::Pex | DiscoveryMethod::Gossip => 10,
+        };
+        // Reported by several backends independently.
+        score += 5 * (peer.sources().len() as i32 - 1).min(3);
+        let topic_priority = peer
+            .topic()
+            .map_or(false, |topic| self.priority_topics.contains(&topic));
+        if topic_priority || self.priority_peers.contains(&peer.addr()) {
+            score += PRIORITY_BONUS;
+        }
+        // The transports listen on IPv4.
+        if peer.addr().is_ipv4() {
+            score += 10;
+        }
+        if let Some(penalty) = self.penalties.get(&peer.addr()) {
+            score -= 10 * (*penalty).min(10) as i32;
+        }
+        if let Some(history) = self.history.get(&peer.addr()) {
+            score += 20 * history.successes.min(3) as i32;
+            score -= 25 * history.failures.min(4) as i32;
+            if let Some(connect_time) = history.connect_time {
+                let ratio = FAST_CONNECT.as_secs_f64() / connect_time.as_secs_f64().max(0.001);
+                score += (10.0 * ratio.min(1.0)) as i32;
+            }
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peer(addr: &str, source: DiscoveryMethod) -> DiscoveredPeer {
+        DiscoveredPeer::new(addr.parse().unwrap(), None, source)
+    }
+
+    #[test]
+    fn test_dial_order() {
+        let mut queue = DialQueue::new();
+        assert!(queue.push(&peer("10.0.0.1:1", DiscoveryMethod::Pex)));
+        assert!(queue.push(&peer("10.0.0.2:1", DiscoveryMethod::Mdns)));
+        assert!(queue.push(&peer("[fd00::3]:1", DiscoveryMethod::Mdns)));
+        assert!(!queue.push(&peer("10.0.0.2:1", DiscoveryMethod::Dht)));
+
+        let first = queue.next_dial().unwrap();
+        assert_eq!(first, "10.0.0.2:1".parse().unwrap());
+        queue.on_failed(first);
+        assert_eq!(queue.next_dial(), Some("[fd00::3]:1".parse().unwrap()));
+
+        // The failed peer now ranks below a fresh one from the same source.
+        assert!(queue.push(&peer("10.0.0.2:1", DiscoveryMethod::Mdns)));
+        assert!(queue.push(&peer("10.0.0.4:1", DiscoveryMethod::Mdns)));
+        assert_eq!(queue.next_dial(), Some("10.0.0.4:1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut queue = DialQueue::new();
+        let topic = [1u8; 32];
+        queue.set_topic_priority(topic, true);
+        let bulk = peer("10.0.0.1:1", DiscoveryMethod::Static);
+        let wanted = DiscoveredPeer::new(
+            "10.0.0.2:1".parse().unwrap(),
+            Some(topic),
+            DiscoveryMethod::Pex,
+        );
+        assert!(queue.push(&bulk));
+        assert!(queue.push(&wanted));
+        assert!(queue.push(&peer("10.0.0.3:1", DiscoveryMethod::Pex)));
+        queue.set_peer_priority("10.0.0.3:1".parse().unwrap(), true);
+
+        assert_eq!(queue.next_dial(), Some("10.0.0.2:1".parse().unwrap()));
+        assert_eq!(queue.next_dial(), Some("10.0.0.3:1".parse().unwrap()));
+        assert_eq!(queue.next_dial(), Some(bulk.addr()));
+    }
+
+    #[test]
+    fn test_cancel_topic() {
+        let mut queue = DialQueue::new();
+        let topic = [1u8; 32];
+        let found = |addr: &str| {
+            DiscoveredPeer::new(addr.parse().unwrap(), Some(topic), DiscoveryMethod::Dht)
+        };
+        assert!(queue.push(&found("10.0.0.1:1")));
+        assert!(queue.push(&peer("10.0.0.2:1", DiscoveryMethod::Dht)));
+        assert_eq!(queue.next_dial(), Some("10.0.0.1:1".parse().unwrap()));
+        assert!(queue.push(&found("10.0.0.3:1")));
+        assert_eq!(queue.in_flight_for(&topic), 1);
+
+        let dialing = queue.cancel_topic(&topic);
+        assert_eq!(dialing, vec!["10.0.0.1:1".parse().unwrap()]);
+        assert_eq!(queue.in_flight(), 0);
+        assert_eq!(queue.queued(), 1);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let mut queue = DialQueue::new();
+        for i in 0..100u8 {
+            assert!(queue.push(&peer(&format!("10.0.1.{}:1", i), DiscoveryMethod::Dht)));
+        }
+        let dialing: Vec<_> = std::iter::from_fn(|| queue.next_dial()).collect();
+        assert_eq!(dialing.len(), INITIAL_IN_FLIGHT);
+        for addr in dialing {
+            queue.on_connected(addr);
+        }
+        assert_eq!(queue.concurrency(), 2 * INITIAL_IN_FLIGHT);
+
+        // Timeouts back off down to the minimum.
+        while let Some(addr) = queue.next_dial() {
+            queue.on_timed_out(addr);
+        }
+        assert_eq!(queue.concurrency(), MIN_IN_FLIGHT);
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut queue = DialQueue::new();
+        assert!(queue.push(&peer("10.0.0.1:1", DiscoveryMethod::Pex)));
+        assert!(queue.push(&peer("10.0.0.2:1", DiscoveryMethod::Mdns)));
+        queue.next_dial();
+        let dump = queue.dump();
+        assert_eq!(dump.len(), 2);
+        assert_eq!(dump[0].addr, "10.0.0.2:1".parse().unwrap());
+        assert!(dump[0].dialing_for.is_some());
+        assert_eq!(dump[1].dialing_for, None);
+    }
+}
@@ -0,0 +1,399 @@
+use futures_lite::Future;
+use log::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Topic;
+use crate::redact::redact;
+use crate::runtime;
+
+const MAX_NODES: usize = 256;
+const MAX_PEERS_PER_TOPIC: usize = 64;
+/// Peers are dropped from the cache after this many failed connection
+/// attempts in a row.
+const MAX_PEER_FAILURES: u32 = 3;
+/// Changes are written to disk at most this often.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// How long cached peers are used for if not configured otherwise.
+pub const DEFAULT_PEER_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub type SharedPeerCache = Arc<Mutex<PeerCache>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PeerEntry {
+    ts: u64,
+    failures: u32,
+}
+
+/// On-disk cache of recently seen DHT nodes and topic peers.
+///
+/// The file format is line based: `node <addr> <timestamp>` and
+/// `peer <topic-hex> <addr> <timestamp> [<failures>]`, with timestamps in
+/// seconds since the unix epoch.
+#[derive(Debug)]
+pub struct PeerCache {
+    path: PathBuf,
+    nodes: HashMap<SocketAddr, u64>,
+    peers: HashMap<Topic, HashMap<SocketAddr, PeerEntry>>,
+    max_nodes: usize,
+    max_peers_per_topic: usize,
+    // Nodes and peers dropped for the limits since the last call.
+    pruned: (u64, u64),
+    // Changed since the last save.
+    dirty: bool,
+}
+
+impl PeerCache {
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut cache = Self {
+            path,
+            nodes: HashMap::new(),
+            peers: HashMap::new(),
+            max_nodes: MAX_NODES,
+            max_peers_per_topic: MAX_PEERS_PER_TOPIC,
+            pruned: (0, 0),
+            dirty: false,
+        };
+        match fs::read_to_string(&cache.path) {
+            Ok(content) => {
+                for line in content.lines() {
+                    if cache.parse_line(line).is_none() {
+                        warn!("skipping invalid peer cache line: {}", redact(line));
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to read peer cache {:?}: {}", cache.path, e),
+        }
+        cache
+    }
+
+    /// Keep at most this many nodes, and peers per topic, dropping the least
+    /// recently seen ones.
+    pub fn set_limits(mut self, max_nodes: usize, max_peers_per_topic: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self.max_peers_per_topic = max_peers_per_topic;
+        self.prune();
+        self
+    }
+
+    /// Nodes and peers dropped for the limits since the last call.
+    pub fn take_pruned(&mut self) -> (u64, u64) {
+        std::mem::take(&mut self.pruned)
+    }
+
+    fn prune(&mut self) {
+        self.pruned.0 += prune(&mut self.nodes, self.max_nodes);
+        for peers in self.peers.values_mut() {
+            if peers.len() > self.max_peers_per_topic {
+                let mut sorted: Vec<_> = peers.iter().map(|(addr, e)| (e.ts, *addr)).collect();
+                sorted.sort_unstable_by(|a, b| b.cmp(a));
+                for (_, addr) in sorted.into_iter().skip(self.max_peers_per_topic) {
+                    peers.remove(&addr);
+                    self.pruned.1 += 1;
+                }
+            }
+        }
+        self.peers.retain(|_, peers| !peers.is_empty());
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let mut parts = line.split_whitespace();
+        match parts.next()? {
+            "node" => {
+                let addr = parts.next()?.parse().ok()?;
+                let ts = parts.next()?.parse().ok()?;
+                self.nodes.insert(addr, ts);
+            }
+            "peer" => {
+                let topic = parse_topic(parts.next()?)?;
+                let addr = parts.next()?.parse().ok()?;
+                let ts = parts.next()?.parse().ok()?;
+                let failures = match parts.next() {
+                    Some(failures) => failures.parse().ok()?,
+                    None => 0,
+                };
+                let entry = PeerEntry { ts, failures };
+                self.peers.entry(topic).or_default().insert(addr, entry);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.prune();
+        let mut content = String::new();
+        for (addr, ts) in self.nodes.iter() {
+            content.push_str(&format!("node {} {}\n", addr, ts));
+        }
+        for (topic, peers) in self.peers.iter() {
+            for (addr, entry) in peers.iter() {
+                content.push_str(&format!(
+                    "peer {} {} {} {}\n",
+                    hex::encode(topic),
+                    addr,
+                    entry.ts,
+                    entry.failures
+                ));
+            }
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Whether there are changes that are not saved yet.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn add_node(&mut self, addr: SocketAddr) {
+        self.nodes.insert(addr, now());
+        self.dirty = true;
+        // Pruned in batches, not on every node once full.
+        if self.nodes.len() > self.max_nodes + self.max_nodes / 8 {
+            self.pruned.0 += prune(&mut self.nodes, self.max_nodes);
+        }
+    }
+
+    pub fn remove_node(&mut self, addr: &SocketAddr) {
+        if self.nodes.remove(addr).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Record a peer as seen for a topic. Returns whether it was new.
+    pub fn add_peer(&mut self, topic: Topic, addr: SocketAddr) -> bool {
+        let ts = now();
+        let peers = self.peers.entry(topic).or_default();
+        match peers.get_mut(&addr) {
+            Some(entry) => {
+                entry.ts = ts;
+                false
+            }
+            None => {
+                if peers.len() >= self.max_peers_per_topic {
+                    let oldest = peers.iter().min_by_key(|(_, e)| e.ts).map(|(a, _)| *a);
+                    if let Some(oldest) = oldest {
+                        peers.remove(&oldest);
+                        self.pruned.1 += 1;
+                    }
+                }
+                if self.max_peers_per_topic == 0 {
+                    return false;
+                }
+                peers.insert(addr, PeerEntry { ts, failures: 0 });
+                self.dirty = true;
+                true
+            }
+        }
+    }
+
+    pub fn on_peer_connected(&mut self, addr: &SocketAddr) {
+        for peers in self.peers.values_mut() {
+            if let Some(entry) = peers.get_mut(addr) {
+                if entry.failures > 0 {
+                    entry.failures = 0;
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Record a failed connection attempt. Returns whether the peer was
+    /// dropped from the cache.
+    pub fn on_peer_failed(&mut self, addr: &SocketAddr) -> bool {
+        let mut dropped = false;
+        for peers in self.peers.values_mut() {
+            if let Some(entry) = peers.get_mut(addr) {
+                entry.failures += 1;
+                self.dirty = true;
+                if entry.failures >= MAX_PEER_FAILURES {
+                    peers.remove(addr);
+                    dropped = true;
+                }
+            }
+        }
+        dropped
+    }
+
+    pub fn fresh_nodes(&self, max_age: Duration) -> Vec<SocketAddr> {
+        fresh(&self.nodes, max_age)
+    }
+
+    pub fn fresh_peers(&self, topic: &Topic, max_age: Duration) -> Vec<SocketAddr> {
+        self.peers
+            .get(topic)
+            .map(|peers| {
+                let min_ts = now().saturating_sub(max_age.as_secs());
+                peers
+                    .iter()
+                    .filter(|(_, entry)| entry.ts >= min_ts)
+                    .map(|(addr, _)| *addr)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Saves a shared peer cache a while after it changed, so the discovery
+/// does not write to disk on every change.
+pub(crate) struct CacheSaver {
+    cache: SharedPeerCache,
+    timer: Option<runtime::Sleep>,
+}
+
+impl fmt::Debug for CacheSaver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheSaver")
+            .field("pending", &self.timer.is_some())
+            .finish()
+    }
+}
+
+impl CacheSaver {
+    pub(crate) fn new(cache: SharedPeerCache) -> Self {
+        Self { cache, timer: None }
+    }
+
+    /// Arm the save timer if the cache changed, and save once it fires.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) {
+        loop {
+            match self.timer.as_mut() {
+                Some(timer) => {
+                    if Pin::new(timer).poll(cx).is_pending() {
+                        return;
+                    }
+                    self.timer = None;
+                    if let Err(e) = self.cache.lock().unwrap().save() {
+                        warn!("failed to save peer cache: {}", e);
+                    }
+                    return;
+                }
+                None if self.cache.lock().unwrap().is_dirty() => {
+                    self.timer = Some(runtime::sleep(SAVE_DELAY));
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+// Changes made since the last save are not lost with the discovery.
+impl Drop for CacheSaver {
+    fn drop(&mut self) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.is_dirty() {
+            if let Err(e) = cache.save() {
+                warn!("failed to save peer cache: {}", e);
+            }
+        }
+    }
+}
+
+fn fresh(entries: &HashMap<SocketAddr, u64>, max_age: Duration) -> Vec<SocketAddr> {
+    let min_ts = now().saturating_sub(max_age.as_secs());
+    entries
+        .iter()
+        .filter(|(_, ts)| **ts >= min_ts)
+        .map(|(addr, _)| *addr)
+        .collect()
+}
+
+// Drop the oldest entries over `max`, returning how many.
+fn prune(entries: &mut HashMap<SocketAddr, u64>, max: usize) -> u64 {
+    if entries.len() <= max {
+        return 0;
+    }
+    let mut sorted: Vec<_> = entries.iter().map(|(addr, ts)| (*ts, *addr)).collect();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+    let mut pruned = 0;
+    for (_, addr) in sorted.into_iter().skip(max) {
+        entries.remove(&addr);
+        pruned += 1;
+    }
+    pruned
+}
+
+fn parse_topic(s: &str) -> Option<Topic> {
+    let bytes = hex::decode(s).ok()?;
+    let mut topic = [0u8; 32];
+    if bytes.len() != topic.len() {
+        return None;
+    }
+    topic.copy_from_slice(&bytes);
+    Some(topic)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::PeerCache;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let path = std::env::temp_dir().join(format!("hyperswarm-cache-{}", std::process::id()));
+        let topic = [7u8; 32];
+        let node = "127.0.0.1:49737".parse().unwrap();
+        let peer = "[::1]:1234".parse().unwrap();
+
+        let mut cache = PeerCache::load(&path);
+        cache.add_node(node);
+        assert!(cache.add_peer(topic, peer));
+        assert!(!cache.add_peer(topic, peer));
+        assert!(!cache.on_peer_failed(&peer));
+        assert!(cache.is_dirty());
+        cache.save().unwrap();
+        assert!(!cache.is_dirty());
+
+        let mut cache = PeerCache::load(&path);
+        let max_age = Duration::from_secs(60);
+        assert_eq!(cache.fresh_nodes(max_age), vec![node]);
+        assert_eq!(cache.fresh_peers(&topic, max_age), vec![peer]);
+        assert!(cache.fresh_peers(&[0u8; 32], max_age).is_empty());
+
+        // The failure count was persisted.
+        assert!(!cache.on_peer_failed(&peer));
+        assert!(cache.on_peer_failed(&peer));
+        assert!(cache.fresh_peers(&topic, max_age).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cache_limits() {
+        let path = std::env::temp_dir().join(format!("hyperswarm-limits-{}", std::process::id()));
+        let topic = [7u8; 32];
+        let mut cache = PeerCache::load(&path).set_limits(2, 2);
+        for port in 1..=3 {
+            assert!(cache.add_peer(topic, ([10, 0, 0, 1], port).into()));
+        }
+        assert_eq!(cache.take_pruned(), (0, 1));
+        for port in 1..=10 {
+            cache.add_node(([10, 0, 0, 1], port).into());
+        }
+        cache.save().unwrap();
+        assert_eq!(cache.take_pruned(), (8, 0));
+        assert_eq!(cache.fresh_nodes(Duration::from_secs(60)).len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
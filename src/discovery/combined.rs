@@ -1,56 +1,749 @@
 use async_std::stream::Stream;
+use futures_lite::Future;
 use log::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
+use super::cache::{CacheSaver, PeerCache, SharedPeerCache, DEFAULT_PEER_TTL};
 use super::dht::DhtDiscovery;
+use super::gossip::{GossipDiscovery, GossipHandle};
+use super::mainline::MainlineDiscovery;
 use super::mdns::MdnsDiscovery;
-use super::{Discovery, PeerInfo, Topic};
+use super::merge::PeerMerger;
+use super::observed::ObservedAddrs;
+use super::pex::{PexDiscovery, PexHandle};
+use super::queue::PeerQueue;
+use super::rendezvous::RendezvousDiscovery;
+use super::scheduler::Scheduler;
+use super::static_peers::{StaticDiscovery, StaticPeer};
+use super::{
+    salt_topic, Backend, BoxedDiscovery, DiscoveredPeer, Discovery, DiscoveryMethod, Topic,
+};
 use crate::config::Config;
+use crate::redact::redact;
+use crate::runtime;
+use crate::status::ScheduleDump;
+
+type BindFut<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
 
-#[derive(Debug)]
 pub struct CombinedDiscovery {
-    dht: DhtDiscovery,
-    mdns: MdnsDiscovery,
+    local_port: u16,
+    config: Config,
+    dht: Option<DhtDiscovery>,
+    mdns: Option<MdnsDiscovery>,
+    pending_dht: Option<BindFut<DhtDiscovery>>,
+    pending_mdns: Option<BindFut<MdnsDiscovery>>,
+    custom: Vec<(Backend, BoxedDiscovery)>,
+    disabled: HashSet<Backend>,
+    lan_only: bool,
+    pex: Option<PexHandle>,
+    gossip: Option<GossipHandle>,
+    observed: ObservedAddrs,
+    salt: Option<Vec<u8>>,
+    salted_topics: HashMap<Topic, Topic>,
+    scheduler: Scheduler,
+    merger: PeerMerger,
+    cache: Option<SharedPeerCache>,
+    saver: Option<CacheSaver>,
+    peer_cache_ttl: Duration,
+    pending_events: PeerQueue,
+    paused: bool,
+    waker: Option<Waker>,
+}
+
+impl fmt::Debug for CombinedDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CombinedDiscovery")
+            .field("dht", &self.dht)
+            .field("mdns", &self.mdns)
+            .field("custom", &self.custom.len())
+            .field("disabled", &self.disabled)
+            .field("paused", &self.paused)
+            .field("scheduler", &self.scheduler)
+            .finish()
+    }
 }
 
 impl CombinedDiscovery {
     pub async fn bind(local_port: u16, config: Config) -> io::Result<Self> {
-        let mdns = MdnsDiscovery::bind(local_port, config.clone()).await?;
-        let dht = DhtDiscovery::bind(local_port, config).await?;
-        Ok(Self { mdns, dht })
+        let salt = config.topic_salt.clone();
+        let lan_only = config.lan_only;
+        let mut custom: Vec<(Backend, BoxedDiscovery)> = vec![];
+        if !config.static_peers.is_empty() {
+            // Static peers are configured with application topics, while
+            // backends see the salted ones.
+            let peers = config
+                .static_peers
+                .iter()
+                .map(|peer| {
+                    let topics = peer
+                        .topics
+                        .iter()
+                        .map(|topic| match salt.as_ref() {
+                            Some(salt) => salt_topic(topic, salt),
+                            None => *topic,
+                        })
+                        .collect();
+                    StaticPeer::new(peer.addr, topics)
+                })
+                .collect();
+            custom.push((Backend::Static, Box::new(StaticDiscovery::new(peers))));
+        }
+        let mut pex = None;
+        if config.pex {
+            let discovery = PexDiscovery::new(salt.clone());
+            pex = Some(discovery.handle());
+            custom.push((Backend::Pex, Box::new(discovery)));
+        }
+        let observed = ObservedAddrs::new(local_port);
+        let mut gossip = None;
+        if config.gossip {
+            let discovery = GossipDiscovery::new(local_port, salt.clone())
+                .with_observed_addrs(observed.clone());
+            gossip = Some(discovery.handle());
+            custom.push((Backend::Gossip, Box::new(discovery)));
+        }
+        if config.mainline && !lan_only {
+            let discovery = MainlineDiscovery::new(local_port);
+            custom.push((Backend::Mainline, Box::new(discovery)));
+        }
+        if let Some(server) = config.rendezvous_server.filter(|_| !lan_only) {
+            let discovery = RendezvousDiscovery::new(server, local_port);
+            custom.push((Backend::Rendezvous, Box::new(discovery)));
+        }
+        #[cfg(feature = "dns")]
+        if let Some(domain) = config.dns_discovery_domain.as_ref().filter(|_| !lan_only) {
+            let discovery = super::dns::DnsDiscovery::new(domain.clone());
+            custom.push((Backend::Dns, Box::new(discovery)));
+        }
+        #[cfg(not(feature = "dns"))]
+        if config.dns_discovery_domain.is_some() {
+            warn!("DNS discovery requires the dns feature");
+        }
+        let mut disabled = config.disabled_backends.clone();
+        if lan_only {
+            disabled.insert(Backend::Dht);
+        }
+        let mut mdns = None;
+        if !disabled.contains(&Backend::Mdns) {
+            mdns = Some(MdnsDiscovery::bind(local_port, config.clone()).await?);
+        }
+        let cache = config.cache_path.as_ref().map(|path| {
+            let limits = &config.resource_limits;
+            let cache = PeerCache::load(path)
+                .set_limits(limits.max_cached_nodes, limits.max_cached_peers_per_topic);
+            Arc::new(Mutex::new(cache))
+        });
+        let mut dht = None;
+        if !disabled.contains(&Backend::Dht) {
+            let dht_cache = cache.clone();
+            dht = Some(DhtDiscovery::bind_with_cache(local_port, config.clone(), dht_cache).await?);
+        }
+        Ok(Self {
+            local_port,
+            mdns,
+            dht,
+            pending_dht: None,
+            pending_mdns: None,
+            custom,
+            disabled,
+            lan_only,
+            pex,
+            gossip,
+            observed,
+            salt,
+            salted_topics: HashMap::new(),
+            scheduler: Scheduler::new(),
+            merger: PeerMerger::with_capacity(config.resource_limits.max_merged_peers),
+            saver: cache.clone().map(CacheSaver::new),
+            cache,
+            peer_cache_ttl: config.peer_cache_ttl.unwrap_or(DEFAULT_PEER_TTL),
+            pending_events: PeerQueue::new(config.resource_limits.max_queued_peers),
+            paused: false,
+            waker: None,
+            config,
+        })
+    }
+
+    /// The DHT backend, if it is enabled and bound.
+    pub fn dht(&self) -> Option<&DhtDiscovery> {
+        self.dht.as_ref()
+    }
+
+    pub fn pex(&self) -> Option<&PexHandle> {
+        self.pex.as_ref()
+    }
+
+    pub fn gossip(&self) -> Option<&GossipHandle> {
+        self.gossip.as_ref()
+    }
+
+    pub fn observed_addrs(&self) -> &ObservedAddrs {
+        &self.observed
+    }
+
+    pub(crate) fn schedules(&self) -> Vec<ScheduleDump> {
+        self.scheduler.dump()
+    }
+
+    /// Add a custom backend. It only sees topics joined after it was added.
+    pub fn add(&mut self, discovery: BoxedDiscovery) {
+        self.custom.push((Backend::Custom, discovery));
+    }
+
+    pub fn is_enabled(&self, backend: Backend) -> bool {
+        !self.disabled.contains(&backend)
+    }
+
+    /// Enable or disable a backend at runtime.
+    ///
+    /// Disabled backends leave all topics and are no longer polled. The DHT
+    /// and mDNS are dropped entirely, so they stop all network activity, and
+    /// are bound again in the background when re-enabled. Joined topics are
+    /// replayed to re-enabled backends.
+    pub fn set_enabled(&mut self, backend: Backend, enabled: bool) {
+        if enabled == self.is_enabled(backend) {
+            return;
+        }
+        if enabled && self.lan_only && backend.is_external() {
+            warn!("not enabling {:?} discovery in LAN-only mode", backend);
+            return;
+        }
+        debug!("set discovery backend {:?} enabled: {}", backend, enabled);
+        if !enabled {
+            self.disabled.insert(backend);
+            match backend {
+                Backend::Dht => {
+                    self.dht = None;
+                    self.pending_dht = None;
+                }
+                Backend::Mdns => {
+                    self.mdns = None;
+                    self.pending_mdns = None;
+                }
+                _ => {
+                    let topics: Vec<Topic> = self.scheduler.topics().map(|r| r.topic).collect();
+                    for (_, discovery) in self.backends_mut(backend) {
+                        for topic in topics.iter() {
+                            discovery.leave(*topic);
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        self.disabled.remove(&backend);
+        if self.paused {
+            // Picked up on resume.
+            return;
+        }
+        let (local_port, config) = (self.local_port, self.config.clone());
+        match backend {
+            Backend::Dht => {
+                let fut = DhtDiscovery::bind_with_cache(local_port, config, self.cache.clone());
+                self.pending_dht = Some(Box::pin(fut));
+            }
+            Backend::Mdns => {
+                self.pending_mdns = Some(Box::pin(MdnsDiscovery::bind(local_port, config)));
+            }
+            _ => {
+                let topics: Vec<_> = self.scheduler.topics().collect();
+                for (_, discovery) in self.backends_mut(backend) {
+                    for refresh in topics.iter() {
+                        replay(
+                            discovery.as_mut(),
+                            refresh.topic,
+                            refresh.announce,
+                            refresh.lookup,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Suspend all discovery, e.g. while a mobile app is in the background.
+    ///
+    /// No queries, probes or re-announces are sent until `resume`; mDNS is
+    /// dropped for the time being. Existing connections are not affected.
+    /// Topics joined or refreshed while paused are picked up on resume.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        debug!("pause discovery");
+        self.paused = true;
+        self.mdns = None;
+        self.pending_mdns = None;
+    }
+
+    /// Send out pending unannounces for up to `timeout`, then stop all
+    /// backends and wait for their background tasks. Topics should be left
+    /// before.
+    pub(crate) async fn shutdown(&mut self, timeout: Duration) {
+        self.paused = false;
+        let flush = futures_lite::future::poll_fn(|cx| loop {
+            match Pin::new(&mut *self).poll_next(cx) {
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => {
+                    let idle = self.dht.as_ref().map_or(true, |dht| dht.is_idle());
+                    return if idle { Poll::Ready(()) } else { Poll::Pending };
+                }
+            }
+        });
+        if runtime::timeout(timeout, flush).await.is_err() {
+            debug!("discovery did not flush before shutdown");
+        }
+        self.paused = true;
+        self.dht = None;
+        self.pending_dht = None;
+        self.pending_mdns = None;
+        self.custom.clear();
+        if let Some(mdns) = self.mdns.take() {
+            mdns.shutdown().await;
+        }
+        if let Some(cache) = self.cache.as_ref() {
+            if let Err(e) = cache.lock().unwrap().save() {
+                warn!("failed to save peer cache: {}", e);
+            }
+        }
+    }
+
+    /// Resume discovery after `pause`, running a new round for all topics.
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        debug!("resume discovery");
+        self.paused = false;
+        let (local_port, config) = (self.local_port, self.config.clone());
+        if self.is_enabled(Backend::Mdns) && self.pending_mdns.is_none() {
+            let fut = MdnsDiscovery::bind(local_port, config.clone());
+            self.pending_mdns = Some(Box::pin(fut));
+        }
+        if self.is_enabled(Backend::Dht) && self.dht.is_none() && self.pending_dht.is_none() {
+            let fut = DhtDiscovery::bind_with_cache(local_port, config, self.cache.clone());
+            self.pending_dht = Some(Box::pin(fut));
+        }
+        let topics: Vec<_> = self.scheduler.topics().collect();
+        for refresh in topics {
+            if let Some(dht) = self.dht.as_mut() {
+                replay(dht, refresh.topic, refresh.announce, refresh.lookup);
+            }
+            for (backend, discovery) in self.custom.iter_mut() {
+                if !self.disabled.contains(backend) {
+                    replay(
+                        discovery.as_mut(),
+                        refresh.topic,
+                        refresh.announce,
+                        refresh.lookup,
+                    );
+                }
+            }
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn backends_mut(
+        &mut self,
+        backend: Backend,
+    ) -> impl Iterator<Item = &mut (Backend, BoxedDiscovery)> {
+        self.custom.iter_mut().filter(move |(b, _)| *b == backend)
+    }
+
+    /// The merged information from all backends that recently reported a
+    /// peer for an application topic.
+    pub fn peer_info(&self, topic: Option<Topic>, addr: SocketAddr) -> Option<&DiscoveredPeer> {
+        self.merger.get(topic, addr)
+    }
+
+    /// Run a new lookup and announce round for a topic right away, bypassing
+    /// its backoff. mDNS is queried again too.
+    pub fn refresh(&mut self, topic: Topic) {
+        let topic = self.network_topic(topic);
+        self.scheduler.refresh(&topic);
+        // A refresh is answered from the DHT itself, not from the results of
+        // a recent lookup.
+        if let Some(dht) = self.dht.as_mut() {
+            dht.forget_lookup(&topic);
+        }
+        let lookup = self
+            .scheduler
+            .topics()
+            .any(|refresh| refresh.topic == topic && refresh.lookup);
+        if let (true, Some(mdns)) = (lookup && !self.paused, self.mdns.as_mut()) {
+            mdns.lookup(topic);
+        }
+    }
+
+    /// Application topics whose announce was confirmed since the last call.
+    pub(crate) fn take_announced(&mut self) -> Vec<Topic> {
+        let topics = match self.dht.as_mut() {
+            Some(dht) => dht.take_announce_results(),
+            None => return vec![],
+        };
+        topics
+            .into_iter()
+            .map(|topic| match self.salt {
+                Some(_) => self.salted_topics.get(&topic).copied().unwrap_or(topic),
+                None => topic,
+            })
+            .collect()
+    }
+
+    /// Application topics whose DHT lookup completed since the last call.
+    pub(crate) fn take_looked_up(&mut self) -> Vec<Topic> {
+        let topics = match self.dht.as_mut() {
+            Some(dht) => dht.take_lookup_results(),
+            None => return vec![],
+        };
+        topics
+            .into_iter()
+            .map(|topic| match self.salt {
+                Some(_) => self.salted_topics.get(&topic).copied().unwrap_or(topic),
+                None => topic,
+            })
+            .collect()
+    }
+
+    /// Number of DHT lookups served by a running or recent lookup of the same
+    /// topic since the last call.
+    pub(crate) fn take_coalesced_lookups(&mut self) -> u64 {
+        self.dht.as_mut().map_or(0, |dht| dht.take_coalesced())
+    }
+
+    /// Number of discovered peers shed from full queues since the last call.
+    pub(crate) fn take_shed(&mut self) -> u64 {
+        let dht = self.dht.as_mut().map_or(0, |dht| dht.take_shed());
+        self.pending_events.take_shed() + dht
+    }
+
+    /// What the `ResourceLimits` other than the queues dropped since the last
+    /// call, by the metric key of the limit.
+    pub(crate) fn take_limit_hits(&mut self) -> Vec<(&'static str, u64)> {
+        let mut hits = vec![];
+        if let Some(dht) = self.dht.as_mut() {
+            hits.push(("dht_nodes", dht.take_dropped_nodes()));
+        }
+        if let Some(cache) = self.cache.as_ref() {
+            let (nodes, peers) = cache.lock().unwrap().take_pruned();
+            hits.push(("cached_nodes", nodes));
+            hits.push(("cached_peers", peers));
+        }
+        hits.push(("merged_peers", self.merger.take_dropped()));
+        hits
+    }
+
+    /// Record a successful connection to a peer, resetting its failures in
+    /// the peer cache.
+    pub fn on_connected(&mut self, addr: SocketAddr) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.lock().unwrap().on_peer_connected(&addr);
+        }
+    }
+
+    /// Record a failed connection to a peer. Peers that fail repeatedly are
+    /// dropped from the peer cache.
+    pub fn on_connect_failed(&mut self, addr: SocketAddr) {
+        if let Some(cache) = self.cache.as_ref() {
+            let mut cache = cache.lock().unwrap();
+            if cache.on_peer_failed(&addr) {
+                debug!("dropping unreachable peer {} from cache", redact(addr));
+            }
+        }
+    }
+
+    fn cache_peer(&mut self, peer_info: &DiscoveredPeer) {
+        let (cache, topic) = match (self.cache.as_ref(), peer_info.topic()) {
+            (Some(cache), Some(topic)) => (cache, topic),
+            _ => return,
+        };
+        if *peer_info.source() == DiscoveryMethod::Cache {
+            return;
+        }
+        cache.lock().unwrap().add_peer(topic, peer_info.addr());
+    }
+
+    pub(crate) fn network_topic(&mut self, topic: Topic) -> Topic {
+        match self.salt.as_ref() {
+            None => topic,
+            Some(salt) => {
+                let salted = salt_topic(&topic, salt);
+                self.salted_topics.insert(salted, topic);
+                salted
+            }
+        }
+    }
+
+    fn on_peer(
+        &mut self,
+        next: Poll<Option<io::Result<DiscoveredPeer>>>,
+    ) -> Poll<Option<io::Result<DiscoveredPeer>>> {
+        if let Poll::Ready(Some(Ok(peer_info))) = &next {
+            if let Some(topic) = peer_info.topic() {
+                self.scheduler.on_peer(topic, peer_info.addr());
+            }
+            self.cache_peer(peer_info);
+        }
+        match next {
+            Poll::Ready(Some(Ok(mut peer_info))) if self.salt.is_some() => {
+                let topic = peer_info
+                    .topic()
+                    .and_then(|topic| self.salted_topics.get(&topic).copied());
+                peer_info.set_topic(topic);
+                Poll::Ready(Some(Ok(peer_info)))
+            }
+            next => next,
+        }
+    }
+
+    // Finish binding re-enabled backends and replay the joined topics.
+    fn poll_pending_binds(&mut self, cx: &mut Context<'_>) {
+        if let Some(fut) = self.pending_dht.as_mut() {
+            if let Poll::Ready(res) = fut.as_mut().poll(cx) {
+                self.pending_dht = None;
+                match res {
+                    Ok(mut dht) => {
+                        for refresh in self.scheduler.topics() {
+                            replay(&mut dht, refresh.topic, refresh.announce, refresh.lookup);
+                        }
+                        self.dht = Some(dht);
+                    }
+                    Err(e) => warn!("failed to bind DHT discovery: {}", e),
+                }
+            }
+        }
+        if let Some(fut) = self.pending_mdns.as_mut() {
+            if let Poll::Ready(res) = fut.as_mut().poll(cx) {
+                self.pending_mdns = None;
+                match res {
+                    Ok(mut mdns) => {
+                        for refresh in self.scheduler.topics() {
+                            replay(&mut mdns, refresh.topic, refresh.announce, refresh.lookup);
+                        }
+                        self.mdns = Some(mdns);
+                    }
+                    Err(e) => warn!("failed to bind mDNS discovery: {}", e),
+                }
+            }
+        }
+    }
+
+    fn poll_backends(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<DiscoveredPeer>>> {
+        if let Some(peer_info) = self.pending_events.pop() {
+            return self.on_peer(Poll::Ready(Some(Ok(peer_info))));
+        }
+        if let Some(dht) = self.dht.as_mut() {
+            let next = Pin::new(dht).poll_next(cx);
+            if next.is_ready() {
+                debug!("Found on DHT: {:?}", redact(&next));
+                return self.on_peer(next);
+            }
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            let next = Pin::new(mdns).poll_next(cx);
+            if next.is_ready() {
+                debug!("Found on MDNS: {:?}", redact(&next));
+                return self.on_peer(next);
+            }
+        }
+        let mut found = None;
+        for (backend, discovery) in self.custom.iter_mut() {
+            if self.disabled.contains(backend) {
+                continue;
+            }
+            let next = Pin::new(discovery).poll_next(cx);
+            if let Poll::Ready(Some(_)) = next {
+                found = Some(next);
+                break;
+            }
+        }
+        match found {
+            Some(next) => {
+                debug!("Found on custom discovery: {:?}", redact(&next));
+                self.on_peer(next)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+fn replay<D: Discovery + ?Sized>(discovery: &mut D, topic: Topic, announce: bool, lookup: bool) {
+    if announce {
+        discovery.announce(topic);
+    }
+    if lookup {
+        discovery.lookup(topic);
+    }
+}
+
+fn is_lan_addr(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses.
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
     }
 }
 
 impl Discovery for CombinedDiscovery {
     fn lookup(&mut self, topic: Topic) {
         debug!("lookup topic {}", hex::encode(topic));
-        self.mdns.lookup(topic);
-        self.dht.lookup(topic);
+        let topic = self.network_topic(topic);
+        if self.paused {
+            self.scheduler.schedule(topic, false, true);
+            return;
+        }
+        // Cached peers are dialed right away while the backends warm up.
+        if let Some(cache) = self.cache.as_ref() {
+            let cached = cache
+                .lock()
+                .unwrap()
+                .fresh_peers(&topic, self.peer_cache_ttl);
+            for addr in cached {
+                let info = DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Cache);
+                self.pending_events.push(info);
+            }
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.lookup(topic);
+        }
+        if let Some(dht) = self.dht.as_mut() {
+            dht.lookup(topic);
+        }
+        for (backend, discovery) in self.custom.iter_mut() {
+            if !self.disabled.contains(backend) {
+                discovery.lookup(topic);
+            }
+        }
+        self.scheduler.schedule(topic, false, true);
     }
 
     fn announce(&mut self, topic: Topic) {
         debug!("announce topic {}", hex::encode(topic));
-        self.mdns.announce(topic);
-        self.dht.announce(topic);
+        let topic = self.network_topic(topic);
+        if self.paused {
+            self.scheduler.schedule(topic, true, false);
+            return;
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.announce(topic);
+        }
+        if let Some(dht) = self.dht.as_mut() {
+            dht.announce(topic);
+        }
+        for (backend, discovery) in self.custom.iter_mut() {
+            if !self.disabled.contains(backend) {
+                discovery.announce(topic);
+            }
+        }
+        self.scheduler.schedule(topic, true, false);
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        debug!("leave topic {}", hex::encode(topic));
+        self.merger.remove_topic(&topic);
+        let topic = self.network_topic(topic);
+        self.pending_events.remove_topic(&topic);
+        self.scheduler.remove(&topic);
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.leave(topic);
+        }
+        if let Some(dht) = self.dht.as_mut() {
+            dht.leave(topic);
+        }
+        for (backend, discovery) in self.custom.iter_mut() {
+            if !self.disabled.contains(backend) {
+                discovery.leave(topic);
+            }
+        }
+        self.salted_topics.remove(&topic);
     }
 }
 
 impl Stream for CombinedDiscovery {
-    type Item = io::Result<PeerInfo>;
+    type Item = io::Result<DiscoveredPeer>;
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
-        let next = Pin::new(&mut this.dht).poll_next(cx);
-        if next.is_ready() {
-            debug!("Found on DHT: {:?}", next);
-            return next;
+        if this.paused {
+            this.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        this.poll_pending_binds(cx);
+        if let Some(saver) = this.saver.as_mut() {
+            saver.poll(cx);
+        }
+
+        // mDNS runs its own query loop, so it is not refreshed here.
+        while let Poll::Ready(Some(refresh)) = Pin::new(&mut this.scheduler).poll_next(cx) {
+            trace!("refresh topic {}", hex::encode(refresh.topic));
+            if let Some(dht) = this.dht.as_mut() {
+                replay(dht, refresh.topic, refresh.announce, refresh.lookup);
+            }
+            for (backend, discovery) in this.custom.iter_mut() {
+                if !this.disabled.contains(backend) {
+                    replay(
+                        discovery.as_mut(),
+                        refresh.topic,
+                        refresh.announce,
+                        refresh.lookup,
+                    );
+                }
+            }
+        }
+
+        // The same peer is often reported by several backends; only the
+        // first report is yielded, later ones are merged into it.
+        loop {
+            match this.poll_backends(cx) {
+                Poll::Ready(Some(Ok(peer_info))) => {
+                    if this.lan_only && !is_lan_addr(&peer_info.addr()) {
+                        debug!("ignoring non-local peer {}", redact(peer_info.addr()));
+                        continue;
+                    }
+                    if let Some(peer_info) = this.merger.insert(peer_info) {
+                        return Poll::Ready(Some(Ok(peer_info)));
+                    }
+                }
+                next => return next,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_lan_addr;
+
+    #[test]
+    fn test_is_lan_addr() {
+        for addr in &[
+            "192.168.1.2:1",
+            "10.0.0.1:1",
+            "127.0.0.1:1",
+            "[fe80::1]:1",
+            "[fd00::1]:1",
+        ] {
+            assert!(is_lan_addr(&addr.parse().unwrap()), "{}", addr);
         }
-        let next = Pin::new(&mut this.mdns).poll_next(cx);
-        if next.is_ready() {
-            debug!("Found on MDNS: {:?}", next);
-            return next;
+        for addr in &["1.1.1.1:1", "[2001:db8::1]:1"] {
+            assert!(!is_lan_addr(&addr.parse().unwrap()), "{}", addr);
         }
-        Poll::Pending
     }
 }
@@ -1,24 +1,78 @@
+use async_std::channel;
 use async_std::stream::Stream;
-use futures_lite::ready;
+use futures::stream::FuturesUnordered;
+use futures_lite::{ready, Future};
 use hyperswarm_dht::{DhtConfig, HyperDht, HyperDhtEvent, QueryOpts};
 use log::*;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::io;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use crate::config::Config;
+use crate::dns;
+use crate::redact::redact;
+use crate::runtime;
 
-use super::{Discovery, DiscoveryMethod, PeerInfo, Topic};
+use super::cache::{CacheSaver, PeerCache, SharedPeerCache};
+use super::instrument::{
+    DhtMessage, DhtMessageKind, DhtQueryEvent, Direction, QueryKind, QueryOutcome,
+};
+use super::nodes::{NodeInfo, NodeTable};
+use super::ping::dht_ping_tapped;
+use super::queue::PeerQueue;
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(10);
+const CACHE_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_CONCURRENT_PINGS: usize = 8;
+/// See `Config::set_lookup_cache_ttl`.
+const DEFAULT_LOOKUP_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Lookups without a result after this long are no longer shared.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+type RebindFut = Pin<Box<dyn Future<Output = io::Result<HyperDht>> + Send>>;
+type TimeoutFut = Pin<Box<dyn Future<Output = ()> + Send>>;
+type PingFut = Pin<Box<dyn Future<Output = (SocketAddr, Instant, io::Result<Duration>)> + Send>>;
 
 // #[derive(Debug)]
 pub struct DhtDiscovery {
     state: HyperDht,
+    config: Config,
     bootstrapped: bool,
     local_port: u16,
     pending_commands: VecDeque<Command>,
-    pending_events: VecDeque<PeerInfo>,
+    announced: HashSet<Topic>,
+    looked_up: HashSet<Topic>,
+    pending_events: PeerQueue,
+    bootstrap_timeout: Option<TimeoutFut>,
+    pending_rebind: Option<RebindFut>,
+    cache: Option<SharedPeerCache>,
+    // Only set when the cache is not shared with the combined discovery,
+    // which saves it otherwise.
+    saver: Option<CacheSaver>,
+    warm_started: bool,
+    nodes: NodeTable,
+    health_interval: runtime::Interval,
+    pending_pings: FuturesUnordered<PingFut>,
+    lookups_started: HashMap<Topic, Instant>,
+    announces_started: HashMap<(QueryKind, Topic), Instant>,
+    // Peers found by recent lookups, and when.
+    recent_lookups: HashMap<Topic, (Instant, Vec<SocketAddr>)>,
+    lookup_cache_ttl: Duration,
+    // Lookups served by a running or recent lookup since the last call.
+    coalesced: u64,
+    announce_results: VecDeque<Topic>,
+    lookup_results: VecDeque<Topic>,
+    // Spans of running queries, with the `tracing` feature.
+    #[cfg(feature = "tracing")]
+    query_spans: HashMap<(QueryKind, Option<Topic>), tracing::Span>,
 }
 
 impl fmt::Debug for DhtDiscovery {
@@ -26,96 +80,547 @@ impl fmt::Debug for DhtDiscovery {
         f.debug_struct("DhtDiscovery")
             .field("bootstrapped", &self.bootstrapped)
             .field("local_port", &self.local_port)
+            .field("warm_started", &self.warm_started)
             .finish()
     }
 }
 
 #[derive(Debug)]
 enum Command {
-    Lookup(QueryOpts),
-    Announce(QueryOpts),
+    Lookup(Topic),
+    Announce(Topic),
+    UnAnnounce(Topic),
+}
+
+impl Command {
+    fn topic(&self) -> &Topic {
+        match self {
+            Command::Lookup(topic) | Command::Announce(topic) | Command::UnAnnounce(topic) => topic,
+        }
+    }
+
+    fn kind(&self) -> DhtMessageKind {
+        match self {
+            Command::Lookup(_) => DhtMessageKind::Lookup,
+            Command::Announce(_) => DhtMessageKind::Announce,
+            Command::UnAnnounce(_) => DhtMessageKind::UnAnnounce,
+        }
+    }
 }
 
 impl DhtDiscovery {
     pub async fn bind(local_port: u16, config: Config) -> io::Result<Self> {
-        let dht_config = DhtConfig::default();
-        let dht_config = if let Some(bootstrap) = config.bootstrap.as_ref() {
-            dht_config.set_bootstrap_nodes(bootstrap)
-        } else {
-            dht_config
-        };
-        let dht_config = dht_config.set_ephemeral(config.ephemeral);
-        let state = HyperDht::with_config(dht_config).await?;
+        let cache = config.cache_path.as_ref().map(|path| {
+            let limits = &config.resource_limits;
+            let cache = PeerCache::load(path)
+                .set_limits(limits.max_cached_nodes, limits.max_cached_peers_per_topic);
+            Arc::new(Mutex::new(cache))
+        });
+        let saver = cache.clone().map(CacheSaver::new);
+        let mut this = Self::bind_with_cache(local_port, config, cache).await?;
+        this.saver = saver;
+        Ok(this)
+    }
+
+    /// Bind with a peer cache that is shared with the combined discovery.
+    pub(crate) async fn bind_with_cache(
+        local_port: u16,
+        config: Config,
+        cache: Option<SharedPeerCache>,
+    ) -> io::Result<Self> {
+        let cached = cache
+            .as_ref()
+            .map(|cache| cache.lock().unwrap().fresh_nodes(CACHE_MAX_AGE))
+            .unwrap_or_default();
+        // Only cached nodes that still answer are bootstrapped from, and the
+        // configured bootstrap nodes if none do.
+        let pinged = ping_all(cached, config.dht_tap.clone()).await;
+        let warm_nodes: Vec<SocketAddr> = pinged
+            .iter()
+            .filter(|(_, res)| res.is_ok())
+            .map(|(addr, _)| *addr)
+            .collect();
+        let warm_started = !warm_nodes.is_empty();
+        if warm_started {
+            debug!(
+                "warm-starting DHT from {} of {} cached nodes",
+                warm_nodes.len(),
+                pinged.len()
+            );
+        } else if !pinged.is_empty() {
+            debug!("no cached DHT node answered, using the bootstrap nodes");
+        }
+        let state = create_dht(config.clone(), warm_nodes).await?;
+        let limits = config.resource_limits.clone();
+        let mut nodes = NodeTable::with_capacity(limits.max_dht_nodes);
+        for (addr, res) in pinged {
+            nodes.on_ping(addr, res.ok());
+        }
+        let lookup_cache_ttl = config.lookup_cache_ttl.unwrap_or(DEFAULT_LOOKUP_CACHE_TTL);
         let this = Self {
             state,
+            config,
             local_port,
             bootstrapped: false,
             pending_commands: VecDeque::new(),
-            pending_events: VecDeque::new(),
+            announced: HashSet::new(),
+            looked_up: HashSet::new(),
+            pending_events: PeerQueue::new(limits.max_queued_peers),
+            bootstrap_timeout: Some(bootstrap_timeout()),
+            pending_rebind: None,
+            cache,
+            saver: None,
+            warm_started,
+            nodes,
+            health_interval: runtime::interval(HEALTH_CHECK_INTERVAL),
+            pending_pings: FuturesUnordered::new(),
+            lookups_started: HashMap::new(),
+            announces_started: HashMap::new(),
+            recent_lookups: HashMap::new(),
+            lookup_cache_ttl,
+            coalesced: 0,
+            announce_results: VecDeque::new(),
+            lookup_results: VecDeque::new(),
+            #[cfg(feature = "tracing")]
+            query_spans: HashMap::new(),
         };
         Ok(this)
     }
 
+    /// Whether all queued lookups, announces and unannounces were sent.
+    pub fn is_idle(&self) -> bool {
+        self.pending_commands.is_empty()
+    }
+
     fn execute_pending_commands(&mut self) {
         while let Some(command) = self.pending_commands.pop_front() {
-            match command {
-                Command::Announce(opts) => self.state.announce(opts),
-                Command::Lookup(opts) => self.state.lookup(opts),
+            let topic = *command.topic();
+            let opts = self.query_opts(topic);
+            self.tap(Direction::Outbound, command.kind(), Some(topic), || {
+                format!("{:?}", command)
+            });
+            let kind = match command {
+                Command::Announce(_) => {
+                    self.state.announce(opts);
+                    self.announces_started
+                        .insert((QueryKind::Announce, topic), runtime::now());
+                    QueryKind::Announce
+                }
+                Command::Lookup(_) => {
+                    self.state.lookup(opts);
+                    self.lookups_started.insert(topic, runtime::now());
+                    QueryKind::Lookup
+                }
+                Command::UnAnnounce(_) => {
+                    self.state.unannounce(opts);
+                    self.announces_started
+                        .insert((QueryKind::UnAnnounce, topic), runtime::now());
+                    QueryKind::UnAnnounce
+                }
             };
+            self.instrument(kind, Some(topic), None, None, QueryOutcome::Started);
         }
     }
-}
 
-impl Discovery for DhtDiscovery {
-    fn lookup(&mut self, topic: Topic) {
-        let opts = QueryOpts {
+    // Send a command to or event of the DHT to the tap, if any.
+    fn tap<F>(&self, direction: Direction, kind: DhtMessageKind, target: Option<Topic>, detail: F)
+    where
+        F: FnOnce() -> String,
+    {
+        if let Some(tap) = self.config.dht_tap.as_ref() {
+            let _ = tap.try_send(DhtMessage {
+                direction,
+                kind,
+                target,
+                node: None,
+                detail: detail(),
+            });
+        }
+    }
+
+    fn instrument(
+        &mut self,
+        kind: QueryKind,
+        topic: Option<Topic>,
+        node: Option<SocketAddr>,
+        latency: Option<Duration>,
+        outcome: QueryOutcome,
+    ) {
+        #[cfg(feature = "tracing")]
+        {
+            self.trace_query(kind, topic, node, latency, &outcome);
+        }
+        if let Some(hook) = self.config.dht_query_hook.as_ref() {
+            hook.call(&DhtQueryEvent {
+                kind,
+                topic,
+                node,
+                latency,
+                outcome,
+            });
+        }
+    }
+
+    // Record a query event in the span of the query, which lasts from its
+    // start until it completes or fails.
+    #[cfg(feature = "tracing")]
+    fn trace_query(
+        &mut self,
+        kind: QueryKind,
+        topic: Option<Topic>,
+        node: Option<SocketAddr>,
+        latency: Option<Duration>,
+        outcome: &QueryOutcome,
+    ) {
+        let key = (kind, topic);
+        let span = match outcome {
+            QueryOutcome::Started => {
+                let topic = topic.map(hex::encode);
+                let span = tracing::debug_span!("dht_query", kind = ?kind, topic = ?topic);
+                self.query_spans.insert(key, span.clone());
+                Some(span)
+            }
+            QueryOutcome::Response => self.query_spans.get(&key).cloned(),
+            _ => self.query_spans.remove(&key),
+        }
+        .unwrap_or_else(tracing::Span::none);
+        span.in_scope(
+            || tracing::debug!(node = ?redact(node), latency = ?latency, outcome = ?outcome),
+        );
+    }
+
+    fn query_opts(&self, topic: Topic) -> QueryOpts {
+        QueryOpts {
             topic: topic.into(),
             port: Some(self.local_port as u32),
             local_addr: None,
-        };
-        self.pending_commands.push_back(Command::Lookup(opts))
+        }
+    }
+
+    fn poll_bootstrap(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        if let Some(ref mut fut) = self.pending_rebind {
+            if let Poll::Ready(res) = Pin::new(fut).poll(cx) {
+                self.pending_rebind = None;
+                self.state = res?;
+                self.bootstrap_timeout = Some(bootstrap_timeout());
+            }
+        }
+
+        if let Some(ref mut fut) = self.bootstrap_timeout {
+            if Pin::new(fut).poll(cx).is_ready() {
+                self.bootstrap_timeout = None;
+                if self.warm_started || !self.config.bootstrap_seeds.is_empty() {
+                    warn!("DHT bootstrap timed out, falling back to full bootstrap");
+                    self.warm_started = false;
+                    let fut = create_dht(self.config.clone(), vec![]);
+                    self.pending_rebind = Some(Box::pin(fut));
+                    // Make sure the new future gets polled.
+                    cx.waker().wake_by_ref();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Topics whose announce completed since the last call.
+    pub(crate) fn take_announce_results(&mut self) -> Vec<Topic> {
+        self.announce_results.drain(..).collect()
+    }
+
+    /// Topics whose lookup completed since the last call.
+    pub(crate) fn take_lookup_results(&mut self) -> Vec<Topic> {
+        self.lookup_results.drain(..).collect()
+    }
+
+    /// Number of found peers shed from the full queue since the last call.
+    pub(crate) fn take_shed(&mut self) -> u64 {
+        self.pending_events.take_shed()
+    }
+
+    /// Number of lookups that were served by a running or recent lookup of
+    /// the same topic since the last call.
+    pub(crate) fn take_coalesced(&mut self) -> u64 {
+        std::mem::take(&mut self.coalesced)
+    }
+
+    /// Forget the result of a recent lookup of a topic, so that the next
+    /// lookup walks the DHT again, e.g. on an explicit refresh.
+    pub(crate) fn forget_lookup(&mut self, topic: &Topic) {
+        self.recent_lookups.remove(topic);
+    }
+
+    // Serve a lookup from a running or recent lookup of the topic, returning
+    // whether it was.
+    fn coalesce_lookup(&mut self, topic: Topic) -> bool {
+        let ttl = self.lookup_cache_ttl;
+        self.recent_lookups
+            .retain(|_, (found, _)| runtime::elapsed(*found) < ttl);
+        if let Some((_, peers)) = self.recent_lookups.get(&topic) {
+            trace!("serving lookup of {} from a recent one", hex::encode(topic));
+            for addr in peers.iter() {
+                let info = DiscoveredPeer::new(*addr, Some(topic), DiscoveryMethod::Dht);
+                self.pending_events.push(info);
+            }
+            self.lookup_results.push_back(topic);
+            self.coalesced += 1;
+            return true;
+        }
+        let queued = self
+            .pending_commands
+            .iter()
+            .any(|command| matches!(command, Command::Lookup(t) if *t == topic));
+        let running = self
+            .lookups_started
+            .get(&topic)
+            .map_or(false, |started| runtime::elapsed(*started) < LOOKUP_TIMEOUT);
+        if queued || running {
+            trace!("sharing the running lookup of {}", hex::encode(topic));
+            self.coalesced += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Number of nodes dropped from the full health table since the last call.
+    pub(crate) fn take_dropped_nodes(&mut self) -> u64 {
+        self.nodes.take_dropped()
+    }
+
+    /// Health of the DHT nodes seen so far.
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.nodes.nodes().cloned().collect()
+    }
+
+    fn poll_health(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some((addr, started, res))) =
+            Pin::new(&mut self.pending_pings).poll_next(cx)
+        {
+            trace!("DHT ping {}: {:?}", redact(addr), redact(&res));
+            let outcome = match res.as_ref() {
+                Ok(_) => QueryOutcome::Response,
+                Err(e) => QueryOutcome::Failed(e.to_string()),
+            };
+            self.instrument(
+                QueryKind::Ping,
+                None,
+                Some(addr),
+                Some(runtime::elapsed(started)),
+                outcome,
+            );
+            self.nodes.on_ping(addr, res.ok());
+        }
+
+        if Pin::new(&mut self.health_interval).poll_next(cx).is_ready() {
+            for addr in self.nodes.regrade() {
+                debug!("evicting unresponsive DHT node {}", redact(addr));
+                if let Some(cache) = self.cache.as_ref() {
+                    cache.lock().unwrap().remove_node(&addr);
+                }
+            }
+            let to_ping: Vec<SocketAddr> = self
+                .nodes
+                .questionable()
+                .take(MAX_CONCURRENT_PINGS.saturating_sub(self.pending_pings.len()))
+                .collect();
+            for addr in to_ping {
+                let started = runtime::now();
+                let tap = self.config.dht_tap.clone();
+                let fut = async move {
+                    let res = dht_ping_tapped(addr, PING_TIMEOUT, tap).await;
+                    (addr, started, res)
+                };
+                self.pending_pings.push(Box::pin(fut));
+            }
+            if !self.pending_pings.is_empty() {
+                cx.waker().wake_by_ref();
+            }
+        }
+    }
+
+    // Complete an announce or unannounce, which finds no peers.
+    fn on_announce_result(&mut self, kind: QueryKind, topic: Topic) {
+        let latency = self
+            .announces_started
+            .remove(&(kind, topic))
+            .map(runtime::elapsed);
+        let outcome = QueryOutcome::Completed { peers: 0 };
+        self.instrument(kind, Some(topic), None, latency, outcome);
+    }
+
+    fn on_lookup_result(&mut self, topic: Topic, nodes: Vec<SocketAddr>, peers: &[SocketAddr]) {
+        let latency = self.lookups_started.remove(&topic).map(runtime::elapsed);
+        if self.config.dht_query_hook.is_some() || cfg!(feature = "tracing") {
+            for node in nodes.iter() {
+                let outcome = QueryOutcome::Response;
+                self.instrument(QueryKind::Lookup, Some(topic), Some(*node), None, outcome);
+            }
+            let outcome = QueryOutcome::Completed { peers: peers.len() };
+            self.instrument(QueryKind::Lookup, Some(topic), None, latency, outcome);
+        }
+        for node in nodes.iter() {
+            self.nodes.on_seen(*node);
+        }
+        // Peers are cached by the combined discovery, for all backends.
+        if let Some(cache) = self.cache.as_ref() {
+            let mut cache = cache.lock().unwrap();
+            for node in nodes {
+                cache.add_node(node);
+            }
+        }
+    }
+}
+
+async fn create_dht(config: Config, warm_nodes: Vec<SocketAddr>) -> io::Result<HyperDht> {
+    let mut bootstrap = config.bootstrap.clone();
+    if !warm_nodes.is_empty() {
+        bootstrap = Some(warm_nodes);
+    } else if !config.bootstrap_seeds.is_empty() {
+        let resolved = dns::resolve_seeds(&config.bootstrap_seeds).await;
+        bootstrap.get_or_insert_with(Vec::new).extend(resolved);
+    }
+    let dht_config = DhtConfig::default();
+    let dht_config = if let Some(bootstrap) = bootstrap.as_ref() {
+        dht_config.set_bootstrap_nodes(bootstrap)
+    } else {
+        dht_config
+    };
+    let dht_config = dht_config.set_ephemeral(config.ephemeral);
+    HyperDht::with_config(dht_config).await
+}
+
+// Ping nodes concurrently, returning the round trip to each or the error.
+async fn ping_all(
+    nodes: Vec<SocketAddr>,
+    tap: Option<channel::Sender<DhtMessage>>,
+) -> Vec<(SocketAddr, io::Result<Duration>)> {
+    let pings = nodes.into_iter().map(|addr| {
+        let tap = tap.clone();
+        async move { (addr, dht_ping_tapped(addr, PING_TIMEOUT, tap).await) }
+    });
+    futures::future::join_all(pings).await
+}
+
+fn bootstrap_timeout() -> TimeoutFut {
+    runtime::sleep(BOOTSTRAP_TIMEOUT)
+}
+
+fn event_topic(event: &HyperDhtEvent) -> Option<Topic> {
+    match event {
+        HyperDhtEvent::AnnounceResult { topic, .. } => Some(topic.0),
+        HyperDhtEvent::UnAnnounceResult { topic, .. } => Some(topic.0),
+        HyperDhtEvent::LookupResult { lookup, .. } => Some(lookup.topic.0),
+        _ => None,
+    }
+}
+
+fn event_kind(event: &HyperDhtEvent) -> DhtMessageKind {
+    match event {
+        HyperDhtEvent::Bootstrapped { .. } => DhtMessageKind::Bootstrapped,
+        HyperDhtEvent::AnnounceResult { .. } => DhtMessageKind::AnnounceResult,
+        HyperDhtEvent::LookupResult { .. } => DhtMessageKind::LookupResult,
+        HyperDhtEvent::UnAnnounceResult { .. } => DhtMessageKind::UnAnnounceResult,
+        _ => DhtMessageKind::Other,
+    }
+}
+
+impl Discovery for DhtDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.looked_up.insert(topic);
+        if self.coalesce_lookup(topic) {
+            return;
+        }
+        self.pending_commands.push_back(Command::Lookup(topic))
     }
 
     fn announce(&mut self, topic: Topic) {
-        let opts = QueryOpts {
-            topic: topic.into(),
-            port: Some(self.local_port as u32),
-            local_addr: None,
-        };
-        self.pending_commands.push_back(Command::Announce(opts))
+        self.announced.insert(topic);
+        self.pending_commands.push_back(Command::Announce(topic))
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        // The DHT has no way to abort a running query, so results for topics
+        // that were left are dropped when they arrive.
+        self.looked_up.remove(&topic);
+        self.lookups_started.remove(&topic);
+        self.recent_lookups.remove(&topic);
+        self.pending_commands
+            .retain(|command| command.topic() != &topic);
+        self.pending_events.remove_topic(&topic);
+        if self.announced.remove(&topic) {
+            self.pending_commands.push_back(Command::UnAnnounce(topic));
+        }
     }
 }
 
 impl Stream for DhtDiscovery {
-    type Item = io::Result<PeerInfo>;
+    type Item = io::Result<DiscoveredPeer>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(saver) = self.saver.as_mut() {
+            saver.poll(cx);
+        }
         loop {
-            if let Some(event) = self.pending_events.pop_front() {
+            if let Some(event) = self.pending_events.pop() {
                 return Poll::Ready(Some(Ok(event)));
             }
 
+            if !self.bootstrapped {
+                if let Err(e) = self.poll_bootstrap(cx) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+                if self.pending_rebind.is_some() {
+                    return Poll::Pending;
+                }
+            }
+
             if self.bootstrapped {
                 self.execute_pending_commands();
+                self.poll_health(cx);
             }
 
             let event = ready!(Pin::new(&mut self.state).poll_next(cx));
-            trace!("DHT event: {:?}", event);
+            trace!("DHT event: {:?}", redact(&event));
             let event = event.unwrap();
+            self.tap(
+                Direction::Inbound,
+                event_kind(&event),
+                event_topic(&event),
+                || format!("{:?}", event),
+            );
             match event {
                 HyperDhtEvent::Bootstrapped { .. } => {
                     debug!("DHT bootstrapped!");
                     self.bootstrapped = true;
+                    self.bootstrap_timeout = None;
+                }
+                HyperDhtEvent::AnnounceResult { topic, .. } => {
+                    self.on_announce_result(QueryKind::Announce, topic.0);
+                    if self.announced.contains(&topic.0) {
+                        self.announce_results.push_back(topic.0);
+                    }
                 }
-                HyperDhtEvent::AnnounceResult { .. } => {}
                 HyperDhtEvent::LookupResult { lookup, .. } => {
                     let topic = lookup.topic.0;
-                    let peers = lookup.remotes();
+                    if !self.looked_up.contains(&topic) {
+                        trace!("drop lookup result for left topic {}", hex::encode(topic));
+                        self.lookups_started.remove(&topic);
+                        continue;
+                    }
+                    let nodes = lookup.peers.iter().map(|peers| peers.node).collect();
+                    let peers: Vec<SocketAddr> = lookup.remotes().copied().collect();
+                    self.on_lookup_result(topic, nodes, &peers);
+                    if self.lookup_cache_ttl > Duration::from_secs(0) {
+                        self.recent_lookups
+                            .insert(topic, (runtime::now(), peers.clone()));
+                    }
+                    self.lookup_results.push_back(topic);
                     for addr in peers {
-                        let info = PeerInfo::new(*addr, Some(topic), DiscoveryMethod::Dht);
-                        self.pending_events.push_back(info);
+                        let info = DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Dht);
+                        self.pending_events.push(info);
                     }
                 }
-                HyperDhtEvent::UnAnnounceResult { .. } => {}
+                HyperDhtEvent::UnAnnounceResult { topic, .. } => {
+                    self.on_announce_result(QueryKind::UnAnnounce, topic.0);
+                }
                 _ => {}
             }
         }
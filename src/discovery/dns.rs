@@ -0,0 +1,111 @@
+use async_std::stream::Stream;
+use futures::stream::FuturesUnordered;
+use futures_lite::Future;
+use log::*;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+use crate::dns;
+
+type LookupFut = Pin<Box<dyn Future<Output = (Topic, Vec<SocketAddr>)> + Send>>;
+
+/// Discovery backend acting as a DNS "tracker".
+///
+/// Peers for a topic are read from the records of
+/// `<first 20 bytes of topic as hex>.<domain>`: TXT records holding
+/// whitespace-separated `host:port` entries, and SRV records.
+#[derive(Debug)]
+pub struct DnsDiscovery {
+    domain: String,
+    topics: HashSet<Topic>,
+    pending_lookups: FuturesUnordered<LookupFut>,
+    pending_events: VecDeque<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl DnsDiscovery {
+    pub fn new(domain: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            topics: HashSet::new(),
+            pending_lookups: FuturesUnordered::new(),
+            pending_events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    fn record_name(&self, topic: &Topic) -> String {
+        format!("{}.{}", hex::encode(&topic[..20]), self.domain)
+    }
+}
+
+async fn resolve_topic(topic: Topic, name: String) -> (Topic, Vec<SocketAddr>) {
+    let mut entries = vec![];
+    match dns::resolve_txt(&name).await {
+        Ok(txt) => entries.extend(
+            txt.iter()
+                .flat_map(|entry| entry.split_whitespace())
+                .map(String::from),
+        ),
+        Err(e) => debug!("no TXT records for {}: {}", name, e),
+    }
+    let mut addrs = dns::resolve_seeds(&entries).await;
+    match dns::resolve_srv(&name).await {
+        Ok(srv) => addrs.extend(srv),
+        Err(e) => debug!("no SRV records for {}: {}", name, e),
+    }
+    addrs.sort();
+    addrs.dedup();
+    (topic, addrs)
+}
+
+impl Discovery for DnsDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.topics.insert(topic);
+        let name = self.record_name(&topic);
+        self.pending_lookups
+            .push(Box::pin(resolve_topic(topic, name)));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn announce(&mut self, _topic: Topic) {}
+
+    fn leave(&mut self, topic: Topic) {
+        self.topics.remove(&topic);
+        self.pending_events
+            .retain(|info| info.topic() != Some(topic));
+    }
+}
+
+impl Stream for DnsDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(info) = this.pending_events.pop_front() {
+                return Poll::Ready(Some(Ok(info)));
+            }
+            match Pin::new(&mut this.pending_lookups).poll_next(cx) {
+                Poll::Ready(Some((topic, addrs))) => {
+                    if !this.topics.contains(&topic) {
+                        continue;
+                    }
+                    for addr in addrs {
+                        let info = DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Dns);
+                        this.pending_events.push_back(info);
+                    }
+                }
+                Poll::Ready(None) | Poll::Pending => {
+                    this.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Epidemic gossip of topic membership over established connections.
+//!
+//! Every node keeps a versioned view of which addresses joined which topics.
+//! On [`GossipHandle::exchange`] both sides send their view for common topics
+//! and merge the other's, newer versions winning. Since every node gossips
+//! with its own neighbours, joins and leaves spread through the whole swarm
+//! without each node querying the DHT.
+
+use async_std::stream::Stream;
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::observed::ObservedAddrs;
+use super::pex::{encode_addr, invalid_data, read_addr};
+use super::{salt_topic, DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+
+const MAX_TOPICS: usize = 16;
+const MAX_MEMBERS_PER_TOPIC: usize = 64;
+/// Entries for members that left are forgotten after this long.
+const LEFT_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Member {
+    joined: bool,
+    version: u64,
+}
+
+#[derive(Debug, Default)]
+struct GossipState {
+    members: HashMap<Topic, HashMap<SocketAddr, Member>>,
+    joined: HashSet<Topic>,
+    pending_events: VecDeque<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl GossipState {
+    fn merge(&mut self, topic: Topic, addr: SocketAddr, update: Member) {
+        let members = self.members.entry(topic).or_default();
+        let is_new_join = match members.get(&addr) {
+            Some(current) if current.version >= update.version => return,
+            Some(current) => update.joined && !current.joined,
+            None => update.joined,
+        };
+        if members.len() >= MAX_MEMBERS_PER_TOPIC && !members.contains_key(&addr) {
+            return;
+        }
+        members.insert(addr, update);
+        if is_new_join && self.joined.contains(&topic) {
+            let info = DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Gossip);
+            self.pending_events.push_back(info);
+        }
+    }
+
+    fn move_member(&mut self, from: &SocketAddr, to: SocketAddr) {
+        for members in self.members.values_mut() {
+            if let Some(member) = members.remove(from) {
+                let version = now();
+                members.insert(to, Member { version, ..member });
+            }
+        }
+    }
+
+    fn expire(&mut self) {
+        let min_version = now().saturating_sub(LEFT_RETENTION.as_millis() as u64);
+        for members in self.members.values_mut() {
+            members.retain(|_, member| member.joined || member.version >= min_version);
+        }
+    }
+}
+
+/// Handle to run gossip exchanges. Topics are the application's topics;
+/// salting, if configured, is applied internally.
+#[derive(Debug, Clone)]
+pub struct GossipHandle {
+    state: Arc<Mutex<GossipState>>,
+    salt: Option<Vec<u8>>,
+}
+
+impl GossipHandle {
+    fn network_topic(&self, topic: &Topic) -> Topic {
+        match self.salt.as_ref() {
+            Some(salt) => salt_topic(topic, salt),
+            None => *topic,
+        }
+    }
+
+    /// Members currently known to have joined a topic.
+    pub fn members(&self, topic: &Topic) -> Vec<SocketAddr> {
+        let topic = self.network_topic(topic);
+        let state = self.state.lock().unwrap();
+        state
+            .members
+            .get(&topic)
+            .map(|members| {
+                members
+                    .iter()
+                    .filter(|(_, member)| member.joined)
+                    .map(|(addr, _)| *addr)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Exchange membership views for `topics` with the peer at `remote_addr`.
+    ///
+    /// Nodes do not know their own public address, so they gossip themselves
+    /// with an unspecified ip. The ip of the direct peer is filled in here.
+    pub async fn exchange<S>(
+        &self,
+        stream: &mut S,
+        remote_addr: SocketAddr,
+        topics: &[Topic],
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let topics: Vec<Topic> = topics
+            .iter()
+            .take(MAX_TOPICS)
+            .map(|topic| self.network_topic(topic))
+            .collect();
+        let frame = {
+            let mut state = self.state.lock().unwrap();
+            state.expire();
+            let mut buf = vec![topics.len() as u8];
+            for topic in topics.iter() {
+                let members = state.members.get(topic).cloned().unwrap_or_default();
+                buf.extend_from_slice(topic);
+                buf.push(members.len().min(MAX_MEMBERS_PER_TOPIC) as u8);
+                for (addr, member) in members.iter().take(MAX_MEMBERS_PER_TOPIC) {
+                    encode_addr(&mut buf, addr);
+                    buf.push(member.joined as u8);
+                    buf.extend_from_slice(&member.version.to_be_bytes());
+                }
+            }
+            buf
+        };
+        stream.write_all(&frame).await?;
+        stream.flush().await?;
+
+        let mut count = [0u8; 1];
+        stream.read_exact(&mut count).await?;
+        if count[0] as usize > MAX_TOPICS {
+            return Err(invalid_data("too many topics in gossip"));
+        }
+        let mut updates = vec![];
+        for _ in 0..count[0] {
+            let mut topic = [0u8; 32];
+            stream.read_exact(&mut topic).await?;
+            let mut members = [0u8; 1];
+            stream.read_exact(&mut members).await?;
+            if members[0] as usize > MAX_MEMBERS_PER_TOPIC {
+                return Err(invalid_data("too many members in gossip"));
+            }
+            for _ in 0..members[0] {
+                let mut addr = read_addr(stream).await?;
+                let mut joined = [0u8; 1];
+                stream.read_exact(&mut joined).await?;
+                let mut version = [0u8; 8];
+                stream.read_exact(&mut version).await?;
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(remote_addr.ip());
+                }
+                let member = Member {
+                    joined: joined[0] != 0,
+                    version: u64::from_be_bytes(version),
+                };
+                updates.push((topic, addr, member));
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let known = state.pending_events.len();
+        for (topic, addr, member) in updates {
+            if topics.contains(&topic) {
+                state.merge(topic, addr, member);
+            }
+        }
+        for info in state.pending_events.iter_mut().skip(known) {
+            info.set_referrer(Some(remote_addr));
+        }
+        if !state.pending_events.is_empty() {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Discovery backend yielding members learned through gossip, and
+/// gossiping this node's own joins and leaves.
+#[derive(Debug)]
+pub struct GossipDiscovery {
+    handle: GossipHandle,
+    self_addr: SocketAddr,
+    observed: Option<ObservedAddrs>,
+}
+
+impl GossipDiscovery {
+    /// `local_port` is the port peers can connect to this node on.
+    pub fn new(local_port: u16, salt: Option<Vec<u8>>) -> Self {
+        Self {
+            handle: GossipHandle {
+                state: Arc::new(Mutex::new(GossipState::default())),
+                salt,
+            },
+            self_addr: SocketAddr::new(IpAddr::from([0, 0, 0, 0]), local_port),
+            observed: None,
+        }
+    }
+
+    /// Gossip the external address learned from peers instead of leaving
+    /// the IP to be filled in by the receiver.
+    pub fn with_observed_addrs(mut self, observed: ObservedAddrs) -> Self {
+        self.observed = Some(observed);
+        self
+    }
+
+    pub fn handle(&self) -> GossipHandle {
+        self.handle.clone()
+    }
+
+    fn set_self(&mut self, topic: Topic, joined: bool) {
+        if let Some(addr) = self.observed.as_ref().and_then(|o| o.external_addr()) {
+            if addr != self.self_addr {
+                let mut state = self.handle.state.lock().unwrap();
+                state.move_member(&self.self_addr, addr);
+                self.self_addr = addr;
+            }
+        }
+        let mut state = self.handle.state.lock().unwrap();
+        let member = Member {
+            joined,
+            version: now(),
+        };
+        state
+            .members
+            .entry(topic)
+            .or_default()
+            .insert(self.self_addr, member);
+    }
+}
+
+impl Discovery for GossipDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.handle.state.lock().unwrap().joined.insert(topic);
+    }
+
+    fn announce(&mut self, topic: Topic) {
+        self.set_self(topic, true);
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        self.set_self(topic, false);
+        let mut state = self.handle.state.lock().unwrap();
+        state.joined.remove(&topic);
+        state
+            .pending_events
+            .retain(|info| info.topic() != Some(topic));
+    }
+}
+
+impl Stream for GossipDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.handle.state.lock().unwrap();
+        match state.pending_events.pop_front() {
+            Some(info) => Poll::Ready(Some(Ok(info))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge_newer_version_wins() {
+        let topic = [1u8; 32];
+        let addr = "10.0.0.1:4000".parse().unwrap();
+        let mut state = GossipState::default();
+        state.joined.insert(topic);
+
+        state.merge(
+            topic,
+            addr,
+            Member {
+                joined: true,
+                version: 2,
+            },
+        );
+        assert_eq!(state.pending_events.len(), 1);
+        state.merge(
+            topic,
+            addr,
+            Member {
+                joined: false,
+                version: 1,
+            },
+        );
+        assert!(state.members[&topic][&addr].joined);
+        state.merge(
+            topic,
+            addr,
+            Member {
+                joined: false,
+                version: 3,
+            },
+        );
+        assert!(!state.members[&topic][&addr].joined);
+        assert_eq!(state.pending_events.len(), 1);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::Topic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryKind {
+    Lookup,
+    Announce,
+    UnAnnounce,
+    Ping,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryOutcome {
+    /// The query was sent to the DHT.
+    Started,
+    /// A node responded to the query.
+    Response,
+    /// The query finished, having found this many peers. Announces and
+    /// unannounces find none.
+    Completed { peers: usize },
+    /// The query failed, e.g. a ping that timed out.
+    Failed(String),
+}
+
+/// A single observation from the DHT layer, passed to a [`DhtQueryHook`].
+#[derive(Debug, Clone)]
+pub struct DhtQueryEvent {
+    pub kind: QueryKind,
+    pub topic: Option<Topic>,
+    pub node: Option<SocketAddr>,
+    /// Time since the query was started, which for a ping is the round trip
+    /// to the node. None when a query starts, and for the responses of the
+    /// nodes a lookup queried, as the DHT reports them merged into the
+    /// result, without their timing.
+    pub latency: Option<Duration>,
+    pub outcome: QueryOutcome,
+}
+
+/// Callback invoked for every DHT query event.
+#[derive(Clone)]
+pub struct DhtQueryHook(Arc<dyn Fn(&DhtQueryEvent) + Send + Sync>);
+
+impl DhtQueryHook {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&DhtQueryEvent) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    pub fn call(&self, event: &DhtQueryEvent) {
+        (self.0)(event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DhtMessageKind {
+    Lookup,
+    Announce,
+    UnAnnounce,
+    /// A `_ping` query or its response.
+    Ping,
+    Bootstrapped,
+    LookupResult,
+    AnnounceResult,
+    UnAnnounceResult,
+    /// Any other event the DHT reported.
+    Other,
+}
+
+/// A DHT message seen by the tap, see `Config::set_dht_tap`.
+///
+/// Pings are encoded and decoded by the swarm, so they are tapped as sent
+/// and received on the wire. The DHT encodes and routes lookups and
+/// announces itself, so they are tapped as handed to it, carrying their
+/// target but no node, and the replies of the nodes queried as merged into
+/// the result of the query.
+#[derive(Debug, Clone)]
+pub struct DhtMessage {
+    pub direction: Direction,
+    pub kind: DhtMessageKind,
+    pub target: Option<Topic>,
+    pub node: Option<SocketAddr>,
+    /// The decoded message, as far as the DHT exposes it.
+    pub detail: String,
+}
+
+impl fmt::Debug for DhtQueryHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DhtQueryHook").finish()
+    }
+}
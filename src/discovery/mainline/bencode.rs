@@ -0,0 +1,174 @@
+//! Just enough bencode for KRPC messages.
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Value::Bytes(bytes.into())
+    }
+
+    pub fn dict(entries: Vec<(&str, Value)>) -> Self {
+        Value::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Int(n) => buf.extend_from_slice(format!("i{}e", n).as_bytes()),
+            Value::Bytes(bytes) => {
+                buf.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+                buf.extend_from_slice(bytes);
+            }
+            Value::List(list) => {
+                buf.push(b'l');
+                for value in list {
+                    value.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+            Value::Dict(dict) => {
+                buf.push(b'd');
+                for (key, value) in dict {
+                    Value::Bytes(key.clone()).encode_into(buf);
+                    value.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+        let value = decode_value(buf, &mut pos, 0)?;
+        if pos == buf.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+// Guards against stack exhaustion on hostile input.
+const MAX_DEPTH: usize = 16;
+
+fn decode_value(buf: &[u8], pos: &mut usize, depth: usize) -> Option<Value> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match *buf.get(*pos)? {
+        b'i' => {
+            let end = find(buf, *pos, b'e')?;
+            let n = std::str::from_utf8(&buf[*pos + 1..end])
+                .ok()?
+                .parse()
+                .ok()?;
+            *pos = end + 1;
+            Some(Value::Int(n))
+        }
+        b'l' => {
+            *pos += 1;
+            let mut list = vec![];
+            while *buf.get(*pos)? != b'e' {
+                list.push(decode_value(buf, pos, depth + 1)?);
+            }
+            *pos += 1;
+            Some(Value::List(list))
+        }
+        b'd' => {
+            *pos += 1;
+            let mut dict = BTreeMap::new();
+            while *buf.get(*pos)? != b'e' {
+                let key = decode_bytes(buf, pos)?;
+                let value = decode_value(buf, pos, depth + 1)?;
+                dict.insert(key, value);
+            }
+            *pos += 1;
+            Some(Value::Dict(dict))
+        }
+        b'0'..=b'9' => decode_bytes(buf, pos).map(Value::Bytes),
+        _ => None,
+    }
+}
+
+fn decode_bytes(buf: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let colon = find(buf, *pos, b':')?;
+    let len: usize = std::str::from_utf8(&buf[*pos..colon]).ok()?.parse().ok()?;
+    let start = colon + 1;
+    let end = start.checked_add(len)?;
+    let bytes = buf.get(start..end)?.to_vec();
+    *pos = end;
+    Some(bytes)
+}
+
+fn find(buf: &[u8], from: usize, byte: u8) -> Option<usize> {
+    buf[from..]
+        .iter()
+        .position(|b| *b == byte)
+        .map(|i| from + i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let value = Value::dict(vec![
+            ("t", Value::bytes(&b"aa"[..])),
+            ("y", Value::bytes(&b"q"[..])),
+            ("q", Value::bytes(&b"ping"[..])),
+            (
+                "a",
+                Value::dict(vec![
+                    ("id", Value::bytes(vec![1u8; 20])),
+                    ("port", Value::Int(6881)),
+                    ("l", Value::List(vec![Value::Int(-1)])),
+                ]),
+            ),
+        ]);
+        let encoded = value.encode();
+        assert_eq!(&encoded[..6], b"d1:ad2");
+        assert_eq!(Value::decode(&encoded), Some(value));
+        assert_eq!(Value::decode(b"d1:a"), None);
+        assert_eq!(Value::decode(b"5:ab"), None);
+    }
+}
@@ -0,0 +1,380 @@
+//! Discovery over the BitTorrent mainline DHT (BEP 5).
+//!
+//! Topics are mapped to infohashes by hashing them down to 20 bytes, and
+//! peers are found with `get_peers` and announced with `announce_peer`. Every
+//! operation runs its own iterative lookup from a fresh ephemeral socket, so
+//! no routing table is kept between operations.
+
+use async_std::net::ToSocketAddrs;
+use async_std::stream::Stream;
+use blake2_rfc::blake2b::blake2b;
+use futures::stream::FuturesUnordered;
+use futures_lite::Future;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+use crate::redact::redact;
+use crate::runtime::{self, UdpSocket};
+
+mod bencode;
+
+use bencode::Value;
+
+/// Well-known mainline DHT routers used to start lookups.
+pub const DEFAULT_BOOTSTRAP: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+type NodeId = [u8; 20];
+type OpFut = Pin<Box<dyn Future<Output = (Topic, io::Result<Vec<SocketAddr>>)> + Send>>;
+
+const ALPHA: usize = 8;
+const K: usize = 8;
+const MAX_ROUNDS: usize = 8;
+const ROUND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Map a topic to the infohash it is announced under.
+pub fn infohash(topic: &Topic) -> NodeId {
+    blake2b(20, &[], topic).as_bytes().try_into().unwrap()
+}
+
+/// Discovery backend announcing topics as infohashes on the mainline DHT.
+#[derive(Debug)]
+pub struct MainlineDiscovery {
+    bootstrap: Vec<String>,
+    local_port: u16,
+    topics: HashSet<Topic>,
+    pending_ops: FuturesUnordered<OpFut>,
+    pending_events: Vec<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl MainlineDiscovery {
+    pub fn new(local_port: u16) -> Self {
+        let bootstrap = DEFAULT_BOOTSTRAP.iter().map(|s| s.to_string()).collect();
+        Self::with_bootstrap(local_port, bootstrap)
+    }
+
+    pub fn with_bootstrap(local_port: u16, bootstrap: Vec<String>) -> Self {
+        Self {
+            bootstrap,
+            local_port,
+            topics: HashSet::new(),
+            pending_ops: FuturesUnordered::new(),
+            pending_events: vec![],
+            waker: None,
+        }
+    }
+
+    fn push(&mut self, topic: Topic, announce: bool) {
+        let bootstrap = self.bootstrap.clone();
+        let port = self.local_port;
+        let fut = async move {
+            let res = run(bootstrap, infohash(&topic), announce.then(|| port)).await;
+            (topic, res)
+        };
+        self.pending_ops.push(Box::pin(fut));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Discovery for MainlineDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.topics.insert(topic);
+        self.push(topic, false);
+    }
+
+    fn announce(&mut self, topic: Topic) {
+        self.push(topic, true);
+    }
+
+    // Mainline has no way to withdraw an announcement, it expires on its own.
+    fn leave(&mut self, topic: Topic) {
+        self.topics.remove(&topic);
+        self.pending_events
+            .retain(|info| info.topic() != Some(topic));
+    }
+}
+
+impl Stream for MainlineDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(info) = this.pending_events.pop() {
+                return Poll::Ready(Some(Ok(info)));
+            }
+            match Pin::new(&mut this.pending_ops).poll_next(cx) {
+                Poll::Ready(Some((topic, res))) => match res {
+                    Ok(peers) if this.topics.contains(&topic) => {
+                        for addr in peers {
+                            let info =
+                                DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Mainline);
+                            this.pending_events.push(info);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                },
+                Poll::Ready(None) | Poll::Pending => {
+                    this.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+fn random_id() -> NodeId {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut seed = nanos.to_be_bytes().to_vec();
+    seed.extend_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    blake2b(20, &[], &seed).as_bytes().try_into().unwrap()
+}
+
+fn distance(a: &NodeId, b: &NodeId) -> NodeId {
+    let mut out = [0u8; 20];
+    for (out, (a, b)) in out.iter_mut().zip(a.iter().zip(b.iter())) {
+        *out = a ^ b;
+    }
+    out
+}
+
+fn query(tid: u16, method: &str, args: Vec<(&str, Value)>) -> Vec<u8> {
+    Value::dict(vec![
+        ("t", Value::bytes(tid.to_be_bytes().to_vec())),
+        ("y", Value::bytes(&b"q"[..])),
+        ("q", Value::bytes(method.as_bytes())),
+        ("a", Value::dict(args)),
+    ])
+    .encode()
+}
+
+fn parse_compact_peer(bytes: &[u8]) -> Option<SocketAddr> {
+    if bytes.len() != 6 {
+        return None;
+    }
+    let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+    let port = u16::from_be_bytes([bytes[4], bytes[5]]);
+    Some(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+}
+
+fn parse_compact_nodes(bytes: &[u8]) -> Vec<(NodeId, SocketAddr)> {
+    bytes
+        .chunks_exact(26)
+        .filter_map(|chunk| {
+            let id = chunk[..20].try_into().ok()?;
+            let addr = parse_compact_peer(&chunk[20..])?;
+            Some((id, addr))
+        })
+        .collect()
+}
+
+struct Response {
+    id: NodeId,
+    token: Option<Vec<u8>>,
+    peers: Vec<SocketAddr>,
+    nodes: Vec<(NodeId, SocketAddr)>,
+}
+
+fn parse_response(buf: &[u8]) -> Option<(u16, Response)> {
+    let message = Value::decode(buf)?;
+    if message.get("y")?.as_bytes()? != b"r" {
+        return None;
+    }
+    let tid = u16::from_be_bytes(message.get("t")?.as_bytes()?.try_into().ok()?);
+    let r = message.get("r")?;
+    let id = r.get("id")?.as_bytes()?.try_into().ok()?;
+    let token = r.get("token").and_then(Value::as_bytes).map(<[u8]>::to_vec);
+    let peers = r
+        .get("values")
+        .and_then(Value::as_list)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|value| value.as_bytes().and_then(parse_compact_peer))
+        .collect();
+    let nodes = r
+        .get("nodes")
+        .and_then(Value::as_bytes)
+        .map(parse_compact_nodes)
+        .unwrap_or_default();
+    Some((
+        tid,
+        Response {
+            id,
+            token,
+            peers,
+            nodes,
+        },
+    ))
+}
+
+async fn resolve_bootstrap(bootstrap: &[String]) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for host in bootstrap {
+        match host.to_socket_addrs().await {
+            Ok(resolved) => addrs.extend(resolved.filter(SocketAddr::is_ipv4)),
+            Err(e) => debug!("[mainline] failed to resolve {}: {}", host, e),
+        }
+    }
+    addrs
+}
+
+// Iterative `get_peers` lookup towards `target`, followed by `announce_peer`
+// to the closest responding nodes if a port is given.
+async fn run(
+    bootstrap: Vec<String>,
+    target: NodeId,
+    announce_port: Option<u16>,
+) -> io::Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await?;
+    let self_id = random_id();
+    let mut candidates: Vec<(NodeId, SocketAddr)> = resolve_bootstrap(&bootstrap)
+        .await
+        .into_iter()
+        .map(|addr| ([0xff; 20], addr))
+        .collect();
+    if candidates.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "no mainline bootstrap node reachable",
+        ));
+    }
+    let mut queried = HashSet::new();
+    let mut responded: Vec<(NodeId, SocketAddr, Vec<u8>)> = vec![];
+    let mut peers = HashSet::new();
+    let mut tid: u16 = 0;
+    let mut buf = vec![0u8; 2048];
+
+    for _ in 0..MAX_ROUNDS {
+        candidates.sort_by_key(|(distance, _)| *distance);
+        let round: Vec<SocketAddr> = candidates
+            .iter()
+            .map(|(_, addr)| *addr)
+            .filter(|addr| !queried.contains(addr))
+            .take(ALPHA)
+            .collect();
+        if round.is_empty() {
+            break;
+        }
+        let mut inflight = HashMap::new();
+        for addr in round {
+            queried.insert(addr);
+            tid = tid.wrapping_add(1);
+            let message = query(
+                tid,
+                "get_peers",
+                vec![
+                    ("id", Value::bytes(self_id.to_vec())),
+                    ("info_hash", Value::bytes(target.to_vec())),
+                ],
+            );
+            if let Err(e) = socket.send_to(&message, addr).await {
+                debug!("[mainline] send to {} failed: {}", redact(addr), e);
+                continue;
+            }
+            inflight.insert(tid, addr);
+        }
+
+        let deadline = Instant::now() + ROUND_TIMEOUT;
+        while !inflight.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (n, from) = match runtime::timeout(remaining, socket.recv_from(&mut buf)).await {
+                Ok(res) => res?,
+                Err(_) => break,
+            };
+            let (tid, response) = match parse_response(&buf[..n]) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            if inflight.get(&tid) != Some(&from) {
+                continue;
+            }
+            inflight.remove(&tid);
+            peers.extend(response.peers);
+            if let Some(token) = response.token {
+                responded.push((distance(&response.id, &target), from, token));
+            }
+            for (id, addr) in response.nodes {
+                if !queried.contains(&addr) {
+                    candidates.push((distance(&id, &target), addr));
+                }
+            }
+        }
+        candidates.retain(|(_, addr)| !queried.contains(addr));
+    }
+
+    if let Some(port) = announce_port {
+        responded.sort_by_key(|(distance, _, _)| *distance);
+        for (_, addr, token) in responded.into_iter().take(K) {
+            tid = tid.wrapping_add(1);
+            let message = query(
+                tid,
+                "announce_peer",
+                vec![
+                    ("id", Value::bytes(self_id.to_vec())),
+                    ("info_hash", Value::bytes(target.to_vec())),
+                    ("port", Value::Int(port as i64)),
+                    ("token", Value::Bytes(token)),
+                    ("implied_port", Value::Int(0)),
+                ],
+            );
+            if let Err(e) = socket.send_to(&message, addr).await {
+                debug!("[mainline] announce to {} failed: {}", redact(addr), e);
+            }
+        }
+    }
+    Ok(peers.into_iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let mut nodes = vec![7u8; 20];
+        nodes.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        let message = Value::dict(vec![
+            ("t", Value::bytes(vec![0, 3])),
+            ("y", Value::bytes(&b"r"[..])),
+            (
+                "r",
+                Value::dict(vec![
+                    ("id", Value::bytes(vec![1u8; 20])),
+                    ("token", Value::bytes(&b"tok"[..])),
+                    ("nodes", Value::Bytes(nodes)),
+                    (
+                        "values",
+                        Value::List(vec![Value::bytes(vec![127, 0, 0, 1, 0, 80])]),
+                    ),
+                ]),
+            ),
+        ]);
+        let (tid, response) = parse_response(&message.encode()).unwrap();
+        assert_eq!(tid, 3);
+        assert_eq!(response.id, [1u8; 20]);
+        assert_eq!(response.token, Some(b"tok".to_vec()));
+        assert_eq!(response.peers, vec!["127.0.0.1:80".parse().unwrap()]);
+        assert_eq!(
+            response.nodes,
+            vec![([7u8; 20], "10.0.0.1:6881".parse().unwrap())]
+        );
+    }
+}
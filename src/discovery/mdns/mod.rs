@@ -0,0 +1,329 @@
+use async_std::channel;
+use async_std::stream::Stream;
+use colmeia_hyperswarm_mdns::{self_id, Announcer, Locator};
+use futures_lite::future::pending;
+use futures_lite::{ready, FutureExt, StreamExt};
+use log::*;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::config::MdnsConfig;
+use crate::runtime::{self, Background, Spawner};
+use crate::Config;
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+
+mod packet;
+mod v6;
+
+use packet::Packet;
+use v6::Ipv6Mdns;
+
+/// The background task waits while this many found peers were not taken.
+const MAX_PENDING_FOUND: usize = 256;
+
+mod socket {
+    use multicast_socket::MulticastSocket;
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const MDNS_IP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+    pub fn create() -> io::Result<MulticastSocket> {
+        let addr = SocketAddrV4::new(MDNS_IP, 5353);
+        MulticastSocket::all_interfaces(addr)
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Lookup(Topic),
+    Announce(Topic),
+}
+
+type FoundResult = io::Result<(Topic, SocketAddr)>;
+
+pub struct MdnsDiscovery {
+    // local_port: u16,
+    // self_id: String,
+    pending_commands_tx: channel::Sender<Command>,
+    found_rx: channel::Receiver<FoundResult>,
+    topics: HashSet<Topic>,
+    task: Background,
+}
+
+impl fmt::Debug for MdnsDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MdnsDiscovery")
+            .field("topics", &self.topics.len())
+            .finish()
+    }
+}
+
+impl MdnsDiscovery {
+    /// Stop the background task and wait for it to finish.
+    pub async fn shutdown(self) {
+        let Self {
+            pending_commands_tx,
+            task,
+            ..
+        } = self;
+        drop(pending_commands_tx);
+        task.await;
+    }
+
+    pub async fn bind(local_port: u16, config: Config) -> io::Result<Self> {
+        let self_id = self_id();
+        let lookup_interval = config.mdns.query_interval;
+        let v4 = if has_default_naming(&config.mdns) {
+            let socket = socket::create()?;
+            let locator = Locator::listen(socket, lookup_interval, self_id.as_bytes());
+            let socket = socket::create()?;
+            let announcer = Announcer::listen(socket, local_port, self_id.clone());
+            Some((locator, announcer))
+        } else {
+            debug!("IPv4 mDNS disabled, it cannot use the configured names");
+            None
+        };
+        let v6 = match Ipv6Mdns::bind(local_port, self_id, &config.mdns) {
+            Ok(v6) => Some(v6),
+            Err(e) => {
+                warn!("IPv6 mDNS unavailable: {}", e);
+                None
+            }
+        };
+        if v4.is_none() && v6.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "custom mDNS names need IPv6 mDNS, which is unavailable",
+            ));
+        }
+        let (pending_commands_tx, pending_commands_rx) = channel::unbounded();
+        let (found_tx, found_rx) = channel::bounded(MAX_PENDING_FOUND);
+        let backends = Backends {
+            v4,
+            v6,
+            query_interval: runtime::interval(lookup_interval),
+        };
+        let task = Background::new(
+            "hyperswarm::mdns",
+            run(backends, pending_commands_rx, found_tx),
+            config.spawn_free,
+            &Spawner::default().set_observer(config.task_observer.clone()),
+        );
+        Ok(Self {
+            // self_id,
+            // local_port,
+            pending_commands_tx,
+            found_rx,
+            topics: HashSet::new(),
+            task,
+        })
+    }
+}
+
+// The IPv4 backend only uses its fixed record format.
+fn has_default_naming(config: &MdnsConfig) -> bool {
+    let default = MdnsConfig::default();
+    config.service == default.service && config.instance_name.is_none() && config.ttl == default.ttl
+}
+
+struct Backends {
+    v4: Option<(Locator, Announcer)>,
+    v6: Option<Ipv6Mdns>,
+    query_interval: runtime::Interval,
+}
+
+enum Event {
+    Command(Option<Command>),
+    Found(Option<(Option<Topic>, SocketAddr)>),
+    Announced,
+    Packet(io::Result<(Packet, SocketAddr)>),
+    Query,
+}
+
+// The locator and announcer are owned by a background task, because their
+// `add_topic` futures borrow them mutably and thus cannot be stored next to
+// them in the discovery struct. The task ends once the discovery is dropped.
+async fn run(
+    backends: Backends,
+    commands: channel::Receiver<Command>,
+    found: channel::Sender<FoundResult>,
+) {
+    let Backends {
+        mut v4,
+        mut v6,
+        mut query_interval,
+    } = backends;
+    'run: loop {
+        let event = {
+            let command = async { Event::Command(commands.recv().await.ok()) };
+            let (locator, announcer) = match v4.as_mut() {
+                Some((locator, announcer)) => (Some(locator), Some(announcer)),
+                None => (None, None),
+            };
+            let located = async {
+                let next = match locator {
+                    Some(locator) => locator.next().await,
+                    None => pending().await,
+                };
+                Event::Found(next.map(|(topic, peer_addr)| (topic.try_into().ok(), peer_addr)))
+            };
+            let announced = async {
+                match announcer {
+                    Some(announcer) => match announcer.next().await {
+                        Some(_) => Event::Announced,
+                        None => pending().await,
+                    },
+                    None => pending().await,
+                }
+            };
+            let received = async {
+                match v6.as_mut() {
+                    Some(v6) => Event::Packet(v6.recv().await),
+                    None => pending().await,
+                }
+            };
+            let query = async {
+                query_interval.next().await;
+                Event::Query
+            };
+            command
+                .race(located)
+                .race(announced)
+                .race(received)
+                .race(query)
+                .await
+        };
+        let res = match event {
+            Event::Command(None) => break,
+            Event::Command(Some(Command::Lookup(topic))) => {
+                if let Some(v6) = v6.as_mut() {
+                    log_v6_error(v6.lookup(topic).await);
+                }
+                match v4.as_mut() {
+                    Some((locator, _)) => locator
+                        .add_topic(&topic)
+                        .await
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+                    None => Ok(()),
+                }
+            }
+            Event::Command(Some(Command::Announce(topic))) => {
+                if let Some(v6) = v6.as_mut() {
+                    log_v6_error(v6.announce(topic).await);
+                }
+                match v4.as_mut() {
+                    Some((_, announcer)) => announcer
+                        .add_topic(&topic)
+                        .await
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+                    None => Ok(()),
+                }
+            }
+            Event::Found(None) => break,
+            Event::Found(Some((Some(topic), peer_addr))) => {
+                if found.send(Ok((topic, peer_addr))).await.is_err() {
+                    break;
+                }
+                Ok(())
+            }
+            Event::Found(Some((None, _))) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Received invalid topic",
+            )),
+            Event::Announced => Ok(()),
+            Event::Packet(Ok((packet, from))) => {
+                let peers = match v6.as_ref() {
+                    Some(v6) => v6.on_packet(packet, from).await,
+                    None => Ok(vec![]),
+                };
+                for peer in peers.unwrap_or_else(|e| {
+                    debug!("IPv6 mDNS error: {}", e);
+                    vec![]
+                }) {
+                    if found.send(Ok(peer)).await.is_err() {
+                        break 'run;
+                    }
+                }
+                Ok(())
+            }
+            Event::Packet(Err(e)) => {
+                log_v6_error(Err(e));
+                Ok(())
+            }
+            Event::Query => {
+                if let Some(v6) = v6.as_ref() {
+                    log_v6_error(v6.query_all().await);
+                }
+                Ok(())
+            }
+        };
+        if let Err(e) = res {
+            if found.send(Err(e)).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!("mdns task finished");
+}
+
+// IPv6 is best effort next to the IPv4 backend, so its errors are only logged.
+fn log_v6_error(res: io::Result<()>) {
+    if let Err(e) = res {
+        debug!("IPv6 mDNS error: {}", e);
+    }
+}
+
+impl Discovery for MdnsDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.topics.insert(topic);
+        self.pending_commands_tx
+            .try_send(Command::Lookup(topic))
+            .unwrap();
+    }
+
+    fn announce(&mut self, topic: Topic) {
+        self.pending_commands_tx
+            .try_send(Command::Announce(topic))
+            .unwrap();
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        // The mDNS locator cannot forget topics, so results for topics that
+        // were left are filtered out instead.
+        self.topics.remove(&topic);
+    }
+}
+
+impl Stream for MdnsDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.task.poll_inline(cx);
+        loop {
+            let res = match ready!(Pin::new(&mut this.found_rx).poll_next(cx)) {
+                Some(res) => res,
+                None => return Poll::Pending,
+            };
+            match res {
+                Err(e) => return Poll::Ready(Some(Err(e))),
+                Ok((topic, _)) if !this.topics.contains(&topic) => {
+                    trace!("drop mdns result for unknown topic {}", hex::encode(topic));
+                }
+                Ok((topic, peer_addr)) => {
+                    return Poll::Ready(Some(Ok(DiscoveredPeer::new(
+                        peer_addr,
+                        Some(topic),
+                        DiscoveryMethod::Mdns,
+                    ))))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Just enough of the DNS wire format for mDNS topic discovery: questions and
+//! SRV, TXT and AAAA records.
+
+use std::convert::TryInto;
+use std::net::Ipv6Addr;
+
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8400;
+const MAX_POINTER_JUMPS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    Srv { port: u16, target: String },
+    Txt(Vec<Vec<u8>>),
+    Aaaa(Ipv6Addr),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Packet {
+    pub is_response: bool,
+    pub questions: Vec<Question>,
+    /// Answers and additional records.
+    pub answers: Vec<Record>,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        let flags = if self.is_response { FLAG_RESPONSE } else { 0 };
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, flags);
+        put_u16(&mut buf, self.questions.len() as u16);
+        put_u16(&mut buf, self.answers.len() as u16);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        for question in self.questions.iter() {
+            put_name(&mut buf, &question.name);
+            put_u16(&mut buf, question.qtype);
+            put_u16(&mut buf, CLASS_IN);
+        }
+        for record in self.answers.iter() {
+            put_name(&mut buf, &record.name);
+            let (rtype, rdata) = match &record.data {
+                RData::Srv { port, target } => {
+                    let mut rdata = vec![];
+                    put_u16(&mut rdata, 0);
+                    put_u16(&mut rdata, 0);
+                    put_u16(&mut rdata, *port);
+                    put_name(&mut rdata, target);
+                    (TYPE_SRV, rdata)
+                }
+                RData::Txt(entries) => {
+                    let mut rdata = vec![];
+                    for entry in entries {
+                        rdata.push(entry.len().min(255) as u8);
+                        rdata.extend_from_slice(&entry[..entry.len().min(255)]);
+                    }
+                    (TYPE_TXT, rdata)
+                }
+                RData::Aaaa(addr) => (TYPE_AAAA, addr.octets().to_vec()),
+                RData::Other => continue,
+            };
+            put_u16(&mut buf, rtype);
+            put_u16(&mut buf, CLASS_IN);
+            buf.extend_from_slice(&record.ttl.to_be_bytes());
+            put_u16(&mut buf, rdata.len() as u16);
+            buf.extend_from_slice(&rdata);
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+        let _id = get_u16(buf, &mut pos)?;
+        let flags = get_u16(buf, &mut pos)?;
+        let qdcount = get_u16(buf, &mut pos)?;
+        let ancount = get_u16(buf, &mut pos)?;
+        let nscount = get_u16(buf, &mut pos)?;
+        let arcount = get_u16(buf, &mut pos)?;
+
+        let mut packet = Packet {
+            is_response: flags & 0x8000 != 0,
+            ..Default::default()
+        };
+        for _ in 0..qdcount {
+            let name = get_name(buf, &mut pos)?;
+            let qtype = get_u16(buf, &mut pos)?;
+            let _qclass = get_u16(buf, &mut pos)?;
+            packet.questions.push(Question { name, qtype });
+        }
+        for _ in 0..(ancount as usize + nscount as usize + arcount as usize) {
+            let name = get_name(buf, &mut pos)?;
+            let rtype = get_u16(buf, &mut pos)?;
+            let _class = get_u16(buf, &mut pos)?;
+            let ttl = u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?);
+            pos += 4;
+            let len = get_u16(buf, &mut pos)? as usize;
+            let end = pos + len;
+            let rdata = buf.get(pos..end)?;
+            let data = match rtype {
+                TYPE_SRV => {
+                    let mut rpos = pos + 4;
+                    let port = get_u16(buf, &mut rpos)?;
+                    let target = get_name(buf, &mut rpos)?;
+                    RData::Srv { port, target }
+                }
+                TYPE_TXT => {
+                    let mut entries = vec![];
+                    let mut rpos = 0;
+                    while rpos < rdata.len() {
+                        let len = rdata[rpos] as usize;
+                        entries.push(rdata.get(rpos + 1..rpos + 1 + len)?.to_vec());
+                        rpos += 1 + len;
+                    }
+                    RData::Txt(entries)
+                }
+                TYPE_AAAA => {
+                    let octets: [u8; 16] = rdata.try_into().ok()?;
+                    RData::Aaaa(Ipv6Addr::from(octets))
+                }
+                _ => RData::Other,
+            };
+            pos = end;
+            packet.answers.push(Record { name, ttl, data });
+        }
+        Some(packet)
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label);
+    }
+    buf.push(0);
+}
+
+fn get_u16(buf: &[u8], pos: &mut usize) -> Option<u16> {
+    let value = u16::from_be_bytes(buf.get(*pos..*pos + 2)?.try_into().ok()?);
+    *pos += 2;
+    Some(value)
+}
+
+fn get_name(buf: &[u8], pos: &mut usize) -> Option<String> {
+    let mut labels = vec![];
+    let mut cursor = *pos;
+    let mut jumps = 0;
+    loop {
+        let len = *buf.get(cursor)? as usize;
+        if len & 0xc0 == 0xc0 {
+            let pointer = ((len & 0x3f) << 8) | *buf.get(cursor + 1)? as usize;
+            if jumps == 0 {
+                *pos = cursor + 2;
+            }
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return None;
+            }
+            cursor = pointer;
+            continue;
+        }
+        cursor += 1;
+        if len == 0 {
+            break;
+        }
+        let label = buf.get(cursor..cursor + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        cursor += len;
+    }
+    if jumps == 0 {
+        *pos = cursor;
+    }
+    Some(labels.join("."))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let packet = Packet {
+            is_response: true,
+            questions: vec![Question {
+                name: "abcd.hyperswarm.local".into(),
+                qtype: TYPE_SRV,
+            }],
+            answers: vec![
+                Record {
+                    name: "abcd.hyperswarm.local".into(),
+                    ttl: 120,
+                    data: RData::Srv {
+                        port: 4000,
+                        target: "peer.local".into(),
+                    },
+                },
+                Record {
+                    name: "abcd.hyperswarm.local".into(),
+                    ttl: 120,
+                    data: RData::Txt(vec![b"id=peer".to_vec()]),
+                },
+                Record {
+                    name: "peer.local".into(),
+                    ttl: 120,
+                    data: RData::Aaaa("fe80::1".parse().unwrap()),
+                },
+            ],
+        };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+
+    #[test]
+    fn test_decode_compressed_name() {
+        let mut buf = vec![0, 0, 0x84, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        // question "a.local", then a second question pointing at it
+        buf.extend_from_slice(&[1, b'a', 5, b'l', b'o', b'c', b'a', b'l', 0, 0, 33, 0, 1]);
+        buf[5] = 2;
+        buf.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1]);
+        let packet = Packet::decode(&buf).unwrap();
+        assert_eq!(packet.questions[0].name, "a.local");
+        assert_eq!(packet.questions[1].name, "a.local");
+    }
+}
@@ -0,0 +1,293 @@
+use log::*;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use super::packet::{Packet, Question, RData, Record, TYPE_SRV};
+use crate::config::MdnsConfig;
+use crate::discovery::Topic;
+use crate::runtime::UdpSocket;
+
+const MDNS_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+const MDNS_PORT: u16 = 5353;
+
+/// mDNS topic discovery over the IPv6 link-local multicast group `ff02::fb`.
+#[derive(Debug)]
+pub struct Ipv6Mdns {
+    socket: UdpSocket,
+    names: Names,
+    lookups: HashMap<String, Topic>,
+    announces: HashMap<String, Topic>,
+    buf: Vec<u8>,
+}
+
+impl Ipv6Mdns {
+    pub fn bind(local_port: u16, self_id: String, config: &MdnsConfig) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_only_v6(true)?;
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, MDNS_PORT));
+        socket.bind(&addr.into())?;
+        socket.join_multicast_v6(&MDNS_IPV6, 0)?;
+        socket.set_multicast_loop_v6(true)?;
+        socket.set_nonblocking(true)?;
+        let socket: std::net::UdpSocket = socket.into();
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            names: Names::new(local_port, self_id, config),
+            lookups: HashMap::new(),
+            announces: HashMap::new(),
+            buf: vec![0u8; 9000],
+        })
+    }
+
+    pub async fn recv(&mut self) -> io::Result<(Packet, SocketAddr)> {
+        loop {
+            let (n, from) = self.socket.recv_from(&mut self.buf).await?;
+            if let Some(packet) = Packet::decode(&self.buf[..n]) {
+                return Ok((packet, from));
+            }
+        }
+    }
+
+    pub async fn lookup(&mut self, topic: Topic) -> io::Result<()> {
+        let name = self.names.topic_name(&topic);
+        self.lookups.insert(name.clone(), topic);
+        self.query(vec![name]).await
+    }
+
+    pub async fn announce(&mut self, topic: Topic) -> io::Result<()> {
+        let name = self.names.topic_name(&topic);
+        self.announces.insert(name.clone(), topic);
+        self.respond(&name).await
+    }
+
+    pub async fn query_all(&self) -> io::Result<()> {
+        if self.lookups.is_empty() {
+            return Ok(());
+        }
+        self.query(self.lookups.keys().cloned().collect()).await
+    }
+
+    /// Handle an incoming packet, answering queries for announced topics and
+    /// returning peers found for looked up topics.
+    pub async fn on_packet(
+        &self,
+        packet: Packet,
+        from: SocketAddr,
+    ) -> io::Result<Vec<(Topic, SocketAddr)>> {
+        if !packet.is_response {
+            for question in packet.questions.iter() {
+                if question.qtype == TYPE_SRV && self.announces.contains_key(&question.name) {
+                    self.respond(&question.name).await?;
+                }
+            }
+            return Ok(vec![]);
+        }
+
+        if self.names.is_own(&packet) {
+            return Ok(vec![]);
+        }
+
+        let mut found = vec![];
+        for record in packet.answers.iter() {
+            if let (Some(topic), RData::Srv { port, target }) =
+                (self.lookups.get(&record.name), &record.data)
+            {
+                let addr = resolve_target(&packet, target, *port, from);
+                found.push((*topic, addr));
+            }
+        }
+        Ok(found)
+    }
+
+    async fn query(&self, names: Vec<String>) -> io::Result<()> {
+        let packet = Packet {
+            is_response: false,
+            questions: names
+                .into_iter()
+                .map(|name| Question {
+                    name,
+                    qtype: TYPE_SRV,
+                })
+                .collect(),
+            answers: vec![],
+        };
+        self.send(&packet).await
+    }
+
+    async fn respond(&self, name: &str) -> io::Result<()> {
+        let packet = self.names.response(name, &local_addrs());
+        self.send(&packet).await
+    }
+
+    async fn send(&self, packet: &Packet) -> io::Result<()> {
+        let addr = SocketAddrV6::new(MDNS_IPV6, MDNS_PORT, 0, 0).into();
+        self.socket.send_to(&packet.encode(), addr).await?;
+        Ok(())
+    }
+}
+
+// The names and timing of own records.
+#[derive(Debug)]
+struct Names {
+    // Random, to tell own responses apart from those of nodes with the same
+    // instance name.
+    self_id: String,
+    instance_name: String,
+    local_port: u16,
+    service: String,
+    ttl: u32,
+}
+
+impl Names {
+    fn new(local_port: u16, self_id: String, config: &MdnsConfig) -> Self {
+        Self {
+            instance_name: config
+                .instance_name
+                .clone()
+                .unwrap_or_else(|| self_id.clone()),
+            self_id,
+            local_port,
+            service: config.service.clone(),
+            ttl: config.ttl.as_secs() as u32,
+        }
+    }
+
+    fn topic_name(&self, topic: &Topic) -> String {
+        format!("{}.{}", hex::encode(&topic[..20]), self.service)
+    }
+
+    fn id_entry(&self) -> Vec<u8> {
+        format!("id={}", self.self_id).into_bytes()
+    }
+
+    fn is_own(&self, packet: &Packet) -> bool {
+        packet.answers.iter().any(|record| match &record.data {
+            RData::Txt(entries) => entries.iter().any(|e| e == &self.id_entry()),
+            _ => false,
+        })
+    }
+
+    // SRV and TXT records for a topic name, and AAAA records for the SRV
+    // target.
+    fn response(&self, name: &str, addrs: &[Ipv6Addr]) -> Packet {
+        let target = format!("{}.local", self.instance_name);
+        let mut answers = vec![
+            Record {
+                name: name.to_string(),
+                ttl: self.ttl,
+                data: RData::Srv {
+                    port: self.local_port,
+                    target: target.clone(),
+                },
+            },
+            Record {
+                name: name.to_string(),
+                ttl: self.ttl,
+                data: RData::Txt(vec![self.id_entry()]),
+            },
+        ];
+        answers.extend(addrs.iter().map(|addr| Record {
+            name: target.clone(),
+            ttl: self.ttl,
+            data: RData::Aaaa(*addr),
+        }));
+        Packet {
+            is_response: true,
+            questions: vec![],
+            answers,
+        }
+    }
+}
+
+// The IPv6 addresses of the interfaces other than loopback.
+fn local_addrs() -> Vec<Ipv6Addr> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            debug!("cannot list interfaces: {}", e);
+            return vec![];
+        }
+    };
+    let mut addrs: Vec<Ipv6Addr> = interfaces
+        .iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter_map(|interface| match interface.ip() {
+            IpAddr::V6(addr) => Some(addr),
+            IpAddr::V4(_) => None,
+        })
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+/// Prefer an AAAA record for the SRV target, falling back to the source
+/// address of the response. Link-local addresses keep the scope of the source.
+fn resolve_target(packet: &Packet, target: &str, port: u16, from: SocketAddr) -> SocketAddr {
+    let aaaa = packet.answers.iter().find_map(|record| match record.data {
+        RData::Aaaa(addr) if record.name == target => Some(addr),
+        _ => None,
+    });
+    match (aaaa, from) {
+        (Some(addr), SocketAddr::V6(from)) => {
+            let scope_id = if is_unicast_link_local(&addr) {
+                from.scope_id()
+            } else {
+                0
+            };
+            SocketAddr::V6(SocketAddrV6::new(addr, port, 0, scope_id))
+        }
+        (Some(addr), _) => SocketAddr::new(IpAddr::V6(addr), port),
+        (None, from) => {
+            let mut addr = from;
+            addr.set_port(port);
+            addr
+        }
+    }
+}
+
+fn is_unicast_link_local(addr: &Ipv6Addr) -> bool {
+    (addr.segments()[0] & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_response() {
+        let config = MdnsConfig::default().set_instance_name(Some("app".into()));
+        let names = Names::new(4000, "a".into(), &config);
+        let name = names.topic_name(&[1u8; 32]);
+        let addrs: Vec<Ipv6Addr> = vec!["fe80::1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        let packet = Packet::decode(&names.response(&name, &addrs).encode()).unwrap();
+
+        let aaaa: Vec<&Record> = packet
+            .answers
+            .iter()
+            .filter(|record| matches!(record.data, RData::Aaaa(_)))
+            .collect();
+        assert_eq!(aaaa.len(), 2);
+        assert!(aaaa.iter().all(|record| record.name == "app.local"));
+        let target = packet.answers.iter().find_map(|record| match &record.data {
+            RData::Srv { port, target } if record.name == name => Some((*port, target.clone())),
+            _ => None,
+        });
+        assert_eq!(target, Some((4000, "app.local".to_string())));
+        // Link-local addresses keep the scope of the source.
+        let from = SocketAddr::V6(SocketAddrV6::new("fe80::2".parse().unwrap(), 5353, 0, 3));
+        assert_eq!(
+            resolve_target(&packet, "app.local", 4000, from),
+            SocketAddr::V6(SocketAddrV6::new(addrs[0], 4000, 0, 3))
+        );
+
+        // Nodes with the same instance name are told apart.
+        assert!(names.is_own(&packet));
+        assert!(!Names::new(4000, "b".into(), &config).is_own(&packet));
+    }
+}
@@ -0,0 +1,84 @@
+use async_std::stream::Stream;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+use crate::transport::memory::MemoryNetwork;
+
+/// Discovery backend on a `MemoryNetwork`, in place of the DHT in tests and
+/// simulations. Announced addresses are kept on the network, and lookups
+/// find them right away, in address order.
+#[derive(Debug)]
+pub struct MemoryDiscovery {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    announced: HashSet<Topic>,
+    pending_events: VecDeque<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl MemoryDiscovery {
+    /// Discovery for the host at `addr`, which is what it announces.
+    pub fn new(network: MemoryNetwork, addr: SocketAddr) -> Self {
+        Self {
+            network,
+            addr,
+            announced: HashSet::new(),
+            pending_events: VecDeque::new(),
+            waker: None,
+        }
+    }
+}
+
+impl Discovery for MemoryDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        for addr in self.network.announced(&topic) {
+            if addr != self.addr {
+                let method = DiscoveryMethod::Custom("memory".into());
+                let info = DiscoveredPeer::new(addr, Some(topic), method);
+                self.pending_events.push_back(info);
+            }
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn announce(&mut self, topic: Topic) {
+        self.network.announce(topic, self.addr);
+        self.announced.insert(topic);
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        if self.announced.remove(&topic) {
+            self.network.unannounce(&topic, &self.addr);
+        }
+        self.pending_events
+            .retain(|info| info.topic() != Some(topic));
+    }
+}
+
+impl Drop for MemoryDiscovery {
+    fn drop(&mut self) {
+        for topic in self.announced.iter() {
+            self.network.unannounce(topic, &self.addr);
+        }
+    }
+}
+
+impl Stream for MemoryDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.pending_events.pop_front() {
+            Some(info) => Poll::Ready(Some(Ok(info))),
+            None => {
+                this.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
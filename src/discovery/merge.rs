@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use super::{DiscoveredPeer, Topic};
+use crate::runtime;
+
+/// Reports of a peer within this window after its first report are merged
+/// into it instead of being yielded again.
+const MERGE_WINDOW: Duration = Duration::from_secs(60);
+/// Expired reports are dropped at most this often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_PEERS: usize = 4096;
+
+type PeerKey = (Option<Topic>, SocketAddr);
+
+/// Merges reports of the same peer from different discovery backends.
+///
+/// Once it holds `max_peers` recent reports, further peers are yielded
+/// without being recorded, so later reports of them are not merged.
+#[derive(Debug)]
+pub struct PeerMerger {
+    peers: HashMap<PeerKey, (DiscoveredPeer, Instant)>,
+    max_peers: usize,
+    last_sweep: Option<Instant>,
+    // Peers not recorded since the last call.
+    dropped: u64,
+}
+
+impl Default for PeerMerger {
+    fn default() -> Self {
+        Self::with_capacity(MAX_PEERS)
+    }
+}
+
+// IPv4-mapped IPv6 addresses are the same peer as their IPv4 address.
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            Some(v4) if ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+                SocketAddr::new(IpAddr::V4(v4), addr.port())
+            }
+            _ => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+impl PeerMerger {
+    pub fn with_capacity(max_peers: usize) -> Self {
+        Self {
+            peers: HashMap::new(),
+            max_peers,
+            last_sweep: None,
+            dropped: 0,
+        }
+    }
+
+    /// Record a report. Returns the peer if it should be yielded, or `None`
+    /// if it was merged into a recent report.
+    pub fn insert(&mut self, peer_info: DiscoveredPeer) -> Option<DiscoveredPeer> {
+        let now = runtime::now();
+        let key = (peer_info.topic(), canonical(peer_info.addr()));
+        if let Some((known, seen)) = self.peers.get_mut(&key) {
+            if now.duration_since(*seen) < MERGE_WINDOW {
+                known.merge(&peer_info);
+                return None;
+            }
+        }
+        self.sweep(now);
+        if self.peers.len() >= self.max_peers && !self.peers.contains_key(&key) {
+            self.dropped += 1;
+            return Some(peer_info);
+        }
+        self.peers.insert(key, (peer_info.clone(), now));
+        Some(peer_info)
+    }
+
+    // Drop expired reports, at most once per interval.
+    fn sweep(&mut self, now: Instant) {
+        let due = self
+            .last_sweep
+            .map_or(true, |last| now.duration_since(last) >= SWEEP_INTERVAL);
+        if !due {
+            return;
+        }
+        self.last_sweep = Some(now);
+        self.peers
+            .retain(|_, (_, seen)| now.duration_since(*seen) < MERGE_WINDOW);
+    }
+
+    /// Number of peers yielded without being recorded since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    pub fn get(&self, topic: Option<Topic>, addr: SocketAddr) -> Option<&DiscoveredPeer> {
+        self.peers
+            .get(&(topic, canonical(addr)))
+            .map(|(peer_info, _)| peer_info)
+    }
+
+    pub fn remove_topic(&mut self, topic: &Topic) {
+        self.peers.retain(|(t, _), _| t.as_ref() != Some(topic));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::discovery::DiscoveryMethod;
+
+    #[test]
+    fn test_merge() {
+        let mut merger = PeerMerger::default();
+        let topic = Some([1u8; 32]);
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:4000".parse().unwrap();
+        assert!(merger
+            .insert(DiscoveredPeer::new(addr, topic, DiscoveryMethod::Mdns))
+            .is_some());
+        assert!(merger
+            .insert(DiscoveredPeer::new(mapped, topic, DiscoveryMethod::Dht))
+            .is_none());
+        assert!(merger
+            .insert(DiscoveredPeer::new(addr, None, DiscoveryMethod::Pex))
+            .is_some());
+        let merged = merger.get(topic, addr).unwrap();
+        assert_eq!(
+            merged.sources(),
+            &[DiscoveryMethod::Mdns, DiscoveryMethod::Dht]
+        );
+        assert_eq!(merged.addrs(), &[addr, mapped]);
+    }
+
+    #[test]
+    fn test_max_peers() {
+        let mut merger = PeerMerger::with_capacity(2);
+        let topic = Some([1u8; 32]);
+        for port in 0..3 {
+            let addr = SocketAddr::from(([10, 0, 0, 1], port));
+            let peer = DiscoveredPeer::new(addr, topic, DiscoveryMethod::Dht);
+            assert!(merger.insert(peer.clone()).is_some());
+            // Only recorded peers are merged.
+            assert_eq!(merger.insert(peer).is_none(), port < 2);
+        }
+        assert_eq!(merger.peers.len(), 2);
+        assert_eq!(merger.take_dropped(), 2);
+    }
+}
@@ -1,55 +1,216 @@
 use async_std::stream::Stream;
+use blake2_rfc::blake2b::Blake2b;
 use std::fmt;
 use std::io;
 use std::net::SocketAddr;
+use std::time::Instant;
 
+use crate::runtime;
+
+mod cache;
 pub mod combined;
 pub mod dht;
+#[cfg(feature = "dns")]
+pub mod dns;
+pub mod gossip;
+pub mod instrument;
+pub mod mainline;
 pub mod mdns;
+#[cfg(feature = "sim")]
+pub mod memory;
+mod merge;
+pub mod nodes;
+pub mod observed;
+pub mod pex;
+pub mod ping;
+pub mod private;
+mod queue;
+pub mod rendezvous;
+mod scheduler;
+pub mod static_peers;
 
 pub type Topic = [u8; 32];
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum DiscoveryMethod {
     Mdns,
     Dht,
+    Cache,
+    Static,
+    Dns,
+    Pex,
+    Gossip,
+    Rendezvous,
+    Mainline,
+    Custom(String),
+}
+
+/// A discovery backend that can be enabled or disabled, see
+/// `CombinedDiscovery::set_enabled`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Dht,
+    Mdns,
+    Static,
+    Dns,
+    Pex,
+    Gossip,
+    Rendezvous,
+    Mainline,
+    /// All backends added with `Hyperswarm::add_discovery`.
+    Custom,
 }
 
+impl Backend {
+    /// Whether the backend talks to hosts outside the local network.
+    pub fn is_external(&self) -> bool {
+        matches!(
+            self,
+            Backend::Dht | Backend::Dns | Backend::Rendezvous | Backend::Mainline
+        )
+    }
+}
+
+/// A peer found by discovery, with where and when it was found.
 #[derive(Clone)]
-pub struct PeerInfo {
-    addr: SocketAddr,
+pub struct DiscoveredPeer {
+    addrs: Vec<SocketAddr>,
     topic: Option<Topic>,
-    discovery_method: DiscoveryMethod,
+    source: DiscoveryMethod,
+    sources: Vec<DiscoveryMethod>,
+    first_seen: Instant,
+    referrer: Option<SocketAddr>,
 }
 
-impl fmt::Debug for PeerInfo {
+impl fmt::Debug for DiscoveredPeer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PeerInfo")
-            .field("addr", &self.addr)
+        f.debug_struct("DiscoveredPeer")
+            .field("addrs", &self.addrs)
             .field(
                 "topic",
                 &self.topic.map(|topic| pretty_hash::fmt(&topic).unwrap()),
             )
-            .field("discovery_method", &self.discovery_method)
+            .field("source", &self.source)
+            .field("sources", &self.sources)
+            .field("first_seen", &self.first_seen)
+            .field("referrer", &self.referrer)
             .finish()
     }
 }
 
-impl PeerInfo {
-    pub fn new(addr: SocketAddr, topic: Option<Topic>, discovery_method: DiscoveryMethod) -> Self {
+impl DiscoveredPeer {
+    pub fn new(addr: SocketAddr, topic: Option<Topic>, source: DiscoveryMethod) -> Self {
         Self {
-            addr,
+            addrs: vec![addr],
             topic,
-            discovery_method,
+            sources: vec![source.clone()],
+            source,
+            first_seen: runtime::now(),
+            referrer: None,
         }
     }
 
+    /// Set the peer that told us about this one, e.g. in a peer exchange.
+    pub fn with_referrer(mut self, referrer: SocketAddr) -> Self {
+        self.referrer = Some(referrer);
+        self
+    }
+
+    /// The address to dial, the first of `addrs()`.
     pub fn addr(&self) -> SocketAddr {
-        self.addr
+        self.addrs[0]
+    }
+
+    /// All addresses this peer was reported with.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn topic(&self) -> Option<Topic> {
+        self.topic
+    }
+
+    /// The backend that reported this peer first.
+    pub fn source(&self) -> &DiscoveryMethod {
+        &self.source
+    }
+
+    /// All backends that reported this peer, the first being `source()`.
+    pub fn sources(&self) -> &[DiscoveryMethod] {
+        &self.sources
+    }
+
+    pub fn first_seen(&self) -> Instant {
+        self.first_seen
+    }
+
+    pub fn referrer(&self) -> Option<SocketAddr> {
+        self.referrer
+    }
+
+    pub(crate) fn merge(&mut self, other: &DiscoveredPeer) {
+        for addr in other.addrs.iter() {
+            if !self.addrs.contains(addr) {
+                self.addrs.push(*addr);
+            }
+        }
+        for source in other.sources.iter() {
+            if !self.sources.contains(source) {
+                self.sources.push(source.clone());
+            }
+        }
+        self.first_seen = self.first_seen.min(other.first_seen);
+        if self.referrer.is_none() {
+            self.referrer = other.referrer;
+        }
+    }
+
+    pub(crate) fn set_topic(&mut self, topic: Option<Topic>) {
+        self.topic = topic;
+    }
+
+    pub(crate) fn set_referrer(&mut self, referrer: Option<SocketAddr>) {
+        self.referrer = referrer;
     }
 }
 
-pub trait Discovery: Stream<Item = io::Result<PeerInfo>> {
+/// Derive the topic that is actually used on the network from a topic and a salt.
+pub fn salt_topic(topic: &Topic, salt: &[u8]) -> Topic {
+    let mut hasher = Blake2b::new(32);
+    hasher.update(salt);
+    hasher.update(topic);
+    let mut salted = [0u8; 32];
+    salted.copy_from_slice(hasher.finalize().as_bytes());
+    salted
+}
+
+/// A source of peers for topics.
+///
+/// Besides the built-in DHT and mDNS backends, custom implementations can be
+/// added to a swarm with `Hyperswarm::add_discovery`. Found peers are yielded
+/// from the stream; the topics passed in are already salted.
+pub trait Discovery: Stream<Item = io::Result<DiscoveredPeer>> {
+    /// Start looking up peers for a topic. Called again on every refresh.
     fn lookup(&mut self, topic: Topic);
+    /// Start announcing this peer for a topic. Called again on every refresh.
     fn announce(&mut self, topic: Topic);
+    /// Stop both announcing and looking up a topic.
+    fn leave(&mut self, topic: Topic);
+}
+
+pub type BoxedDiscovery = Box<dyn Discovery + Send + Unpin>;
+
+#[cfg(test)]
+mod test {
+    use super::salt_topic;
+
+    #[test]
+    fn test_salt_topic() {
+        let topic = [1u8; 32];
+        let a = salt_topic(&topic, b"app-a");
+        let b = salt_topic(&topic, b"app-b");
+        assert_ne!(a, topic);
+        assert_ne!(a, b);
+        assert_eq!(a, salt_topic(&topic, b"app-a"));
+    }
 }
@@ -0,0 +1,143 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::runtime;
+
+/// Nodes that responded within this window are considered good.
+const GOOD_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Nodes that failed this many pings in a row are evicted.
+const MAX_FAILURES: u32 = 3;
+/// Nodes tracked if not configured otherwise.
+const MAX_NODES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeGrade {
+    /// Responded recently.
+    Good,
+    /// Not heard from recently, should be pinged.
+    Questionable,
+    /// Failed repeated pings.
+    Bad,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub addr: SocketAddr,
+    pub grade: NodeGrade,
+    pub last_seen: Option<Instant>,
+    pub rtt: Option<Duration>,
+    pub failures: u32,
+}
+
+/// Health state of the DHT nodes this swarm has talked to.
+#[derive(Debug)]
+pub struct NodeTable {
+    nodes: HashMap<SocketAddr, NodeInfo>,
+    max_nodes: usize,
+    // Nodes dropped to make room since the last call.
+    dropped: u64,
+}
+
+impl Default for NodeTable {
+    fn default() -> Self {
+        Self::with_capacity(MAX_NODES)
+    }
+}
+
+impl NodeTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track at most `max_nodes`. Once full, the worst graded and least
+    /// recently seen node makes room for a new one.
+    pub fn with_capacity(max_nodes: usize) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            max_nodes: max_nodes.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Nodes dropped to make room since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    fn entry(&mut self, addr: SocketAddr) -> &mut NodeInfo {
+        if self.nodes.len() >= self.max_nodes && !self.nodes.contains_key(&addr) {
+            let worst = self
+                .nodes
+                .values()
+                .max_by_key(|node| (node.grade as u8, node.failures, Reverse(node.last_seen)))
+                .map(|node| node.addr);
+            if let Some(worst) = worst {
+                self.nodes.remove(&worst);
+                self.dropped += 1;
+            }
+        }
+        self.nodes.entry(addr).or_insert_with(|| NodeInfo {
+            addr,
+            grade: NodeGrade::Questionable,
+            last_seen: None,
+            rtt: None,
+            failures: 0,
+        })
+    }
+
+    pub fn on_seen(&mut self, addr: SocketAddr) {
+        let node = self.entry(addr);
+        node.grade = NodeGrade::Good;
+        node.last_seen = Some(runtime::now());
+        node.failures = 0;
+    }
+
+    pub fn on_ping(&mut self, addr: SocketAddr, rtt: Option<Duration>) {
+        let node = self.entry(addr);
+        match rtt {
+            Some(rtt) => {
+                node.grade = NodeGrade::Good;
+                node.last_seen = Some(runtime::now());
+                node.rtt = Some(rtt);
+                node.failures = 0;
+            }
+            None => node.failures += 1,
+        }
+    }
+
+    /// Regrade all nodes and evict the bad ones, returning their addresses.
+    pub fn regrade(&mut self) -> Vec<SocketAddr> {
+        let now = runtime::now();
+        let mut evicted = vec![];
+        for node in self.nodes.values_mut() {
+            node.grade = if node.failures >= MAX_FAILURES {
+                NodeGrade::Bad
+            } else {
+                match node.last_seen {
+                    Some(seen) if now.duration_since(seen) < GOOD_WINDOW => NodeGrade::Good,
+                    _ => NodeGrade::Questionable,
+                }
+            };
+            if node.grade == NodeGrade::Bad {
+                evicted.push(node.addr);
+            }
+        }
+        for addr in evicted.iter() {
+            self.nodes.remove(addr);
+        }
+        evicted
+    }
+
+    pub fn questionable(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.nodes
+            .values()
+            .filter(|node| node.grade == NodeGrade::Questionable)
+            .map(|node| node.addr)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeInfo> {
+        self.nodes.values()
+    }
+}
@@ -0,0 +1,120 @@
+//! Learning this node's external address from what connected peers observe.
+//!
+//! Over an established stream, each side writes the address it sees the
+//! other side connecting from and reads back what the other side saw. Once
+//! enough distinct peers agree on an IP, it is taken as the external address.
+
+use futures_lite::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::pex::{encode_addr, read_addr};
+use crate::runtime;
+
+/// Distinct reporters that must agree on an IP before it is believed.
+const MIN_REPORTERS: usize = 2;
+const MAX_REPORTERS: usize = 64;
+/// Observations older than this are ignored, as addresses change.
+const OBSERVATION_TTL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Default)]
+struct ObservedState {
+    // Latest observation per reporting IP, so one peer cannot outvote others
+    // by connecting repeatedly.
+    observations: HashMap<IpAddr, (IpAddr, Instant)>,
+}
+
+/// Handle to exchange and aggregate observed addresses.
+#[derive(Debug, Clone)]
+pub struct ObservedAddrs {
+    state: Arc<Mutex<ObservedState>>,
+    local_port: u16,
+}
+
+impl ObservedAddrs {
+    /// `local_port` is the port peers can connect to this node on.
+    pub fn new(local_port: u16) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ObservedState::default())),
+            local_port,
+        }
+    }
+
+    /// Record that `reporter` saw this node connect from `observed`.
+    pub fn observe(&self, reporter: SocketAddr, observed: SocketAddr) {
+        let ip = observed.ip();
+        if ip.is_unspecified() || ip.is_loopback() || reporter.ip() == ip {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let now = runtime::now();
+        state
+            .observations
+            .retain(|_, (_, seen)| now.duration_since(*seen) < OBSERVATION_TTL);
+        if state.observations.len() >= MAX_REPORTERS
+            && !state.observations.contains_key(&reporter.ip())
+        {
+            return;
+        }
+        state.observations.insert(reporter.ip(), (ip, now));
+    }
+
+    /// The believed external address: the IP most reporters agree on, with
+    /// the local listening port.
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        let state = self.state.lock().unwrap();
+        let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+        for (ip, seen) in state.observations.values() {
+            if runtime::elapsed(*seen) < OBSERVATION_TTL {
+                *votes.entry(*ip).or_default() += 1;
+            }
+        }
+        votes
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_REPORTERS)
+            .max_by_key(|(ip, count)| (*count, *ip))
+            .map(|(ip, _)| SocketAddr::new(ip, self.local_port))
+    }
+
+    /// Tell the remote end of `stream`, connected to `remote_addr`, which
+    /// address it was seen from, and record what it saw for this node.
+    pub async fn exchange<S>(&self, stream: &mut S, remote_addr: SocketAddr) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buf = vec![];
+        encode_addr(&mut buf, &remote_addr);
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+        let observed = read_addr(stream).await?;
+        self.observe(remote_addr, observed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_external_addr_needs_agreement() {
+        let observed = ObservedAddrs::new(4000);
+        let external: SocketAddr = "203.0.113.7:52311".parse().unwrap();
+        observed.observe("198.51.100.1:1".parse().unwrap(), external);
+        observed.observe("198.51.100.1:2".parse().unwrap(), external);
+        assert_eq!(observed.external_addr(), None);
+
+        observed.observe("198.51.100.2:1".parse().unwrap(), external);
+        observed.observe(
+            "198.51.100.3:1".parse().unwrap(),
+            "192.0.2.1:1".parse().unwrap(),
+        );
+        assert_eq!(
+            observed.external_addr(),
+            Some("203.0.113.7:4000".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Peer exchange: peers that are connected share the addresses they know for
+//! common topics.
+//!
+//! The exchange runs over any established stream with [`PexHandle::exchange`].
+//! Each side writes one frame per topic and then reads the other side's
+//! frames; received peers are yielded from [`PexDiscovery`].
+
+use async_std::stream::Stream;
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::queue::PeerQueue;
+use super::{salt_topic, DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+
+const MAX_TOPICS: usize = 16;
+const MAX_PEERS_PER_TOPIC: usize = 32;
+
+#[derive(Debug, Default)]
+struct PexState {
+    // Oldest first.
+    known: HashMap<Topic, VecDeque<SocketAddr>>,
+    joined: HashSet<Topic>,
+    pending_events: PeerQueue,
+    waker: Option<Waker>,
+}
+
+/// Handle to run peer exchanges and record known peers. Topics are the
+/// application's topics; salting, if configured, is applied internally.
+#[derive(Debug, Clone)]
+pub struct PexHandle {
+    state: Arc<Mutex<PexState>>,
+    salt: Option<Vec<u8>>,
+}
+
+impl PexHandle {
+    fn network_topic(&self, topic: &Topic) -> Topic {
+        match self.salt.as_ref() {
+            Some(salt) => salt_topic(topic, salt),
+            None => *topic,
+        }
+    }
+
+    /// Record a peer as known for a topic, to be shared in later exchanges.
+    /// The peer that was recorded first is forgotten if there are too many.
+    pub fn add_known(&self, topic: Topic, addr: SocketAddr) {
+        let topic = self.network_topic(&topic);
+        let mut state = self.state.lock().unwrap();
+        let known = state.known.entry(topic).or_default();
+        known.retain(|peer| *peer != addr);
+        known.push_back(addr);
+        if known.len() > MAX_PEERS_PER_TOPIC {
+            known.pop_front();
+        }
+    }
+
+    /// Stop sharing a peer, e.g. after it could not be reached.
+    pub fn remove_known(&self, addr: &SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        for known in state.known.values_mut() {
+            known.retain(|peer| peer != addr);
+        }
+        state.known.retain(|_, known| !known.is_empty());
+    }
+
+    /// Exchange known peers for `topics` with the remote end of `stream`,
+    /// which is connected to `remote_addr`.
+    pub async fn exchange<S>(
+        &self,
+        stream: &mut S,
+        remote_addr: SocketAddr,
+        topics: &[Topic],
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let topics: Vec<Topic> = topics
+            .iter()
+            .take(MAX_TOPICS)
+            .map(|topic| self.network_topic(topic))
+            .collect();
+        let frames: Vec<Vec<u8>> = {
+            let state = self.state.lock().unwrap();
+            topics
+                .iter()
+                .map(|topic| {
+                    let peers = state.known.get(topic).cloned().unwrap_or_default();
+                    encode_frame(topic, peers.iter())
+                })
+                .collect()
+        };
+        stream.write_all(&[frames.len() as u8]).await?;
+        for frame in frames {
+            stream.write_all(&frame).await?;
+        }
+        stream.flush().await?;
+
+        let mut count = [0u8; 1];
+        stream.read_exact(&mut count).await?;
+        if count[0] as usize > MAX_TOPICS {
+            return Err(invalid_data("too many topics in peer exchange"));
+        }
+        let mut received = vec![];
+        for _ in 0..count[0] {
+            received.push(read_frame(stream).await?);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        for (topic, peers) in received {
+            if !state.joined.contains(&topic) {
+                continue;
+            }
+            for addr in peers {
+                let info = DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Pex)
+                    .with_referrer(remote_addr);
+                state.pending_events.push(info);
+            }
+        }
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+/// Discovery backend yielding peers learned through peer exchange.
+#[derive(Debug)]
+pub struct PexDiscovery {
+    handle: PexHandle,
+}
+
+impl PexDiscovery {
+    pub fn new(salt: Option<Vec<u8>>) -> Self {
+        Self {
+            handle: PexHandle {
+                state: Arc::new(Mutex::new(PexState::default())),
+                salt,
+            },
+        }
+    }
+
+    pub fn handle(&self) -> PexHandle {
+        self.handle.clone()
+    }
+}
+
+impl Discovery for PexDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.handle.state.lock().unwrap().joined.insert(topic);
+    }
+
+    fn announce(&mut self, _topic: Topic) {}
+
+    fn leave(&mut self, topic: Topic) {
+        let mut state = self.handle.state.lock().unwrap();
+        state.joined.remove(&topic);
+        state.known.remove(&topic);
+        state.pending_events.remove_topic(&topic);
+    }
+}
+
+impl Stream for PexDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.handle.state.lock().unwrap();
+        match state.pending_events.pop() {
+            Some(info) => Poll::Ready(Some(Ok(info))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Frame: topic (32 bytes), peer count (1 byte), then per peer the address
+// family (4 or 6), the ip octets and the port (big endian).
+fn encode_frame<'a>(topic: &Topic, peers: impl Iterator<Item = &'a SocketAddr>) -> Vec<u8> {
+    let peers: Vec<&SocketAddr> = peers.take(MAX_PEERS_PER_TOPIC).collect();
+    let mut buf = topic.to_vec();
+    buf.push(peers.len() as u8);
+    for addr in peers {
+        encode_addr(&mut buf, addr);
+    }
+    buf
+}
+
+pub(super) fn encode_addr(buf: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+async fn read_frame<S>(stream: &mut S) -> io::Result<(Topic, Vec<SocketAddr>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut topic = [0u8; 32];
+    stream.read_exact(&mut topic).await?;
+    let mut count = [0u8; 1];
+    stream.read_exact(&mut count).await?;
+    if count[0] as usize > MAX_PEERS_PER_TOPIC {
+        return Err(invalid_data("too many peers in peer exchange"));
+    }
+    let mut peers = vec![];
+    for _ in 0..count[0] {
+        peers.push(read_addr(stream).await?);
+    }
+    Ok((topic, peers))
+}
+
+pub(super) async fn read_addr<S>(stream: &mut S) -> io::Result<SocketAddr>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut family = [0u8; 1];
+    stream.read_exact(&mut family).await?;
+    let ip = match family[0] {
+        4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(invalid_data("invalid address family")),
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+pub(super) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_lite::future::block_on;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let topic = [3u8; 32];
+        let peers: Vec<SocketAddr> = vec![
+            "10.0.0.1:4000".parse().unwrap(),
+            "[fe80::1]:5000".parse().unwrap(),
+        ];
+        let frame = encode_frame(&topic, peers.iter());
+        let mut reader = futures_lite::io::Cursor::new(frame);
+        let (decoded_topic, decoded) = block_on(read_frame(&mut reader)).unwrap();
+        assert_eq!(decoded_topic, topic);
+        assert_eq!(decoded, peers);
+    }
+
+    #[test]
+    fn test_known_peers() {
+        let handle = PexDiscovery::new(None).handle();
+        let topic = [3u8; 32];
+        let addr = |port| SocketAddr::from(([10, 0, 0, 1], port));
+        for port in 0..MAX_PEERS_PER_TOPIC as u16 + 2 {
+            handle.add_known(topic, addr(port));
+        }
+        // Seen again, so it is kept over later peers.
+        handle.add_known(topic, addr(2));
+        handle.add_known(topic, addr(100));
+        handle.remove_known(&addr(5));
+        let state = handle.state.lock().unwrap();
+        let known = &state.known[&topic];
+        assert_eq!(known.len(), MAX_PEERS_PER_TOPIC - 1);
+        assert_eq!(known.front(), Some(&addr(4)));
+        assert!(known.contains(&addr(2)));
+        assert!(!known.contains(&addr(3)) && !known.contains(&addr(5)));
+    }
+}
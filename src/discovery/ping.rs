@@ -0,0 +1,198 @@
+//! Minimal DHT ping, speaking just enough of the dht-rpc wire format to send a
+//! `_ping` query and match its response.
+
+use async_std::channel;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::instrument::{DhtMessage, DhtMessageKind, Direction};
+use crate::runtime::{self, UdpSocket};
+
+const TYPE_QUERY: u64 = 1;
+const TYPE_RESPONSE: u64 = 2;
+const PING_COMMAND: &str = "_ping";
+
+/// The fields of a dht-rpc message that a ping uses.
+#[derive(Debug, Clone, PartialEq)]
+struct RpcMessage {
+    msg_type: Option<u64>,
+    rid: Option<u64>,
+    command: Option<String>,
+}
+
+/// Ping a DHT node and return the round-trip time.
+pub async fn dht_ping(addr: SocketAddr, max_wait: Duration) -> io::Result<Duration> {
+    dht_ping_tapped(addr, max_wait, None).await
+}
+
+/// Like `dht_ping`, sending the query and the messages received from the
+/// node to a tap, see `Config::set_dht_tap`.
+pub async fn dht_ping_tapped(
+    addr: SocketAddr,
+    max_wait: Duration,
+    tap: Option<channel::Sender<DhtMessage>>,
+) -> io::Result<Duration> {
+    let local_addr: SocketAddr = if addr.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(local_addr).await?;
+    let rid = rand_rid();
+    let message = encode_ping(rid);
+    let start = Instant::now();
+    socket.send_to(&message, addr).await?;
+    send_to_tap(tap.as_ref(), Direction::Outbound, addr, &message);
+
+    let mut buf = vec![0u8; 2048];
+    runtime::timeout(max_wait, async {
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await?;
+            if from != addr {
+                continue;
+            }
+            send_to_tap(tap.as_ref(), Direction::Inbound, addr, &buf[..n]);
+            if is_response_to(&buf[..n], rid) {
+                return io::Result::Ok(start.elapsed());
+            }
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DHT ping timed out"))?
+}
+
+fn rand_rid() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    // Request ids are u16 in dht-rpc.
+    1 + (nanos as u64 % 0xfffe)
+}
+
+fn encode_ping(rid: u64) -> Vec<u8> {
+    let mut buf = vec![];
+    // field 1 (type), varint
+    buf.push(1 << 3);
+    encode_varint(TYPE_QUERY, &mut buf);
+    // field 2 (rid), varint
+    buf.push(2 << 3);
+    encode_varint(rid, &mut buf);
+    // field 7 (command), length delimited
+    buf.push((7 << 3) | 2);
+    encode_varint(PING_COMMAND.len() as u64, &mut buf);
+    buf.extend_from_slice(PING_COMMAND.as_bytes());
+    buf
+}
+
+fn send_to_tap(
+    tap: Option<&channel::Sender<DhtMessage>>,
+    direction: Direction,
+    node: SocketAddr,
+    buf: &[u8],
+) {
+    if let Some(tap) = tap {
+        let detail = match decode(buf) {
+            Some(message) => format!("{:?}", message),
+            None => format!("undecodable message of {} bytes", buf.len()),
+        };
+        let _ = tap.try_send(DhtMessage {
+            direction,
+            kind: DhtMessageKind::Ping,
+            target: None,
+            node: Some(node),
+            detail,
+        });
+    }
+}
+
+fn is_response_to(buf: &[u8], rid: u64) -> bool {
+    decode(buf).map_or(false, |message| {
+        message.msg_type == Some(TYPE_RESPONSE) && message.rid == Some(rid)
+    })
+}
+
+fn decode(buf: &[u8]) -> Option<RpcMessage> {
+    let mut message = RpcMessage {
+        msg_type: None,
+        rid: None,
+        command: None,
+    };
+    let mut pos = 0;
+    while pos < buf.len() {
+        let key = decode_varint(buf, &mut pos)?;
+        match key & 0x7 {
+            0 => {
+                let value = decode_varint(buf, &mut pos)?;
+                match key >> 3 {
+                    1 => message.msg_type = Some(value),
+                    2 => message.rid = Some(value),
+                    _ => {}
+                }
+            }
+            2 => {
+                let len = decode_varint(buf, &mut pos)? as usize;
+                let value = buf.get(pos..pos.checked_add(len)?)?;
+                if key >> 3 == 7 {
+                    message.command = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                pos += len;
+            }
+            _ => return None,
+        }
+    }
+    Some(message)
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn decode_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ping_response_matching() {
+        let mut response = vec![];
+        response.push(1 << 3);
+        encode_varint(TYPE_RESPONSE, &mut response);
+        response.push(2 << 3);
+        encode_varint(300, &mut response);
+        response.push((9 << 3) | 2);
+        encode_varint(3, &mut response);
+        response.extend_from_slice(&[1, 2, 3]);
+
+        assert!(is_response_to(&response, 300));
+        assert!(!is_response_to(&response, 301));
+        assert!(!is_response_to(&encode_ping(300), 300));
+        assert!(!is_response_to(&response[..response.len() - 1], 300));
+    }
+
+    #[test]
+    fn test_decode() {
+        let query = decode(&encode_ping(7)).unwrap();
+        assert_eq!(query.msg_type, Some(TYPE_QUERY));
+        assert_eq!(query.rid, Some(7));
+        assert_eq!(query.command.as_deref(), Some(PING_COMMAND));
+        assert!(decode(&[0xff]).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+//! Private topics, only discoverable by parties holding the topic key.
+//!
+//! The topic that is joined is derived from the key with a keyed hash, so the
+//! DHT and every other backend only ever see an identifier that cannot be
+//! linked back to the key or to a guessable topic name. Peers that share the
+//! key derive the same identifier and find each other as usual.
+
+#[cfg(feature = "noise")]
+use argon2::{Algorithm, Argon2, Params, Version};
+use blake2_rfc::blake2b::Blake2b;
+use std::fmt;
+
+use super::Topic;
+
+const CONTEXT: &[u8] = b"hyperswarm-private-topic-v1";
+// Argon2id with the parameters of the key store, see `keys::KeyStore`.
+#[cfg(feature = "noise")]
+const KDF_MEMORY_KIB: u32 = 19 * 1024;
+#[cfg(feature = "noise")]
+const KDF_PASSES: u32 = 2;
+
+/// Secret key of a private topic.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TopicKey([u8; 32]);
+
+impl fmt::Debug for TopicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never log the key itself.
+        f.debug_tuple("TopicKey").field(&"..").finish()
+    }
+}
+
+impl TopicKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Derive a key from a shared passphrase with Argon2id, so that guessing
+    /// the passphrase of an announced topic is slow. The salt is fixed, for
+    /// peers to derive the same key, so weak passphrases are still found by
+    /// a determined observer.
+    #[cfg(feature = "noise")]
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        let params = Params::new(KDF_MEMORY_KIB, KDF_PASSES, 1, Some(32))
+            .expect("valid key derivation parameters");
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), CONTEXT, &mut key)
+            .expect("valid key derivation input");
+        Self(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The topic to join, e.g. with `Hyperswarm::configure`.
+    pub fn topic(&self) -> Topic {
+        let mut hasher = Blake2b::with_key(32, &self.0);
+        hasher.update(CONTEXT);
+        let mut topic = [0u8; 32];
+        topic.copy_from_slice(hasher.finalize().as_bytes());
+        topic
+    }
+}
+
+#[cfg(all(test, feature = "noise"))]
+mod test {
+    use super::TopicKey;
+
+    #[test]
+    fn test_private_topic() {
+        let key = TopicKey::from_passphrase("correct horse");
+        assert_eq!(key, TopicKey::from_passphrase("correct horse"));
+        assert_ne!(&key.topic(), key.as_bytes());
+        assert_eq!(key.topic(), TopicKey::new(*key.as_bytes()).topic());
+        assert_ne!(key.topic(), TopicKey::from_passphrase("battery").topic());
+        assert_eq!(format!("{:?}", key), "TopicKey(\"..\")");
+    }
+}
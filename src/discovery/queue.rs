@@ -0,0 +1,130 @@
+use log::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+use super::{DiscoveredPeer, Topic};
+use crate::redact::redact;
+
+/// Peers queued per backend before the oldest ones are shed.
+const MAX_QUEUED: usize = 1024;
+
+/// Peers found by a backend that the swarm did not take yet.
+///
+/// The queue is bounded, so a topic returning tens of thousands of peers
+/// cannot balloon memory. Once it is full, the oldest peer of the topic with
+/// the most queued peers is shed, so busy topics never crowd out quiet ones.
+/// Peers already queued for a topic are not queued again.
+#[derive(Debug)]
+pub(crate) struct PeerQueue {
+    capacity: usize,
+    peers: VecDeque<DiscoveredPeer>,
+    queued: HashSet<(SocketAddr, Option<Topic>)>,
+    per_topic: HashMap<Option<Topic>, usize>,
+    shed: u64,
+}
+
+impl Default for PeerQueue {
+    fn default() -> Self {
+        Self::new(MAX_QUEUED)
+    }
+}
+
+impl PeerQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            peers: VecDeque::new(),
+            queued: HashSet::new(),
+            per_topic: HashMap::new(),
+            shed: 0,
+        }
+    }
+
+    pub fn push(&mut self, peer: DiscoveredPeer) {
+        if !self.queued.insert((peer.addr(), peer.topic())) {
+            return;
+        }
+        if self.peers.len() >= self.capacity {
+            let busiest = self
+                .per_topic
+                .iter()
+                .max_by_key(|(_, count)| **count)
+                .map(|(topic, _)| *topic);
+            let oldest = busiest
+                .and_then(|topic| self.peers.iter().position(|queued| queued.topic() == topic));
+            if let Some(shed) = oldest.and_then(|i| self.peers.remove(i)) {
+                trace!("shedding discovered peer {}", redact(shed.addr()));
+                self.forget(&shed);
+                self.shed += 1;
+            }
+        }
+        *self.per_topic.entry(peer.topic()).or_default() += 1;
+        self.peers.push_back(peer);
+    }
+
+    pub fn pop(&mut self) -> Option<DiscoveredPeer> {
+        let peer = self.peers.pop_front()?;
+        self.forget(&peer);
+        Some(peer)
+    }
+
+    /// Drop all queued peers of a topic.
+    pub fn remove_topic(&mut self, topic: &Topic) {
+        let topic = Some(*topic);
+        self.peers.retain(|peer| peer.topic() != topic);
+        self.queued.retain(|(_, t)| *t != topic);
+        self.per_topic.remove(&topic);
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Number of peers shed since the last call.
+    pub fn take_shed(&mut self) -> u64 {
+        std::mem::take(&mut self.shed)
+    }
+
+    fn forget(&mut self, peer: &DiscoveredPeer) {
+        self.queued.remove(&(peer.addr(), peer.topic()));
+        if let Some(count) = self.per_topic.get_mut(&peer.topic()) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_topic.remove(&peer.topic());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::discovery::DiscoveryMethod;
+
+    fn peer(port: u16, topic: u8) -> DiscoveredPeer {
+        let addr = SocketAddr::from(([10, 0, 0, 1], port));
+        DiscoveredPeer::new(addr, Some([topic; 32]), DiscoveryMethod::Dht)
+    }
+
+    #[test]
+    fn test_shedding() {
+        let mut queue = PeerQueue::new(4);
+        queue.push(peer(1, 1));
+        queue.push(peer(1, 1));
+        assert_eq!(queue.len(), 1);
+        queue.push(peer(2, 2));
+        for port in 10..20 {
+            queue.push(peer(port, 1));
+        }
+        assert_eq!(queue.len(), 4);
+        assert!(queue.take_shed() > 0);
+        assert_eq!(queue.take_shed(), 0);
+        // The quiet topic keeps its peer, the busy one keeps its newest.
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(popped[0].addr().port(), 2);
+        assert_eq!(popped[3].addr().port(), 19);
+        queue.push(peer(1, 1));
+        queue.remove_topic(&[1; 32]);
+        assert!(queue.pop().is_none());
+    }
+}
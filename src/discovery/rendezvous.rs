@@ -0,0 +1,267 @@
+//! Client for a rendezvous server, for networks where UDP and thus the DHT
+//! are blocked.
+//!
+//! The protocol is line based over TCP. Requests are
+//! `REGISTER <topic> <port> <ttl-secs>`, `UNREGISTER <topic> <port>` and
+//! `DISCOVER <topic>`, with topics in hex. The server answers `OK`,
+//! `PEERS <addr>...` or `ERROR <message>`. Registered peers are reported with
+//! the ip the server saw them connect from.
+
+use async_std::io::BufReader;
+use async_std::stream::Stream;
+use futures::stream::FuturesUnordered;
+use futures_lite::{AsyncBufReadExt, AsyncWriteExt, Future};
+use log::*;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+use crate::runtime::{self, TcpStream};
+
+/// Registrations expire unless refreshed within this time.
+pub const REGISTRATION_TTL: Duration = Duration::from_secs(15 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type RequestFut = Pin<Box<dyn Future<Output = (Topic, io::Result<Response>)> + Send>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Register {
+        topic: Topic,
+        port: u16,
+        ttl: Duration,
+    },
+    Unregister {
+        topic: Topic,
+        port: u16,
+    },
+    Discover {
+        topic: Topic,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Ok,
+    Peers(Vec<SocketAddr>),
+    Error(String),
+}
+
+impl Request {
+    pub fn encode(&self) -> String {
+        match self {
+            Request::Register { topic, port, ttl } => format!(
+                "REGISTER {} {} {}\n",
+                hex::encode(topic),
+                port,
+                ttl.as_secs()
+            ),
+            Request::Unregister { topic, port } => {
+                format!("UNREGISTER {} {}\n", hex::encode(topic), port)
+            }
+            Request::Discover { topic } => format!("DISCOVER {}\n", hex::encode(topic)),
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let command = parts.next()?;
+        let topic = parse_topic(parts.next()?)?;
+        let request = match command {
+            "REGISTER" => Request::Register {
+                topic,
+                port: parts.next()?.parse().ok()?,
+                ttl: Duration::from_secs(parts.next()?.parse().ok()?),
+            },
+            "UNREGISTER" => Request::Unregister {
+                topic,
+                port: parts.next()?.parse().ok()?,
+            },
+            "DISCOVER" => Request::Discover { topic },
+            _ => return None,
+        };
+        Some(request)
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> String {
+        match self {
+            Response::Ok => "OK\n".to_string(),
+            Response::Peers(peers) => {
+                let peers: Vec<String> = peers.iter().map(|addr| addr.to_string()).collect();
+                format!("PEERS {}\n", peers.join(" "))
+            }
+            Response::Error(message) => format!("ERROR {}\n", message),
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "OK" => Some(Response::Ok),
+            "PEERS" => rest
+                .split_whitespace()
+                .map(|addr| addr.parse().ok())
+                .collect::<Option<Vec<_>>>()
+                .map(Response::Peers),
+            "ERROR" => Some(Response::Error(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+fn parse_topic(s: &str) -> Option<Topic> {
+    let bytes = hex::decode(s).ok()?;
+    let mut topic = [0u8; 32];
+    if bytes.len() != topic.len() {
+        return None;
+    }
+    topic.copy_from_slice(&bytes);
+    Some(topic)
+}
+
+async fn request(server: SocketAddr, request: Request) -> io::Result<Response> {
+    runtime::timeout(REQUEST_TIMEOUT, send_request(server, request))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "rendezvous request timed out"))?
+}
+
+async fn send_request(server: SocketAddr, request: Request) -> io::Result<Response> {
+    let mut stream = TcpStream::connect(server).await?;
+    stream.write_all(request.encode().as_bytes()).await?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    Response::decode(&line).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid rendezvous server response",
+        )
+    })
+}
+
+/// Discovery backend registering with and querying a rendezvous server.
+#[derive(Debug)]
+pub struct RendezvousDiscovery {
+    server: SocketAddr,
+    local_port: u16,
+    topics: HashSet<Topic>,
+    pending_requests: FuturesUnordered<RequestFut>,
+    pending_events: VecDeque<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl RendezvousDiscovery {
+    pub fn new(server: SocketAddr, local_port: u16) -> Self {
+        Self {
+            server,
+            local_port,
+            topics: HashSet::new(),
+            pending_requests: FuturesUnordered::new(),
+            pending_events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    fn send(&mut self, topic: Topic, req: Request) {
+        let server = self.server;
+        let fut = async move { (topic, request(server, req).await) };
+        self.pending_requests.push(Box::pin(fut));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Discovery for RendezvousDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        self.topics.insert(topic);
+        self.send(topic, Request::Discover { topic });
+    }
+
+    fn announce(&mut self, topic: Topic) {
+        let port = self.local_port;
+        let ttl = REGISTRATION_TTL;
+        self.send(topic, Request::Register { topic, port, ttl });
+    }
+
+    fn leave(&mut self, topic: Topic) {
+        self.topics.remove(&topic);
+        self.pending_events
+            .retain(|info| info.topic() != Some(topic));
+        let port = self.local_port;
+        self.send(topic, Request::Unregister { topic, port });
+    }
+}
+
+impl Stream for RendezvousDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(info) = this.pending_events.pop_front() {
+                return Poll::Ready(Some(Ok(info)));
+            }
+            match Pin::new(&mut this.pending_requests).poll_next(cx) {
+                Poll::Ready(Some((topic, res))) => match res {
+                    Ok(Response::Peers(peers)) if this.topics.contains(&topic) => {
+                        for addr in peers {
+                            let info =
+                                DiscoveredPeer::new(addr, Some(topic), DiscoveryMethod::Rendezvous);
+                            this.pending_events.push_back(info);
+                        }
+                    }
+                    Ok(Response::Error(message)) => {
+                        warn!("rendezvous server error: {}", message);
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                },
+                Poll::Ready(None) | Poll::Pending => {
+                    this.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protocol_roundtrip() {
+        let topic = [9u8; 32];
+        let requests = vec![
+            Request::Register {
+                topic,
+                port: 4000,
+                ttl: Duration::from_secs(60),
+            },
+            Request::Unregister { topic, port: 4000 },
+            Request::Discover { topic },
+        ];
+        for request in requests {
+            assert_eq!(Request::decode(&request.encode()), Some(request));
+        }
+        let responses = vec![
+            Response::Ok,
+            Response::Peers(vec![
+                "10.0.0.1:1".parse().unwrap(),
+                "[::1]:2".parse().unwrap(),
+            ]),
+            Response::Peers(vec![]),
+            Response::Error("nope".into()),
+        ];
+        for response in responses {
+            assert_eq!(Response::decode(&response.encode()), Some(response));
+        }
+    }
+}
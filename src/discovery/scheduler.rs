@@ -0,0 +1,220 @@
+use async_std::stream::Stream;
+use futures_lite::Future;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use super::Topic;
+use crate::runtime;
+use crate::status::ScheduleDump;
+
+/// Interval for topics where no peers were seen in the last round.
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+/// Interval for topics with plenty of peers.
+const MAX_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Number of peers per round at which a topic is considered saturated.
+const SATURATION: usize = 16;
+/// Upper bound for the backoff of topics that stay empty.
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Intervals are randomized by up to this fraction in either direction, so
+/// swarms started together do not query in lockstep.
+const JITTER: f64 = 0.2;
+
+type TimeoutFut = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Refresh {
+    pub topic: Topic,
+    pub announce: bool,
+    pub lookup: bool,
+}
+
+#[derive(Debug)]
+struct TopicSchedule {
+    announce: bool,
+    lookup: bool,
+    peers: HashSet<SocketAddr>,
+    empty_rounds: u32,
+    next: Instant,
+}
+
+/// Schedules repeated announces and lookups per topic.
+///
+/// The interval adapts to the number of distinct peers seen for a topic since
+/// the last round: busy topics back off up to `MAX_INTERVAL`, while topics that
+/// stay empty back off exponentially from `MIN_INTERVAL` up to `MAX_BACKOFF`,
+/// until a peer appears or the topic is refreshed explicitly.
+#[derive(Default)]
+pub struct Scheduler {
+    topics: HashMap<Topic, TopicSchedule>,
+    timer: Option<(Instant, TimeoutFut)>,
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("topics", &self.topics.len())
+            .finish()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a topic to the schedule. The caller is expected to have run the
+    /// first round already, so the first refresh is scheduled after one interval.
+    pub fn schedule(&mut self, topic: Topic, announce: bool, lookup: bool) {
+        let schedule = self.topics.entry(topic).or_insert_with(|| TopicSchedule {
+            announce: false,
+            lookup: false,
+            peers: HashSet::new(),
+            empty_rounds: 0,
+            next: runtime::now() + MIN_INTERVAL,
+        });
+        schedule.announce |= announce;
+        schedule.lookup |= lookup;
+    }
+
+    pub fn remove(&mut self, topic: &Topic) {
+        self.topics.remove(topic);
+    }
+
+    /// All scheduled topics, with what is scheduled for them.
+    pub fn topics(&self) -> impl Iterator<Item = Refresh> + '_ {
+        self.topics.iter().map(|(topic, schedule)| Refresh {
+            topic: *topic,
+            announce: schedule.announce,
+            lookup: schedule.lookup,
+        })
+    }
+
+    /// The schedule of every topic, for `Hyperswarm::debug_dump`.
+    pub fn dump(&self) -> Vec<ScheduleDump> {
+        let now = runtime::now();
+        self.topics
+            .iter()
+            .map(|(topic, schedule)| ScheduleDump {
+                topic: hex::encode(topic),
+                announce: schedule.announce,
+                lookup: schedule.lookup,
+                peers: schedule.peers.len(),
+                empty_rounds: schedule.empty_rounds,
+                next_round: schedule.next.saturating_duration_since(now),
+            })
+            .collect()
+    }
+
+    pub fn on_peer(&mut self, topic: Topic, addr: SocketAddr) {
+        if let Some(schedule) = self.topics.get_mut(&topic) {
+            schedule.peers.insert(addr);
+            schedule.empty_rounds = 0;
+        }
+    }
+
+    /// Run the next round for a topic right away, resetting its backoff.
+    pub fn refresh(&mut self, topic: &Topic) {
+        if let Some(schedule) = self.topics.get_mut(topic) {
+            schedule.empty_rounds = 0;
+            schedule.next = runtime::now();
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.topics.values().map(|schedule| schedule.next).min()
+    }
+}
+
+fn interval_for(peers: usize) -> Duration {
+    let peers = peers.min(SATURATION) as u32;
+    MIN_INTERVAL + (MAX_INTERVAL - MIN_INTERVAL) * peers / SATURATION as u32
+}
+
+fn backoff_for(empty_rounds: u32) -> Duration {
+    let factor = 1u32 << empty_rounds.saturating_sub(1).min(16);
+    (MIN_INTERVAL * factor).min(MAX_BACKOFF)
+}
+
+fn jitter(interval: Duration) -> Duration {
+    let random = runtime::random();
+    let unit = (random % 10_000) as f64 / 10_000.0;
+    interval.mul_f64(1.0 - JITTER + 2.0 * JITTER * unit)
+}
+
+impl Stream for Scheduler {
+    type Item = Refresh;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let now = runtime::now();
+            let due = this
+                .topics
+                .iter_mut()
+                .find(|(_, schedule)| schedule.next <= now);
+            if let Some((topic, schedule)) = due {
+                let interval = if schedule.peers.is_empty() {
+                    schedule.empty_rounds += 1;
+                    backoff_for(schedule.empty_rounds)
+                } else {
+                    interval_for(schedule.peers.len())
+                };
+                schedule.peers.clear();
+                schedule.next = now + jitter(interval);
+                return Poll::Ready(Some(Refresh {
+                    topic: *topic,
+                    announce: schedule.announce,
+                    lookup: schedule.lookup,
+                }));
+            }
+
+            let next = match this.next_due() {
+                Some(next) => next,
+                None => {
+                    this.timer = None;
+                    return Poll::Pending;
+                }
+            };
+            match this.timer.as_ref() {
+                Some((deadline, _)) if *deadline == next => {}
+                _ => {
+                    let sleep = runtime::sleep(next.saturating_duration_since(now));
+                    this.timer = Some((next, sleep));
+                }
+            }
+            let (_, timer) = this.timer.as_mut().unwrap();
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.timer = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interval_adapts_to_peers() {
+        assert_eq!(interval_for(0), MIN_INTERVAL);
+        assert!(interval_for(4) > interval_for(1));
+        assert_eq!(interval_for(SATURATION), MAX_INTERVAL);
+        assert_eq!(interval_for(SATURATION * 10), MAX_INTERVAL);
+    }
+
+    #[test]
+    fn test_backoff_for_empty_topics() {
+        assert_eq!(backoff_for(1), MIN_INTERVAL);
+        assert_eq!(backoff_for(2), MIN_INTERVAL * 2);
+        assert_eq!(backoff_for(3), MIN_INTERVAL * 4);
+        assert_eq!(backoff_for(100), MAX_BACKOFF);
+        for _ in 0..100 {
+            let jittered = jitter(MIN_INTERVAL);
+            assert!(jittered >= MIN_INTERVAL.mul_f64(1.0 - JITTER));
+            assert!(jittered <= MIN_INTERVAL.mul_f64(1.0 + JITTER));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use async_std::stream::Stream;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use super::{DiscoveredPeer, Discovery, DiscoveryMethod, Topic};
+
+/// A peer that is always reported for its topics, or for all topics if
+/// `topics` is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticPeer {
+    pub addr: SocketAddr,
+    pub topics: Vec<Topic>,
+}
+
+impl StaticPeer {
+    pub fn new(addr: SocketAddr, topics: Vec<Topic>) -> Self {
+        Self { addr, topics }
+    }
+
+    fn matches(&self, topic: &Topic) -> bool {
+        self.topics.is_empty() || self.topics.contains(topic)
+    }
+}
+
+/// Read static peers from a file with one peer per line: an address followed
+/// by optional hex-encoded topics. Empty lines and lines starting with `#`
+/// are skipped.
+pub fn read_static_peers(path: impl AsRef<Path>) -> io::Result<Vec<StaticPeer>> {
+    let content = fs::read_to_string(path)?;
+    let mut peers = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let addr = parts
+            .next()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or_else(|| invalid_line(line))?;
+        let topics = parts
+            .map(|topic| parse_topic(topic).ok_or_else(|| invalid_line(line)))
+            .collect::<io::Result<Vec<_>>>()?;
+        peers.push(StaticPeer::new(addr, topics));
+    }
+    Ok(peers)
+}
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid static peer: {}", line),
+    )
+}
+
+fn parse_topic(s: &str) -> Option<Topic> {
+    let bytes = hex::decode(s).ok()?;
+    let mut topic = [0u8; 32];
+    if bytes.len() != topic.len() {
+        return None;
+    }
+    topic.copy_from_slice(&bytes);
+    Some(topic)
+}
+
+/// Discovery backend reporting a fixed list of peers, for deployments where
+/// the DHT and mDNS are unavailable.
+#[derive(Debug)]
+pub struct StaticDiscovery {
+    peers: Vec<StaticPeer>,
+    pending_events: VecDeque<DiscoveredPeer>,
+    waker: Option<Waker>,
+}
+
+impl StaticDiscovery {
+    pub fn new(peers: Vec<StaticPeer>) -> Self {
+        Self {
+            peers,
+            pending_events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(read_static_peers(path)?))
+    }
+}
+
+impl Discovery for StaticDiscovery {
+    fn lookup(&mut self, topic: Topic) {
+        for peer in self.peers.iter().filter(|peer| peer.matches(&topic)) {
+            let info = DiscoveredPeer::new(peer.addr, Some(topic), DiscoveryMethod::Static);
+            self.pending_events.push_back(info);
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn announce(&mut self, _topic: Topic) {}
+
+    fn leave(&mut self, topic: Topic) {
+        self.pending_events
+            .retain(|info| info.topic() != Some(topic));
+    }
+}
+
+impl Stream for StaticDiscovery {
+    type Item = io::Result<DiscoveredPeer>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.pending_events.pop_front() {
+            Some(info) => Poll::Ready(Some(Ok(info))),
+            None => {
+                this.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
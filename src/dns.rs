@@ -0,0 +1,86 @@
+use async_std::net::ToSocketAddrs;
+use log::*;
+use std::io;
+use std::net::SocketAddr;
+
+/// Resolve a list of seed records into socket addresses.
+///
+/// Seeds in the form `host:port` are resolved through A/AAAA records. Seeds
+/// without a port are treated as SRV record names (requires the `dns` feature).
+pub async fn resolve_seeds(seeds: &[String]) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for seed in seeds {
+        let res = if has_port(seed) {
+            resolve_host(seed).await
+        } else {
+            resolve_srv(seed).await
+        };
+        match res {
+            Ok(resolved) => {
+                debug!("resolved seed {} to {:?}", seed, resolved);
+                addrs.extend(resolved);
+            }
+            Err(e) => warn!("failed to resolve seed {}: {}", seed, e),
+        }
+    }
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+fn has_port(seed: &str) -> bool {
+    matches!(seed.rsplit_once(':'), Some((_, port)) if port.parse::<u16>().is_ok())
+}
+
+async fn resolve_host(seed: &str) -> io::Result<Vec<SocketAddr>> {
+    Ok(seed.to_socket_addrs().await?.collect())
+}
+
+#[cfg(feature = "dns")]
+pub(crate) async fn resolve_srv(seed: &str) -> io::Result<Vec<SocketAddr>> {
+    let resolver = resolver().await?;
+    let records = resolver.srv_lookup(seed).await.map_err(to_io_error)?;
+    let mut addrs = vec![];
+    for record in records.iter() {
+        let ips = resolver
+            .lookup_ip(record.target().to_utf8())
+            .await
+            .map_err(to_io_error)?;
+        addrs.extend(ips.iter().map(|ip| SocketAddr::new(ip, record.port())));
+    }
+    Ok(addrs)
+}
+
+#[cfg(not(feature = "dns"))]
+async fn resolve_srv(_seed: &str) -> io::Result<Vec<SocketAddr>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SRV seeds require the dns feature",
+    ))
+}
+
+/// Resolve the TXT records of a name into their strings.
+#[cfg(feature = "dns")]
+pub async fn resolve_txt(name: &str) -> io::Result<Vec<String>> {
+    let resolver = resolver().await?;
+    let records = resolver.txt_lookup(name).await.map_err(to_io_error)?;
+    let mut entries = vec![];
+    for record in records.iter() {
+        for data in record.txt_data() {
+            entries.push(String::from_utf8_lossy(data).into_owned());
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(feature = "dns")]
+pub(crate) async fn resolver() -> io::Result<async_std_resolver::AsyncStdResolver> {
+    async_std_resolver::resolver_from_system_conf()
+        .await
+        .map_err(to_io_error)
+}
+
+#[cfg(feature = "dns")]
+pub(crate) fn to_io_error(e: async_std_resolver::ResolveError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", e))
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io;
+
+/// Why an outgoing connection failed or an established one broke, see
+/// `SwarmEvent::ConnectFailed` and `CloseReason::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectError {
+    /// The remote refused the connection.
+    Refused,
+    /// Connecting, or reading or writing, timed out.
+    TimedOut,
+    /// The connection was reset or aborted by the remote.
+    Reset,
+    /// The handshake failed or timed out.
+    HandshakeFailed(String),
+    /// The remote authenticated with a key other than the one dialed, see
+    /// `Hyperswarm::connect_to_key`.
+    UnexpectedKey,
+    /// The remote address or key is banned.
+    Banned,
+    /// The firewall rejected the connection.
+    Rejected,
+    /// A connection to the same peer is open already, see `DedupPolicy`.
+    Duplicate,
+    /// The swarm is at its connection limit, see `ConnectionLimits`.
+    ConnectionLimit,
+    /// Any other I/O error.
+    Io(io::ErrorKind, String),
+}
+
+impl From<&io::Error> for ConnectError {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => ConnectError::Refused,
+            io::ErrorKind::TimedOut => ConnectError::TimedOut,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => ConnectError::Reset,
+            kind => ConnectError::Io(kind, e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Refused => write!(f, "connection refused"),
+            ConnectError::TimedOut => write!(f, "timed out"),
+            ConnectError::Reset => write!(f, "connection reset"),
+            ConnectError::HandshakeFailed(e) => write!(f, "handshake failed: {}", e),
+            ConnectError::UnexpectedKey => write!(f, "unexpected remote key"),
+            ConnectError::Banned => write!(f, "peer is banned"),
+            ConnectError::Rejected => write!(f, "rejected by firewall"),
+            ConnectError::Duplicate => write!(f, "duplicate connection"),
+            ConnectError::ConnectionLimit => write!(f, "at connection limit"),
+            ConnectError::Io(_, e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_io_error() {
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(ConnectError::from(&refused), ConnectError::Refused);
+        let other = io::Error::new(io::ErrorKind::InvalidData, "bad frame");
+        assert_eq!(
+            ConnectError::from(&other),
+            ConnectError::Io(io::ErrorKind::InvalidData, "bad frame".into())
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use async_std::channel;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use log::*;
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::data::ConnectionData;
+use crate::discovery::{DiscoveredPeer, Topic};
+use crate::error::ConnectError;
+use crate::transport::CloseReason;
+
+const EVENT_BUFFER: usize = 256;
+
+/// Lifecycle events of a swarm, see `Hyperswarm::events`.
+#[derive(Debug, Clone)]
+pub enum SwarmEvent {
+    PeerDiscovered(DiscoveredPeer),
+    Connecting(SocketAddr),
+    /// An outgoing connection failed, or was dropped before it was
+    /// established.
+    ConnectFailed {
+        peer_addr: SocketAddr,
+        error: ConnectError,
+    },
+    ConnectionEstablished {
+        peer_addr: SocketAddr,
+        is_initiator: bool,
+        protocol: String,
+        data: ConnectionData,
+    },
+    ConnectionClosed {
+        peer_addr: SocketAddr,
+        reason: CloseReason,
+        data: ConnectionData,
+    },
+    /// A lost peer is being redialed, see `ReconnectPolicy`.
+    Reconnecting {
+        peer_addr: SocketAddr,
+        attempt: u32,
+    },
+    /// The DHT confirmed an announce for a topic.
+    TopicAnnounced(Topic),
+    /// The first DHT lookup for a topic completed without any peer found
+    /// for it by any backend; mDNS answers arrive well before that. Only
+    /// emitted with the DHT enabled.
+    TopicLookupEmpty(Topic),
+}
+
+impl SwarmEvent {
+    /// The event as a single line JSON object, with its name under `event`,
+    /// addresses as strings and topics hex encoded. Connection data is left
+    /// out, as it holds arbitrary application values.
+    pub fn to_json(&self) -> String {
+        let mut json = JsonObject::new();
+        match self {
+            SwarmEvent::PeerDiscovered(peer) => {
+                json.str("event", "peer_discovered");
+                json.str("peer_addr", &peer.addr().to_string());
+                if let Some(topic) = peer.topic() {
+                    json.str("topic", &hex::encode(topic));
+                }
+                json.str("source", &format!("{:?}", peer.source()));
+            }
+            SwarmEvent::Connecting(addr) => {
+                json.str("event", "connecting");
+                json.str("peer_addr", &addr.to_string());
+            }
+            SwarmEvent::ConnectFailed { peer_addr, error } => {
+                json.str("event", "connect_failed");
+                json.str("peer_addr", &peer_addr.to_string());
+                json.str("error", &error.to_string());
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_addr,
+                is_initiator,
+                protocol,
+                ..
+            } => {
+                json.str("event", "connection_established");
+                json.str("peer_addr", &peer_addr.to_string());
+                json.raw("is_initiator", &is_initiator.to_string());
+                json.str("protocol", protocol);
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_addr, reason, ..
+            } => {
+                json.str("event", "connection_closed");
+                json.str("peer_addr", &peer_addr.to_string());
+                json.str("reason", &format!("{:?}", reason));
+            }
+            SwarmEvent::Reconnecting { peer_addr, attempt } => {
+                json.str("event", "reconnecting");
+                json.str("peer_addr", &peer_addr.to_string());
+                json.raw("attempt", &attempt.to_string());
+            }
+            SwarmEvent::TopicAnnounced(topic) => {
+                json.str("event", "topic_announced");
+                json.str("topic", &hex::encode(topic));
+            }
+            SwarmEvent::TopicLookupEmpty(topic) => {
+                json.str("event", "topic_lookup_empty");
+                json.str("topic", &hex::encode(topic));
+            }
+        }
+        json.finish()
+    }
+}
+
+/// Callbacks for lifecycle events, an alternative to `Hyperswarm::events`
+/// for embedders that cannot own a receiver, see `Hyperswarm::add_observer`.
+///
+/// Callbacks run synchronously on whichever task emits the event, either the
+/// one polling the swarm or the one dropping a connection, so they must not
+/// block. Override `on_event` to see every event, or the methods for the
+/// events of interest.
+#[allow(unused_variables)]
+pub trait SwarmObserver: Send + Sync {
+    fn on_event(&self, event: &SwarmEvent) {
+        match event {
+            SwarmEvent::PeerDiscovered(peer) => self.on_peer_discovered(peer),
+            SwarmEvent::Connecting(addr) => self.on_connecting(*addr),
+            SwarmEvent::ConnectFailed { peer_addr, error } => {
+                self.on_connect_failed(*peer_addr, error)
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_addr,
+                is_initiator,
+                protocol,
+                ..
+            } => self.on_connection_established(*peer_addr, *is_initiator, protocol),
+            SwarmEvent::ConnectionClosed {
+                peer_addr, reason, ..
+            } => self.on_connection_closed(*peer_addr, reason),
+            SwarmEvent::Reconnecting { peer_addr, attempt } => {
+                self.on_reconnecting(*peer_addr, *attempt)
+            }
+            SwarmEvent::TopicAnnounced(topic) => self.on_topic_announced(topic),
+            SwarmEvent::TopicLookupEmpty(topic) => self.on_topic_lookup_empty(topic),
+        }
+    }
+
+    fn on_peer_discovered(&self, peer: &DiscoveredPeer) {}
+
+    fn on_connecting(&self, peer_addr: SocketAddr) {}
+
+    fn on_connect_failed(&self, peer_addr: SocketAddr, error: &ConnectError) {}
+
+    fn on_connection_established(&self, peer_addr: SocketAddr, is_initiator: bool, protocol: &str) {
+    }
+
+    fn on_connection_closed(&self, peer_addr: SocketAddr, reason: &CloseReason) {}
+
+    fn on_reconnecting(&self, peer_addr: SocketAddr, attempt: u32) {}
+
+    fn on_topic_announced(&self, topic: &Topic) {}
+
+    fn on_topic_lookup_empty(&self, topic: &Topic) {}
+}
+
+/// Write events as JSON lines until the writer fails or the swarm is gone,
+/// see `Hyperswarm::write_events_json`. Each line has the time in
+/// milliseconds since the epoch under `time`.
+pub(crate) async fn write_json_lines<W>(
+    events: channel::Receiver<SwarmEvent>,
+    mut writer: W,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Ok(event) = events.recv().await {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut line = event.to_json();
+        line.pop();
+        let _ = write!(line, ",\"time\":{}}}\n", time);
+        writer.write_all(line.as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+// Builds a flat JSON object.
+struct JsonObject(String);
+
+impl JsonObject {
+    fn new() -> Self {
+        Self(String::from("{"))
+    }
+
+    fn raw(&mut self, key: &str, value: &str) {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        let _ = write!(self.0, "\"{}\":{}", key, value);
+    }
+
+    fn str(&mut self, key: &str, value: &str) {
+        let mut quoted = String::from("\"");
+        for c in value.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                c if (c as u32) < 0x20 => {
+                    let _ = write!(quoted, "\\u{:04x}", c as u32);
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        self.raw(key, &quoted);
+    }
+
+    fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+/// Fans events out to all subscribers and observers. Events are dropped for
+/// subscribers that do not keep up, instead of buffering without bound.
+#[derive(Clone, Default)]
+pub(crate) struct EventBus {
+    subscribers: Arc<Mutex<Vec<channel::Sender<SwarmEvent>>>>,
+    observers: Arc<Mutex<Vec<Arc<dyn SwarmObserver>>>>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
+            .field("observers", &self.observers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> channel::Receiver<SwarmEvent> {
+        let (tx, rx) = channel::bounded(EVENT_BUFFER);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn observe(&self, observer: Arc<dyn SwarmObserver>) {
+        self.observers.lock().unwrap().push(observer);
+    }
+
+    pub fn emit(&self, event: SwarmEvent) {
+        // Observers may register further observers.
+        let observers = self.observers.lock().unwrap().clone();
+        for observer in observers.iter() {
+            observer.on_event(&event);
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| match tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(channel::TrySendError::Full(_)) => {
+                trace!("dropping swarm event for slow subscriber");
+                true
+            }
+            Err(channel::TrySendError::Closed(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let event = SwarmEvent::ConnectionClosed {
+            peer_addr: addr,
+            reason: CloseReason::Error(ConnectError::Io(
+                io::ErrorKind::InvalidData,
+                "bad \"frame\"".into(),
+            )),
+            data: ConnectionData::default(),
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"event":"connection_closed","peer_addr":"10.0.0.1:4000","reason":"Error(Io(InvalidData, \"bad \\\"frame\\\"\"))"}"#
+        );
+    }
+
+    #[test]
+    fn test_observers() {
+        #[derive(Default)]
+        struct Announced(Mutex<Vec<Topic>>);
+
+        impl SwarmObserver for Announced {
+            fn on_topic_announced(&self, topic: &Topic) {
+                self.0.lock().unwrap().push(*topic);
+            }
+        }
+
+        let bus = EventBus::default();
+        let first = Arc::new(Announced::default());
+        let second = Arc::new(Announced::default());
+        bus.observe(first.clone());
+        bus.observe(second.clone());
+        bus.emit(SwarmEvent::Connecting("10.0.0.1:4000".parse().unwrap()));
+        bus.emit(SwarmEvent::TopicAnnounced([1u8; 32]));
+        assert_eq!(*first.0.lock().unwrap(), vec![[1u8; 32]]);
+        assert_eq!(*second.0.lock().unwrap(), vec![[1u8; 32]]);
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::discovery::Topic;
+
+type CheckFut = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// The remote end of a new connection, passed to a [`Firewall`].
+#[derive(Debug, Clone)]
+pub struct RemotePeer {
+    pub addr: SocketAddr,
+    pub is_initiator: bool,
+    pub protocol: String,
+    /// The static key the peer authenticated with in the handshake, None
+    /// without the `noise` feature.
+    pub public_key: Option<[u8; 32]>,
+    /// The application payload the peer sent in the handshake, e.g. an auth
+    /// token to check.
+    pub handshake_payload: Vec<u8>,
+    /// Joined topics the peer was found for. For incoming connections, only
+    /// the private topics the peer proved in the handshake, as other topics
+    /// are only exchanged once connected.
+    pub topics: Vec<Topic>,
+}
+
+/// Async callback deciding whether a new connection is handed to the
+/// application. Resolving to false closes the connection.
+///
+/// It runs after the handshake, so checking `RemotePeer::public_key` against
+/// known keys makes a private swarm.
+#[derive(Clone)]
+pub struct Firewall(Arc<dyn Fn(RemotePeer) -> CheckFut + Send + Sync>);
+
+impl Firewall {
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(RemotePeer) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        Self(Arc::new(move |peer| Box::pin(f(peer))))
+    }
+
+    pub fn check(&self, peer: RemotePeer) -> impl Future<Output = bool> + Send {
+        (self.0)(peer)
+    }
+}
+
+impl fmt::Debug for Firewall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Firewall").finish()
+    }
+}
@@ -0,0 +1,100 @@
+use log::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::redact::redact;
+use crate::runtime;
+
+/// The first failure greylists an IP for this long, doubling with every
+/// further failure.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Failures are forgotten after this long without new ones.
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+struct Entry {
+    failures: u32,
+    last: Instant,
+    until: Instant,
+}
+
+/// IPs whose handshakes failed recently. Incoming connections from them are
+/// dropped before any handshake work, for an escalating time up to the
+/// `HandshakePolicy::greylist` maximum.
+#[derive(Debug)]
+pub(crate) struct Greylist {
+    max_delay: Option<Duration>,
+    entries: HashMap<IpAddr, Entry>,
+}
+
+impl Greylist {
+    pub fn new(max_delay: Option<Duration>) -> Self {
+        Self {
+            max_delay,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Whether connections from an IP may be handshaked now.
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        self.entries
+            .get(ip)
+            .map_or(true, |entry| entry.until <= runtime::now())
+    }
+
+    pub fn on_failed(&mut self, ip: IpAddr) {
+        let max_delay = match self.max_delay {
+            Some(max_delay) => max_delay,
+            None => return,
+        };
+        let now = runtime::now();
+        self.entries
+            .retain(|_, entry| now - entry.last < FORGET_AFTER);
+        let entry = self.entries.entry(ip).or_insert(Entry {
+            failures: 0,
+            last: now,
+            until: now,
+        });
+        entry.failures += 1;
+        entry.last = now;
+        let delay = BASE_DELAY
+            .checked_mul(1 << (entry.failures - 1).min(16))
+            .map_or(max_delay, |delay| delay.min(max_delay));
+        entry.until = now + delay;
+        debug!(
+            "greylisting {} for {:?} after {} failed handshakes",
+            redact(ip),
+            delay,
+            entry.failures
+        );
+    }
+
+    pub fn on_success(&mut self, ip: &IpAddr) {
+        self.entries.remove(ip);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escalation() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut greylist = Greylist::new(Some(Duration::from_secs(3)));
+        assert!(greylist.allows(&ip));
+        greylist.on_failed(ip);
+        assert!(!greylist.allows(&ip));
+        greylist.on_failed(ip);
+        greylist.on_failed(ip);
+        let until = greylist.entries[&ip].until;
+        assert!(until - greylist.entries[&ip].last <= Duration::from_secs(3));
+        greylist.on_success(&ip);
+        assert!(greylist.allows(&ip));
+
+        let mut disabled = Greylist::new(None);
+        disabled.on_failed(ip);
+        assert!(disabled.allows(&ip));
+    }
+}
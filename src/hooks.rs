@@ -0,0 +1,71 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::peer::PeerInfo;
+use crate::runtime::Spawner;
+use crate::transport::CloseReason;
+
+type HookFut = Pin<Box<dyn Future<Output = ()> + Send>>;
+type OpenHook = Arc<dyn Fn(PeerInfo) -> HookFut + Send + Sync>;
+type CloseHook = Arc<dyn Fn(PeerInfo, CloseReason) -> HookFut + Send + Sync>;
+
+/// Async callbacks run when connections open and close, see
+/// `Hyperswarm::on_open`. Every call is spawned as its own task, so a slow
+/// callback does not hold up the swarm.
+#[derive(Clone, Default)]
+pub(crate) struct LifecycleHooks {
+    open: Arc<Mutex<Vec<OpenHook>>>,
+    close: Arc<Mutex<Vec<CloseHook>>>,
+    spawner: Spawner,
+}
+
+impl LifecycleHooks {
+    pub fn new(spawner: Spawner) -> Self {
+        Self {
+            spawner,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_open<F, Fut>(&self, f: F)
+    where
+        F: Fn(PeerInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: OpenHook = Arc::new(move |peer| Box::pin(f(peer)));
+        self.open.lock().unwrap().push(hook);
+    }
+
+    pub fn add_close<F, Fut>(&self, f: F)
+    where
+        F: Fn(PeerInfo, CloseReason) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: CloseHook = Arc::new(move |peer, reason| Box::pin(f(peer, reason)));
+        self.close.lock().unwrap().push(hook);
+    }
+
+    pub fn opened(&self, peer: &PeerInfo) {
+        for hook in self.open.lock().unwrap().iter() {
+            self.spawner.spawn("hyperswarm::hook", hook(peer.clone()));
+        }
+    }
+
+    pub fn closed(&self, peer: &PeerInfo, reason: &CloseReason) {
+        for hook in self.close.lock().unwrap().iter() {
+            self.spawner
+                .spawn("hyperswarm::hook", hook(peer.clone(), reason.clone()));
+        }
+    }
+}
+
+impl fmt::Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LifecycleHooks")
+            .field("open", &self.open.lock().unwrap().len())
+            .field("close", &self.close.lock().unwrap().len())
+            .finish()
+    }
+}
@@ -0,0 +1,157 @@
+//! Swarm identities and the keys of known peers.
+//!
+//! A [`KeyStore`] keeps the identity keypair and named peer keys in a
+//! directory, so applications can keep a stable identity and an allowlist
+//! across restarts. With a passphrase both files are encrypted at rest with
+//! ChaCha20-Poly1305 and a random nonce, under a key derived from the
+//! passphrase with Argon2id. That makes guessing slow and memory hungry, but
+//! weak passphrases are still weak.
+
+use blake2_rfc::blake2b::blake2b;
+#[cfg(feature = "noise")]
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_TABLE, edwards::CompressedEdwardsY, scalar::Scalar,
+};
+#[cfg(feature = "noise")]
+use rand::Rng;
+#[cfg(feature = "noise")]
+use sha2::{Digest, Sha512};
+use std::fmt;
+
+use crate::discovery::Topic;
+
+#[cfg(feature = "noise")]
+mod store;
+#[cfg(feature = "noise")]
+pub use store::KeyStore;
+
+/// The Ed25519 keypair a swarm authenticates itself with in the Noise
+/// handshake, its identity like in JS hyperswarm.
+///
+/// Swarms generate a new keypair when bound, unless one is set with
+/// `Config::set_keypair`. Reusing a keypair keeps the same identity across
+/// restarts, so peers can recognize or allowlist it.
+#[derive(Clone, PartialEq)]
+pub struct Keypair {
+    public: [u8; 32],
+    // The seed of the Ed25519 key, the first half of a libsodium secret key.
+    secret: [u8; 32],
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &pretty_hash::fmt(&self.public).unwrap())
+            .finish()
+    }
+}
+
+impl Keypair {
+    /// Generate a random keypair.
+    #[cfg(feature = "noise")]
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill(&mut secret);
+        Self::from_secret_key(secret)
+    }
+
+    /// The keypair of an Ed25519 secret key, e.g. one loaded from disk. This
+    /// is the 32 byte seed, the first half of a libsodium secret key.
+    #[cfg(feature = "noise")]
+    pub fn from_secret_key(secret: [u8; 32]) -> Self {
+        let public = (&secret_scalar(&secret) * &ED25519_BASEPOINT_TABLE)
+            .compress()
+            .to_bytes();
+        Self { public, secret }
+    }
+
+    /// The Ed25519 public key, which peers see as the remote key.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public
+    }
+
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.secret
+    }
+
+    /// The X25519 public key of the same identity, as libsodium's
+    /// `crypto_sign_ed25519_pk_to_curve25519` derives it.
+    #[cfg(feature = "noise")]
+    pub fn x25519_public_key(&self) -> [u8; 32] {
+        CompressedEdwardsY(self.public)
+            .decompress()
+            .expect("public key of a keypair is a valid point")
+            .to_montgomery()
+            .to_bytes()
+    }
+
+    /// The X25519 secret key of the same identity, as libsodium's
+    /// `crypto_sign_ed25519_sk_to_curve25519` derives it.
+    #[cfg(feature = "noise")]
+    pub fn x25519_secret_key(&self) -> [u8; 32] {
+        secret_scalar(&self.secret).to_bytes()
+    }
+
+    /// The scalar both forms of the secret key share.
+    #[cfg(feature = "noise")]
+    pub(crate) fn scalar(&self) -> Scalar {
+        secret_scalar(&self.secret)
+    }
+}
+
+// The clamped first half of the SHA-512 of the seed, like in Ed25519.
+#[cfg(feature = "noise")]
+fn secret_scalar(secret: &[u8; 32]) -> Scalar {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&Sha512::digest(secret)[..32]);
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+/// The topic a swarm's public key is announced under, the BLAKE2b hash of
+/// the key like in hyperdht. See `Hyperswarm::connect_to_key`.
+pub fn key_topic(public_key: &[u8; 32]) -> Topic {
+    let mut topic = [0u8; 32];
+    topic.copy_from_slice(blake2b(32, &[], public_key).as_bytes());
+    topic
+}
+
+#[cfg(all(test, feature = "noise"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_secret_key() {
+        let keypair = Keypair::generate();
+        assert_eq!(Keypair::from_secret_key(keypair.secret_key()), keypair);
+
+        // The first test vector of RFC 8032.
+        let mut secret = [0u8; 32];
+        hex::decode_to_slice(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            &mut secret,
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(Keypair::from_secret_key(secret).public_key()),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+    }
+
+    #[test]
+    fn test_x25519() {
+        use curve25519_dalek::montgomery::MontgomeryPoint;
+
+        let a = Keypair::generate();
+        let b = Keypair::generate();
+        let shared = |secret: [u8; 32], public: [u8; 32]| {
+            (MontgomeryPoint(public) * Scalar::from_bits(secret)).to_bytes()
+        };
+        assert_eq!(
+            shared(a.x25519_secret_key(), b.x25519_public_key()),
+            shared(b.x25519_secret_key(), a.x25519_public_key())
+        );
+    }
+}
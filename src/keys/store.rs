@@ -0,0 +1,278 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::Keypair;
+
+const IDENTITY_FILE: &str = "identity";
+const KNOWN_PEERS_FILE: &str = "known_peers";
+const ENCRYPTED_PREFIX: &str = "encrypted ";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+// Argon2id with 19MiB of memory and two passes, as OWASP recommends.
+const KDF_MEMORY_KIB: u32 = 19 * 1024;
+const KDF_PASSES: u32 = 2;
+
+/// Persists the swarm identity and known peer keys in a directory, see the
+/// module docs.
+///
+/// The identity is stored in `identity` as the hex encoded secret key, known
+/// peers in `known_peers` as lines of `<hex key> <name>`. Encrypted files
+/// hold the hex encoded salt, nonce and ciphertext instead, after a prefix.
+#[derive(Clone)]
+pub struct KeyStore {
+    dir: PathBuf,
+    passphrase: Option<String>,
+}
+
+impl fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("dir", &self.dir)
+            .field("encrypted", &self.passphrase.is_some())
+            .finish()
+    }
+}
+
+impl KeyStore {
+    /// Use a directory as key store, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            passphrase: None,
+        })
+    }
+
+    /// Encrypt files written from now on with a passphrase, and decrypt
+    /// encrypted files read. Unencrypted files are still read, and encrypted
+    /// when next saved.
+    pub fn set_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+
+    /// The stored identity, generating and saving one if there is none yet.
+    pub fn identity(&self) -> io::Result<Keypair> {
+        if let Some(keypair) = self.load_identity()? {
+            return Ok(keypair);
+        }
+        let keypair = Keypair::generate();
+        self.save_identity(&keypair)?;
+        Ok(keypair)
+    }
+
+    pub fn load_identity(&self) -> io::Result<Option<Keypair>> {
+        let content = match self.read(IDENTITY_FILE)? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        let secret =
+            parse_key(content.trim()).ok_or_else(|| invalid_data("invalid identity file"))?;
+        Ok(Some(Keypair::from_secret_key(secret)))
+    }
+
+    pub fn save_identity(&self, keypair: &Keypair) -> io::Result<()> {
+        let content = format!("{}\n", hex::encode(keypair.secret_key()));
+        self.write(IDENTITY_FILE, &content)
+    }
+
+    /// Known peer keys by name.
+    pub fn known_peers(&self) -> io::Result<BTreeMap<String, [u8; 32]>> {
+        let content = match self.read(KNOWN_PEERS_FILE)? {
+            Some(content) => content,
+            None => return Ok(BTreeMap::new()),
+        };
+        let mut peers = BTreeMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().and_then(parse_key);
+            match (key, parts.next()) {
+                (Some(key), Some(name)) => {
+                    peers.insert(name.to_string(), key);
+                }
+                _ => return Err(invalid_data("invalid known peers file")),
+            }
+        }
+        Ok(peers)
+    }
+
+    /// Store the key of a peer under a name, replacing a key of the same
+    /// name.
+    pub fn add_known_peer(&self, name: &str, key: [u8; 32]) -> io::Result<()> {
+        if name.is_empty() || name.contains('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "peer names must be a single non-empty line",
+            ));
+        }
+        let mut peers = self.known_peers()?;
+        peers.insert(name.to_string(), key);
+        self.save_known_peers(&peers)
+    }
+
+    /// Forget a peer. Returns whether it was known.
+    pub fn remove_known_peer(&self, name: &str) -> io::Result<bool> {
+        let mut peers = self.known_peers()?;
+        if peers.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save_known_peers(&peers)?;
+        Ok(true)
+    }
+
+    fn save_known_peers(&self, peers: &BTreeMap<String, [u8; 32]>) -> io::Result<()> {
+        let mut content = String::new();
+        for (name, key) in peers.iter() {
+            content.push_str(&format!("{} {}\n", hex::encode(key), name));
+        }
+        self.write(KNOWN_PEERS_FILE, &content)
+    }
+
+    fn read(&self, file: &str) -> io::Result<Option<String>> {
+        let content = match fs::read_to_string(self.dir.join(file)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !content.starts_with(ENCRYPTED_PREFIX) {
+            return Ok(Some(content));
+        }
+        let passphrase = self.passphrase.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is encrypted, but no passphrase is set", file),
+            )
+        })?;
+        let sealed = hex::decode(content[ENCRYPTED_PREFIX.len()..].trim())
+            .map_err(|_| invalid_data("invalid encrypted file"))?;
+        if sealed.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+            return Err(invalid_data("invalid encrypted file"));
+        }
+        let (salt, sealed) = sealed.split_at(SALT_LEN);
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        // The file name is authenticated too, so files cannot be swapped.
+        let payload = Payload {
+            msg: ciphertext,
+            aad: file.as_bytes(),
+        };
+        let plaintext = cipher(passphrase, salt)?
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| invalid_data("wrong passphrase or corrupted file"))?;
+        String::from_utf8(plaintext)
+            .map(Some)
+            .map_err(|_| invalid_data("invalid encrypted file"))
+    }
+
+    fn write(&self, file: &str, content: &str) -> io::Result<()> {
+        let content = match self.passphrase.as_ref() {
+            None => content.to_string(),
+            Some(passphrase) => {
+                let mut salt = [0u8; SALT_LEN];
+                rand::thread_rng().fill(&mut salt);
+                let mut nonce = [0u8; NONCE_LEN];
+                rand::thread_rng().fill(&mut nonce);
+                let payload = Payload {
+                    msg: content.as_bytes(),
+                    aad: file.as_bytes(),
+                };
+                let ciphertext = cipher(passphrase, &salt)?
+                    .encrypt(Nonce::from_slice(&nonce), payload)
+                    .map_err(|_| invalid_data("encryption failed"))?;
+                format!(
+                    "{}{}{}{}\n",
+                    ENCRYPTED_PREFIX,
+                    hex::encode(salt),
+                    hex::encode(nonce),
+                    hex::encode(ciphertext)
+                )
+            }
+        };
+        let path = self.dir.join(file);
+        let tmp_path = path.with_extension("tmp");
+        write_private(&tmp_path, &content)?;
+        fs::rename(&tmp_path, &path)
+    }
+}
+
+// The cipher under the key derived from a passphrase and salt.
+fn cipher(passphrase: &str, salt: &[u8]) -> io::Result<ChaCha20Poly1305> {
+    let params = Params::new(KDF_MEMORY_KIB, KDF_PASSES, 1, Some(32))
+        .map_err(|_| invalid_data("invalid key derivation parameters"))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| invalid_data("key derivation failed"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+// Key files are only readable by the owner.
+#[cfg(unix)]
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    fs::write(path, content)
+}
+
+fn parse_key(s: &str) -> Option<[u8; 32]> {
+    let bytes = hex::decode(s).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_store() {
+        let dir = std::env::temp_dir().join(format!("hyperswarm-keys-{}", std::process::id()));
+        let passphrase = Some("correct horse".to_string());
+        let store = KeyStore::open(&dir).unwrap().set_passphrase(passphrase);
+        let identity = store.identity().unwrap();
+        store.add_known_peer("alice", [1u8; 32]).unwrap();
+
+        let content = fs::read_to_string(dir.join(IDENTITY_FILE)).unwrap();
+        assert!(content.starts_with(ENCRYPTED_PREFIX));
+        assert_eq!(store.identity().unwrap(), identity);
+        assert_eq!(store.known_peers().unwrap()["alice"], [1u8; 32]);
+
+        let wrong = KeyStore::open(&dir)
+            .unwrap()
+            .set_passphrase(Some("wrong".to_string()));
+        assert!(wrong.load_identity().is_err());
+        assert!(KeyStore::open(&dir).unwrap().known_peers().is_err());
+        // Files are bound to their name.
+        fs::copy(dir.join(KNOWN_PEERS_FILE), dir.join(IDENTITY_FILE)).unwrap();
+        assert!(store.load_identity().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -10,16 +10,62 @@
 #![deny(missing_debug_implementations, nonstandard_style)]
 // #![warn(missing_docs, missing_doc_code_examples, unreachable_pub)]
 
+mod bans;
 mod bootstrap;
 mod config;
+mod data;
+mod dedup;
+mod dial;
+mod dns;
+mod error;
+mod events;
+mod firewall;
+#[cfg(feature = "noise")]
+mod greylist;
+mod hooks;
+mod limits;
+mod metrics;
+mod peer;
+mod reconnect;
+mod redact;
+#[cfg(feature = "rendezvous_server")]
+mod rendezvous_server;
+mod reputation;
+mod runtime;
+mod status;
 mod swarm;
+mod topic;
 
 pub mod discovery;
+pub mod keys;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod transport;
 
+pub use bans::PeerFilter;
 pub use bootstrap::run_bootstrap_node;
-pub use config::{Config, TopicConfig};
+pub use config::{
+    CoalescePolicy, Config, ConnectionLimits, DedupPolicy, EvictionPolicy, HandshakePolicy,
+    KeepalivePolicy, MdnsConfig, ReconnectPolicy, RekeyPolicy, ReputationPolicy, ResourceLimits,
+    SwarmMode, TopicConfig,
+};
+pub use data::ConnectionData;
+pub use error::ConnectError;
+pub use events::{SwarmEvent, SwarmObserver};
+pub use firewall::{Firewall, RemotePeer};
+pub use keys::Keypair;
+pub use metrics::{Histogram, Metrics, TopicMetrics};
+pub use peer::PeerInfo;
+#[cfg(feature = "rendezvous_server")]
+pub use rendezvous_server::run_rendezvous_server;
+pub use reputation::Misbehavior;
+pub use runtime::{JoinHandle, TaskObserver};
+pub use status::{
+    ByteCount, ConnectionCount, ConnectionDump, DebugDump, DhtStatus, DialDump, Health, NodeDump,
+    ScheduleDump, SwarmStatus, TopicStatus,
+};
 pub use swarm::Hyperswarm;
+pub use topic::{TopicExchange, TopicHandle};
 
 use transport::combined::CombinedStream;
 pub use transport::Connection;
@@ -0,0 +1,366 @@
+use log::*;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use crate::config::{ConnectionLimits, EvictionPolicy};
+use crate::data::ConnectionData;
+use crate::redact::redact;
+use crate::runtime;
+use crate::status::{ConnectionCount, ConnectionDump};
+use crate::transport::ConnectionControl;
+
+#[derive(Debug)]
+struct Tracked {
+    is_initiator: bool,
+    protocol: String,
+    control: ConnectionControl,
+}
+
+/// Open connections of a swarm, kept within the configured limits.
+///
+/// Connections are indexed by address, and the counts the limits are checked
+/// against are kept up to date on every change, so the hot paths do not scan
+/// all connections. Connections that closed on their own are only swept out
+/// when a limit seems reached, or before reporting on all connections.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTracker {
+    limits: ConnectionLimits,
+    // Usually one connection per address, more if dedup is off.
+    open: HashMap<SocketAddr, Vec<Tracked>>,
+    inbound: usize,
+    outbound: usize,
+    inbound_per_ip: HashMap<IpAddr, usize>,
+    accepts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl ConnectionTracker {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Whether an incoming connection from an IP stays within the per-IP
+    /// cap and accept rate. Checked before anything else about the
+    /// connection, and counted against the rate even if refused later.
+    pub fn accept_from(&mut self, ip: IpAddr) -> bool {
+        if let Some(max) = self.limits.max_per_ip {
+            let count = |tracker: &Self| tracker.inbound_per_ip.get(&ip).copied().unwrap_or(0);
+            if count(self) >= max {
+                self.prune();
+            }
+            if count(self) >= max {
+                debug!("refusing connection from {}: at per-IP limit", redact(ip));
+                return false;
+            }
+        }
+        if let Some((max, window)) = self.limits.accept_rate {
+            let now = runtime::now();
+            self.accepts
+                .retain(|_, times| times.back().map_or(false, |t| now - *t < window));
+            let times = self.accepts.entry(ip).or_default();
+            while times.front().map_or(false, |t| now - *t >= window) {
+                times.pop_front();
+            }
+            if times.len() >= max {
+                debug!(
+                    "refusing connection from {}: accept rate exceeded",
+                    redact(ip)
+                );
+                return false;
+            }
+            times.push_back(now);
+        }
+        true
+    }
+
+    /// Make room for a new connection, evicting open ones if the policy
+    /// allows. Returns false if the connection should be refused.
+    pub fn admit(&mut self, is_initiator: bool) -> bool {
+        // `Some(None)` if the total is at its cap, `Some(Some(dir))` if the
+        // direction is.
+        while let Some(direction) = self.at_cap_pruned(is_initiator) {
+            if self.limits.eviction == EvictionPolicy::Reject {
+                return false;
+            }
+            let (addr, idx) = match self.victim(direction) {
+                Some(victim) => victim,
+                None => return false,
+            };
+            let conns = self.open.get_mut(&addr).unwrap();
+            let evicted = conns.swap_remove(idx);
+            if conns.is_empty() {
+                self.open.remove(&addr);
+            }
+            self.uncount(addr, evicted.is_initiator);
+            debug!("evicting connection to {}", redact(addr));
+            evicted.control.evict();
+        }
+        true
+    }
+
+    pub fn insert(
+        &mut self,
+        addr: SocketAddr,
+        is_initiator: bool,
+        protocol: &str,
+        control: ConnectionControl,
+    ) {
+        self.open.entry(addr).or_default().push(Tracked {
+            is_initiator,
+            protocol: protocol.to_string(),
+            control,
+        });
+        if is_initiator {
+            self.outbound += 1;
+        } else {
+            self.inbound += 1;
+            *self.inbound_per_ip.entry(addr.ip()).or_default() += 1;
+        }
+    }
+
+    /// Open connections by transport and direction.
+    pub fn counts(&mut self) -> Vec<ConnectionCount> {
+        self.prune();
+        let mut counts: Vec<ConnectionCount> = vec![];
+        for (_, conn) in self.iter() {
+            let idx = match counts.iter().position(|c| c.protocol == conn.protocol) {
+                Some(idx) => idx,
+                None => {
+                    counts.push(ConnectionCount {
+                        protocol: conn.protocol.clone(),
+                        inbound: 0,
+                        outbound: 0,
+                    });
+                    counts.len() - 1
+                }
+            };
+            if conn.is_initiator {
+                counts[idx].outbound += 1;
+            } else {
+                counts[idx].inbound += 1;
+            }
+        }
+        counts
+    }
+
+    /// Bytes read and written so far by open connections, with their
+    /// address and transport.
+    pub fn bytes(&mut self) -> Vec<(SocketAddr, String, (u64, u64))> {
+        self.prune();
+        self.iter()
+            .map(|(addr, conn)| (addr, conn.protocol.clone(), conn.control.bytes()))
+            .collect()
+    }
+
+    pub fn dump(&mut self) -> Vec<ConnectionDump> {
+        self.prune();
+        self.iter()
+            .map(|(addr, conn)| ConnectionDump {
+                addr,
+                protocol: conn.protocol.clone(),
+                outbound: conn.is_initiator,
+                idle_for: conn.control.idle_for(),
+            })
+            .collect()
+    }
+
+    /// Evict open connections to the addresses `f` returns true for.
+    pub fn evict_where<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&SocketAddr) -> bool,
+    {
+        let addrs: Vec<SocketAddr> = self.open.keys().filter(|addr| f(addr)).copied().collect();
+        for addr in addrs {
+            for conn in self.open.remove(&addr).unwrap_or_default() {
+                debug!("evicting connection to {}", redact(addr));
+                conn.control.evict();
+                self.uncount(addr, conn.is_initiator);
+            }
+        }
+    }
+
+    /// Ask all open connections to close.
+    pub fn close_all(&self) {
+        for (_, conn) in self.iter() {
+            conn.control.close();
+        }
+    }
+
+    /// Whether an open connection has a pending read, which sees a close
+    /// once woken.
+    pub fn has_pending_reads(&mut self) -> bool {
+        self.prune();
+        self.iter().any(|(_, conn)| conn.control.is_reading())
+    }
+
+    /// The data of the open connection to an address.
+    pub fn data(&self, addr: &SocketAddr) -> Option<ConnectionData> {
+        self.open
+            .get(addr)?
+            .iter()
+            .find(|conn| !conn.control.is_closed())
+            .map(|conn| conn.control.data().clone())
+    }
+
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.open.get(addr).map_or(false, |conns| {
+            conns.iter().any(|conn| !conn.control.is_closed())
+        })
+    }
+
+    /// Number of open connections.
+    pub fn len(&mut self) -> usize {
+        self.prune();
+        self.inbound + self.outbound
+    }
+
+    /// Whether a new outgoing connection would be admitted without evicting.
+    pub fn can_dial(&mut self) -> bool {
+        self.limits.eviction != EvictionPolicy::Reject || self.at_cap_pruned(true).is_none()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (SocketAddr, &Tracked)> {
+        self.open
+            .iter()
+            .flat_map(|(addr, conns)| conns.iter().map(move |conn| (*addr, conn)))
+    }
+
+    fn prune(&mut self) {
+        let mut closed = vec![];
+        self.open.retain(|addr, conns| {
+            conns.retain(|conn| {
+                let is_closed = conn.control.is_closed();
+                if is_closed {
+                    closed.push((*addr, conn.is_initiator));
+                }
+                !is_closed
+            });
+            !conns.is_empty()
+        });
+        for (addr, is_initiator) in closed {
+            self.uncount(addr, is_initiator);
+        }
+    }
+
+    fn uncount(&mut self, addr: SocketAddr, is_initiator: bool) {
+        if is_initiator {
+            self.outbound -= 1;
+            return;
+        }
+        self.inbound -= 1;
+        if let Some(count) = self.inbound_per_ip.get_mut(&addr.ip()) {
+            *count -= 1;
+            if *count == 0 {
+                self.inbound_per_ip.remove(&addr.ip());
+            }
+        }
+    }
+
+    // Like `at_cap`, but only trusts a reached cap after sweeping out
+    // connections that closed since.
+    fn at_cap_pruned(&mut self, is_initiator: bool) -> Option<Option<bool>> {
+        self.at_cap(is_initiator)?;
+        self.prune();
+        self.at_cap(is_initiator)
+    }
+
+    fn at_cap(&self, is_initiator: bool) -> Option<Option<bool>> {
+        if let Some(max) = self.limits.max_connections {
+            if self.inbound + self.outbound >= max {
+                return Some(None);
+            }
+        }
+        let (max, count) = if is_initiator {
+            (self.limits.max_outbound, self.outbound)
+        } else {
+            (self.limits.max_inbound, self.inbound)
+        };
+        match max {
+            Some(max) if count >= max => Some(Some(is_initiator)),
+            _ => None,
+        }
+    }
+
+    fn victim(&self, direction: Option<bool>) -> Option<(SocketAddr, usize)> {
+        let candidates = self
+            .open
+            .iter()
+            .flat_map(|(addr, conns)| {
+                conns
+                    .iter()
+                    .enumerate()
+                    .map(move |(idx, conn)| ((*addr, idx), conn))
+            })
+            .filter(|(_, conn)| direction.map_or(true, |dir| conn.is_initiator == dir));
+        match self.limits.eviction {
+            EvictionPolicy::Reject => None,
+            EvictionPolicy::Idle => candidates
+                .max_by_key(|(_, conn)| conn.control.idle_for())
+                .map(|(victim, _)| victim),
+            EvictionPolicy::LowestPriority => candidates
+                .min_by_key(|(_, conn)| (conn.is_initiator, Reverse(conn.control.idle_for())))
+                .map(|(victim, _)| victim),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_eviction() {
+        let limits = ConnectionLimits::default().set_max_connections(Some(2));
+        let a = ConnectionControl::default();
+        let b = ConnectionControl::default();
+        {
+            let mut tracker = ConnectionTracker::new(limits.clone());
+            tracker.insert(addr(1), true, "tcp", a.clone());
+            tracker.insert(addr(2), false, "tcp", b.clone());
+            assert!(!tracker.admit(true));
+            assert!(!tracker.can_dial());
+        }
+
+        let limits = limits.set_eviction(EvictionPolicy::LowestPriority);
+        let mut tracker = ConnectionTracker::new(limits);
+        tracker.insert(addr(1), true, "tcp", a.clone());
+        tracker.insert(addr(2), false, "tcp", b.clone());
+        assert!(tracker.admit(true));
+        assert!(b.is_closed());
+        assert!(!a.is_closed());
+        assert_eq!(tracker.len(), 1);
+
+        // Dropped connections free their slot.
+        drop(a);
+        assert!(tracker.admit(true));
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn test_per_ip() {
+        let limits = ConnectionLimits::default()
+            .set_max_per_ip(Some(1))
+            .set_accept_rate(Some((2, Duration::from_secs(60))));
+        let mut tracker = ConnectionTracker::new(limits);
+        let ip = addr(1).ip();
+        let a = ConnectionControl::default();
+        assert!(tracker.accept_from(ip));
+        tracker.insert(addr(1), false, "tcp", a.clone());
+        assert!(!tracker.accept_from(ip));
+
+        drop(a);
+        assert!(tracker.accept_from(ip));
+        // Two accepts within the window used up the rate.
+        assert!(!tracker.accept_from(ip));
+        assert!(tracker.accept_from(SocketAddr::from(([10, 0, 0, 2], 1)).ip()));
+    }
+}
@@ -0,0 +1,696 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::discovery::instrument::{DhtQueryEvent, QueryKind, QueryOutcome};
+use crate::discovery::Topic;
+use crate::runtime;
+
+#[derive(Debug, Default)]
+struct Counters {
+    // Open and opened connections by transport and whether outbound.
+    open: Mutex<BTreeMap<(String, bool), (u64, u64)>>,
+    dials_started: AtomicU64,
+    dials_succeeded: AtomicU64,
+    dials_failed: AtomicU64,
+    handshakes_failed: AtomicU64,
+    lookups: AtomicU64,
+    lookups_coalesced: AtomicU64,
+    announces: AtomicU64,
+    unannounces: AtomicU64,
+    pings: AtomicU64,
+    peers_shed: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    topics: Mutex<BTreeMap<Topic, TopicCounters>>,
+    // Bytes read and written over closed connections, by transport.
+    closed_bytes: Mutex<BTreeMap<String, (u64, u64)>>,
+    dial_latency: Mutex<BTreeMap<String, Histogram>>,
+    handshake_latency: Mutex<BTreeMap<String, Histogram>>,
+    limit_hits: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Upper bounds of the latency histogram buckets, besides the last unbounded
+/// one.
+const LATENCY_BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Durations counted into fixed buckets, see `Metrics::dial_latency`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    /// Counts per bucket of `LATENCY_BUCKETS_MS`, then of longer durations.
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    sum: Duration,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let idx = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|ms| duration <= Duration::from_millis(*ms))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[idx] += 1;
+        self.sum += duration;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The upper bound of each bucket with the number of durations up to
+    /// it, ending with an unbounded bucket that counts all of them.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        let mut total = 0;
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                total += count;
+                let bound = LATENCY_BUCKETS_MS
+                    .get(idx)
+                    .map(|ms| Duration::from_millis(*ms));
+                (bound, total)
+            })
+            .collect()
+    }
+
+    /// The upper bound of the bucket holding the given quantile, e.g. 0.99,
+    /// or None if it is in the unbounded bucket or nothing was observed.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = (q * count as f64).ceil().max(1.0) as u64;
+        self.buckets()
+            .into_iter()
+            .find(|(_, total)| *total >= rank)
+            .and_then(|(bound, _)| bound)
+    }
+}
+
+#[derive(Debug)]
+struct TopicCounters {
+    joined_at: Instant,
+    first_peer_at: Option<Instant>,
+    announced_at: Option<Instant>,
+    discovered: u64,
+    connected: u64,
+    dials_succeeded: u64,
+    dials_failed: u64,
+    closed_bytes: (u64, u64),
+}
+
+/// Health of a joined topic, see `TopicHandle::metrics`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TopicMetrics {
+    /// Distinct peers discovered for the topic.
+    pub peers_discovered: u64,
+    /// Open connections attributed to the topic when they were established.
+    pub peers_connected: u64,
+    /// The share of finished dials to peers of the topic that connected, if
+    /// any finished yet.
+    pub connect_success_rate: Option<f64>,
+    /// Time from joining to the first connection, if any was established.
+    pub time_to_first_peer: Option<Duration>,
+    /// Time since the DHT last confirmed an announce.
+    pub since_announced: Option<Duration>,
+}
+
+impl TopicCounters {
+    fn snapshot(&self) -> TopicMetrics {
+        TopicMetrics {
+            peers_discovered: self.discovered,
+            peers_connected: self.connected,
+            connect_success_rate: match self.dials_succeeded + self.dials_failed {
+                0 => None,
+                total => Some(self.dials_succeeded as f64 / total as f64),
+            },
+            time_to_first_peer: self.first_peer_at.map(|at| at - self.joined_at),
+            since_announced: self.announced_at.map(runtime::elapsed),
+        }
+    }
+}
+
+/// Counters and gauges of a swarm, see `Hyperswarm::metrics`. Cheap to clone
+/// and update from any task.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Counters>);
+
+impl Metrics {
+    /// The share of finished dials that connected, if any finished yet.
+    pub fn dial_success_rate(&self) -> Option<f64> {
+        let succeeded = self.0.dials_succeeded.load(Ordering::Relaxed);
+        let failed = self.0.dials_failed.load(Ordering::Relaxed);
+        match succeeded + failed {
+            0 => None,
+            total => Some(succeeded as f64 / total as f64),
+        }
+    }
+
+    /// DHT lookups served by a running or recent lookup of the same topic,
+    /// instead of a query of their own.
+    pub fn lookups_coalesced(&self) -> u64 {
+        self.0.lookups_coalesced.load(Ordering::Relaxed)
+    }
+
+    /// Discovered peers dropped because the queues to the dialer were full.
+    pub fn peers_shed(&self) -> u64 {
+        self.0.peers_shed.load(Ordering::Relaxed)
+    }
+
+    /// Things dropped for a `ResourceLimits` cap, by the metric key of the
+    /// cap.
+    pub fn limit_hits(&self) -> BTreeMap<String, u64> {
+        self.0.limit_hits.lock().unwrap().clone()
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.0.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.0.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Times from dialing to an established connection, by transport.
+    pub fn dial_latency(&self) -> BTreeMap<String, Histogram> {
+        self.0.dial_latency.lock().unwrap().clone()
+    }
+
+    /// Times from an established connection to a completed handshake, by
+    /// transport.
+    pub fn handshake_latency(&self) -> BTreeMap<String, Histogram> {
+        self.0.handshake_latency.lock().unwrap().clone()
+    }
+
+    /// Metrics of a joined topic.
+    pub fn topic(&self, topic: &Topic) -> Option<TopicMetrics> {
+        let topics = self.0.topics.lock().unwrap();
+        topics.get(topic).map(TopicCounters::snapshot)
+    }
+
+    /// Encode all metrics in the OpenMetrics text format, e.g. to serve them
+    /// to Prometheus.
+    pub fn gather(&self) -> String {
+        let mut out = String::new();
+        let open = self.0.open.lock().unwrap();
+        header(&mut out, "connections", "gauge", "Open connections.");
+        for ((transport, outbound), (count, _)) in open.iter() {
+            let labels = connection_labels(transport, *outbound);
+            let _ = writeln!(out, "hyperswarm_connections{{{}}} {}", labels, count);
+        }
+        header(
+            &mut out,
+            "connections_opened",
+            "counter",
+            "Connections established.",
+        );
+        for ((transport, outbound), (_, total)) in open.iter() {
+            let labels = connection_labels(transport, *outbound);
+            let _ = writeln!(
+                out,
+                "hyperswarm_connections_opened_total{{{}}} {}",
+                labels, total
+            );
+        }
+        drop(open);
+        let counter = |out: &mut String, name: &str, label: &str, value: &AtomicU64| {
+            let _ = writeln!(
+                out,
+                "hyperswarm_{}_total{{{}}} {}",
+                name,
+                label,
+                value.load(Ordering::Relaxed)
+            );
+        };
+        header(
+            &mut out,
+            "dials",
+            "counter",
+            "Outgoing connection attempts.",
+        );
+        counter(
+            &mut out,
+            "dials",
+            "result=\"started\"",
+            &self.0.dials_started,
+        );
+        counter(
+            &mut out,
+            "dials",
+            "result=\"succeeded\"",
+            &self.0.dials_succeeded,
+        );
+        counter(&mut out, "dials", "result=\"failed\"", &self.0.dials_failed);
+        header(
+            &mut out,
+            "handshake_failures",
+            "counter",
+            "Handshakes that failed or timed out.",
+        );
+        let _ = writeln!(
+            out,
+            "hyperswarm_handshake_failures_total {}",
+            self.0.handshakes_failed.load(Ordering::Relaxed)
+        );
+        header(&mut out, "dht_queries", "counter", "DHT queries sent.");
+        counter(&mut out, "dht_queries", "kind=\"lookup\"", &self.0.lookups);
+        counter(
+            &mut out,
+            "dht_queries",
+            "kind=\"announce\"",
+            &self.0.announces,
+        );
+        counter(
+            &mut out,
+            "dht_queries",
+            "kind=\"unannounce\"",
+            &self.0.unannounces,
+        );
+        counter(&mut out, "dht_queries", "kind=\"ping\"", &self.0.pings);
+        header(
+            &mut out,
+            "dht_lookups_coalesced",
+            "counter",
+            "DHT lookups served by a running or recent lookup of the same topic.",
+        );
+        let _ = writeln!(
+            out,
+            "hyperswarm_dht_lookups_coalesced_total {}",
+            self.0.lookups_coalesced.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "peers_shed",
+            "counter",
+            "Discovered peers dropped from full dial queues.",
+        );
+        let _ = writeln!(
+            out,
+            "hyperswarm_peers_shed_total {}",
+            self.0.peers_shed.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "limit_hits",
+            "counter",
+            "Things dropped for a resource limit.",
+        );
+        for (resource, count) in self.limit_hits() {
+            let _ = writeln!(
+                out,
+                "hyperswarm_limit_hits_total{{resource=\"{}\"}} {}",
+                resource, count
+            );
+        }
+        header(&mut out, "bytes", "counter", "Bytes transferred.");
+        counter(&mut out, "bytes", "direction=\"read\"", &self.0.bytes_read);
+        counter(
+            &mut out,
+            "bytes",
+            "direction=\"written\"",
+            &self.0.bytes_written,
+        );
+        gather_histograms(
+            &mut out,
+            "dial_duration_seconds",
+            "Time from dialing to an established connection.",
+            &self.dial_latency(),
+        );
+        gather_histograms(
+            &mut out,
+            "handshake_duration_seconds",
+            "Time from an established connection to a completed handshake.",
+            &self.handshake_latency(),
+        );
+        self.gather_topics(&mut out);
+        out.push_str("# EOF\n");
+        out
+    }
+
+    fn gather_topics(&self, out: &mut String) {
+        let topics: Vec<(String, TopicMetrics)> = self
+            .0
+            .topics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(topic, counters)| (hex::encode(topic), counters.snapshot()))
+            .collect();
+        let family = |out: &mut String,
+                      name: &str,
+                      kind: &str,
+                      help: &str,
+                      value: &dyn Fn(&TopicMetrics) -> Option<f64>| {
+            header(out, name, kind, help);
+            let suffix = if kind == "counter" { "_total" } else { "" };
+            for (topic, metrics) in topics.iter() {
+                if let Some(value) = value(metrics) {
+                    let _ = writeln!(
+                        out,
+                        "hyperswarm_{}{}{{topic=\"{}\"}} {}",
+                        name, suffix, topic, value
+                    );
+                }
+            }
+        };
+        family(
+            out,
+            "topic_peers_discovered",
+            "counter",
+            "Distinct peers discovered per topic.",
+            &|m| Some(m.peers_discovered as f64),
+        );
+        family(
+            out,
+            "topic_peers_connected",
+            "gauge",
+            "Open connections per topic.",
+            &|m| Some(m.peers_connected as f64),
+        );
+        family(
+            out,
+            "topic_connect_success_ratio",
+            "gauge",
+            "Share of finished dials per topic that connected.",
+            &|m| m.connect_success_rate,
+        );
+        family(
+            out,
+            "topic_time_to_first_peer_seconds",
+            "gauge",
+            "Time from joining a topic to its first connection.",
+            &|m| m.time_to_first_peer.map(|d| d.as_secs_f64()),
+        );
+        family(
+            out,
+            "topic_since_announced_seconds",
+            "gauge",
+            "Time since the last confirmed announce per topic.",
+            &|m| m.since_announced.map(|d| d.as_secs_f64()),
+        );
+    }
+
+    pub(crate) fn on_topic_joined(&self, topic: Topic) {
+        let mut topics = self.0.topics.lock().unwrap();
+        topics.entry(topic).or_insert_with(|| TopicCounters {
+            joined_at: runtime::now(),
+            first_peer_at: None,
+            announced_at: None,
+            discovered: 0,
+            connected: 0,
+            dials_succeeded: 0,
+            dials_failed: 0,
+            closed_bytes: (0, 0),
+        });
+    }
+
+    pub(crate) fn on_topic_left(&self, topic: &Topic) {
+        self.0.topics.lock().unwrap().remove(topic);
+    }
+
+    fn with_topic<F: FnOnce(&mut TopicCounters)>(&self, topic: &Topic, f: F) {
+        if let Some(counters) = self.0.topics.lock().unwrap().get_mut(topic) {
+            f(counters);
+        }
+    }
+
+    pub(crate) fn on_topic_discovered(&self, topic: &Topic) {
+        self.with_topic(topic, |counters| counters.discovered += 1);
+    }
+
+    pub(crate) fn on_topic_announced(&self, topic: &Topic) {
+        self.with_topic(topic, |counters| {
+            counters.announced_at = Some(runtime::now())
+        });
+    }
+
+    pub(crate) fn on_topic_dial_finished(&self, topic: &Topic, succeeded: bool) {
+        self.with_topic(topic, |counters| {
+            if succeeded {
+                counters.dials_succeeded += 1;
+            } else {
+                counters.dials_failed += 1;
+            }
+        });
+    }
+
+    pub(crate) fn on_topic_opened(&self, topic: &Topic) {
+        self.with_topic(topic, |counters| {
+            counters.connected += 1;
+            counters.first_peer_at.get_or_insert_with(Instant::now);
+        });
+    }
+
+    pub(crate) fn on_topic_closed(&self, topic: &Topic) {
+        self.with_topic(topic, |counters| {
+            counters.connected = counters.connected.saturating_sub(1)
+        });
+    }
+
+    /// Roll up the bytes of a closed connection.
+    pub(crate) fn on_connection_bytes(&self, protocol: &str, bytes: (u64, u64)) {
+        let mut closed = self.0.closed_bytes.lock().unwrap();
+        add_bytes(closed.entry(protocol.to_string()).or_default(), bytes);
+    }
+
+    /// Roll up the bytes of a closed connection attributed to a topic.
+    pub(crate) fn on_topic_bytes(&self, topic: &Topic, bytes: (u64, u64)) {
+        self.with_topic(topic, |counters| {
+            add_bytes(&mut counters.closed_bytes, bytes)
+        });
+    }
+
+    /// Bytes read and written over closed connections, by transport.
+    pub(crate) fn closed_bytes(&self) -> BTreeMap<String, (u64, u64)> {
+        self.0.closed_bytes.lock().unwrap().clone()
+    }
+
+    pub(crate) fn topic_closed_bytes(&self, topic: &Topic) -> (u64, u64) {
+        let topics = self.0.topics.lock().unwrap();
+        topics
+            .get(topic)
+            .map_or((0, 0), |counters| counters.closed_bytes)
+    }
+
+    pub(crate) fn on_dial_latency(&self, protocol: &str, duration: Duration) {
+        let mut latency = self.0.dial_latency.lock().unwrap();
+        latency
+            .entry(protocol.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub(crate) fn on_handshake_latency(&self, protocol: &str, duration: Duration) {
+        let mut latency = self.0.handshake_latency.lock().unwrap();
+        latency
+            .entry(protocol.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub(crate) fn on_dial(&self) {
+        self.0.dials_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_dial_finished(&self, succeeded: bool) {
+        let counter = if succeeded {
+            &self.0.dials_succeeded
+        } else {
+            &self.0.dials_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_lookups_coalesced(&self, count: u64) {
+        self.0.lookups_coalesced.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_peers_shed(&self, count: u64) {
+        self.0.peers_shed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_limit_hit(&self, resource: &str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        *self
+            .0
+            .limit_hits
+            .lock()
+            .unwrap()
+            .entry(resource.to_string())
+            .or_default() += count;
+    }
+
+    pub(crate) fn on_handshake_failed(&self) {
+        self.0.handshakes_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_opened(&self, protocol: &str, is_initiator: bool) {
+        let mut open = self.0.open.lock().unwrap();
+        let (count, total) = open
+            .entry((protocol.to_string(), is_initiator))
+            .or_default();
+        *count += 1;
+        *total += 1;
+    }
+
+    pub(crate) fn on_closed(&self, protocol: &str, is_initiator: bool) {
+        let mut open = self.0.open.lock().unwrap();
+        if let Some((count, _)) = open.get_mut(&(protocol.to_string(), is_initiator)) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    pub(crate) fn on_dht_query(&self, event: &DhtQueryEvent) {
+        // Pings are only reported once they finish.
+        let counter = match (event.kind, &event.outcome) {
+            (QueryKind::Lookup, QueryOutcome::Started) => &self.0.lookups,
+            (QueryKind::Announce, QueryOutcome::Started) => &self.0.announces,
+            (QueryKind::UnAnnounce, QueryOutcome::Started) => &self.0.unannounces,
+            (QueryKind::Ping, QueryOutcome::Response)
+            | (QueryKind::Ping, QueryOutcome::Failed(_)) => &self.0.pings,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_read(&self, bytes: usize) {
+        self.0.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_written(&self, bytes: usize) {
+        self.0
+            .bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+fn add_bytes(total: &mut (u64, u64), bytes: (u64, u64)) {
+    total.0 += bytes.0;
+    total.1 += bytes.1;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE hyperswarm_{} {}", name, kind);
+    let _ = writeln!(out, "# HELP hyperswarm_{} {}", name, help);
+}
+
+fn gather_histograms(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &BTreeMap<String, Histogram>,
+) {
+    header(out, name, "histogram", help);
+    for (transport, histogram) in histograms.iter() {
+        for (bound, total) in histogram.buckets() {
+            let le = bound.map_or("+Inf".to_string(), |bound| bound.as_secs_f64().to_string());
+            let _ = writeln!(
+                out,
+                "hyperswarm_{}_bucket{{transport=\"{}\",le=\"{}\"}} {}",
+                name, transport, le, total
+            );
+        }
+        let _ = writeln!(
+            out,
+            "hyperswarm_{}_sum{{transport=\"{}\"}} {}",
+            name,
+            transport,
+            histogram.sum().as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "hyperswarm_{}_count{{transport=\"{}\"}} {}",
+            name,
+            transport,
+            histogram.count()
+        );
+    }
+}
+
+fn connection_labels(transport: &str, outbound: bool) -> String {
+    let direction = if outbound { "outbound" } else { "inbound" };
+    format!("transport=\"{}\",direction=\"{}\"", transport, direction)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gather() {
+        let metrics = Metrics::default();
+        metrics.on_dial();
+        metrics.on_dial_finished(true);
+        metrics.on_opened("tcp", true);
+        metrics.on_opened("tcp", true);
+        metrics.on_closed("tcp", true);
+        metrics.on_read(10);
+        let text = metrics.gather();
+        assert!(
+            text.contains("hyperswarm_connections{transport=\"tcp\",direction=\"outbound\"} 1\n")
+        );
+        assert!(text.contains(
+            "hyperswarm_connections_opened_total{transport=\"tcp\",direction=\"outbound\"} 2\n"
+        ));
+        assert!(text.contains("hyperswarm_bytes_total{direction=\"read\"} 10\n"));
+        assert!(text.ends_with("# EOF\n"));
+        assert_eq!(metrics.dial_success_rate(), Some(1.0));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(40));
+        histogram.observe(Duration::from_secs(60));
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(histogram.quantile(1.0), None);
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(buckets.last(), Some(&(None, 3)));
+
+        let metrics = Metrics::default();
+        metrics.on_dial_latency("tcp", Duration::from_millis(3));
+        let text = metrics.gather();
+        assert!(text.contains(
+            "hyperswarm_dial_duration_seconds_bucket{transport=\"tcp\",le=\"0.005\"} 1\n"
+        ));
+        assert!(text.contains("hyperswarm_dial_duration_seconds_count{transport=\"tcp\"} 1\n"));
+    }
+
+    #[test]
+    fn test_topic_metrics() {
+        let metrics = Metrics::default();
+        let topic = [1u8; 32];
+        metrics.on_topic_discovered(&topic);
+        assert_eq!(metrics.topic(&topic), None);
+        metrics.on_topic_joined(topic);
+        metrics.on_topic_discovered(&topic);
+        metrics.on_topic_dial_finished(&topic, false);
+        metrics.on_topic_dial_finished(&topic, true);
+        metrics.on_topic_opened(&topic);
+        let topic_metrics = metrics.topic(&topic).unwrap();
+        assert_eq!(topic_metrics.peers_discovered, 1);
+        assert_eq!(topic_metrics.peers_connected, 1);
+        assert_eq!(topic_metrics.connect_success_rate, Some(0.5));
+        assert!(topic_metrics.time_to_first_peer.is_some());
+        assert_eq!(topic_metrics.since_announced, None);
+        let text = metrics.gather();
+        let label = format!("{{topic=\"{}\"}}", hex::encode(topic));
+        assert!(text.contains(&format!("hyperswarm_topic_peers_connected{} 1\n", label)));
+        assert!(!text.contains("hyperswarm_topic_since_announced_seconds{"));
+        metrics.on_topic_left(&topic);
+        assert_eq!(metrics.topic(&topic), None);
+    }
+}
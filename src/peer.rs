@@ -0,0 +1,146 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::discovery::{DiscoveredPeer, DiscoveryMethod, Topic};
+use crate::runtime;
+
+/// What is known about the remote end of a connection, see
+/// `Connection::peer_info`.
+#[derive(Clone)]
+pub struct PeerInfo {
+    topics: Vec<Topic>,
+    protocol: String,
+    is_initiator: bool,
+    addrs: Vec<SocketAddr>,
+    sources: Vec<DiscoveryMethod>,
+    relayed: bool,
+    remote_public_key: Option<[u8; 32]>,
+    handshake_payload: Vec<u8>,
+    discovered_at: Option<Instant>,
+    connected_at: Instant,
+}
+
+impl fmt::Debug for PeerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let topics: Vec<String> = self
+            .topics
+            .iter()
+            .map(|topic| pretty_hash::fmt(topic).unwrap())
+            .collect();
+        f.debug_struct("PeerInfo")
+            .field("topics", &topics)
+            .field("protocol", &self.protocol)
+            .field("is_initiator", &self.is_initiator)
+            .field("addrs", &self.addrs)
+            .field("sources", &self.sources)
+            .field("relayed", &self.relayed)
+            .field(
+                "remote_public_key",
+                &self
+                    .remote_public_key
+                    .map(|key| pretty_hash::fmt(&key).unwrap()),
+            )
+            .field("handshake_payload", &self.handshake_payload.len())
+            .field("discovered_at", &self.discovered_at)
+            .field("connected_at", &self.connected_at)
+            .finish()
+    }
+}
+
+impl PeerInfo {
+    pub(crate) fn new(peer_addr: SocketAddr, is_initiator: bool, protocol: String) -> Self {
+        Self {
+            topics: vec![],
+            protocol,
+            is_initiator,
+            addrs: vec![peer_addr],
+            sources: vec![],
+            relayed: false,
+            remote_public_key: None,
+            handshake_payload: vec![],
+            discovered_at: None,
+            connected_at: runtime::now(),
+        }
+    }
+
+    /// Joined topics the peer was found for. For incoming connections, only
+    /// the private topics the peer proved in the handshake.
+    pub fn topics(&self) -> &[Topic] {
+        &self.topics
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    pub fn is_initiator(&self) -> bool {
+        self.is_initiator
+    }
+
+    /// All known addresses of the peer, the first being the connected one.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// The backends that reported the peer.
+    pub fn sources(&self) -> &[DiscoveryMethod] {
+        &self.sources
+    }
+
+    /// Whether the connection goes through a relay. Always false, as relaying
+    /// is not supported yet.
+    pub fn is_relayed(&self) -> bool {
+        self.relayed
+    }
+
+    /// The remote static key, once a handshake authenticated the peer.
+    pub fn remote_public_key(&self) -> Option<[u8; 32]> {
+        self.remote_public_key
+    }
+
+    /// The application payload the peer sent in the handshake, see
+    /// `HandshakePolicy::payload`.
+    pub fn handshake_payload(&self) -> &[u8] {
+        &self.handshake_payload
+    }
+
+    pub fn discovered_at(&self) -> Option<Instant> {
+        self.discovered_at
+    }
+
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    pub(crate) fn set_remote_public_key(&mut self, key: [u8; 32]) {
+        self.remote_public_key = Some(key);
+    }
+
+    pub(crate) fn set_handshake_payload(&mut self, payload: Vec<u8>) {
+        self.handshake_payload = payload;
+    }
+
+    pub(crate) fn add_topic(&mut self, topic: Topic) {
+        if !self.topics.contains(&topic) {
+            self.topics.push(topic);
+        }
+    }
+
+    pub(crate) fn merge(&mut self, peer: &DiscoveredPeer) {
+        for addr in peer.addrs() {
+            if !self.addrs.contains(addr) {
+                self.addrs.push(*addr);
+            }
+        }
+        for source in peer.sources() {
+            if !self.sources.contains(source) {
+                self.sources.push(source.clone());
+            }
+        }
+        self.discovered_at = Some(match self.discovered_at {
+            Some(at) => at.min(peer.first_seen()),
+            None => peer.first_seen(),
+        });
+    }
+}
@@ -0,0 +1,154 @@
+use futures::stream::FuturesUnordered;
+use futures_lite::{Future, Stream};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::config::ReconnectPolicy;
+use crate::runtime::TimerWheel;
+
+type TimerFut = Pin<Box<dyn Future<Output = SocketAddr> + Send>>;
+
+/// Redials lost peers with exponential backoff. Yields addresses that are
+/// due for a reconnect.
+pub(crate) struct Reconnects {
+    policy: ReconnectPolicy,
+    attempts: HashMap<SocketAddr, u32>,
+    pinned: HashSet<SocketAddr>,
+    timers: FuturesUnordered<TimerFut>,
+    wheel: TimerWheel,
+}
+
+impl fmt::Debug for Reconnects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnects")
+            .field("policy", &self.policy)
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+impl Reconnects {
+    pub fn new(policy: ReconnectPolicy, wheel: &TimerWheel) -> Self {
+        Self {
+            policy,
+            attempts: HashMap::new(),
+            pinned: HashSet::new(),
+            timers: FuturesUnordered::new(),
+            wheel: wheel.clone(),
+        }
+    }
+
+    /// Schedule a reconnect after a lost connection.
+    pub fn on_lost(&mut self, addr: SocketAddr) {
+        self.attempts.insert(addr, 0);
+        self.schedule(addr);
+    }
+
+    /// Never give up reconnecting to a peer.
+    pub fn pin(&mut self, addr: SocketAddr) {
+        self.pinned.insert(addr);
+    }
+
+    pub fn unpin(&mut self, addr: &SocketAddr) {
+        self.pinned.remove(addr);
+        self.attempts.remove(addr);
+    }
+
+    /// Track a first dial, so that a failure schedules a reconnect.
+    pub fn on_dial(&mut self, addr: SocketAddr) {
+        self.attempts.entry(addr).or_insert(0);
+    }
+
+    /// Schedule the next attempt if a reconnect failed.
+    pub fn on_failed(&mut self, addr: SocketAddr) {
+        if self.attempts.contains_key(&addr) {
+            self.schedule(addr);
+        }
+    }
+
+    pub fn on_connected(&mut self, addr: &SocketAddr) {
+        self.attempts.remove(addr);
+    }
+
+    /// Stop reconnecting to a peer.
+    pub fn cancel(&mut self, addr: &SocketAddr) {
+        self.attempts.remove(addr);
+    }
+
+    /// The number of the attempt that is due for a peer.
+    pub fn attempt(&self, addr: &SocketAddr) -> Option<u32> {
+        self.attempts.get(addr).copied()
+    }
+
+    /// Drop all scheduled attempts, keeping pinned peers.
+    pub fn cancel_all(&mut self) {
+        self.attempts.clear();
+        self.timers = FuturesUnordered::new();
+    }
+
+    pub fn clear(&mut self) {
+        self.attempts.clear();
+        self.pinned.clear();
+        self.timers = FuturesUnordered::new();
+    }
+
+    fn schedule(&mut self, addr: SocketAddr) {
+        let attempt = self.attempts.get_mut(&addr).unwrap();
+        if *attempt >= self.policy.max_attempts && !self.pinned.contains(&addr) {
+            self.attempts.remove(&addr);
+            return;
+        }
+        *attempt += 1;
+        let delay = delay_for(&self.policy, *attempt);
+        let timer = self.wheel.sleep(delay);
+        self.timers.push(Box::pin(async move {
+            timer.await;
+            addr
+        }));
+    }
+}
+
+fn delay_for(policy: &ReconnectPolicy, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    policy
+        .initial_delay
+        .checked_mul(factor)
+        .unwrap_or(policy.max_delay)
+        .min(policy.max_delay)
+}
+
+impl Stream for Reconnects {
+    type Item = SocketAddr;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.timers).poll_next(cx) {
+                // Skip peers that were cancelled or reconnected meanwhile.
+                Poll::Ready(Some(addr)) if self.attempts.contains_key(&addr) => {
+                    return Poll::Ready(Some(addr))
+                }
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let policy = ReconnectPolicy::default()
+            .set_initial_delay(Duration::from_secs(1))
+            .set_max_delay(Duration::from_secs(10));
+        assert_eq!(delay_for(&policy, 1), Duration::from_secs(1));
+        assert_eq!(delay_for(&policy, 3), Duration::from_secs(4));
+        assert_eq!(delay_for(&policy, 5), Duration::from_secs(10));
+        assert_eq!(delay_for(&policy, 40), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The salt of redacted values, or 0 while redaction is off.
+static SALT: AtomicU64 = AtomicU64::new(0);
+
+/// Redact peer addresses and keys in the logs of the whole process from now
+/// on, see `Config::set_redact_logs`. They are replaced by a hash prefix that
+/// stays the same until the process exits, so log lines about one peer can
+/// still be correlated.
+pub(crate) fn enable() {
+    let salt = RandomState::new().build_hasher().finish() | 1;
+    let _ = SALT.compare_exchange(0, salt, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// Wrap a peer address, IP, key or anything derived from one for logging.
+pub(crate) fn redact<T>(value: T) -> Redacted<T> {
+    Redacted(value)
+}
+
+pub(crate) struct Redacted<T>(T);
+
+fn write_hash(f: &mut fmt::Formatter<'_>, salt: u64, rendered: &str) -> fmt::Result {
+    let mut hasher = DefaultHasher::new();
+    salt.hash(&mut hasher);
+    rendered.hash(&mut hasher);
+    write!(f, "#{:08x}", hasher.finish() as u32)
+}
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SALT.load(Ordering::Relaxed) {
+            0 => self.0.fmt(f),
+            salt => write_hash(f, salt, &self.0.to_string()),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SALT.load(Ordering::Relaxed) {
+            0 => self.0.fmt(f),
+            salt => write_hash(f, salt, &format!("{:?}", self.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_redact() {
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let plain = format!("{}", redact(addr));
+        enable();
+        let redacted = format!("{}", redact(addr));
+        // Other tests may have enabled redaction already.
+        assert!(plain == "10.0.0.1:4000" || plain == redacted);
+        assert!(redacted.starts_with('#') && redacted.len() == 9);
+        assert_eq!(redacted, format!("{}", redact(&addr)));
+        assert_ne!(redacted, format!("{:?}", redact(Some(addr))));
+    }
+}
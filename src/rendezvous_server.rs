@@ -0,0 +1,226 @@
+use async_std::io::BufReader;
+use async_std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use async_std::stream::StreamExt;
+use async_std::task::{self, JoinHandle};
+use futures_lite::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use log::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::discovery::rendezvous::{Request, Response};
+use crate::discovery::Topic;
+use crate::redact::redact;
+
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_PEERS_PER_RESPONSE: usize = 64;
+// Requests are short, a REGISTER line is well below this.
+const MAX_LINE_LEN: usize = 256;
+const MAX_REGISTRATIONS_PER_IP: usize = 64;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+type Registrations = Arc<Mutex<State>>;
+
+#[derive(Debug)]
+struct State {
+    topics: HashMap<Topic, HashMap<SocketAddr, Instant>>,
+    per_ip: HashMap<IpAddr, usize>,
+    last_sweep: Instant,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            topics: HashMap::new(),
+            per_ip: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    fn register(&mut self, topic: Topic, addr: SocketAddr, expires: Instant) -> bool {
+        let count = self.per_ip.entry(addr.ip()).or_default();
+        let peers = self.topics.entry(topic).or_default();
+        if !peers.contains_key(&addr) {
+            if *count >= MAX_REGISTRATIONS_PER_IP {
+                if peers.is_empty() {
+                    self.topics.remove(&topic);
+                }
+                return false;
+            }
+            *count += 1;
+        }
+        peers.insert(addr, expires);
+        true
+    }
+
+    fn unregister(&mut self, topic: &Topic, addr: &SocketAddr) {
+        let peers = match self.topics.get_mut(topic) {
+            Some(peers) => peers,
+            None => return,
+        };
+        if peers.remove(addr).is_some() {
+            self.forget(addr.ip());
+        }
+        if peers.is_empty() {
+            self.topics.remove(topic);
+        }
+    }
+
+    // Drop expired registrations, at most once per interval.
+    fn sweep(&mut self, now: Instant) {
+        if now.duration_since(self.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = now;
+        let mut expired = vec![];
+        self.topics.retain(|_, peers| {
+            peers.retain(|addr, expires| {
+                let keep = *expires > now;
+                if !keep {
+                    expired.push(addr.ip());
+                }
+                keep
+            });
+            !peers.is_empty()
+        });
+        for ip in expired {
+            self.forget(ip);
+        }
+    }
+
+    fn forget(&mut self, ip: IpAddr) {
+        if let Some(count) = self.per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Run a rendezvous server for `RendezvousDiscovery` clients.
+pub async fn run_rendezvous_server<A: ToSocketAddrs>(
+    local_addr: A,
+) -> io::Result<(SocketAddr, JoinHandle<io::Result<()>>)> {
+    let listener = TcpListener::bind(local_addr).await?;
+    let addr = listener.local_addr()?;
+    debug!("Running rendezvous server on address: {}", addr);
+    let registrations: Registrations = Arc::new(Mutex::new(State::new()));
+    let task = task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            let stream = stream?;
+            let registrations = registrations.clone();
+            task::spawn(async move {
+                if let Err(e) = on_connection(stream, registrations).await {
+                    debug!("[rendezvous] connection error: {}", e);
+                }
+            });
+        }
+        Ok(())
+    });
+    Ok((addr, task))
+}
+
+async fn on_connection(stream: TcpStream, registrations: Registrations) -> io::Result<()> {
+    let peer_addr = stream.peer_addr()?;
+    let mut writer = stream.clone();
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        let n = (&mut reader)
+            .take(MAX_LINE_LEN as u64)
+            .read_line(&mut line)
+            .await?;
+        if n == 0 {
+            break;
+        }
+        if n == MAX_LINE_LEN && !line.ends_with('\n') {
+            let response = Response::Error("request too long".into());
+            writer.write_all(response.encode().as_bytes()).await?;
+            break;
+        }
+        let response = match Request::decode(&line) {
+            Some(request) => handle(request, peer_addr, &registrations),
+            None => Response::Error("invalid request".into()),
+        };
+        // Requests and responses carry addresses too.
+        trace!(
+            "[rendezvous] {} {:?} -> {:?}",
+            redact(peer_addr),
+            redact(&line),
+            redact(&response)
+        );
+        writer.write_all(response.encode().as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn handle(request: Request, peer_addr: SocketAddr, registrations: &Registrations) -> Response {
+    let mut registrations = registrations.lock().unwrap();
+    let now = Instant::now();
+    registrations.sweep(now);
+    match request {
+        Request::Register { topic, port, ttl } => {
+            let addr = SocketAddr::new(peer_addr.ip(), port);
+            let expires = now + ttl.min(MAX_TTL);
+            if registrations.register(topic, addr, expires) {
+                Response::Ok
+            } else {
+                Response::Error("too many registrations".into())
+            }
+        }
+        Request::Unregister { topic, port } => {
+            let addr = SocketAddr::new(peer_addr.ip(), port);
+            registrations.unregister(&topic, &addr);
+            Response::Ok
+        }
+        Request::Discover { topic } => {
+            let peers = match registrations.topics.get(&topic) {
+                Some(peers) => peers
+                    .iter()
+                    .filter(|(_, expires)| **expires > now)
+                    .map(|(addr, _)| *addr)
+                    .take(MAX_PEERS_PER_RESPONSE)
+                    .collect(),
+                None => vec![],
+            };
+            Response::Peers(peers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registration_limits() {
+        let registrations: Registrations = Arc::new(Mutex::new(State::new()));
+        let peer_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let ttl = Duration::from_secs(60);
+        for port in 0..MAX_REGISTRATIONS_PER_IP as u16 {
+            let topic = [port as u8; 32];
+            let request = Request::Register { topic, port, ttl };
+            assert_eq!(handle(request, peer_addr, &registrations), Response::Ok);
+        }
+        let request = Request::Register {
+            topic: [0u8; 32],
+            port: u16::MAX,
+            ttl,
+        };
+        let response = handle(request, peer_addr, &registrations);
+        assert!(matches!(response, Response::Error(_)));
+
+        let request = Request::Unregister {
+            topic: [1u8; 32],
+            port: 1,
+        };
+        assert_eq!(handle(request, peer_addr, &registrations), Response::Ok);
+        let state = registrations.lock().unwrap();
+        assert_eq!(state.topics.len(), MAX_REGISTRATIONS_PER_IP - 1);
+        assert_eq!(state.per_ip[&peer_addr.ip()], MAX_REGISTRATIONS_PER_IP - 1);
+    }
+}
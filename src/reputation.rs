@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::config::ReputationPolicy;
+use crate::runtime;
+
+/// Records are forgotten after this long without new misbehavior.
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Ways a peer can misbehave, see `Hyperswarm::report_peer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// The peer failed or aborted the connection handshake.
+    FailedHandshake,
+    /// The connection to the peer broke with an error.
+    AbruptDisconnect,
+    /// The peer sent invalid data.
+    ProtocolError,
+}
+
+impl Misbehavior {
+    fn weight(self) -> u32 {
+        match self {
+            Misbehavior::AbruptDisconnect => 1,
+            Misbehavior::FailedHandshake => 2,
+            Misbehavior::ProtocolError => 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    score: u32,
+    last: Instant,
+}
+
+/// Misbehavior of peers, used to dial broken peers last and to ban them
+/// once they cross the policy's threshold.
+#[derive(Debug)]
+pub(crate) struct Reputation {
+    policy: ReputationPolicy,
+    records: HashMap<SocketAddr, Record>,
+    // Incoming connections come from ephemeral ports, so their misbehavior
+    // is recorded by IP.
+    ips: HashMap<IpAddr, Record>,
+}
+
+impl Reputation {
+    pub fn new(policy: ReputationPolicy) -> Self {
+        Self {
+            policy,
+            records: HashMap::new(),
+            ips: HashMap::new(),
+        }
+    }
+
+    /// Record misbehavior and return the new score of the peer, and whether
+    /// it should be banned.
+    pub fn record(&mut self, addr: SocketAddr, misbehavior: Misbehavior) -> (u32, bool) {
+        record(
+            &mut self.records,
+            addr,
+            misbehavior,
+            self.policy.ban_threshold,
+        )
+    }
+
+    /// Like `record`, for misbehavior of an IP.
+    pub fn record_ip(&mut self, ip: IpAddr, misbehavior: Misbehavior) -> (u32, bool) {
+        record(&mut self.ips, ip, misbehavior, self.policy.ban_threshold)
+    }
+
+    pub fn ban_duration(&self) -> Duration {
+        self.policy.ban_duration
+    }
+}
+
+fn record<K>(
+    records: &mut HashMap<K, Record>,
+    key: K,
+    misbehavior: Misbehavior,
+    ban_threshold: Option<u32>,
+) -> (u32, bool)
+where
+    K: Hash + Eq + Copy,
+{
+    records.retain(|_, record| runtime::elapsed(record.last) < FORGET_AFTER);
+    let record = records.entry(key).or_insert(Record {
+        score: 0,
+        last: runtime::now(),
+    });
+    record.score += misbehavior.weight();
+    record.last = runtime::now();
+    let score = record.score;
+    let ban = ban_threshold.map_or(false, |threshold| score >= threshold);
+    if ban {
+        records.remove(&key);
+    }
+    (score, ban)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ban_threshold() {
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let policy = ReputationPolicy::default().set_ban_threshold(Some(5));
+        let mut reputation = Reputation::new(policy);
+        assert_eq!(
+            reputation.record(addr, Misbehavior::AbruptDisconnect),
+            (1, false)
+        );
+        assert_eq!(
+            reputation.record(addr, Misbehavior::ProtocolError),
+            (5, true)
+        );
+        // Banning starts over.
+        assert_eq!(
+            reputation.record(addr, Misbehavior::AbruptDisconnect),
+            (1, false)
+        );
+    }
+}
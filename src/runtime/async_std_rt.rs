@@ -0,0 +1,63 @@
+use async_std::net::{TcpListener, TcpStream, UdpSocket};
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::{Runtime, Sleep, Task};
+
+pub(crate) struct AsyncStd;
+
+#[async_trait]
+impl Runtime for AsyncStd {
+    type TcpStream = TcpStream;
+    type TcpListener = TcpListener;
+    type UdpSocket = UdpSocket;
+
+    fn spawn(name: &'static str, task: Task) {
+        async_std::task::Builder::new()
+            .name(name.to_string())
+            .spawn(task)
+            .expect("failed to spawn task");
+    }
+
+    fn sleep(duration: Duration) -> Sleep {
+        Box::pin(async_std::task::sleep(duration))
+    }
+
+    async fn tcp_connect(addr: SocketAddr) -> io::Result<TcpStream> {
+        TcpStream::connect(addr).await
+    }
+
+    fn tcp_peer_addr(stream: &TcpStream) -> io::Result<SocketAddr> {
+        stream.peer_addr()
+    }
+
+    async fn tcp_bind(addr: SocketAddr) -> io::Result<TcpListener> {
+        TcpListener::bind(addr).await
+    }
+
+    fn tcp_local_addr(listener: &TcpListener) -> io::Result<SocketAddr> {
+        listener.local_addr()
+    }
+
+    async fn tcp_accept(listener: &TcpListener) -> io::Result<(TcpStream, SocketAddr)> {
+        listener.accept().await
+    }
+
+    async fn udp_bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr).await
+    }
+
+    fn udp_from_std(socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
+        Ok(UdpSocket::from(socket))
+    }
+
+    async fn udp_send_to(socket: &UdpSocket, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        socket.send_to(buf, addr).await
+    }
+
+    async fn udp_recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        socket.recv_from(buf).await
+    }
+}
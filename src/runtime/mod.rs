@@ -0,0 +1,595 @@
+//! The async runtime the swarm spawns its tasks, runs its timers and opens
+//! its sockets on.
+//!
+//! This is async-std by default. With the `runtime-tokio` feature it is
+//! tokio, so the swarm can be embedded in tokio applications without running
+//! a second executor; it then has to be bound and polled from within a tokio
+//! runtime. The DHT and uTP internals still run on the runtimes of their own
+//! crates, and so do the standalone bootstrap node and rendezvous server.
+//!
+//! Everything else goes through the [`Runtime`] trait, so supporting another
+//! executor only needs another impl of it and a line selecting it below.
+//! Timeouts, intervals and join handles are built on top of its primitives.
+//!
+//! Spawn-free swarms, see `Config::set_spawn_free`, spawn their tasks into
+//! [`LocalTasks`] instead and run them while being polled. Their timers and
+//! sockets still need the reactor of the runtime, but not its executor.
+//!
+//! Per-connection timers, of keepalives, write coalescing and reconnect
+//! backoff, run on the [`TimerWheel`] of their swarm instead, and so need
+//! only one timer of the runtime.
+//!
+//! Within a simulation of the `sim` feature, timers and [`now`] follow the
+//! virtual clock of the simulation instead of the real one.
+//!
+//! Every task is spawned with a name, which async-std shows in panics and
+//! `task::current`. With the `tracing` feature tasks run in a `task` span
+//! carrying the name, and a [`TaskObserver`] sees tasks of a swarm start
+//! and stop.
+
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite, IoSlice};
+use futures::stream::FuturesUnordered;
+use futures::task::AtomicWaker;
+use futures::FutureExt;
+use futures_channel::oneshot;
+use futures_lite::{ready, Future, Stream};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "runtime-tokio"))]
+mod async_std_rt;
+#[cfg(feature = "runtime-tokio")]
+mod tokio_rt;
+pub(crate) mod wheel;
+
+pub(crate) use wheel::{Timer, TimerWheel};
+
+#[cfg(not(feature = "runtime-tokio"))]
+type Rt = async_std_rt::AsyncStd;
+#[cfg(feature = "runtime-tokio")]
+type Rt = tokio_rt::Tokio;
+
+pub(crate) type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+pub(crate) type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The primitives the swarm needs from an executor and its reactor.
+#[async_trait]
+pub(crate) trait Runtime {
+    type TcpStream: AsyncRead + AsyncWrite + Unpin + Send + Sync + fmt::Debug + 'static;
+    type TcpListener: Send + Sync + fmt::Debug + 'static;
+    type UdpSocket: Send + Sync + fmt::Debug + 'static;
+
+    /// Run a task in the background, detached.
+    fn spawn(name: &'static str, task: Task);
+
+    fn sleep(duration: Duration) -> Sleep;
+
+    async fn tcp_connect(addr: SocketAddr) -> io::Result<Self::TcpStream>;
+
+    fn tcp_peer_addr(stream: &Self::TcpStream) -> io::Result<SocketAddr>;
+
+    async fn tcp_bind(addr: SocketAddr) -> io::Result<Self::TcpListener>;
+
+    fn tcp_local_addr(listener: &Self::TcpListener) -> io::Result<SocketAddr>;
+
+    async fn tcp_accept(listener: &Self::TcpListener) -> io::Result<(Self::TcpStream, SocketAddr)>;
+
+    async fn udp_bind(addr: SocketAddr) -> io::Result<Self::UdpSocket>;
+
+    /// Register a non-blocking socket configured with the std or socket2
+    /// APIs.
+    fn udp_from_std(socket: std::net::UdpSocket) -> io::Result<Self::UdpSocket>;
+
+    async fn udp_send_to(
+        socket: &Self::UdpSocket,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> io::Result<usize>;
+
+    async fn udp_recv_from(
+        socket: &Self::UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)>;
+}
+
+/// A task spawned by the swarm. Awaiting it yields the output of the task,
+/// dropping it lets the task run on detached.
+pub struct JoinHandle<T> {
+    output: oneshot::Receiver<std::thread::Result<T>>,
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle").finish()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match ready!(Pin::new(&mut self.output).poll(cx)) {
+            Ok(Ok(output)) => Poll::Ready(output),
+            Ok(Err(panic)) => panic::resume_unwind(panic),
+            // The runtime dropped the task while shutting down.
+            Err(_) => Poll::Pending,
+        }
+    }
+}
+
+/// Sees the tasks a swarm spawns start and stop, see
+/// `Config::set_task_observer`. For profilers that attribute time to tasks.
+///
+/// Tasks are named after what they do, e.g. `hyperswarm::mdns` or
+/// `hyperswarm::hook`. Hooks are called from within the task, so
+/// thread-local profiler state set in `on_task_start` applies to it.
+#[allow(unused_variables)]
+pub trait TaskObserver: fmt::Debug + Send + Sync {
+    fn on_task_start(&self, name: &'static str) {}
+
+    /// Called when the task finishes, panics or is dropped by the runtime.
+    fn on_task_stop(&self, name: &'static str) {}
+}
+
+// Reports the stop of a task once it is dropped, however it ended.
+struct StopGuard {
+    name: &'static str,
+    observer: Arc<dyn TaskObserver>,
+}
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.observer.on_task_stop(self.name);
+    }
+}
+
+fn named<F>(name: &'static str, observer: Option<Arc<dyn TaskObserver>>, fut: F) -> Task
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let task = async move {
+        let _stop = observer.map(|observer| {
+            observer.on_task_start(name);
+            StopGuard { name, observer }
+        });
+        fut.await
+    };
+    #[cfg(feature = "tracing")]
+    let task = tracing::Instrument::instrument(task, tracing::debug_span!("task", name));
+    Box::pin(task)
+}
+
+// Wrap a future into a named task that reports its output to a join handle.
+fn joinable<F>(
+    name: &'static str,
+    observer: Option<Arc<dyn TaskObserver>>,
+    fut: F,
+) -> (Task, JoinHandle<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (output_tx, output) = oneshot::channel();
+    let task = named(name, observer, async move {
+        let output = AssertUnwindSafe(fut).catch_unwind().await;
+        let _ = output_tx.send(output);
+    });
+    (task, JoinHandle { output })
+}
+
+pub(crate) fn spawn<F>(name: &'static str, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = joinable(name, None, fut);
+    Rt::spawn(name, task);
+    handle
+}
+
+/// Spawns tasks on the runtime, or into the `LocalTasks` it was taken from.
+#[derive(Clone, Default)]
+pub(crate) struct Spawner {
+    local: Option<Arc<LocalQueue>>,
+    observer: Option<Arc<dyn TaskObserver>>,
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("local", &self.local.is_some())
+            .field("observer", &self.observer)
+            .finish()
+    }
+}
+
+impl Spawner {
+    pub fn set_observer(mut self, observer: Option<Arc<dyn TaskObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    pub fn spawn<F>(&self, name: &'static str, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, handle) = joinable(name, self.observer.clone(), fut);
+        match self.local.as_ref() {
+            Some(queue) => {
+                queue.tasks.lock().unwrap().push(task);
+                queue.waker.wake();
+            }
+            None => Rt::spawn(name, task),
+        }
+        handle
+    }
+}
+
+#[derive(Default)]
+struct LocalQueue {
+    tasks: Mutex<Vec<Task>>,
+    waker: AtomicWaker,
+}
+
+/// Tasks run by their owner whenever it is polled, instead of by the
+/// executor.
+#[derive(Default)]
+pub(crate) struct LocalTasks {
+    queue: Arc<LocalQueue>,
+    running: FuturesUnordered<Task>,
+}
+
+impl fmt::Debug for LocalTasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalTasks")
+            .field("running", &self.running.len())
+            .finish()
+    }
+}
+
+impl LocalTasks {
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            local: Some(self.queue.clone()),
+            observer: None,
+        }
+    }
+
+    /// Poll all tasks until they are pending. The waker of `cx` is woken
+    /// when one of them, or a newly spawned one, can make progress.
+    pub fn poll(&mut self, cx: &mut Context<'_>) {
+        self.queue.waker.register(cx.waker());
+        loop {
+            self.running
+                .extend(self.queue.tasks.lock().unwrap().drain(..));
+            match Pin::new(&mut self.running).poll_next(cx) {
+                Poll::Ready(Some(())) => continue,
+                _ if self.queue.tasks.lock().unwrap().is_empty() => return,
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// The background task of a component, either spawned or, for spawn-free
+/// swarms, run inline by the component whenever it calls `poll_inline`.
+/// Awaiting it waits for the task to finish in both cases.
+pub(crate) enum Background {
+    Spawned(JoinHandle<()>),
+    Inline(Option<Task>),
+}
+
+impl fmt::Debug for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::Spawned(_) => write!(f, "Background::Spawned"),
+            Background::Inline(_) => write!(f, "Background::Inline"),
+        }
+    }
+}
+
+impl Background {
+    pub fn new<F>(name: &'static str, fut: F, inline: bool, spawner: &Spawner) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if inline {
+            Background::Inline(Some(named(name, spawner.observer.clone(), fut)))
+        } else {
+            Background::Spawned(spawner.spawn(name, fut))
+        }
+    }
+
+    pub fn poll_inline(&mut self, cx: &mut Context<'_>) {
+        if let Background::Inline(Some(task)) = self {
+            if task.as_mut().poll(cx).is_ready() {
+                *self = Background::Inline(None);
+            }
+        }
+    }
+}
+
+impl Future for Background {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if let Background::Spawned(handle) = this {
+            return Pin::new(handle).poll(cx);
+        }
+        this.poll_inline(cx);
+        match this {
+            Background::Inline(None) => Poll::Ready(()),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    #[cfg(feature = "sim")]
+    {
+        if let Some(sleep) = crate::sim::sleep(duration) {
+            return sleep;
+        }
+    }
+    Rt::sleep(duration)
+}
+
+/// The current time, on the virtual clock within a simulation.
+pub(crate) fn now() -> Instant {
+    #[cfg(feature = "sim")]
+    {
+        if let Some(now) = crate::sim::now() {
+            return now;
+        }
+    }
+    Instant::now()
+}
+
+/// The time passed since `since`, see `now`.
+pub(crate) fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+/// A random number for jitter, from the seeded generator within a
+/// simulation.
+pub(crate) fn random() -> u64 {
+    #[cfg(feature = "sim")]
+    {
+        if let Some(random) = crate::sim::random() {
+            return random;
+        }
+    }
+    RandomState::new().build_hasher().finish()
+}
+
+/// A future did not complete in time, see `timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeoutError;
+
+impl From<TimeoutError> for io::Error {
+    fn from(_: TimeoutError) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, "future timed out")
+    }
+}
+
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    fut: F,
+) -> Result<F::Output, TimeoutError> {
+    let elapsed = async {
+        sleep(duration).await;
+        Err(TimeoutError)
+    };
+    futures_lite::future::or(async { Ok(fut.await) }, elapsed).await
+}
+
+/// A stream yielding every `duration`, first after one `duration` has
+/// passed. Ticks missed while not polled are not made up for.
+pub(crate) struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+impl fmt::Debug for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interval")
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+pub(crate) fn interval(period: Duration) -> Interval {
+    Interval {
+        period,
+        sleep: sleep(period),
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        ready!(self.sleep.as_mut().poll(cx));
+        self.sleep = sleep(self.period);
+        Poll::Ready(Some(()))
+    }
+}
+
+/// A TCP connection on the runtime.
+pub struct TcpStream {
+    inner: <Rt as Runtime>::TcpStream,
+}
+
+impl fmt::Debug for TcpStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl TcpStream {
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let inner = Rt::tcp_connect(addr).await?;
+        Ok(Self { inner })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Rt::tcp_peer_addr(&self.inner)
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A TCP listener on the runtime.
+#[derive(Debug)]
+pub struct TcpListener {
+    inner: <Rt as Runtime>::TcpListener,
+}
+
+impl TcpListener {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let inner = Rt::tcp_bind(addr).await?;
+        Ok(Self { inner })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Rt::tcp_local_addr(&self.inner)
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (inner, addr) = Rt::tcp_accept(&self.inner).await?;
+        Ok((TcpStream { inner }, addr))
+    }
+}
+
+/// A UDP socket on the runtime.
+#[derive(Debug)]
+pub struct UdpSocket {
+    inner: <Rt as Runtime>::UdpSocket,
+}
+
+impl UdpSocket {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let inner = Rt::udp_bind(addr).await?;
+        Ok(Self { inner })
+    }
+
+    /// Register a non-blocking socket configured with the std or socket2
+    /// APIs.
+    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        let inner = Rt::udp_from_std(socket)?;
+        Ok(Self { inner })
+    }
+
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        Rt::udp_send_to(&self.inner, buf, addr).await
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        Rt::udp_recv_from(&self.inner, buf).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_tasks() {
+        let mut tasks = LocalTasks::default();
+        let spawner = tasks.spawner();
+        let nested = spawner.clone();
+        let handle = spawner.spawn(
+            "outer",
+            async move { nested.spawn("inner", async { 42 }).await },
+        );
+        futures::executor::block_on(async {
+            futures_lite::future::poll_fn(|cx| {
+                tasks.poll(cx);
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(handle.await, 42);
+        });
+        assert_eq!(tasks.running.len(), 0);
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl TaskObserver for Recorder {
+        fn on_task_start(&self, name: &'static str) {
+            self.0.lock().unwrap().push(format!("start {}", name));
+        }
+
+        fn on_task_stop(&self, name: &'static str) {
+            self.0.lock().unwrap().push(format!("stop {}", name));
+        }
+    }
+
+    #[test]
+    fn test_task_observer() {
+        let recorder = Arc::new(Recorder::default());
+        let mut tasks = LocalTasks::default();
+        let spawner = tasks.spawner().set_observer(Some(recorder.clone()));
+        let _ = spawner.spawn("done", async {});
+        let _ = spawner.spawn("pending", futures::future::pending::<()>());
+        futures::executor::block_on(futures_lite::future::poll_fn(|cx| {
+            tasks.poll(cx);
+            Poll::Ready(())
+        }));
+        assert_eq!(recorder.0.lock().unwrap().len(), 3);
+        // Dropping the tasks stops the pending one.
+        drop(tasks);
+        let mut events = recorder.0.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(
+            events,
+            ["start done", "start pending", "stop done", "stop pending"]
+        );
+    }
+}
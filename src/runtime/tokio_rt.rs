@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+use super::{Runtime, Sleep, Task};
+
+pub(crate) struct Tokio;
+
+#[async_trait]
+impl Runtime for Tokio {
+    // Tokio streams have their own IO traits.
+    type TcpStream = Compat<TcpStream>;
+    type TcpListener = TcpListener;
+    type UdpSocket = UdpSocket;
+
+    // Naming tokio tasks needs `tokio_unstable`, so the name is only in
+    // the tracing span and observer hooks.
+    fn spawn(_name: &'static str, task: Task) {
+        tokio::spawn(task);
+    }
+
+    fn sleep(duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    async fn tcp_connect(addr: SocketAddr) -> io::Result<Compat<TcpStream>> {
+        Ok(TcpStream::connect(addr).await?.compat())
+    }
+
+    fn tcp_peer_addr(stream: &Compat<TcpStream>) -> io::Result<SocketAddr> {
+        stream.get_ref().peer_addr()
+    }
+
+    async fn tcp_bind(addr: SocketAddr) -> io::Result<TcpListener> {
+        TcpListener::bind(addr).await
+    }
+
+    fn tcp_local_addr(listener: &TcpListener) -> io::Result<SocketAddr> {
+        listener.local_addr()
+    }
+
+    async fn tcp_accept(listener: &TcpListener) -> io::Result<(Compat<TcpStream>, SocketAddr)> {
+        let (stream, addr) = listener.accept().await?;
+        Ok((stream.compat(), addr))
+    }
+
+    async fn udp_bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr).await
+    }
+
+    fn udp_from_std(socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
+        UdpSocket::from_std(socket)
+    }
+
+    async fn udp_send_to(socket: &UdpSocket, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        socket.send_to(buf, addr).await
+    }
+
+    async fn udp_recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        socket.recv_from(buf).await
+    }
+}
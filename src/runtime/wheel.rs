@@ -0,0 +1,418 @@
+//! A hierarchical timer wheel, so that the timers of thousands of
+//! connections do not each own a timer of the runtime.
+//!
+//! Deadlines are rounded up to ticks of `TICK`. The wheel has `LEVELS`
+//! levels of `SLOTS` slots each, a slot of level `n` spanning `SLOTS^n`
+//! ticks. A timer is put into the lowest level whose slot holds its
+//! deadline, and moved down a level whenever the wheel reaches its slot,
+//! so inserting, cancelling and firing a timer are all O(1). A single
+//! driver task sleeps on the runtime until the next slot is due, and exits
+//! once no timers are left.
+
+use futures_lite::{Future, Stream};
+use std::collections::HashMap;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use super::{Sleep, Spawner};
+
+/// Timers fire at the first tick at or after their deadline.
+const TICK: Duration = Duration::from_millis(10);
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+/// Deadlines further out, about 46 hours, are clamped to this.
+const MAX_TICKS: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+#[derive(Debug)]
+struct Entry {
+    deadline: u64,
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+struct State {
+    // The tick the wheel advanced to.
+    now: u64,
+    // Ids of the timers in each slot. Cancelled ones are skipped once their
+    // slot is reached.
+    levels: Vec<Vec<Vec<u64>>>,
+    entries: HashMap<u64, Entry>,
+    // Entries that did not fire yet.
+    pending: usize,
+    next_id: u64,
+    driving: bool,
+    // The tick the driver sleeps until, and its waker.
+    driver_until: Option<u64>,
+    driver_waker: Option<Waker>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            now: 0,
+            levels: vec![vec![vec![]; SLOTS]; LEVELS],
+            entries: HashMap::new(),
+            pending: 0,
+            next_id: 0,
+            driving: false,
+            driver_until: None,
+            driver_waker: None,
+        }
+    }
+
+    fn insert(&mut self, deadline: u64) -> u64 {
+        let deadline = deadline.min(self.now + MAX_TICKS);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(
+            id,
+            Entry {
+                deadline,
+                fired: false,
+                waker: None,
+            },
+        );
+        self.pending += 1;
+        let mut woken = vec![];
+        self.place(id, &mut woken);
+        id
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(entry) = self.entries.remove(&id) {
+            if !entry.fired {
+                self.pending -= 1;
+            }
+        }
+    }
+
+    // Put a timer into the slot holding its deadline, or fire it if due.
+    fn place(&mut self, id: u64, woken: &mut Vec<Waker>) {
+        let entry = match self.entries.get_mut(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.deadline <= self.now {
+            entry.fired = true;
+            self.pending -= 1;
+            woken.extend(entry.waker.take());
+            return;
+        }
+        // The highest bit in which the deadline and now differ picks the
+        // level, so the slot always lies ahead of now on it.
+        let differing = 63 - (entry.deadline ^ self.now).leading_zeros();
+        let level = ((differing / SLOT_BITS) as usize).min(LEVELS - 1);
+        let slot = (entry.deadline >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1);
+        self.levels[level][slot].push(id);
+    }
+
+    // Advance to a tick, returning the wakers of the timers that fired.
+    fn advance(&mut self, to: u64) -> Vec<Waker> {
+        let mut woken = vec![];
+        while self.now < to {
+            if self.pending == 0 {
+                self.now = to;
+                break;
+            }
+            self.now += 1;
+            for level in (0..LEVELS).rev() {
+                let shift = SLOT_BITS * level as u32;
+                if self.now & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+                let slot = (self.now >> shift) as usize & (SLOTS - 1);
+                for id in std::mem::take(&mut self.levels[level][slot]) {
+                    self.place(id, &mut woken);
+                }
+            }
+        }
+        woken
+    }
+
+    // The tick at which the next slot with timers is reached.
+    fn next_expiration(&self) -> Option<u64> {
+        for (level, slots) in self.levels.iter().enumerate() {
+            let shift = SLOT_BITS * level as u32;
+            let current = (self.now >> shift) as usize & (SLOTS - 1);
+            let rotation = (self.now >> (shift + SLOT_BITS)) << (shift + SLOT_BITS);
+            for ahead in 1..=SLOTS {
+                if !slots[(current + ahead) & (SLOTS - 1)].is_empty() {
+                    return Some(rotation + (((current + ahead) as u64) << shift));
+                }
+            }
+        }
+        None
+    }
+}
+
+struct Shared {
+    start: Instant,
+    state: Mutex<State>,
+    spawner: Spawner,
+}
+
+impl Shared {
+    fn tick_now(&self) -> u64 {
+        (super::elapsed(self.start).as_millis() / TICK.as_millis()) as u64
+    }
+
+    // The first tick at or after a duration from now.
+    fn tick_after(&self, duration: Duration) -> u64 {
+        let at = super::elapsed(self.start) + duration;
+        let tick = TICK.as_nanos();
+        ((at.as_nanos() + tick - 1) / tick) as u64
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.start + Duration::from_nanos(TICK.as_nanos() as u64 * tick)
+    }
+}
+
+/// The timer wheel of a swarm, see the module docs. Cheap to clone.
+#[derive(Clone)]
+pub(crate) struct TimerWheel(Arc<Shared>);
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.state.lock().unwrap();
+        f.debug_struct("TimerWheel")
+            .field("timers", &state.pending)
+            .field("driving", &state.driving)
+            .finish()
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new(Spawner::default())
+    }
+}
+
+impl TimerWheel {
+    /// A wheel whose driver runs on a spawner, e.g. of a spawn-free swarm.
+    pub fn new(spawner: Spawner) -> Self {
+        Self(Arc::new(Shared {
+            start: super::now(),
+            state: Mutex::new(State::new()),
+            spawner,
+        }))
+    }
+
+    /// A future completing after `duration`, rounded up to the next tick.
+    pub fn sleep(&self, duration: Duration) -> Timer {
+        let deadline = self.0.tick_after(duration);
+        let mut state = self.0.state.lock().unwrap();
+        // The wheel stands still without a driver, so catch up first.
+        let mut woken = state.advance(self.0.tick_now());
+        let id = state.insert(deadline);
+        let start_driver = !state.driving;
+        if start_driver {
+            state.driving = true;
+        } else if state.driver_until.map_or(false, |until| deadline < until) {
+            woken.extend(state.driver_waker.take());
+        }
+        drop(state);
+        woken.into_iter().for_each(Waker::wake);
+        if start_driver {
+            let driver = Driver {
+                shared: self.0.clone(),
+                sleep: None,
+                finished: false,
+            };
+            self.0.spawner.spawn("hyperswarm::timers", driver);
+        }
+        Timer {
+            shared: self.0.clone(),
+            id,
+        }
+    }
+
+    /// Like `runtime::interval`, on the wheel.
+    pub fn interval(&self, period: Duration) -> Interval {
+        Interval {
+            wheel: self.clone(),
+            period,
+            timer: self.sleep(period),
+        }
+    }
+
+    /// Number of timers that did not fire yet.
+    pub fn pending(&self) -> usize {
+        self.0.state.lock().unwrap().pending
+    }
+}
+
+/// A timer on a `TimerWheel`. Dropping it cancels it.
+pub(crate) struct Timer {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer").field("id", &self.id).finish()
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        let entry = match state.entries.get_mut(&self.id) {
+            Some(entry) => entry,
+            None => return Poll::Ready(()),
+        };
+        if entry.fired {
+            return Poll::Ready(());
+        }
+        if !entry
+            .waker
+            .as_ref()
+            .map_or(false, |waker| waker.will_wake(cx.waker()))
+        {
+            entry.waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().remove(self.id);
+    }
+}
+
+/// A stream yielding every `period`, see `TimerWheel::interval`.
+pub(crate) struct Interval {
+    wheel: TimerWheel,
+    period: Duration,
+    timer: Timer,
+}
+
+impl fmt::Debug for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interval")
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        futures_lite::ready!(Pin::new(&mut self.timer).poll(cx));
+        self.timer = self.wheel.sleep(self.period);
+        Poll::Ready(Some(()))
+    }
+}
+
+// Advances the wheel, sleeping on the runtime until the next slot is due.
+struct Driver {
+    shared: Arc<Shared>,
+    sleep: Option<(u64, Sleep)>,
+    finished: bool,
+}
+
+impl Future for Driver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            let mut state = self.shared.state.lock().unwrap();
+            let woken = state.advance(self.shared.tick_now());
+            let next = match state.next_expiration().filter(|_| state.pending > 0) {
+                Some(next) => next,
+                None => {
+                    state.driving = false;
+                    state.driver_until = None;
+                    drop(state);
+                    woken.into_iter().for_each(Waker::wake);
+                    self.finished = true;
+                    return Poll::Ready(());
+                }
+            };
+            state.driver_until = Some(next);
+            state.driver_waker = Some(cx.waker().clone());
+            drop(state);
+            woken.into_iter().for_each(Waker::wake);
+
+            if self
+                .sleep
+                .as_ref()
+                .map_or(true, |(until, _)| *until != next)
+            {
+                let at = self.shared.instant_of(next);
+                let sleep = super::sleep(at.saturating_duration_since(super::now()));
+                self.sleep = Some((next, sleep));
+            }
+            let (_, sleep) = self.sleep.as_mut().unwrap();
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.sleep = None;
+        }
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // Dropped by the runtime or with its spawner, so let the next timer
+        // start another one.
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.driving = false;
+            state.driver_until = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cascade() {
+        let mut state = State::new();
+        let deadlines = [1, 63, 64, 65, 4095, 4096, 300_000, MAX_TICKS * 2];
+        let ids: Vec<u64> = deadlines.iter().map(|d| state.insert(*d)).collect();
+        let mut fired_at = HashMap::new();
+        while fired_at.len() < ids.len() {
+            let next = state.next_expiration().unwrap();
+            assert!(next > state.now);
+            state.advance(next);
+            for (id, entry) in state.entries.iter() {
+                if entry.fired {
+                    fired_at.entry(*id).or_insert(state.now);
+                }
+            }
+        }
+        for (id, deadline) in ids.iter().zip(deadlines.iter()) {
+            assert_eq!(fired_at[id], (*deadline).min(MAX_TICKS));
+        }
+        assert_eq!(state.next_expiration(), None);
+    }
+
+    #[async_std::test]
+    async fn test_timers() {
+        let wheel = TimerWheel::default();
+        let started = Instant::now();
+        let long = wheel.sleep(Duration::from_secs(60));
+        futures::join!(
+            wheel.sleep(Duration::from_millis(30)),
+            wheel.sleep(Duration::from_millis(1))
+        );
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(wheel.pending(), 1);
+        drop(long);
+        assert_eq!(wheel.pending(), 0);
+    }
+}
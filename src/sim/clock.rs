@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The virtual clock of a simulation.
+///
+/// Time only moves when advanced, either explicitly or by
+/// `Simulation::run` once nothing else can make progress. Sleeps due at the
+/// same time are woken in the order they were created.
+#[derive(Clone)]
+pub struct Clock(Arc<Mutex<State>>);
+
+struct State {
+    start: Instant,
+    elapsed: Duration,
+    // Wakers of pending sleeps, by deadline and creation.
+    sleepers: BTreeMap<(Duration, u64), Waker>,
+    next_id: u64,
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.lock().unwrap();
+        f.debug_struct("Clock")
+            .field("elapsed", &state.elapsed)
+            .field("sleepers", &state.sleepers.len())
+            .finish()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            start: Instant::now(),
+            elapsed: Duration::default(),
+            sleepers: BTreeMap::new(),
+            next_id: 0,
+        })))
+    }
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The virtual time, starting at the real time the clock was created.
+    pub fn now(&self) -> Instant {
+        let state = self.0.lock().unwrap();
+        state.start + state.elapsed
+    }
+
+    /// The virtual time passed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed
+    }
+
+    /// A future completing once the clock advanced by `duration`.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        let mut state = self.0.lock().unwrap();
+        let deadline = state.elapsed + duration;
+        let id = state.next_id;
+        state.next_id += 1;
+        Sleep {
+            clock: self.clone(),
+            key: (deadline, id),
+            registered: false,
+        }
+    }
+
+    /// Move the clock forward, waking all sleeps that are due.
+    pub fn advance(&self, duration: Duration) {
+        let target = self.elapsed() + duration;
+        self.advance_to(target);
+    }
+
+    /// Move the clock to the deadline of the next sleep and wake it, along
+    /// with all others due then. False if no sleep is pending.
+    pub fn advance_to_next(&self) -> bool {
+        let next = self
+            .0
+            .lock()
+            .unwrap()
+            .sleepers
+            .keys()
+            .next()
+            .map(|key| key.0);
+        match next {
+            Some(next) => {
+                self.advance_to(next);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn advance_to(&self, target: Duration) {
+        let mut woken = vec![];
+        let mut state = self.0.lock().unwrap();
+        state.elapsed = state.elapsed.max(target);
+        while let Some(key) = state.sleepers.keys().next().copied() {
+            if key.0 > state.elapsed {
+                break;
+            }
+            woken.extend(state.sleepers.remove(&key));
+        }
+        drop(state);
+        woken.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// A sleep on a virtual `Clock`.
+pub struct Sleep {
+    clock: Clock,
+    key: (Duration, u64),
+    registered: bool,
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sleep")
+            .field("deadline", &self.key.0)
+            .finish()
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let key = self.key;
+        let mut state = self.clock.0.lock().unwrap();
+        if state.elapsed >= key.0 {
+            state.sleepers.remove(&key);
+            drop(state);
+            self.registered = false;
+            return Poll::Ready(());
+        }
+        state.sleepers.insert(key, cx.waker().clone());
+        drop(state);
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            if let Ok(mut state) = self.clock.0.lock() {
+                state.sleepers.remove(&self.key);
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Deterministic simulations of whole swarms, with the `sim` feature.
+//!
+//! A [`Simulation`] runs swarms on a [`MemoryNetwork`] instead of sockets,
+//! on a virtual [`Clock`] instead of real time, and with a seeded random
+//! number generator for the jitter of timers. Its executor runs everything
+//! on the calling thread and, whenever nothing can make progress, moves the
+//! clock to the next timer, so minutes of backoff pass in an instant and
+//! runs with the same seed fire the same timers at the same virtual times.
+//! Tests that are flaky on real networks because of timing become
+//! reproducible this way.
+//!
+//! Swarms of a simulation are bound with the config of
+//! `Simulation::config`, and bound and polled within `Simulation::run`.
+//! They are spawn-free and find each other through a `MemoryDiscovery`
+//! instead of the DHT and mDNS, which need real sockets. The tasks of a
+//! `Mux` and anything else spawned on the runtime are not part of the
+//! simulation. The swarm still iterates hash maps in places, so the order
+//! of events that are due at the same virtual time can differ between runs;
+//! tests should assert on outcomes and times rather than on that order.
+
+use futures::task::ArcWake;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+pub use crate::discovery::memory::MemoryDiscovery;
+use crate::discovery::Backend;
+#[cfg(feature = "noise")]
+use crate::keys::Keypair;
+use crate::runtime;
+pub use crate::transport::memory::{
+    Latency, LinkConfig, MemoryNetwork, MemoryStream, MemoryTransport,
+};
+
+mod clock;
+
+pub use clock::{Clock, Sleep};
+
+/// A simulated world of swarms, see the module docs.
+pub struct Simulation {
+    seed: u64,
+    clock: Clock,
+    network: MemoryNetwork,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("seed", &self.seed)
+            .field("clock", &self.clock)
+            .field("network", &self.network)
+            .finish()
+    }
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            clock: Clock::new(),
+            network: MemoryNetwork::new(),
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// A random number from the seeded generator.
+    pub fn random(&self) -> u64 {
+        self.rng.lock().unwrap().gen()
+    }
+
+    /// The config of a swarm in the simulation: on the memory network,
+    /// spawn-free, without the DHT and mDNS, and with a keypair from the
+    /// seeded generator. Further settings can be applied on top.
+    pub fn config(&self) -> Config {
+        #[cfg(feature = "noise")]
+        let keypair = Some(self.keypair());
+        #[cfg(not(feature = "noise"))]
+        let keypair = None;
+        Config::default()
+            .set_memory_network(Some(self.network.clone()))
+            .set_backend_enabled(Backend::Dht, false)
+            .set_backend_enabled(Backend::Mdns, false)
+            .set_spawn_free(true)
+            .set_keypair(keypair)
+    }
+
+    /// A keypair from the seeded generator.
+    #[cfg(feature = "noise")]
+    pub fn keypair(&self) -> Keypair {
+        let mut secret = [0u8; 32];
+        self.rng.lock().unwrap().fill(&mut secret);
+        Keypair::from_secret_key(secret)
+    }
+
+    /// Run a future to completion within the simulation, on this thread.
+    ///
+    /// Whenever the future cannot make progress, the clock is moved to the
+    /// next pending sleep. Panics if there is none, as the future would then
+    /// wait forever.
+    pub fn run<F: Future>(&self, fut: F) -> F::Output {
+        let _enter = self.enter();
+        let woken = Arc::new(Woken(AtomicBool::new(true)));
+        let waker = futures::task::waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        futures::pin_mut!(fut);
+        loop {
+            if woken.0.swap(false, Ordering::SeqCst) {
+                if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                    return output;
+                }
+                continue;
+            }
+            if !self.clock.advance_to_next() {
+                panic!(
+                    "simulation {} stalled at {:?}: nothing can make progress and no timer is pending",
+                    self.seed,
+                    self.clock.elapsed()
+                );
+            }
+        }
+    }
+
+    /// Run the clock of the simulation and the sleeps on it, and make
+    /// timers and randomness of swarms use them, until the guard is dropped.
+    fn enter(&self) -> Enter {
+        let current = Current {
+            clock: self.clock.clone(),
+            rng: self.rng.clone(),
+        };
+        let previous = CURRENT.with(|cell| cell.borrow_mut().replace(current));
+        Enter { previous }
+    }
+}
+
+struct Woken(AtomicBool);
+
+impl ArcWake for Woken {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
+struct Current {
+    clock: Clock,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = RefCell::new(None);
+}
+
+struct Enter {
+    previous: Option<Current>,
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|cell| *cell.borrow_mut() = previous);
+    }
+}
+
+fn with_current<T>(f: impl FnOnce(&Current) -> T) -> Option<T> {
+    CURRENT.with(|cell| cell.borrow().as_ref().map(f))
+}
+
+pub(crate) fn now() -> Option<Instant> {
+    with_current(|current| current.clock.now())
+}
+
+pub(crate) fn sleep(duration: Duration) -> Option<runtime::Sleep> {
+    with_current(|current| Box::pin(current.clock.sleep(duration)) as runtime::Sleep)
+}
+
+pub(crate) fn random() -> Option<u64> {
+    with_current(|current| current.rng.lock().unwrap().gen())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Hyperswarm, TopicConfig};
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use futures::StreamExt;
+    use std::io;
+
+    // Connect two swarms and exchange a message, returning the virtual time
+    // it took.
+    fn connect(seed: u64) -> io::Result<Duration> {
+        let sim = Simulation::new(seed);
+        sim.network().set_latency(Duration::from_millis(50));
+        let topic = [1u8; 32];
+        sim.run(async {
+            let mut server = Hyperswarm::bind(sim.config()).await?;
+            let mut client = Hyperswarm::bind(sim.config()).await?;
+            server.configure(topic, TopicConfig::server());
+            client.configure(topic, TopicConfig::client());
+            let (conn_a, conn_b) = futures::join!(server.next(), client.next());
+            let (mut conn_a, mut conn_b) = (conn_a.unwrap()?, conn_b.unwrap()?);
+            assert!(conn_b.is_initiator());
+            conn_b.write_all(b"hello").await?;
+            let mut buf = [0u8; 5];
+            conn_a.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            Ok(sim.clock().elapsed())
+        })
+    }
+
+    #[test]
+    fn test_simulation() -> io::Result<()> {
+        let elapsed = connect(7)?;
+        // At least the round trip of the connect and one for the handshake.
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert_eq!(connect(7)?, elapsed);
+        Ok(())
+    }
+}
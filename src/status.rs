@@ -0,0 +1,159 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::Serialize;
+
+/// A snapshot of the state of a swarm, see `Hyperswarm::status`.
+/// Serializable with the `serde` feature.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct SwarmStatus {
+    pub name: Option<String>,
+    pub local_addr: SocketAddr,
+    pub external_addr: Option<SocketAddr>,
+    pub topics: Vec<TopicStatus>,
+    pub connections: Vec<ConnectionCount>,
+    /// Bytes transferred since the swarm started, by transport.
+    pub bytes: Vec<ByteCount>,
+    pub queued_dials: usize,
+    pub pending_dials: usize,
+    /// Number of dials that may be in progress at once, which adapts to how
+    /// quickly dials succeed.
+    pub dial_concurrency: usize,
+    pub dht: Option<DhtStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct TopicStatus {
+    /// The topic, hex encoded.
+    pub topic: String,
+    pub announce: bool,
+    pub lookup: bool,
+    /// Whether the DHT confirmed an announce.
+    pub announced: bool,
+    /// Peers found for the topic.
+    pub known_peers: usize,
+    /// Dials in progress to peers found for the topic.
+    pub pending_dials: usize,
+    /// Bytes read from and written to connections attributed to the topic.
+    /// Connections shared by several topics count for each.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Open connections over one transport.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct ConnectionCount {
+    pub protocol: String,
+    pub inbound: usize,
+    pub outbound: usize,
+}
+
+/// Bytes read and written over one transport, after decryption.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct ByteCount {
+    pub protocol: String,
+    pub read: u64,
+    pub written: u64,
+}
+
+/// Health of the DHT nodes seen so far, by grade.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct DhtStatus {
+    pub good_nodes: usize,
+    pub questionable_nodes: usize,
+    pub bad_nodes: usize,
+}
+
+/// A health verdict for liveness and readiness probes, see
+/// `Hyperswarm::health`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct Health {
+    /// Whether any DHT node responded recently, None with the DHT disabled.
+    pub dht_reachable: Option<bool>,
+    /// Whether a listener is bound, false in `SwarmMode::DialOnly`.
+    pub listening: bool,
+    pub external_addr_known: bool,
+    pub connections: usize,
+    pub min_connections: usize,
+}
+
+impl Health {
+    /// Whether the DHT, if enabled, is reachable and there are at least
+    /// `min_connections` open connections.
+    pub fn is_healthy(&self) -> bool {
+        self.dht_reachable != Some(false) && self.connections >= self.min_connections
+    }
+}
+
+/// A detailed report of the internal state of a swarm, see
+/// `Hyperswarm::debug_dump`. Meant for humans debugging a swarm, the layout
+/// may change between releases.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct DebugDump {
+    /// The joined topics and connection counts.
+    pub status: SwarmStatus,
+    /// DHT nodes this swarm talked to, by address.
+    pub routing_table: Vec<NodeDump>,
+    pub schedules: Vec<ScheduleDump>,
+    pub dials: Vec<DialDump>,
+    pub connections: Vec<ConnectionDump>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct NodeDump {
+    pub addr: SocketAddr,
+    /// `good`, `questionable` or `bad`.
+    pub grade: String,
+    /// Time since the node last responded.
+    pub last_seen: Option<Duration>,
+    pub rtt: Option<Duration>,
+    /// Pings failed in a row.
+    pub failures: u32,
+}
+
+/// The announce and lookup rounds scheduled for a topic.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct ScheduleDump {
+    /// The topic as sent to the network, hex encoded. Differs from the
+    /// joined topic if a network salt is set.
+    pub topic: String,
+    pub announce: bool,
+    pub lookup: bool,
+    /// Distinct peers seen since the last round.
+    pub peers: usize,
+    /// Rounds in a row without peers.
+    pub empty_rounds: u32,
+    /// Time until the next round, zero if it is due.
+    pub next_round: Duration,
+}
+
+/// A queued or in-progress dial.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct DialDump {
+    pub addr: SocketAddr,
+    /// The topic the peer was found for, hex encoded.
+    pub topic: Option<String>,
+    /// Time since the dial started, or None while it is queued.
+    pub dialing_for: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct ConnectionDump {
+    pub addr: SocketAddr,
+    pub protocol: String,
+    pub outbound: bool,
+    /// Time since data was last read or written.
+    pub idle_for: Duration,
+}
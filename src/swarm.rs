@@ -1,32 +1,153 @@
 use async_std::channel;
+use futures::io::AsyncWrite;
+use futures::stream::FuturesUnordered;
 use futures_lite::Stream;
 use log::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::future::Future;
 use std::io;
+#[cfg(feature = "noise")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, TopicConfig};
-use crate::discovery::Topic;
+use crate::bans::{BanList, PeerFilter};
+#[cfg(feature = "noise")]
+use crate::config::HandshakePolicy;
+use crate::config::{CoalescePolicy, Config, DedupPolicy, KeepalivePolicy, SwarmMode, TopicConfig};
+use crate::data::ConnectionData;
+use crate::dedup::{KeyDedup, PublicKey};
+use crate::dial::DialQueue;
+use crate::discovery::gossip::GossipHandle;
+use crate::discovery::instrument::DhtMessage;
+#[cfg(feature = "sim")]
+use crate::discovery::memory::MemoryDiscovery;
+use crate::discovery::nodes::NodeGrade;
+use crate::discovery::nodes::NodeInfo;
+use crate::discovery::observed::ObservedAddrs;
+use crate::discovery::pex::PexHandle;
+use crate::discovery::ping::dht_ping_tapped;
+use crate::discovery::private::TopicKey;
 use crate::discovery::{combined::CombinedDiscovery, Discovery};
+use crate::discovery::{BoxedDiscovery, Topic};
+use crate::error::ConnectError;
+use crate::events::{write_json_lines, EventBus, SwarmEvent, SwarmObserver};
+use crate::firewall::{Firewall, RemotePeer};
+#[cfg(feature = "noise")]
+use crate::greylist::Greylist;
+use crate::hooks::LifecycleHooks;
+#[cfg(feature = "noise")]
+use crate::keys::{key_topic, Keypair};
+use crate::limits::ConnectionTracker;
+use crate::metrics::Metrics;
+use crate::peer::PeerInfo;
+use crate::reconnect::Reconnects;
+use crate::redact::{self, redact};
+use crate::reputation::{Misbehavior, Reputation};
+use crate::runtime::{self, JoinHandle, LocalTasks, Spawner, TimerWheel};
+use crate::status::{ByteCount, DebugDump, DhtStatus, Health, NodeDump, SwarmStatus, TopicStatus};
+use crate::topic::{TopicExchange, TopicHandle, TopicState};
+#[cfg(feature = "noise")]
+use crate::transport::handshake::{HandshakeRequest, Handshaker, Session};
+#[cfg(feature = "noise")]
+use crate::transport::noise::{self, HandshakeOptions, NoiseHandshaker};
+#[cfg(feature = "noise")]
+use crate::transport::tickets::Tickets;
 use crate::transport::{
     combined::{CombinedStream, CombinedTransport},
-    Connection, Transport,
+    CloseReason, Connection, Transport,
 };
 
-type ConfigureCommand = (Topic, TopicConfig);
+#[derive(Debug)]
+pub(crate) enum Command {
+    Configure(Topic, TopicConfig),
+    Refresh(Topic),
+    Flush(channel::Sender<()>),
+}
+
+/// How long `Hyperswarm::shutdown` waits for each of unannouncing and
+/// closing connections.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type CheckFut = Pin<Box<dyn Future<Output = (bool, Connection<CombinedStream>)> + Send>>;
+#[cfg(feature = "noise")]
+type HandshakeFut = Pin<
+    Box<dyn Future<Output = (io::Result<Session>, Duration, Connection<CombinedStream>)> + Send>,
+>;
 
 pub struct Hyperswarm {
+    name: Option<String>,
     topics: HashMap<Topic, TopicConfig>,
     discovery: CombinedDiscovery,
     transport: CombinedTransport,
-    command_tx: channel::Sender<ConfigureCommand>,
-    command_rx: channel::Receiver<ConfigureCommand>,
+    dials: DialQueue,
+    connections: ConnectionTracker,
+    bans: BanList,
+    local_key: Option<PublicKey>,
+    #[cfg(feature = "noise")]
+    handshaker: Arc<dyn Handshaker>,
+    #[cfg(feature = "noise")]
+    handshake: HandshakePolicy,
+    #[cfg(feature = "noise")]
+    plaintext: bool,
+    #[cfg(feature = "noise")]
+    private_keys: HashMap<Topic, [u8; 32]>,
+    // Keys dialed with `connect_to_key`, by their topic.
+    #[cfg(feature = "noise")]
+    expected_keys: HashMap<Topic, [u8; 32]>,
+    #[cfg(feature = "noise")]
+    greylist: Greylist,
+    dedup: DedupPolicy,
+    keepalive: Option<KeepalivePolicy>,
+    coalesce: Option<CoalescePolicy>,
+    max_write_buffer: usize,
+    max_known_peers: usize,
+    dht_tap: Option<channel::Sender<DhtMessage>>,
+    by_key: KeyDedup,
+    firewall: Option<Firewall>,
+    pending_checks: FuturesUnordered<CheckFut>,
+    #[cfg(feature = "noise")]
+    pending_handshakes: FuturesUnordered<HandshakeFut>,
+    // When in-flight dials started, for the dial latency metrics.
+    dial_started: HashMap<SocketAddr, Instant>,
+    #[cfg(feature = "tracing")]
+    dial_spans: HashMap<SocketAddr, tracing::Span>,
+    mode: SwarmMode,
+    reconnects: Reconnects,
+    reputation: Reputation,
+    // Closed connections, with the bytes they read and wrote.
+    closed_tx: channel::Sender<(SocketAddr, bool, CloseReason, (u64, u64))>,
+    closed_rx: channel::Receiver<(SocketAddr, bool, CloseReason, (u64, u64))>,
+    topic_exchange: TopicExchange,
+    exchanged_rx: channel::Receiver<(SocketAddr, Vec<Topic>)>,
+    events: EventBus,
+    hooks: LifecycleHooks,
+    // Tasks of spawn-free swarms, see `Config::set_spawn_free`.
+    tasks: Option<LocalTasks>,
+    spawner: Spawner,
+    // Timers of connections and reconnects.
+    timers: TimerWheel,
+    metrics: Metrics,
+    suspended: bool,
+    waker: Option<Waker>,
+    joined: HashMap<Topic, Arc<Mutex<TopicState>>>,
+    peer_topics: HashMap<SocketAddr, Vec<Topic>>,
+    pinned: HashSet<SocketAddr>,
+    announced: HashSet<Topic>,
+    looked_up: HashSet<Topic>,
+    topic_conns: HashMap<Topic, HashSet<SocketAddr>>,
+    command_tx: channel::Sender<Command>,
+    command_rx: channel::Receiver<Command>,
+    flushes: Vec<channel::Sender<()>>,
 }
 impl fmt::Debug for Hyperswarm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Hyperswarm")
+            .field("name", &self.name)
             .field("topics", &self.topics)
             .field("discovery", &self.discovery)
             .field("transport", &self.transport)
@@ -34,27 +155,190 @@ impl fmt::Debug for Hyperswarm {
     }
 }
 
+// Bind the transport of a swarm, on a memory network if one is configured.
+async fn bind_transport(config: &Config) -> io::Result<CombinedTransport> {
+    #[cfg(feature = "sim")]
+    {
+        if let Some(network) = config.memory_network.as_ref() {
+            let memory = match config.mode {
+                SwarmMode::DialOnly => network.dial_only(),
+                _ => network.bind(),
+            };
+            return Ok(CombinedTransport::memory(memory));
+        }
+    }
+    match config.mode {
+        SwarmMode::DialOnly => Ok(CombinedTransport::dial_only()),
+        _ => CombinedTransport::bind("localhost:0").await,
+    }
+}
+
 impl Hyperswarm {
     pub async fn bind(config: Config) -> io::Result<Self> {
-        let local_addr = "localhost:0";
-
-        let transport = CombinedTransport::bind(local_addr).await?;
+        if config.redact_logs {
+            redact::enable();
+        }
+        let mut transport = bind_transport(&config).await?;
+        #[cfg(feature = "sim")]
+        let memory_network = config.memory_network.clone();
+        let dedup = config.dedup;
+        let keepalive = config.keepalive.clone();
+        let coalesce = config.coalesce.clone();
+        let resource_limits = config.resource_limits.clone();
+        let dht_tap = config.dht_tap.clone();
+        transport.set_dedup(dedup == DedupPolicy::Addr);
         let local_addr = transport.local_addr();
         let port = local_addr.port();
-        let discovery = CombinedDiscovery::bind(port, config).await?;
+        let name = config.name.clone();
+        debug!(
+            "swarm {} bound to {}",
+            name.as_deref().unwrap_or("(unnamed)"),
+            redact(local_addr)
+        );
+        let connections = ConnectionTracker::new(config.connection_limits.clone());
+        let firewall = config.firewall.clone();
+        let reconnect = config.reconnect.clone();
+        let reputation = Reputation::new(config.reputation.clone());
+        let mode = config.mode;
+        let bans = match config.ban_list_path.as_ref() {
+            Some(path) => BanList::load(path),
+            None => BanList::new(),
+        };
+        #[cfg(feature = "noise")]
+        let handshake = config.handshake.clone();
+        #[cfg(feature = "noise")]
+        {
+            if handshake.payload.len() > noise::MAX_PAYLOAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "handshake payload too large",
+                ));
+            }
+        }
+        #[cfg(feature = "noise")]
+        let (local_key, handshaker) = {
+            let keypair = config.keypair.clone().unwrap_or_else(Keypair::generate);
+            let options = HandshakeOptions {
+                keypair: keypair.clone(),
+                max_bytes: handshake.max_bytes,
+                tickets: Arc::new(Mutex::new(Tickets::new(handshake.resumption))),
+                topic_keys: vec![],
+                payload: handshake.payload.clone(),
+            };
+            let handshaker: Arc<dyn Handshaker> = match config.handshaker.clone() {
+                Some(handshaker) => handshaker,
+                None => Arc::new(NoiseHandshaker { options }),
+            };
+            (Some(keypair.public_key()), handshaker)
+        };
+        #[cfg(feature = "noise")]
+        let plaintext = config.plaintext;
+        #[cfg(feature = "noise")]
+        let greylist = Greylist::new(handshake.greylist);
+        if config.plaintext {
+            warn!("connections are not encrypted");
+        }
+        #[cfg(not(feature = "noise"))]
+        let local_key = None;
+        let tasks = if config.spawn_free {
+            Some(LocalTasks::default())
+        } else {
+            None
+        };
+        let spawner = tasks
+            .as_ref()
+            .map_or_else(Spawner::default, LocalTasks::spawner)
+            .set_observer(config.task_observer.clone());
+        let timers = TimerWheel::new(spawner.clone());
+        let metrics = Metrics::default();
+        let config = {
+            let metrics = metrics.clone();
+            let hook = config.dht_query_hook.clone();
+            config.set_dht_query_hook(move |event| {
+                metrics.on_dht_query(event);
+                if let Some(hook) = hook.as_ref() {
+                    hook.call(event);
+                }
+            })
+        };
+        #[allow(unused_mut)]
+        let mut discovery = CombinedDiscovery::bind(port, config).await?;
+        #[cfg(feature = "sim")]
+        if let Some(network) = memory_network {
+            discovery.add(Box::new(MemoryDiscovery::new(network, local_addr)));
+        }
 
-        let (command_tx, command_rx) = channel::unbounded::<ConfigureCommand>();
+        let (command_tx, command_rx) = channel::unbounded::<Command>();
+        let (closed_tx, closed_rx) = channel::unbounded();
+        let (exchanged_tx, exchanged_rx) = channel::unbounded();
 
         Ok(Self {
+            name,
             topics: HashMap::new(),
             discovery,
             transport,
+            dials: DialQueue::with_capacity(resource_limits.max_queued_dials),
+            connections,
+            bans,
+            local_key,
+            #[cfg(feature = "noise")]
+            handshaker,
+            #[cfg(feature = "noise")]
+            handshake,
+            #[cfg(feature = "noise")]
+            plaintext,
+            #[cfg(feature = "noise")]
+            private_keys: HashMap::new(),
+            #[cfg(feature = "noise")]
+            expected_keys: HashMap::new(),
+            #[cfg(feature = "noise")]
+            greylist,
+            dedup,
+            keepalive,
+            coalesce,
+            max_write_buffer: resource_limits.max_write_buffer,
+            max_known_peers: resource_limits.max_known_peers,
+            dht_tap,
+            by_key: KeyDedup::default(),
+            firewall,
+            pending_checks: FuturesUnordered::new(),
+            #[cfg(feature = "noise")]
+            pending_handshakes: FuturesUnordered::new(),
+            dial_started: HashMap::new(),
+            #[cfg(feature = "tracing")]
+            dial_spans: HashMap::new(),
+            mode,
+            reconnects: Reconnects::new(reconnect, &timers),
+            reputation,
+            closed_tx,
+            closed_rx,
+            topic_exchange: TopicExchange::new(exchanged_tx),
+            exchanged_rx,
+            events: EventBus::default(),
+            hooks: LifecycleHooks::new(spawner.clone()),
+            tasks,
+            spawner,
+            timers,
+            metrics,
+            suspended: false,
+            waker: None,
+            joined: HashMap::new(),
+            peer_topics: HashMap::new(),
+            pinned: HashSet::new(),
+            announced: HashSet::new(),
+            looked_up: HashSet::new(),
+            topic_conns: HashMap::new(),
             command_tx,
             command_rx,
+            flushes: vec![],
         })
     }
 
-    pub fn configure(&mut self, topic: Topic, config: TopicConfig) {
+    pub fn configure(&mut self, topic: Topic, mut config: TopicConfig) {
+        if self.mode == SwarmMode::DialOnly && config.announce {
+            warn!("not announcing {} in dial-only mode", hex::encode(topic));
+            config.announce = false;
+        }
         let old = self.topics.remove(&topic).unwrap_or_default();
         debug!("configure swarm: {} {:?}", hex::encode(topic), config);
         if config.announce && !old.announce {
@@ -63,8 +347,1015 @@ impl Hyperswarm {
         if config.lookup && !old.lookup {
             self.discovery.lookup(topic);
         }
-        // TODO: unannounce and stop-lookup
-        self.topics.insert(topic, config);
+        if (old.announce && !config.announce) || (old.lookup && !config.lookup) {
+            // Leaving stops both, so restart whatever is still configured.
+            self.discovery.leave(topic);
+            if config.announce {
+                self.discovery.announce(topic);
+            }
+            if config.lookup {
+                self.discovery.lookup(topic);
+            }
+        }
+        if old.lookup && !config.lookup {
+            for addr in self.dials.cancel_topic(&topic) {
+                self.transport.cancel_connect(addr);
+                self.dial_started.remove(&addr);
+                #[cfg(feature = "tracing")]
+                {
+                    self.dial_spans.remove(&addr);
+                }
+            }
+        }
+        let joined = config.announce || config.lookup;
+        self.topic_exchange.set_joined(topic, joined);
+        if joined {
+            self.topics.insert(topic, config);
+            self.metrics.on_topic_joined(topic);
+        } else {
+            self.metrics.on_topic_left(&topic);
+            self.announced.remove(&topic);
+            self.looked_up.remove(&topic);
+            self.topic_conns.remove(&topic);
+            self.dials.set_topic_priority(topic, false);
+            #[cfg(feature = "noise")]
+            {
+                self.private_keys.remove(&topic);
+                self.expected_keys.remove(&topic);
+            }
+            if let Some(state) = self.joined.remove(&topic) {
+                state.lock().unwrap().on_leave();
+            }
+            self.peer_topics.retain(|_, topics| {
+                topics.retain(|t| *t != topic);
+                !topics.is_empty()
+            });
+        }
+    }
+
+    /// Join a topic and return a handle to follow it. Joining the same topic
+    /// again returns a handle sharing the same state.
+    pub fn join(&mut self, topic: Topic, config: TopicConfig) -> TopicHandle {
+        self.configure(topic, config);
+        let state = self.joined.entry(topic).or_default().clone();
+        TopicHandle::new(topic, state, self.command_tx.clone(), self.metrics.clone())
+    }
+
+    pub fn leave(&mut self, topic: Topic) {
+        self.configure(topic, TopicConfig::default());
+    }
+
+    /// Look up and announce a topic again now, bypassing the backoff of the
+    /// discovery schedule, e.g. for a "pull to refresh" in a UI. A
+    /// `TopicLookupEmpty` event is emitted again if the lookup finds nobody.
+    pub fn refresh(&mut self, topic: Topic) {
+        if self.topics.contains_key(&topic) {
+            self.looked_up.remove(&topic);
+            self.discovery.refresh(topic);
+        }
+    }
+
+    /// Configure a private topic, see `TopicKey`. Returns the derived topic
+    /// the swarm joined.
+    ///
+    /// With the `noise` feature, peers dialing for the topic prove in the
+    /// handshake that they hold the key. Once all announced topics are
+    /// private, incoming connections without such a proof are refused, so
+    /// peers that only saw the address in the DHT cannot connect. Likewise,
+    /// peers found only for private topics have to prove one of them when
+    /// dialed, so addresses announced without the key are not connected to.
+    ///
+    /// A swarm that also announces public topics cannot tell which topic an
+    /// incoming peer found it for, so it accepts connections without a proof.
+    /// Peers that saw its address under the private topic can then connect,
+    /// though their connections are not attributed to the private topic.
+    /// Announce private topics from a swarm of their own to refuse them.
+    pub fn configure_private(&mut self, key: &TopicKey, config: TopicConfig) -> Topic {
+        let topic = key.topic();
+        self.configure(topic, config);
+        #[cfg(feature = "noise")]
+        {
+            if self.topics.contains_key(&topic) {
+                self.private_keys.insert(topic, *key.as_bytes());
+            }
+        }
+        topic
+    }
+
+    /// Announce this swarm's public key, so that peers can dial it with
+    /// `connect_to_key`. Returns the topic the key is announced under.
+    #[cfg(feature = "noise")]
+    pub fn announce_key(&mut self) -> Option<Topic> {
+        let topic = key_topic(&self.local_key?);
+        let config = self.topics.get(&topic).cloned().unwrap_or_default();
+        self.configure(topic, config.set_server(true));
+        Some(topic)
+    }
+
+    /// Dial the peer with a public key, the "connect to this server"
+    /// primitive of hyperdht. The key is looked up on the DHT, see
+    /// `announce_key`, and connections to peers found for it that
+    /// authenticate with another key fail with `ConnectFailed`. Leave the
+    /// returned topic to stop.
+    #[cfg(feature = "noise")]
+    pub fn connect_to_key(&mut self, public_key: [u8; 32]) -> TopicHandle {
+        if self.plaintext {
+            warn!(
+                "plaintext mode: cannot verify {}",
+                redact(hex::encode(public_key))
+            );
+        }
+        let topic = key_topic(&public_key);
+        let config = self.topics.get(&topic).cloned().unwrap_or_default();
+        let handle = self.join(topic, config.set_client(true).set_max_peers(Some(1)));
+        self.expected_keys.insert(topic, public_key);
+        handle
+    }
+
+    /// Add a custom discovery backend next to the DHT and mDNS. Topics that
+    /// are already joined are replayed to it.
+    pub fn add_discovery<D>(&mut self, discovery: D)
+    where
+        D: Discovery + Send + Unpin + 'static,
+    {
+        let mut discovery: BoxedDiscovery = Box::new(discovery);
+        for (topic, config) in self.topics.iter() {
+            let topic = self.discovery.network_topic(*topic);
+            if config.announce {
+                discovery.announce(topic);
+            }
+            if config.lookup {
+                discovery.lookup(topic);
+            }
+        }
+        self.discovery.add(discovery);
+    }
+
+    /// The combined discovery, e.g. to enable or disable backends at runtime.
+    pub fn discovery(&mut self) -> &mut CombinedDiscovery {
+        &mut self.discovery
+    }
+
+    /// Handle to exchange known peers over established connections, if peer
+    /// exchange is enabled in the config.
+    pub fn pex(&self) -> Option<PexHandle> {
+        self.discovery.pex().cloned()
+    }
+
+    /// Handle to gossip topic membership over established connections, if
+    /// gossip is enabled in the config.
+    pub fn gossip(&self) -> Option<GossipHandle> {
+        self.discovery.gossip().cloned()
+    }
+
+    /// Handle to exchange observed addresses over established connections,
+    /// to learn this node's external address.
+    pub fn observed_addrs(&self) -> ObservedAddrs {
+        self.discovery.observed_addrs().clone()
+    }
+
+    /// The external address as agreed on by connected peers, if known yet.
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        self.discovery.observed_addrs().external_addr()
+    }
+
+    /// Ping a DHT node and return the round-trip time.
+    pub async fn dht_ping(&self, addr: SocketAddr) -> io::Result<Duration> {
+        dht_ping_tapped(addr, Duration::from_secs(2), self.dht_tap.clone()).await
+    }
+
+    /// Health of the DHT nodes seen so far.
+    pub fn dht_nodes(&self) -> Vec<NodeInfo> {
+        self.discovery
+            .dht()
+            .map(|dht| dht.nodes())
+            .unwrap_or_default()
+    }
+
+    /// Dial a peer now and keep redialing it whenever the outgoing connection
+    /// is lost or fails, regardless of topics.
+    pub fn join_peer(&mut self, addr: SocketAddr) {
+        debug!("join peer {}", redact(addr));
+        self.pinned.insert(addr);
+        self.reconnects.pin(addr);
+        if !self.suspended {
+            self.dial_pinned(addr);
+        }
+    }
+
+    fn dial_pinned(&mut self, addr: SocketAddr) {
+        if self.mode == SwarmMode::ListenOnly {
+            debug!("not dialing {} in listen-only mode", redact(addr));
+            return;
+        }
+        if !self.connections.is_connected(&addr) && self.bans.is_allowed(&addr, None) {
+            self.reconnects.on_dial(addr);
+            self.events.emit(SwarmEvent::Connecting(addr));
+            self.dial(addr);
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Quiesce the swarm, e.g. when a mobile app goes to the background:
+    /// discovery is paused, dials and reconnects are cancelled and the
+    /// swarm stream stays pending until `resume`. Open connections are kept
+    /// if `keep_connections` is set, and closed otherwise.
+    pub fn suspend(&mut self, keep_connections: bool) {
+        if self.suspended {
+            return;
+        }
+        debug!("suspend swarm");
+        self.suspended = true;
+        self.discovery.pause();
+        self.dials.cancel_all();
+        self.reconnects.cancel_all();
+        self.transport.cancel_connects();
+        self.dial_started.clear();
+        #[cfg(feature = "tracing")]
+        {
+            self.dial_spans.clear();
+        }
+        if !keep_connections {
+            self.connections.close_all();
+        }
+    }
+
+    /// Resume after `suspend`: all topics are announced and looked up again,
+    /// peers added with `join_peer` are redialed if their connection did not
+    /// survive, and peers lost meanwhile are reconnected.
+    pub fn resume(&mut self) {
+        if !self.suspended {
+            return;
+        }
+        debug!("resume swarm");
+        self.suspended = false;
+        self.discovery.resume();
+        let pinned: Vec<SocketAddr> = self.pinned.iter().copied().collect();
+        for addr in pinned {
+            self.dial_pinned(addr);
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Stop redialing a peer added with `join_peer`. An open connection to it
+    /// is kept.
+    pub fn leave_peer(&mut self, addr: SocketAddr) {
+        self.pinned.remove(&addr);
+        self.reconnects.unpin(&addr);
+    }
+
+    /// Dial a discovered peer before peers without priority, e.g. because it
+    /// is known to hold the data the user is waiting on.
+    pub fn prioritize_peer(&mut self, addr: SocketAddr, high: bool) {
+        self.dials.set_peer_priority(addr, high);
+    }
+
+    /// Dial peers found for a topic before peers without priority.
+    pub fn prioritize_topic(&mut self, topic: Topic, high: bool) {
+        self.dials.set_topic_priority(topic, high);
+    }
+
+    /// Ban a peer for `duration`, or until unbanned if None. Open connections
+    /// to it are closed, and it is neither dialed nor accepted.
+    pub fn ban(&mut self, peer: impl Into<PeerFilter>, duration: Option<Duration>) {
+        let peer = peer.into();
+        debug!("banning {}", redact(&peer));
+        self.bans.ban(peer, duration);
+        let bans = &mut self.bans;
+        self.connections
+            .evict_where(|addr| !bans.is_allowed(addr, None));
+    }
+
+    /// Report misbehavior of a peer, e.g. a protocol error detected by the
+    /// application. Misbehaving peers are dialed last, and banned once they
+    /// reach the threshold of the `ReputationPolicy`.
+    pub fn report_peer(&mut self, addr: SocketAddr, misbehavior: Misbehavior) {
+        let (score, ban) = self.reputation.record(addr, misbehavior);
+        debug!(
+            "peer {} misbehaved: {:?} (score {})",
+            redact(addr),
+            misbehavior,
+            score
+        );
+        self.dials.set_penalty(addr, score);
+        if ban {
+            self.dials.set_penalty(addr, 0);
+            let duration = self.reputation.ban_duration();
+            self.ban(addr, Some(duration));
+        }
+    }
+
+    // Like `report_peer`, for incoming connections that misbehaved before
+    // their peer could be told apart from others behind the same IP.
+    #[cfg(feature = "noise")]
+    fn report_ip(&mut self, ip: IpAddr, misbehavior: Misbehavior) {
+        let (score, ban) = self.reputation.record_ip(ip, misbehavior);
+        debug!(
+            "ip {} misbehaved: {:?} (score {})",
+            redact(ip),
+            misbehavior,
+            score
+        );
+        if ban {
+            let duration = self.reputation.ban_duration();
+            self.ban(ip, Some(duration));
+        }
+    }
+
+    pub fn unban(&mut self, peer: impl Into<PeerFilter>) {
+        self.bans.unban(&peer.into());
+    }
+
+    /// Only dial and accept peers matching one of `peers`, or any peer that
+    /// is not banned if None. Open connections to other peers are closed.
+    pub fn allow_only(&mut self, peers: Option<HashSet<PeerFilter>>) {
+        self.bans.allow_only(peers);
+        let bans = &mut self.bans;
+        self.connections
+            .evict_where(|addr| !bans.is_allowed(addr, None));
+    }
+
+    /// Leave all topics, unannouncing them, cancel pending dials and close
+    /// all connections, then stop discovery. Connections that nobody reads
+    /// from, or that are not closed within a timeout, are evicted. Resolves
+    /// once background tasks finished.
+    pub async fn shutdown(&mut self) {
+        debug!("shutting down swarm");
+        let topics: Vec<Topic> = self.topics.keys().copied().collect();
+        for topic in topics {
+            self.leave(topic);
+        }
+        self.dials = DialQueue::with_capacity(self.dials.capacity());
+        self.pending_checks = FuturesUnordered::new();
+        #[cfg(feature = "noise")]
+        {
+            self.pending_handshakes = FuturesUnordered::new();
+        }
+        self.reconnects.clear();
+        self.pinned.clear();
+        self.flushes.clear();
+        self.transport.cancel_connects();
+        self.dial_started.clear();
+        #[cfg(feature = "tracing")]
+        {
+            self.dial_spans.clear();
+        }
+
+        // A close only takes effect on the next read, so only connections
+        // with a pending read are waited for.
+        self.connections.close_all();
+        let closed = async {
+            while self.connections.has_pending_reads() {
+                runtime::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        if runtime::timeout(SHUTDOWN_TIMEOUT, closed).await.is_err() {
+            debug!("connections did not close in time");
+        }
+        let unread = self.connections.len();
+        if unread > 0 {
+            debug!("evicting {} connections that were not closed", unread);
+            self.connections.evict_where(|_| true);
+        }
+
+        self.discovery.shutdown(SHUTDOWN_TIMEOUT).await;
+    }
+
+    /// Handle to exchange joined topics over established connections, see
+    /// `TopicExchange`.
+    pub fn topic_exchange(&self) -> TopicExchange {
+        self.topic_exchange.clone()
+    }
+
+    /// Values attached to the open connection to a peer, which are also
+    /// passed along with its connection events.
+    pub fn connection_data(&mut self, addr: &SocketAddr) -> Option<ConnectionData> {
+        self.connections.data(addr)
+    }
+
+    /// The name set with `Config::set_name`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The public key peers see in the handshake, if the `noise` feature is
+    /// enabled.
+    pub fn public_key(&self) -> Option<[u8; 32]> {
+        self.local_key
+    }
+
+    /// A snapshot of joined topics, connections, dials and DHT health.
+    pub fn status(&mut self) -> SwarmStatus {
+        let open = self.connections.bytes();
+        let mut bytes = self.metrics.closed_bytes();
+        for (_, protocol, (read, written)) in open.iter() {
+            let total = bytes.entry(protocol.clone()).or_default();
+            total.0 += read;
+            total.1 += written;
+        }
+        let topic_bytes = |topic: &Topic| {
+            let conns = self.topic_conns.get(topic);
+            open.iter()
+                .filter(|(addr, _, _)| conns.map_or(false, |conns| conns.contains(addr)))
+                .fold(
+                    self.metrics.topic_closed_bytes(topic),
+                    |total, (_, _, bytes)| (total.0 + bytes.0, total.1 + bytes.1),
+                )
+        };
+        let mut topics: Vec<TopicStatus> = self
+            .topics
+            .iter()
+            .map(|(topic, config)| TopicStatus {
+                topic: hex::encode(topic),
+                announce: config.announce,
+                lookup: config.lookup,
+                announced: self.announced.contains(topic),
+                known_peers: self
+                    .peer_topics
+                    .values()
+                    .filter(|topics| topics.contains(topic))
+                    .count(),
+                pending_dials: self.dials.in_flight_for(topic),
+                bytes_read: topic_bytes(topic).0,
+                bytes_written: topic_bytes(topic).1,
+            })
+            .collect();
+        topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+        let dht = self.discovery.dht().map(|dht| {
+            let nodes = dht.nodes();
+            let count = |grade| nodes.iter().filter(|node| node.grade == grade).count();
+            DhtStatus {
+                good_nodes: count(NodeGrade::Good),
+                questionable_nodes: count(NodeGrade::Questionable),
+                bad_nodes: count(NodeGrade::Bad),
+            }
+        });
+        SwarmStatus {
+            name: self.name.clone(),
+            local_addr: self.transport.local_addr(),
+            external_addr: self.external_addr(),
+            topics,
+            connections: self.connections.counts(),
+            bytes: bytes
+                .into_iter()
+                .map(|(protocol, (read, written))| ByteCount {
+                    protocol,
+                    read,
+                    written,
+                })
+                .collect(),
+            queued_dials: self.dials.queued(),
+            pending_dials: self.dials.in_flight(),
+            dial_concurrency: self.dials.concurrency(),
+            dht,
+        }
+    }
+
+    /// Check the swarm for a liveness or readiness probe, requiring at least
+    /// `min_connections` open connections to be healthy, e.g.
+    /// `swarm.health(1).is_healthy()`.
+    pub fn health(&mut self, min_connections: usize) -> Health {
+        let dht_reachable = self
+            .discovery
+            .dht()
+            .map(|dht| dht.nodes().iter().any(|node| node.grade == NodeGrade::Good));
+        Health {
+            dht_reachable,
+            listening: self.mode != SwarmMode::DialOnly,
+            external_addr_known: self.external_addr().is_some(),
+            connections: self
+                .connections
+                .counts()
+                .iter()
+                .map(|c| c.inbound + c.outbound)
+                .sum(),
+            min_connections,
+        }
+    }
+
+    /// A detailed report of the routing table, topic schedules, dials and
+    /// connections, e.g. to attach to bug reports. Serializable with the
+    /// `serde` feature.
+    pub fn debug_dump(&mut self) -> DebugDump {
+        let mut routing_table: Vec<NodeDump> = self
+            .discovery
+            .dht()
+            .map(|dht| dht.nodes())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|node| NodeDump {
+                addr: node.addr,
+                grade: match node.grade {
+                    NodeGrade::Good => "good",
+                    NodeGrade::Questionable => "questionable",
+                    NodeGrade::Bad => "bad",
+                }
+                .to_string(),
+                last_seen: node.last_seen.map(runtime::elapsed),
+                rtt: node.rtt,
+                failures: node.failures,
+            })
+            .collect();
+        routing_table.sort_by_key(|node| node.addr);
+        let mut schedules = self.discovery.schedules();
+        schedules.sort_by(|a, b| a.topic.cmp(&b.topic));
+        DebugDump {
+            status: self.status(),
+            routing_table,
+            schedules,
+            dials: self.dials.dump(),
+            connections: self.connections.dump(),
+        }
+    }
+
+    /// Counters and gauges of the swarm, e.g. to export to Prometheus with
+    /// `Metrics::gather`.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Subscribe to lifecycle events. Each call returns a new receiver that
+    /// sees all events from then on; events are dropped for receivers that
+    /// fall behind.
+    pub fn events(&self) -> channel::Receiver<SwarmEvent> {
+        self.events.subscribe()
+    }
+
+    /// Register an observer that is called for every event from then on,
+    /// see `SwarmObserver`. Any number of observers can be registered.
+    pub fn add_observer(&self, observer: Arc<dyn SwarmObserver>) {
+        self.events.observe(observer);
+    }
+
+    /// Write every event as a line of JSON to a file or socket, see
+    /// `SwarmEvent::to_json`, until writing fails or the swarm is dropped.
+    /// Events are dropped if the writer does not keep up.
+    pub fn write_events_json<W>(&self, writer: W) -> JoinHandle<io::Result<()>>
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        self.spawner.spawn(
+            "hyperswarm::events",
+            write_json_lines(self.events.subscribe(), writer),
+        )
+    }
+
+    /// Run an async callback for every connection that is established, for
+    /// embedders that do not consume the swarm stream or events themselves.
+    pub fn on_open<F, Fut>(&self, f: F)
+    where
+        F: Fn(PeerInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.add_open(f);
+    }
+
+    /// Run an async callback for every connection that closes, with the
+    /// reason it closed.
+    pub fn on_close<F, Fut>(&self, f: F)
+    where
+        F: Fn(PeerInfo, CloseReason) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.add_close(f);
+    }
+
+    // Returns the connection unless it has to complete a handshake first.
+    #[cfg(feature = "noise")]
+    fn handshake(
+        &mut self,
+        mut conn: Connection<CombinedStream>,
+    ) -> Option<Connection<CombinedStream>> {
+        if self.plaintext {
+            return Some(conn);
+        }
+        let is_initiator = conn.is_initiator();
+        let peer_addr = conn.peer_addr();
+        // Both sides prove and check the private topics they share:
+        // initiators those they dial for, responders those they announce.
+        let topic_keys = self
+            .private_keys
+            .iter()
+            .filter(|(topic, _)| {
+                if is_initiator {
+                    self.peer_topics
+                        .get(&peer_addr)
+                        .map_or(false, |topics| topics.contains(topic))
+                } else {
+                    self.topics.get(topic).map_or(false, |c| c.announce)
+                }
+            })
+            .map(|(topic, key)| (*topic, *key))
+            .collect();
+        let request = HandshakeRequest {
+            is_initiator,
+            peer_addr,
+            topic_keys,
+        };
+        let handshaker = self.handshaker.clone();
+        let timeout = self.handshake.timeout;
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(parent: conn.span(), "handshake");
+        let fut = async move {
+            let started = runtime::now();
+            let handshake = handshaker.handshake(&mut conn, request);
+            let res = match runtime::timeout(timeout, handshake).await {
+                Ok(res) => res,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "handshake timed out",
+                )),
+            };
+            (res, runtime::elapsed(started), conn)
+        };
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
+        self.pending_handshakes.push(Box::pin(fut));
+        None
+    }
+
+    #[cfg(not(feature = "noise"))]
+    fn handshake(
+        &mut self,
+        conn: Connection<CombinedStream>,
+    ) -> Option<Connection<CombinedStream>> {
+        Some(conn)
+    }
+
+    // Returns the connection unless it has to pass the firewall first.
+    fn check_connection(
+        &mut self,
+        conn: Connection<CombinedStream>,
+    ) -> Option<Connection<CombinedStream>> {
+        let firewall = match self.firewall.as_ref() {
+            Some(firewall) => firewall,
+            None => return Some(conn),
+        };
+        let topics = match self.peer_topics.get(&conn.peer_addr()) {
+            Some(topics) if conn.is_initiator() => topics.clone(),
+            _ => conn.peer_info().topics().to_vec(),
+        };
+        let check = firewall.check(RemotePeer {
+            addr: conn.peer_addr(),
+            is_initiator: conn.is_initiator(),
+            protocol: conn.protocol().to_string(),
+            public_key: conn.peer_info().remote_public_key(),
+            handshake_payload: conn.peer_info().handshake_payload().to_vec(),
+            topics,
+        });
+        self.pending_checks
+            .push(Box::pin(async move { (check.await, conn) }));
+        None
+    }
+
+    // Returns the connection unless it was refused or routed to a topic
+    // handle.
+    fn on_connection(
+        &mut self,
+        mut conn: Connection<CombinedStream>,
+    ) -> Option<Connection<CombinedStream>> {
+        let peer_addr = conn.peer_addr();
+        // Address duplicates are dropped by the transport already.
+        if let (DedupPolicy::Key, Some(local_key), Some(remote_key)) = (
+            self.dedup,
+            self.local_key,
+            conn.peer_info().remote_public_key(),
+        ) {
+            let control = conn.control();
+            if !self
+                .by_key
+                .insert(&local_key, remote_key, conn.is_initiator(), control)
+            {
+                self.on_refused(&conn, ConnectError::Duplicate);
+                return None;
+            }
+        }
+        if !self.connections.admit(conn.is_initiator()) {
+            debug!(
+                "refusing connection to {}: at connection limit",
+                redact(peer_addr)
+            );
+            self.on_refused(&conn, ConnectError::ConnectionLimit);
+            return None;
+        }
+        if let Some(policy) = self.keepalive.clone() {
+            conn.set_keepalive(policy, &self.timers);
+        }
+        if let Some(policy) = self.coalesce.clone() {
+            conn.set_coalesce(policy, &self.timers);
+        }
+        conn.set_max_write_buffer(self.max_write_buffer);
+        self.connections.insert(
+            peer_addr,
+            conn.is_initiator(),
+            conn.protocol(),
+            conn.control(),
+        );
+        let mut state = None;
+        if conn.is_initiator() {
+            self.discovery.on_connected(peer_addr);
+            self.dials.on_connected(peer_addr);
+            self.reconnects.on_connected(&peer_addr);
+            let topics = self
+                .peer_topics
+                .get(&peer_addr)
+                .cloned()
+                .unwrap_or_default();
+            for topic in topics.iter() {
+                if let Some(pex) = self.discovery.pex() {
+                    pex.add_known(*topic, peer_addr);
+                }
+                self.metrics.on_topic_dial_finished(topic, true);
+                self.topic_conns
+                    .entry(*topic)
+                    .or_default()
+                    .insert(peer_addr);
+                let info = conn.peer_info_mut();
+                info.add_topic(*topic);
+                if let Some(peer) = self.discovery.peer_info(Some(*topic), peer_addr) {
+                    info.merge(peer);
+                }
+            }
+            state = topics
+                .iter()
+                .find_map(|topic| self.joined.get(topic))
+                .cloned();
+        } else if !conn.peer_info().topics().is_empty() {
+            // Topics proven in the handshake.
+            let topics = conn.peer_info().topics().to_vec();
+            state = topics
+                .iter()
+                .find_map(|topic| self.joined.get(topic))
+                .cloned();
+            self.on_exchanged(peer_addr, topics);
+        }
+        self.events.emit(SwarmEvent::ConnectionEstablished {
+            peer_addr,
+            is_initiator: conn.is_initiator(),
+            protocol: conn.protocol().to_string(),
+            data: conn.data().clone(),
+        });
+        self.hooks.opened(conn.peer_info());
+        let events = self.events.clone();
+        let hooks = self.hooks.clone();
+        let info = conn.peer_info().clone();
+        let data = conn.data().clone();
+        let hook_state = state.clone();
+        let closed_tx = self.closed_tx.clone();
+        let is_initiator = conn.is_initiator();
+        let metrics = self.metrics.clone();
+        let protocol = conn.protocol().to_string();
+        let topics = conn.peer_info().topics().to_vec();
+        let control = conn.control();
+        metrics.on_opened(&protocol, is_initiator);
+        for topic in topics.iter() {
+            metrics.on_topic_opened(topic);
+        }
+        conn.set_close_hook(move |reason| {
+            metrics.on_closed(&protocol, is_initiator);
+            for topic in topics.iter() {
+                metrics.on_topic_closed(topic);
+            }
+            let bytes = control.bytes();
+            metrics.on_connection_bytes(&protocol, bytes);
+            if let Some(state) = hook_state {
+                state.lock().unwrap().on_closed(&peer_addr);
+            }
+            let _ = closed_tx.try_send((peer_addr, is_initiator, reason.clone(), bytes));
+            hooks.closed(&info, &reason);
+            events.emit(SwarmEvent::ConnectionClosed {
+                peer_addr,
+                reason,
+                data,
+            });
+        });
+        match state {
+            Some(state) => {
+                let mut state = state.lock().unwrap();
+                state.on_connected(peer_addr);
+                state.route(conn)
+            }
+            None => Some(conn),
+        }
+    }
+
+    // Whether all server topics are private, so that incoming connections
+    // have to prove one of them in the handshake.
+    #[cfg(feature = "noise")]
+    fn requires_topic_proof(&self) -> bool {
+        let mut announced = self
+            .topics
+            .iter()
+            .filter(|(_, config)| config.announce)
+            .peekable();
+        announced.peek().is_some()
+            && announced.all(|(topic, _)| self.private_keys.contains_key(topic))
+    }
+
+    // Whether a dialed peer was only found for private topics, so that it has
+    // to prove one of them in the handshake.
+    #[cfg(feature = "noise")]
+    fn requires_proof_from(&self, addr: &SocketAddr) -> bool {
+        self.peer_topics.get(addr).map_or(false, |topics| {
+            !topics.is_empty() && topics.iter().all(|t| self.private_keys.contains_key(t))
+        })
+    }
+
+    // Whether a dialed peer has the key expected for the topics it was
+    // found for, if any.
+    #[cfg(feature = "noise")]
+    fn is_expected_key(&self, addr: &SocketAddr, remote_key: &[u8; 32]) -> bool {
+        self.peer_topics.get(addr).map_or(true, |topics| {
+            topics
+                .iter()
+                .filter_map(|topic| self.expected_keys.get(topic))
+                .all(|key| key == remote_key)
+        })
+    }
+
+    // Incoming connections are only accepted while a topic is joined as a
+    // server with room for more peers.
+    fn is_server(&self) -> bool {
+        self.topics
+            .iter()
+            .any(|(topic, config)| config.announce && self.has_room(topic))
+    }
+
+    // Whether a topic has not reached its peer cap. Only outgoing connections
+    // are counted, as the topics of incoming ones are not known.
+    fn has_room(&self, topic: &Topic) -> bool {
+        let max = match self.topics.get(topic).and_then(|c| c.max_peers) {
+            Some(max) => max,
+            None => return true,
+        };
+        self.topic_conns.get(topic).map_or(0, |conns| conns.len()) < max
+    }
+
+    // Whether a peer was found for a topic with room, or for no topic.
+    fn has_room_for(&self, addr: &SocketAddr) -> bool {
+        match self.peer_topics.get(addr) {
+            Some(topics) => topics.iter().any(|topic| self.has_room(topic)),
+            None => true,
+        }
+    }
+
+    // Peers are only dialed for topics joined as a client.
+    fn is_client(&self, topic: Option<Topic>) -> bool {
+        if self.mode == SwarmMode::ListenOnly {
+            return false;
+        }
+        match topic {
+            Some(topic) => self.topics.get(&topic).map_or(false, |c| c.lookup),
+            None => true,
+        }
+    }
+
+    // Whether a peer was joined or found for a topic that is still joined.
+    fn is_wanted(&mut self, addr: &SocketAddr) -> bool {
+        let joined = self.pinned.contains(addr)
+            || self.peer_topics.get(addr).map_or(false, |topics| {
+                topics.iter().any(|t| self.topics.contains_key(t))
+            });
+        joined && self.bans.is_allowed(addr, None)
+    }
+
+    // Report an outgoing connection dropped before it was established.
+    fn on_refused(&self, conn: &Connection<CombinedStream>, error: ConnectError) {
+        if conn.is_initiator() {
+            self.events.emit(SwarmEvent::ConnectFailed {
+                peer_addr: conn.peer_addr(),
+                error,
+            });
+        }
+    }
+
+    fn on_closed(
+        &mut self,
+        addr: SocketAddr,
+        is_initiator: bool,
+        reason: CloseReason,
+        bytes: (u64, u64),
+    ) {
+        for (topic, conns) in self.topic_conns.iter_mut() {
+            if conns.remove(&addr) {
+                self.metrics.on_topic_bytes(topic, bytes);
+            }
+        }
+        if let CloseReason::Error(_) = reason {
+            self.report_peer(addr, Misbehavior::AbruptDisconnect);
+        }
+        let lost = matches!(reason, CloseReason::Remote | CloseReason::Error(_));
+        if is_initiator && lost && self.is_wanted(&addr) {
+            debug!("lost connection to {} ({:?})", redact(addr), reason);
+            self.reconnects.on_lost(addr);
+        }
+        if is_initiator && !self.connections.is_connected(&addr) {
+            if let Some(pex) = self.discovery.pex() {
+                pex.remove_known(&addr);
+            }
+        }
+        // The peer's topics are kept for reconnects, and learned again when
+        // it is found again.
+        if !self.connections.is_connected(&addr)
+            && self.reconnects.attempt(&addr).is_none()
+            && !self.pinned.contains(&addr)
+        {
+            self.peer_topics.remove(&addr);
+        }
+    }
+
+    // Forget the topics of peers that are neither connected nor being
+    // dialed once too many are known.
+    fn prune_peer_topics(&mut self) {
+        if self.peer_topics.len() < self.max_known_peers {
+            return;
+        }
+        let before = self.peer_topics.len();
+        let (connections, dials, pinned) = (&self.connections, &self.dials, &self.pinned);
+        self.peer_topics.retain(|addr, _| {
+            connections.is_connected(addr) || dials.contains(addr) || pinned.contains(addr)
+        });
+        let dropped = before - self.peer_topics.len();
+        self.metrics.on_limit_hit("known_peers", dropped as u64);
+    }
+
+    // Attribute a connection to the topics found in a topic exchange.
+    fn on_exchanged(&mut self, addr: SocketAddr, topics: Vec<Topic>) {
+        if !self.connections.is_connected(&addr) {
+            return;
+        }
+        let known = self.peer_topics.entry(addr).or_default();
+        for topic in topics {
+            if !self.topics.contains_key(&topic) {
+                continue;
+            }
+            if !known.contains(&topic) {
+                known.push(topic);
+            }
+            self.topic_conns.entry(topic).or_default().insert(addr);
+        }
+    }
+
+    fn reconnect(&mut self, addr: SocketAddr) {
+        if !self.is_wanted(&addr) || !self.connections.can_dial() {
+            self.reconnects.cancel(&addr);
+            return;
+        }
+        let attempt = self.reconnects.attempt(&addr).unwrap_or_default();
+        debug!("reconnecting to {} (attempt {})", redact(addr), attempt);
+        self.events.emit(SwarmEvent::Reconnecting {
+            peer_addr: addr,
+            attempt,
+        });
+        self.dial(addr);
+    }
+
+    // Dial through the transport, in a span until it connects or fails with
+    // the `tracing` feature.
+    fn dial(&mut self, addr: SocketAddr) {
+        self.metrics.on_dial();
+        self.dial_started.insert(addr, runtime::now());
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::debug_span!("dial", peer = %redact(addr));
+            span.in_scope(|| tracing::debug!("dialing"));
+            self.dial_spans.insert(addr, span);
+        }
+        self.transport.connect(addr);
+    }
+
+    // Returns whether any dial was started.
+    fn dial_next(&mut self) -> bool {
+        let mut dialed = false;
+        while self.connections.can_dial() {
+            let addr = match self.dials.next_dial() {
+                Some(addr) => addr,
+                None => break,
+            };
+            if !self.bans.is_allowed(&addr, None) {
+                debug!("not dialing banned peer {}", redact(addr));
+                self.dials.cancel(&addr);
+                continue;
+            }
+            if !self.has_room_for(&addr) {
+                debug!(
+                    "not dialing {}: its topics are at their peer cap",
+                    redact(addr)
+                );
+                self.dials.cancel(&addr);
+                continue;
+            }
+            debug!("dialing {}", redact(addr));
+            self.events.emit(SwarmEvent::Connecting(addr));
+            self.dial(addr);
+            dialed = true;
+        }
+        dialed
     }
 
     pub fn handle(&self) -> SwarmHandle {
@@ -76,12 +1367,28 @@ impl Hyperswarm {
 
 #[derive(Debug, Clone)]
 pub struct SwarmHandle {
-    command_tx: channel::Sender<ConfigureCommand>,
+    command_tx: channel::Sender<Command>,
 }
 
 impl SwarmHandle {
     pub fn configure(&self, topic: Topic, config: TopicConfig) {
-        self.command_tx.try_send((topic, config)).unwrap();
+        self.command_tx
+            .try_send(Command::Configure(topic, config))
+            .unwrap();
+    }
+
+    pub fn leave(&self, topic: Topic) {
+        self.configure(topic, TopicConfig::default());
+    }
+
+    /// Resolves once every peer discovered so far was either connected or
+    /// failed, i.e. no dials are queued or in flight. The swarm has to be
+    /// polled meanwhile, e.g. by the task consuming its connections.
+    pub async fn flush(&self) {
+        let (tx, rx) = channel::bounded(1);
+        if self.command_tx.try_send(Command::Flush(tx)).is_ok() {
+            let _ = rx.recv().await;
+        }
     }
 }
 
@@ -90,17 +1397,242 @@ impl Stream for Hyperswarm {
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
 
+        if let Some(tasks) = this.tasks.as_mut() {
+            tasks.poll(cx);
+        }
+
+        if this.suspended {
+            this.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
         // Poll new connections.
-        let res = Pin::new(&mut this.transport).poll_next(cx);
-        if let Poll::Ready(Some(res)) = res {
-            debug!("new connection: {:?}", res);
-            return Poll::Ready(Some(res));
+        loop {
+            let res = Pin::new(&mut this.transport).poll_next(cx);
+            for (addr, error) in this.transport.take_failed_connects() {
+                this.discovery.on_connect_failed(addr);
+                if let Some(pex) = this.discovery.pex() {
+                    pex.remove_known(&addr);
+                }
+                if error == ConnectError::TimedOut {
+                    this.dials.on_timed_out(addr);
+                } else {
+                    this.dials.on_failed(addr);
+                }
+                this.reconnects.on_failed(addr);
+                this.events.emit(SwarmEvent::ConnectFailed {
+                    peer_addr: addr,
+                    error,
+                });
+                this.metrics.on_dial_finished(false);
+                this.dial_started.remove(&addr);
+                for topic in this.peer_topics.get(&addr).into_iter().flatten() {
+                    this.metrics.on_topic_dial_finished(topic, false);
+                }
+                #[cfg(feature = "tracing")]
+                {
+                    if let Some(span) = this.dial_spans.remove(&addr) {
+                        span.in_scope(|| tracing::debug!("connect failed"));
+                    }
+                }
+            }
+            let mut res = match res {
+                Poll::Ready(Some(res)) => res,
+                _ => break,
+            };
+            debug!("new connection: {:?}", redact(&res));
+            if this.dial_next() {
+                cx.waker().wake_by_ref();
+            }
+            if let Ok(conn) = res.as_mut() {
+                conn.set_metrics(this.metrics.clone());
+                if conn.is_initiator() {
+                    this.metrics.on_dial_finished(true);
+                    if let Some(started) = this.dial_started.remove(&conn.peer_addr()) {
+                        this.metrics
+                            .on_dial_latency(conn.protocol(), runtime::elapsed(started));
+                    }
+                    #[cfg(feature = "tracing")]
+                    {
+                        if let Some(span) = this.dial_spans.remove(&conn.peer_addr()) {
+                            conn.span().follows_from(&span);
+                        }
+                    }
+                }
+            }
+            match res {
+                Err(e) => return Poll::Ready(Some(Err(e))),
+                Ok(conn) if !this.bans.is_allowed(&conn.peer_addr(), None) => {
+                    debug!(
+                        "dropping connection to banned peer {}",
+                        redact(conn.peer_addr())
+                    );
+                }
+                Ok(conn)
+                    if !conn.is_initiator()
+                        && !this.connections.accept_from(conn.peer_addr().ip()) => {}
+                #[cfg(feature = "noise")]
+                Ok(conn)
+                    if !conn.is_initiator() && !this.greylist.allows(&conn.peer_addr().ip()) =>
+                {
+                    debug!(
+                        "dropping connection from greylisted {}",
+                        redact(conn.peer_addr())
+                    );
+                }
+                Ok(conn) if !conn.is_initiator() && !this.is_server() => {
+                    debug!(
+                        "refusing connection from {}: no server topic",
+                        redact(conn.peer_addr())
+                    );
+                }
+                Ok(conn) => {
+                    if let Some(conn) = this.handshake(conn) {
+                        if let Some(conn) = this.check_connection(conn) {
+                            if let Some(conn) = this.on_connection(conn) {
+                                return Poll::Ready(Some(Ok(conn)));
+                            }
+                        }
+                    }
+                }
+            }
         }
 
-        // Poll commands.
-        while let Poll::Ready(Some((topic, config))) = Pin::new(&mut this.command_rx).poll_next(cx)
+        // Poll handshakes, then check authenticated connections.
+        #[cfg(feature = "noise")]
+        while let Poll::Ready(Some((res, duration, mut conn))) =
+            Pin::new(&mut this.pending_handshakes).poll_next(cx)
+        {
+            let addr = conn.peer_addr();
+            let handshake = match res {
+                Ok(handshake) => {
+                    this.metrics.on_handshake_latency(conn.protocol(), duration);
+                    handshake
+                }
+                Err(e) => {
+                    debug!("handshake with {} failed: {}", redact(addr), e);
+                    this.metrics.on_handshake_failed();
+                    if conn.is_initiator() {
+                        this.dials.on_failed(addr);
+                        this.events.emit(SwarmEvent::ConnectFailed {
+                            peer_addr: addr,
+                            error: ConnectError::HandshakeFailed(e.to_string()),
+                        });
+                        this.report_peer(addr, Misbehavior::FailedHandshake);
+                    } else {
+                        this.greylist.on_failed(addr.ip());
+                        this.report_ip(addr.ip(), Misbehavior::FailedHandshake);
+                    }
+                    continue;
+                }
+            };
+            if !conn.is_initiator() {
+                this.greylist.on_success(&addr.ip());
+            }
+            if !this.bans.is_allowed(&addr, Some(&handshake.remote_key)) {
+                debug!(
+                    "dropping connection to banned key {}",
+                    redact(hex::encode(handshake.remote_key))
+                );
+                if conn.is_initiator() {
+                    this.dials.on_failed(addr);
+                    this.events.emit(SwarmEvent::ConnectFailed {
+                        peer_addr: addr,
+                        error: ConnectError::Banned,
+                    });
+                }
+                continue;
+            }
+            if conn.is_initiator() && !this.is_expected_key(&addr, &handshake.remote_key) {
+                debug!(
+                    "dropping connection to {}: unexpected key {}",
+                    redact(addr),
+                    redact(hex::encode(handshake.remote_key))
+                );
+                this.dials.on_failed(addr);
+                this.events.emit(SwarmEvent::ConnectFailed {
+                    peer_addr: addr,
+                    error: ConnectError::UnexpectedKey,
+                });
+                this.report_peer(addr, Misbehavior::FailedHandshake);
+                continue;
+            }
+            if conn.is_initiator() && handshake.topics.is_empty() && this.requires_proof_from(&addr)
+            {
+                debug!("dropping connection to {}: no topic proven", redact(addr));
+                this.dials.on_failed(addr);
+                this.events.emit(SwarmEvent::ConnectFailed {
+                    peer_addr: addr,
+                    error: ConnectError::HandshakeFailed("no topic proven".into()),
+                });
+                continue;
+            }
+            if !conn.is_initiator() && handshake.topics.is_empty() && this.requires_topic_proof() {
+                debug!("refusing connection from {}: no topic proven", redact(addr));
+                continue;
+            }
+            conn.set_cipher(
+                handshake.cipher,
+                handshake.hash,
+                this.handshake.rekey.clone(),
+            );
+            let info = conn.peer_info_mut();
+            info.set_remote_public_key(handshake.remote_key);
+            info.set_handshake_payload(handshake.payload);
+            for topic in handshake.topics {
+                info.add_topic(topic);
+            }
+            if let Some(conn) = this.check_connection(conn) {
+                if let Some(conn) = this.on_connection(conn) {
+                    return Poll::Ready(Some(Ok(conn)));
+                }
+            }
+        }
+
+        // Poll connections that passed the firewall.
+        while let Poll::Ready(Some((accepted, conn))) =
+            Pin::new(&mut this.pending_checks).poll_next(cx)
         {
-            this.configure(topic, config);
+            if !accepted {
+                debug!(
+                    "firewall rejected connection to {}",
+                    redact(conn.peer_addr())
+                );
+                if conn.is_initiator() {
+                    this.dials.on_failed(conn.peer_addr());
+                    this.events.emit(SwarmEvent::ConnectFailed {
+                        peer_addr: conn.peer_addr(),
+                        error: ConnectError::Rejected,
+                    });
+                }
+                continue;
+            }
+            if let Some(conn) = this.on_connection(conn) {
+                return Poll::Ready(Some(Ok(conn)));
+            }
+        }
+
+        // Poll lost connections and due reconnects.
+        while let Poll::Ready(Some((addr, is_initiator, reason, bytes))) =
+            Pin::new(&mut this.closed_rx).poll_next(cx)
+        {
+            this.on_closed(addr, is_initiator, reason, bytes);
+        }
+        while let Poll::Ready(Some((addr, topics))) = Pin::new(&mut this.exchanged_rx).poll_next(cx)
+        {
+            this.on_exchanged(addr, topics);
+        }
+        while let Poll::Ready(Some(addr)) = Pin::new(&mut this.reconnects).poll_next(cx) {
+            this.reconnect(addr);
+        }
+
+        // Poll commands.
+        while let Poll::Ready(Some(command)) = Pin::new(&mut this.command_rx).poll_next(cx) {
+            match command {
+                Command::Configure(topic, config) => this.configure(topic, config),
+                Command::Refresh(topic) => this.refresh(topic),
+                Command::Flush(tx) => this.flushes.push(tx),
+            }
         }
 
         // Poll discovery results.
@@ -109,7 +1641,71 @@ impl Stream for Hyperswarm {
             Poll::Pending | Poll::Ready(None) => {}
             Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
             Poll::Ready(Some(Ok(peer_info))) => {
-                this.transport.connect(peer_info.addr());
+                this.events
+                    .emit(SwarmEvent::PeerDiscovered(peer_info.clone()));
+                if let Some(topic) = peer_info.topic() {
+                    if this.topics.contains_key(&topic) {
+                        if !this.peer_topics.contains_key(&peer_info.addr()) {
+                            this.prune_peer_topics();
+                        }
+                        let topics = this.peer_topics.entry(peer_info.addr()).or_default();
+                        if !topics.contains(&topic) {
+                            topics.push(topic);
+                            this.metrics.on_topic_discovered(&topic);
+                        }
+                    }
+                }
+                let has_room = peer_info.topic().map_or(true, |t| this.has_room(&t));
+                if this.is_client(peer_info.topic()) && has_room {
+                    this.dials.push(&peer_info);
+                }
+            }
+        }
+        let discovery_shed = this.discovery.take_shed();
+        let dial_shed = this.dials.take_shed();
+        if discovery_shed + dial_shed > 0 {
+            this.metrics.on_peers_shed(discovery_shed + dial_shed);
+        }
+        this.metrics.on_limit_hit("discovery_queue", discovery_shed);
+        this.metrics.on_limit_hit("dial_queue", dial_shed);
+        for (resource, count) in this.discovery.take_limit_hits() {
+            this.metrics.on_limit_hit(resource, count);
+        }
+        let coalesced = this.discovery.take_coalesced_lookups();
+        if coalesced > 0 {
+            this.metrics.on_lookups_coalesced(coalesced);
+        }
+        for topic in this.discovery.take_announced() {
+            if let Some(state) = this.joined.get(&topic) {
+                state.lock().unwrap().set_announced();
+            }
+            this.announced.insert(topic);
+            this.metrics.on_topic_announced(&topic);
+            this.events.emit(SwarmEvent::TopicAnnounced(topic));
+        }
+        for topic in this.discovery.take_looked_up() {
+            if !this.is_client(Some(topic)) || !this.looked_up.insert(topic) {
+                continue;
+            }
+            let found = this.peer_topics.values().any(|t| t.contains(&topic));
+            if !found {
+                debug!("no peers found for {}", hex::encode(topic));
+                this.events.emit(SwarmEvent::TopicLookupEmpty(topic));
+            }
+        }
+
+        // New dials are only driven once the transport is polled again.
+        if this.dial_next() {
+            cx.waker().wake_by_ref();
+        }
+
+        let settled = this.dials.queued() == 0
+            && this.dials.in_flight() == 0
+            && this.pending_checks.is_empty();
+        if settled && !this.flushes.is_empty() {
+            debug!("swarm flushed");
+            for tx in this.flushes.drain(..) {
+                let _ = tx.try_send(());
             }
         }
 
@@ -119,13 +1715,382 @@ impl Stream for Hyperswarm {
 
 #[cfg(test)]
 mod test {
+    #[cfg(feature = "sim")]
+    use super::SHUTDOWN_TIMEOUT;
+    #[cfg(feature = "sim")]
+    use super::{CombinedStream, Connection};
     use super::{Config, Hyperswarm, TopicConfig};
     use crate::run_bootstrap_node;
+    #[cfg(feature = "sim")]
+    use crate::sim::Simulation;
+    #[cfg(all(feature = "sim", feature = "noise"))]
+    use crate::{discovery::private::TopicKey, ConnectError, DedupPolicy};
+    #[cfg(feature = "sim")]
+    use crate::{runtime, ConnectionLimits, EvictionPolicy, ReconnectPolicy, SwarmEvent};
     use async_std::channel;
     use async_std::task;
+    #[cfg(feature = "sim")]
+    use futures_lite::{future, Stream};
     use futures_lite::{AsyncReadExt, AsyncWriteExt, StreamExt};
+    #[cfg(feature = "sim")]
+    use std::future::Future;
     use std::io::Result;
     use std::net::SocketAddr;
+    #[cfg(feature = "sim")]
+    use std::pin::Pin;
+    #[cfg(feature = "sim")]
+    use std::task::Poll;
+    #[cfg(feature = "sim")]
+    use std::time::Duration;
+
+    // Poll the swarms of a simulation until `until` completes, keeping the
+    // connections they yield.
+    #[cfg(feature = "sim")]
+    async fn poll_until<F: Future>(
+        swarms: &mut [&mut Hyperswarm],
+        conns: &mut Vec<Connection<CombinedStream>>,
+        until: F,
+    ) -> F::Output {
+        futures::pin_mut!(until);
+        future::poll_fn(|cx| {
+            for swarm in swarms.iter_mut() {
+                while let Poll::Ready(Some(conn)) = Pin::new(&mut **swarm).poll_next(cx) {
+                    conns.extend(conn.ok());
+                }
+            }
+            until.as_mut().poll(cx)
+        })
+        .await
+    }
+
+    // Wait for the next connection a swarm established, returning the
+    // address of the peer.
+    #[cfg(feature = "sim")]
+    async fn established(events: &channel::Receiver<SwarmEvent>) -> SocketAddr {
+        loop {
+            if let Ok(SwarmEvent::ConnectionEstablished { peer_addr, .. }) = events.recv().await {
+                return peer_addr;
+            }
+        }
+    }
+
+    // Bind a server and a client of a topic, and wait until the client
+    // connected to the server.
+    #[cfg(feature = "sim")]
+    async fn connected(
+        server: Config,
+        client: Config,
+        topic: [u8; 32],
+    ) -> Result<(Hyperswarm, Hyperswarm, Vec<Connection<CombinedStream>>)> {
+        let mut server = Hyperswarm::bind(server).await?;
+        let mut client = Hyperswarm::bind(client).await?;
+        let (server_events, client_events) = (server.events(), client.events());
+        server.configure(topic, TopicConfig::server());
+        client.configure(topic, TopicConfig::client());
+        let mut conns = vec![];
+        let both = future::zip(established(&server_events), established(&client_events));
+        poll_until(&mut [&mut server, &mut client], &mut conns, both).await;
+        Ok((server, client, conns))
+    }
+
+    // Open incoming and outgoing connections of a swarm.
+    #[cfg(feature = "sim")]
+    fn open_connections(swarm: &mut Hyperswarm) -> (usize, usize) {
+        let counts = swarm.status().connections;
+        counts.iter().fold((0, 0), |(inbound, outbound), count| {
+            (inbound + count.inbound, outbound + count.outbound)
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_status() -> Result<()> {
+        let sim = Simulation::new(2);
+        let topic = [2u8; 32];
+        sim.run(async {
+            let config = sim.config().set_name(Some("server".into()));
+            let (mut server, mut client, _conns) = connected(config, sim.config(), topic).await?;
+            let status = server.status();
+            assert_eq!(status.name.as_deref(), Some("server"));
+            assert_eq!(status.topics.len(), 1);
+            assert_eq!(status.topics[0].topic, hex::encode(topic));
+            assert!(status.topics[0].announce);
+            assert!(!status.topics[0].lookup);
+            assert_eq!(open_connections(&mut server), (1, 0));
+            assert_eq!(open_connections(&mut client), (0, 1));
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_forget_closed_peer() -> Result<()> {
+        let sim = Simulation::new(13);
+        let topic = [13u8; 32];
+        sim.run(async {
+            let (mut server, mut client, mut conns) =
+                connected(sim.config(), sim.config(), topic).await?;
+            assert_eq!(client.status().topics[0].known_peers, 1);
+            let client_events = client.events();
+            // The client closes its end, so it does not reconnect.
+            conns.retain(|conn| !conn.is_initiator());
+            let closed = async {
+                while !matches!(
+                    client_events.recv().await,
+                    Ok(SwarmEvent::ConnectionClosed { .. })
+                ) {}
+                runtime::sleep(Duration::from_millis(1)).await;
+            };
+            poll_until(&mut [&mut server, &mut client], &mut conns, closed).await;
+            assert_eq!(client.status().topics[0].known_peers, 0);
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_ban() -> Result<()> {
+        let sim = Simulation::new(3);
+        let topic = [3u8; 32];
+        sim.run(async {
+            let (mut server, _client, mut conns) =
+                connected(sim.config(), sim.config(), topic).await?;
+            let incoming = conns.iter_mut().find(|conn| !conn.is_initiator()).unwrap();
+            server.ban(incoming.peer_addr().ip(), None);
+            assert_eq!(open_connections(&mut server), (0, 0));
+            assert!(server.connection_data(&incoming.peer_addr()).is_none());
+            let mut buf = [0u8; 1];
+            assert!(incoming.read(&mut buf).await.is_err());
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_evict_idle() -> Result<()> {
+        let sim = Simulation::new(4);
+        let topic = [4u8; 32];
+        let limits = ConnectionLimits::default()
+            .set_max_inbound(Some(1))
+            .set_eviction(EvictionPolicy::Idle);
+        sim.run(async {
+            let config = sim.config().set_connection_limits(limits);
+            let (mut server, mut first, mut conns) = connected(config, sim.config(), topic).await?;
+            // The first connection is idle for a while before the second.
+            let idle = runtime::sleep(Duration::from_secs(1));
+            poll_until(&mut [&mut server, &mut first], &mut conns, idle).await;
+            let server_events = server.events();
+            let mut second = Hyperswarm::bind(sim.config()).await?;
+            second.configure(topic, TopicConfig::client());
+            let swarms = &mut [&mut server, &mut first, &mut second];
+            poll_until(swarms, &mut conns, established(&server_events)).await;
+
+            assert_eq!(open_connections(&mut server), (1, 0));
+            let oldest = conns.iter_mut().find(|conn| !conn.is_initiator()).unwrap();
+            let mut buf = [0u8; 1];
+            assert!(oldest.read(&mut buf).await.is_err());
+            Ok(())
+        })
+    }
+
+    #[cfg(all(feature = "sim", feature = "noise"))]
+    #[test]
+    fn test_dedup_by_key() -> Result<()> {
+        let sim = Simulation::new(5);
+        let topic = [5u8; 32];
+        sim.run(async {
+            let config = sim.config().set_dedup_policy(DedupPolicy::Key);
+            let mut a = Hyperswarm::bind(config.clone()).await?;
+            let mut b = Hyperswarm::bind(config).await?;
+            // Both dial each other.
+            a.configure(topic, TopicConfig::both());
+            b.configure(topic, TopicConfig::both());
+            let mut conns = vec![];
+            let settled = runtime::sleep(Duration::from_secs(10));
+            poll_until(&mut [&mut a, &mut b], &mut conns, settled).await;
+
+            // Both sides kept the same one of the connections.
+            let (inbound, outbound) = open_connections(&mut a);
+            assert_eq!(inbound + outbound, 1);
+            assert_eq!(open_connections(&mut b), (outbound, inbound));
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_reconnect() -> Result<()> {
+        let sim = Simulation::new(6);
+        let topic = [6u8; 32];
+        sim.run(async {
+            let policy = ReconnectPolicy::default().set_initial_delay(Duration::from_secs(2));
+            let config = sim.config().set_reconnect_policy(policy);
+            let (mut server, mut client, mut conns) =
+                connected(sim.config(), config, topic).await?;
+            let client_events = client.events();
+            // The server drops its end, which the client sees on its next read.
+            conns.retain(|conn| conn.is_initiator());
+            let server_addr = conns[0].peer_addr();
+            let mut buf = [0u8; 1];
+            let _ = conns[0].read(&mut buf).await;
+            conns.clear();
+
+            let reconnected = async {
+                loop {
+                    if let Ok(SwarmEvent::Reconnecting { peer_addr, .. }) =
+                        client_events.recv().await
+                    {
+                        assert_eq!(peer_addr, server_addr);
+                        break;
+                    }
+                }
+                established(&client_events).await
+            };
+            let swarms = &mut [&mut server, &mut client];
+            assert_eq!(
+                poll_until(swarms, &mut conns, reconnected).await,
+                server_addr
+            );
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_shutdown() -> Result<()> {
+        let sim = Simulation::new(7);
+        let topic = [7u8; 32];
+        sim.run(async {
+            let (mut server, _client, _conns) =
+                connected(sim.config(), sim.config(), topic).await?;
+            server.shutdown().await;
+            assert!(server.status().topics.is_empty());
+            assert_eq!(open_connections(&mut server), (0, 0));
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_shutdown_idle_connection() -> Result<()> {
+        let sim = Simulation::new(9);
+        let topic = [9u8; 32];
+        sim.run(async {
+            let (mut server, _client, mut conns) =
+                connected(sim.config(), sim.config(), topic).await?;
+            // Nobody reads the incoming connection.
+            let started = sim.clock().elapsed();
+            server.shutdown().await;
+            assert!(sim.clock().elapsed() - started < SHUTDOWN_TIMEOUT);
+            assert_eq!(open_connections(&mut server), (0, 0));
+            let incoming = conns.iter_mut().find(|conn| !conn.is_initiator()).unwrap();
+            let mut buf = [0u8; 1];
+            assert!(incoming.read(&mut buf).await.is_err());
+            Ok(())
+        })
+    }
+
+    #[cfg(all(feature = "sim", feature = "noise"))]
+    #[test]
+    fn test_private_topic() -> Result<()> {
+        let sim = Simulation::new(8);
+        let key = TopicKey::new([8u8; 32]);
+        sim.run(async {
+            let mut server = Hyperswarm::bind(sim.config()).await?;
+            let mut client = Hyperswarm::bind(sim.config()).await?;
+            let (server_events, client_events) = (server.events(), client.events());
+            let topic = server.configure_private(&key, TopicConfig::server());
+            assert_eq!(client.configure_private(&key, TopicConfig::client()), topic);
+            let mut conns = vec![];
+            let both = future::zip(established(&server_events), established(&client_events));
+            poll_until(&mut [&mut server, &mut client], &mut conns, both).await;
+            // Each side proved the topic to the other.
+            assert_eq!(conns.len(), 2);
+            for conn in conns.iter() {
+                assert!(conn.peer_info().topics().contains(&topic));
+            }
+            Ok(())
+        })
+    }
+
+    #[cfg(all(feature = "sim", feature = "noise"))]
+    #[test]
+    fn test_mixed_topics() -> Result<()> {
+        let sim = Simulation::new(14);
+        let key = TopicKey::new([14u8; 32]);
+        sim.run(async {
+            let mut server = Hyperswarm::bind(sim.config()).await?;
+            let mut client = Hyperswarm::bind(sim.config()).await?;
+            let (server_events, client_events) = (server.events(), client.events());
+            server.configure([15u8; 32], TopicConfig::server());
+            let topic = server.configure_private(&key, TopicConfig::server());
+            // Found the server under the private topic, without the key.
+            client.configure(topic, TopicConfig::client());
+            let mut conns = vec![];
+            let both = future::zip(established(&server_events), established(&client_events));
+            poll_until(&mut [&mut server, &mut client], &mut conns, both).await;
+            // Accepted, as the server announces a public topic too, but not
+            // attributed to the private topic.
+            let incoming = conns.iter().find(|conn| !conn.is_initiator()).unwrap();
+            assert!(incoming.peer_info().topics().is_empty());
+            let status = server.status();
+            let private = status
+                .topics
+                .iter()
+                .find(|status| status.topic == hex::encode(topic))
+                .unwrap();
+            assert_eq!(private.known_peers, 0);
+            Ok(())
+        })
+    }
+
+    #[cfg(all(feature = "sim", feature = "noise"))]
+    #[test]
+    fn test_responder_without_topic_key() -> Result<()> {
+        let sim = Simulation::new(1);
+        let key = TopicKey::new([7u8; 32]);
+        sim.run(async {
+            let mut server = Hyperswarm::bind(sim.config()).await?;
+            let mut client = Hyperswarm::bind(sim.config()).await?;
+            // Announced by a server that does not know the key.
+            server.configure(key.topic(), TopicConfig::server());
+            client.configure_private(&key, TopicConfig::client());
+            let events = client.events();
+            let failed = async {
+                loop {
+                    if let Ok(SwarmEvent::ConnectFailed { error, .. }) = events.recv().await {
+                        return error;
+                    }
+                }
+            };
+            let mut conns = vec![];
+            let error = poll_until(&mut [&mut server, &mut client], &mut conns, failed).await;
+            assert_eq!(
+                error,
+                ConnectError::HandshakeFailed("no topic proven".into())
+            );
+            assert!(conns.iter().all(|conn| !conn.is_initiator()));
+            Ok(())
+        })
+    }
+
+    #[async_std::test]
+    async fn test_multiple_instances() -> Result<()> {
+        let (bs_addr, _bs_task) = run_bootstrap_node::<SocketAddr>(None).await?;
+        let config = Config::default().set_bootstrap_nodes(Some(vec![bs_addr]));
+        let mut swarm_a = Hyperswarm::bind(config.clone().set_name(Some("a".into()))).await?;
+        let mut swarm_b = Hyperswarm::bind(config.set_name(Some("b".into())).set_pex(true)).await?;
+        assert_eq!(swarm_a.name(), Some("a"));
+        assert_eq!(swarm_b.name(), Some("b"));
+        assert_ne!(swarm_a.status().local_addr, swarm_b.status().local_addr);
+        assert!(swarm_a.pex().is_none());
+        assert!(swarm_b.pex().is_some());
+
+        swarm_a.join([1u8; 32], TopicConfig::both());
+        assert_eq!(swarm_a.status().topics.len(), 1);
+        assert!(swarm_b.status().topics.is_empty());
+        Ok(())
+    }
 
     #[async_std::test]
     async fn test_swarm() -> Result<()> {
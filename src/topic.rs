@@ -0,0 +1,243 @@
+use async_std::channel;
+use blake2_rfc::blake2b::Blake2b;
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Debug;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::config::TopicConfig;
+use crate::discovery::Topic;
+use crate::metrics::{Metrics, TopicMetrics};
+use crate::swarm::Command;
+use crate::transport::{combined::CombinedStream, Connection};
+
+type TopicConnection = Connection<CombinedStream>;
+
+const MAX_EXCHANGED_TOPICS: usize = 64;
+
+#[derive(Debug, Default)]
+pub(crate) struct TopicState {
+    announced: bool,
+    connected: HashSet<SocketAddr>,
+    connections: Option<channel::Sender<TopicConnection>>,
+}
+
+impl TopicState {
+    pub fn set_announced(&mut self) {
+        self.announced = true;
+    }
+
+    pub fn on_connected(&mut self, addr: SocketAddr) {
+        self.connected.insert(addr);
+    }
+
+    pub fn on_closed(&mut self, addr: &SocketAddr) {
+        self.connected.remove(addr);
+    }
+
+    pub fn on_leave(&mut self) {
+        self.announced = false;
+        self.connections = None;
+    }
+
+    /// Send a connection to the topic's connection stream. Returns it back if
+    /// nobody is listening.
+    pub fn route(&mut self, conn: TopicConnection) -> Option<TopicConnection> {
+        let tx = match self.connections.as_ref() {
+            Some(tx) => tx,
+            None => return Some(conn),
+        };
+        match tx.try_send(conn) {
+            Ok(()) => None,
+            Err(err) => {
+                self.connections = None;
+                Some(err.into_inner())
+            }
+        }
+    }
+}
+
+/// A joined topic, returned from `Hyperswarm::join`.
+#[derive(Clone)]
+pub struct TopicHandle {
+    topic: Topic,
+    state: Arc<Mutex<TopicState>>,
+    command_tx: channel::Sender<Command>,
+    metrics: Metrics,
+}
+
+impl fmt::Debug for TopicHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopicHandle")
+            .field("topic", &pretty_hash::fmt(&self.topic).unwrap())
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl TopicHandle {
+    pub(crate) fn new(
+        topic: Topic,
+        state: Arc<Mutex<TopicState>>,
+        command_tx: channel::Sender<Command>,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            topic,
+            state,
+            command_tx,
+            metrics,
+        }
+    }
+
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+
+    /// Whether the DHT confirmed an announce for this topic.
+    pub fn is_announced(&self) -> bool {
+        self.state.lock().unwrap().announced
+    }
+
+    /// Number of open outgoing connections to peers found for this topic.
+    pub fn peer_count(&self) -> usize {
+        self.state.lock().unwrap().connected.len()
+    }
+
+    /// Peer counts, connect success rate, time to the first peer and
+    /// announce freshness of this topic. Empty once the topic was left.
+    pub fn metrics(&self) -> TopicMetrics {
+        self.metrics.topic(&self.topic).unwrap_or_default()
+    }
+
+    /// Outgoing connections to peers found for this topic. Once called, these
+    /// connections are yielded here instead of from the swarm; calling it
+    /// again replaces the previous stream. Incoming connections are always
+    /// yielded from the swarm, as their topic is not known.
+    pub fn connections(&self) -> channel::Receiver<TopicConnection> {
+        let (tx, rx) = channel::unbounded();
+        self.state.lock().unwrap().connections = Some(tx);
+        rx
+    }
+
+    /// Look up and announce the topic again now, see `Hyperswarm::refresh`.
+    pub fn refresh(&self) {
+        self.command_tx
+            .try_send(Command::Refresh(self.topic))
+            .unwrap();
+    }
+
+    /// Stop announcing and looking up the topic. This ends the connection
+    /// stream.
+    pub fn leave(&self) {
+        self.command_tx
+            .try_send(Command::Configure(self.topic, TopicConfig::default()))
+            .unwrap();
+    }
+}
+
+/// Handle to exchange joined topics over established connections, so that a
+/// single connection is attributed to all topics both peers joined.
+///
+/// Only hashes of the topics are sent, so peers learn nothing about topics
+/// they did not join themselves.
+#[derive(Debug, Clone)]
+pub struct TopicExchange {
+    joined: Arc<Mutex<HashSet<Topic>>>,
+    updates: channel::Sender<(SocketAddr, Vec<Topic>)>,
+}
+
+impl TopicExchange {
+    pub(crate) fn new(updates: channel::Sender<(SocketAddr, Vec<Topic>)>) -> Self {
+        Self {
+            joined: Arc::new(Mutex::new(HashSet::new())),
+            updates,
+        }
+    }
+
+    pub(crate) fn set_joined(&self, topic: Topic, joined: bool) {
+        let mut topics = self.joined.lock().unwrap();
+        if joined {
+            topics.insert(topic);
+        } else {
+            topics.remove(&topic);
+        }
+    }
+
+    /// Exchange joined topics with the remote end of `conn`. The topics in
+    /// common are added to its `PeerInfo`, counted against the topics' peer
+    /// caps and returned.
+    pub async fn exchange<T>(&self, conn: &mut Connection<T>) -> io::Result<Vec<Topic>>
+    where
+        T: Debug + AsyncRead + AsyncWrite + Unpin,
+    {
+        let joined: Vec<Topic> = self
+            .joined
+            .lock()
+            .unwrap()
+            .iter()
+            .take(MAX_EXCHANGED_TOPICS)
+            .copied()
+            .collect();
+        let mut frame = vec![joined.len() as u8];
+        for topic in joined.iter() {
+            frame.extend_from_slice(&topic_hash(topic));
+        }
+        conn.write_all(&frame).await?;
+        conn.flush().await?;
+
+        let mut count = [0u8; 1];
+        conn.read_exact(&mut count).await?;
+        if count[0] as usize > MAX_EXCHANGED_TOPICS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many topics in topic exchange",
+            ));
+        }
+        let mut remote = HashSet::new();
+        for _ in 0..count[0] {
+            let mut hash = [0u8; 32];
+            conn.read_exact(&mut hash).await?;
+            remote.insert(hash);
+        }
+
+        let common = common_topics(&joined, &remote);
+        for topic in common.iter() {
+            conn.peer_info_mut().add_topic(*topic);
+        }
+        let _ = self.updates.try_send((conn.peer_addr(), common.clone()));
+        Ok(common)
+    }
+}
+
+fn topic_hash(topic: &Topic) -> [u8; 32] {
+    let mut hasher = Blake2b::with_key(32, b"hyperswarm topic exchange");
+    hasher.update(topic);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(hasher.finalize().as_bytes());
+    hash
+}
+
+fn common_topics(joined: &[Topic], remote: &HashSet<[u8; 32]>) -> Vec<Topic> {
+    joined
+        .iter()
+        .filter(|topic| remote.contains(&topic_hash(topic)))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_common_topics() {
+        let a = [1u8; 32];
+        let b = [2u8; 32];
+        let remote: HashSet<[u8; 32]> = vec![topic_hash(&b), [9u8; 32]].into_iter().collect();
+        assert_eq!(common_topics(&[a, b], &remote), vec![b]);
+    }
+}
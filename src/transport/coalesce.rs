@@ -0,0 +1,280 @@
+use futures::io::{AsyncRead, AsyncWrite, IoSlice};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::config::CoalescePolicy;
+use crate::runtime::{Timer, TimerWheel};
+
+/// Buffering of small writes, see `CoalescePolicy`.
+///
+/// Writes smaller than `max_bytes` are collected and written out together
+/// once `max_bytes` would be exceeded, on flush, or after `max_delay`. The
+/// delay is armed on write, and its timer wakes the task reading the
+/// connection, as the writer may be parked elsewhere by then. Without a
+/// pending read nothing would write the buffer out in time, so writes then
+/// go straight through, after what is buffered.
+pub(crate) struct Coalesce {
+    policy: CoalescePolicy,
+    buf: Vec<u8>,
+    timers: TimerWheel,
+    deadline: Option<Timer>,
+    // Whether the deadline passed with data still buffered.
+    due: bool,
+    read_waker: Option<Waker>,
+}
+
+impl fmt::Debug for Coalesce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Coalesce")
+            .field("policy", &self.policy)
+            .field("buffered", &self.buf.len())
+            .field("due", &self.due)
+            .finish()
+    }
+}
+
+impl Coalesce {
+    pub fn new(policy: CoalescePolicy, timers: &TimerWheel) -> Self {
+        Self {
+            buf: Vec::with_capacity(policy.max_bytes),
+            policy,
+            timers: timers.clone(),
+            deadline: None,
+            due: false,
+            read_waker: None,
+        }
+    }
+
+    /// Lower `max_bytes`, e.g. to a resource limit.
+    pub fn set_max_bytes(&mut self, max: usize) {
+        self.policy.max_bytes = self.policy.max_bytes.min(max);
+    }
+
+    pub fn poll_write<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        reading: bool,
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        if !reading || self.due || self.buf.len() + buf.len() > self.policy.max_bytes {
+            futures::ready!(self.poll_drain(inner, cx))?;
+        }
+        if !reading || buf.len() >= self.policy.max_bytes {
+            return Pin::new(inner).poll_write(cx, buf);
+        }
+        self.buf.extend_from_slice(buf);
+        if self.deadline.is_none() {
+            self.deadline = Some(self.timers.sleep(self.policy.max_delay));
+            self.poll_timer(inner, cx)?;
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Write out buffered data.
+    pub fn poll_drain<T>(&mut self, inner: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        while !self.buf.is_empty() {
+            let n = futures::ready!(Pin::new(&mut *inner).poll_write(cx, &self.buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.buf.drain(..n);
+        }
+        self.deadline = None;
+        self.due = false;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write out buffered data if the deadline passed.
+    fn poll_timer<T>(&mut self, inner: &mut T, cx: &mut Context<'_>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        if let Some(deadline) = self.deadline.as_mut() {
+            let waker = self
+                .read_waker
+                .clone()
+                .unwrap_or_else(|| cx.waker().clone());
+            if Pin::new(deadline)
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                self.deadline = None;
+                self.due = true;
+            }
+        }
+        if self.due {
+            let _ = self.poll_drain(inner, cx)?;
+        }
+        Ok(())
+    }
+}
+
+/// A stream whose small writes are coalesced.
+pub(crate) struct Coalesced<'a, T: ?Sized> {
+    pub inner: &'a mut T,
+    pub coalesce: &'a mut Coalesce,
+    /// Whether a read of the connection is pending or in progress, so that
+    /// the reader writes the buffer out after the delay.
+    pub reading: bool,
+}
+
+impl<T> AsyncRead for Coalesced<'_, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.reading = true;
+        let coalesce = &mut *this.coalesce;
+        if !coalesce
+            .read_waker
+            .as_ref()
+            .map_or(false, |waker| waker.will_wake(cx.waker()))
+        {
+            coalesce.read_waker = Some(cx.waker().clone());
+        }
+        coalesce.poll_timer(this.inner, cx)?;
+        Pin::new(&mut *this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Coalesced<'_, T>
+where
+    T: AsyncWrite + Unpin + ?Sized,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.coalesce.poll_write(this.inner, cx, buf, this.reading)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if !this.reading || total >= this.coalesce.policy.max_bytes {
+            futures::ready!(this.coalesce.poll_drain(this.inner, cx))?;
+            return Pin::new(&mut *this.inner).poll_write_vectored(cx, bufs);
+        }
+        let mut written = 0;
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            match this.coalesce.poll_write(this.inner, cx, buf, this.reading) {
+                Poll::Ready(Ok(n)) => written += n,
+                res if written == 0 => return res,
+                _ => break,
+            }
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.coalesce.poll_drain(this.inner, cx))?;
+        Pin::new(&mut *this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.coalesce.poll_drain(this.inner, cx))?;
+        Pin::new(&mut *this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::io::{AsyncWriteExt, Cursor};
+    use std::time::Duration;
+
+    // Counts the writes reaching the transport.
+    #[derive(Default)]
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        writes: usize,
+    }
+
+    impl AsyncWrite for Counting {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_close(cx)
+        }
+    }
+
+    #[async_std::test]
+    async fn test_coalesce() -> io::Result<()> {
+        let policy = CoalescePolicy::default()
+            .set_max_bytes(16)
+            .set_max_delay(Duration::from_secs(60));
+        let mut coalesce = Coalesce::new(policy, &TimerWheel::default());
+        let mut inner = Counting::default();
+        let mut stream = Coalesced {
+            inner: &mut inner,
+            coalesce: &mut coalesce,
+            reading: true,
+        };
+        for _ in 0..3 {
+            stream.write_all(b"hello").await?;
+        }
+        // Over the limit, so the first three go out in one write.
+        stream.write_all(b"hello").await?;
+        // Large writes pass straight through after the buffer.
+        stream.write_all(&[0u8; 32]).await?;
+        stream.write_all(b"bye").await?;
+        stream.flush().await?;
+        assert_eq!(inner.writes, 4);
+        assert_eq!(inner.inner.get_ref().len(), 5 * 4 + 32 + 3);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_write_only() -> io::Result<()> {
+        let policy = CoalescePolicy::default().set_max_delay(Duration::from_secs(60));
+        let mut coalesce = Coalesce::new(policy, &TimerWheel::default());
+        let mut inner = Counting::default();
+        let mut stream = Coalesced {
+            inner: &mut inner,
+            coalesce: &mut coalesce,
+            reading: true,
+        };
+        stream.write_all(b"hello").await?;
+        // Once nobody reads, the buffer goes out with the next write instead
+        // of waiting for a reader to flush it.
+        stream.reading = false;
+        stream.write_all(b"bye").await?;
+        assert_eq!(inner.writes, 2);
+        assert_eq!(inner.inner.get_ref(), b"hellobye");
+        Ok(())
+    }
+}
@@ -3,23 +3,36 @@ use log::*;
 use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Debug;
-use std::io;
+use std::io::{self, IoSlice};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+#[cfg(feature = "sim")]
+use super::memory::{MemoryStream, MemoryTransport};
 use super::tcp::{TcpStream, TcpTransport};
 #[cfg(feature = "transport_utp")]
 use super::utp::{UtpStream, UtpTransport};
 use super::{Connection, Transport};
+use crate::error::ConnectError;
+use crate::redact::redact;
 
 #[derive(Debug)]
 pub struct CombinedTransport {
     tcp: TcpTransport,
     #[cfg(feature = "transport_utp")]
-    utp: UtpTransport,
+    utp: Option<UtpTransport>,
+    // Replaces both TCP and uTP if set.
+    #[cfg(feature = "sim")]
+    memory: Option<MemoryTransport>,
     local_addr: SocketAddr,
     connected: HashSet<SocketAddr>,
+    dedup: bool,
+    cancelled: HashSet<SocketAddr>,
+    // Whether uTP is polled before TCP next time, flipped whenever one of
+    // them yields a connection.
+    #[cfg(feature = "transport_utp")]
+    utp_first: bool,
 }
 
 impl CombinedTransport {
@@ -34,30 +47,143 @@ impl CombinedTransport {
         Ok(Self {
             tcp,
             #[cfg(feature = "transport_utp")]
-            utp,
+            utp: Some(utp),
+            #[cfg(feature = "sim")]
+            memory: None,
             local_addr,
             connected: HashSet::new(), // pending_connects: HashSet::new(),
+            dedup: true,
+            cancelled: HashSet::new(),
+            #[cfg(feature = "transport_utp")]
+            utp_first: false,
         })
     }
 
+    /// A transport that binds no sockets and only dials out. uTP needs a
+    /// bound UDP socket, so only TCP is used.
+    pub fn dial_only() -> Self {
+        let tcp = TcpTransport::dial_only();
+        let local_addr = tcp.local_addr();
+        Self {
+            tcp,
+            #[cfg(feature = "transport_utp")]
+            utp: None,
+            #[cfg(feature = "sim")]
+            memory: None,
+            local_addr,
+            connected: HashSet::new(),
+            dedup: true,
+            cancelled: HashSet::new(),
+            #[cfg(feature = "transport_utp")]
+            utp_first: false,
+        }
+    }
+
+    /// A transport on a `MemoryNetwork` instead of sockets, for tests and
+    /// simulations.
+    #[cfg(feature = "sim")]
+    pub fn memory(memory: MemoryTransport) -> Self {
+        let local_addr = memory.local_addr();
+        Self {
+            memory: Some(memory),
+            local_addr,
+            ..Self::dial_only()
+        }
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
 
-    fn on_poll_connection<T, F>(
+    /// Whether a second outgoing connection to an address, e.g. over the
+    /// other transport, is dropped. Enabled by default.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Outgoing connections that failed since the last call. Only TCP
+    /// reports failures, as uTP connects do not carry the address.
+    pub fn take_failed_connects(&mut self) -> Vec<(SocketAddr, ConnectError)> {
+        let mut failed = self.tcp.take_failed_connects();
+        #[cfg(feature = "sim")]
+        if let Some(memory) = self.memory.as_mut() {
+            failed.extend(memory.take_failed_connects());
+        }
+        let cancelled = &self.cancelled;
+        failed
+            .into_iter()
+            .filter(|(addr, _)| !cancelled.contains(addr))
+            .collect()
+    }
+
+    /// Drop the outgoing connection to an address if it is established after
+    /// all, until the address is dialed again.
+    pub fn cancel_connect(&mut self, peer_addr: SocketAddr) {
+        self.cancelled.insert(peer_addr);
+    }
+
+    /// Abort all outgoing connections that are still being established.
+    pub fn cancel_connects(&mut self) {
+        self.cancelled.clear();
+        self.tcp.cancel_connects();
+        #[cfg(feature = "transport_utp")]
+        if let Some(utp) = self.utp.as_mut() {
+            utp.cancel_connects();
+        }
+        #[cfg(feature = "sim")]
+        if let Some(memory) = self.memory.as_mut() {
+            memory.cancel_connects();
+        }
+    }
+
+    fn poll_tcp(&mut self, cx: &mut Context<'_>) -> Option<io::Result<Connection<CombinedStream>>> {
+        // Connections that are dropped are skipped, until TCP is pending and
+        // will wake the task again.
+        loop {
+            match Pin::new(&mut self.tcp).poll_next(cx) {
+                Poll::Pending | Poll::Ready(None) => return None,
+                Poll::Ready(Some(Err(err))) => return Some(Err(err)),
+                Poll::Ready(Some(Ok(conn))) => {
+                    if let Some(res) = self.on_connection(conn, CombinedStream::Tcp) {
+                        return Some(res);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "transport_utp")]
+    fn poll_utp(&mut self, cx: &mut Context<'_>) -> Option<io::Result<Connection<CombinedStream>>> {
+        loop {
+            let utp = self.utp.as_mut()?;
+            match Pin::new(utp).poll_next(cx) {
+                Poll::Pending | Poll::Ready(None) => return None,
+                Poll::Ready(Some(Err(err))) => return Some(Err(err)),
+                Poll::Ready(Some(Ok(conn))) => {
+                    if let Some(res) = self.on_connection(conn, CombinedStream::Utp) {
+                        return Some(res);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "sim")]
+    fn poll_memory(
         &mut self,
-        poll: Poll<Option<io::Result<Connection<T>>>>,
-        map: F,
-    ) -> Option<io::Result<Connection<CombinedStream>>>
-    where
-        T: std::fmt::Debug + AsyncRead + AsyncWrite + Unpin,
-        F: Fn(T) -> CombinedStream,
-    {
-        match poll {
-            Poll::Pending => None,
-            Poll::Ready(None) => None,
-            Poll::Ready(Some(Err(err))) => Some(Err(err)),
-            Poll::Ready(Some(Ok(conn))) => self.on_connection(conn, map),
+        cx: &mut Context<'_>,
+    ) -> Option<io::Result<Connection<CombinedStream>>> {
+        loop {
+            let memory = self.memory.as_mut()?;
+            match Pin::new(memory).poll_next(cx) {
+                Poll::Pending | Poll::Ready(None) => return None,
+                Poll::Ready(Some(Err(err))) => return Some(Err(err)),
+                Poll::Ready(Some(Ok(conn))) => {
+                    if let Some(res) = self.on_connection(conn, CombinedStream::Memory) {
+                        return Some(res);
+                    }
+                }
+            }
         }
     }
 
@@ -84,7 +210,15 @@ impl CombinedTransport {
 
         // let addr_without_port = peer_addr.set_port(0);
         let (stream, peer_addr, is_initiator, protocol) = conn.into_parts();
-        let take_connection = if !is_initiator {
+        if is_initiator && self.cancelled.contains(&peer_addr) {
+            debug!(
+                "drop cancelled connection to {} via {}",
+                redact(peer_addr),
+                protocol
+            );
+            return None;
+        }
+        let take_connection = if !is_initiator || !self.dedup {
             true
         } else {
             if !self.connected.contains(&peer_addr) {
@@ -97,7 +231,9 @@ impl CombinedTransport {
         if take_connection {
             debug!(
                 "new connection to {} via {} (init {})",
-                peer_addr, protocol, is_initiator
+                redact(peer_addr),
+                protocol,
+                is_initiator
             );
             let stream = map(stream);
             let conn = Connection::new(stream, peer_addr, is_initiator, protocol);
@@ -105,7 +241,9 @@ impl CombinedTransport {
         } else {
             debug!(
                 "skip double connection to {} via {} (init {})",
-                peer_addr, protocol, is_initiator
+                redact(peer_addr),
+                protocol,
+                is_initiator
             );
             None
         }
@@ -115,36 +253,95 @@ impl CombinedTransport {
 impl Transport for CombinedTransport {
     type Connection = CombinedStream;
     fn connect(&mut self, peer_addr: SocketAddr) {
+        self.cancelled.remove(&peer_addr);
+        #[cfg(feature = "sim")]
+        if let Some(memory) = self.memory.as_mut() {
+            memory.connect(peer_addr);
+            return;
+        }
         self.tcp.connect(peer_addr);
         #[cfg(feature = "transport_utp")]
-        self.utp.connect(peer_addr);
+        if let Some(utp) = self.utp.as_mut() {
+            utp.connect(peer_addr);
+        }
     }
 }
 
 impl Stream for CombinedTransport {
     type Item = io::Result<Connection<<Self as Transport>::Connection>>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let tcp_next = Pin::new(&mut self.tcp).poll_next(cx);
-        if let Some(res) = self.on_poll_connection(tcp_next, CombinedStream::Tcp) {
-            return Poll::Ready(Some(res));
+        #[cfg(feature = "sim")]
+        if self.memory.is_some() {
+            return match self.poll_memory(cx) {
+                Some(res) => Poll::Ready(Some(res)),
+                None => Poll::Pending,
+            };
         }
 
         #[cfg(feature = "transport_utp")]
-        {
-            let utp_next = Pin::new(&mut self.utp).poll_next(cx);
-            if let Some(res) = self.on_poll_connection(utp_next, CombinedStream::Utp) {
-                return Poll::Ready(Some(res));
-            }
+        let res = {
+            let mut utp_first = self.utp_first;
+            let res = poll_alternating(
+                &mut *self,
+                cx,
+                &mut utp_first,
+                Self::poll_utp,
+                Self::poll_tcp,
+            );
+            self.utp_first = utp_first;
+            res
+        };
+        #[cfg(not(feature = "transport_utp"))]
+        let res = self.poll_tcp(cx);
+
+        match res {
+            Some(res) => Poll::Ready(Some(res)),
+            None => Poll::Pending,
         }
+    }
+}
 
-        Poll::Pending
+// Poll two transports, the one that yielded last going last, so a flood of
+// connections over one cannot starve the other. `first_first` tells whether
+// the first transport goes first, and is updated whenever one yields.
+#[cfg(any(feature = "transport_utp", all(test, feature = "sim")))]
+fn poll_alternating<S, T>(
+    state: &mut S,
+    cx: &mut Context<'_>,
+    first_first: &mut bool,
+    mut first: impl FnMut(&mut S, &mut Context<'_>) -> Option<T>,
+    mut second: impl FnMut(&mut S, &mut Context<'_>) -> Option<T>,
+) -> Option<T> {
+    if *first_first {
+        if let Some(res) = first(state, cx) {
+            *first_first = false;
+            return Some(res);
+        }
     }
+    if let Some(res) = second(state, cx) {
+        *first_first = true;
+        return Some(res);
+    }
+    if !*first_first {
+        return first(state, cx);
+    }
+    None
 }
 
+/// A connection over either transport, or over the memory transport in
+/// tests and simulations.
+///
+/// Writes go straight to the socket or the uTP send queue, and vectored
+/// writes stay vectored, so large buffers are not copied on the way.
+/// Above it, `Connection` only copies small writes: keepalive framing
+/// writes large payloads from the caller's buffer behind their header, and
+/// encrypted frames are written from the ciphertext.
 pub enum CombinedStream {
     Tcp(TcpStream),
     #[cfg(feature = "transport_utp")]
     Utp(UtpStream),
+    #[cfg(feature = "sim")]
+    Memory(MemoryStream),
 }
 
 impl Debug for CombinedStream {
@@ -153,6 +350,8 @@ impl Debug for CombinedStream {
             Self::Tcp(_) => "Tcp",
             #[cfg(feature = "transport_utp")]
             Self::Utp(_) => "Utp",
+            #[cfg(feature = "sim")]
+            Self::Memory(_) => "Memory",
         };
         write!(f, "CombinedStream::{}", name)
     }
@@ -164,6 +363,8 @@ impl CombinedStream {
             Self::Tcp(stream) => stream.peer_addr().unwrap(),
             #[cfg(feature = "transport_utp")]
             Self::Utp(stream) => stream.peer_addr(),
+            #[cfg(feature = "sim")]
+            Self::Memory(stream) => stream.peer_addr(),
         }
     }
 
@@ -172,6 +373,8 @@ impl CombinedStream {
             CombinedStream::Tcp(_) => "tcp".into(),
             #[cfg(feature = "transport_utp")]
             CombinedStream::Utp(_) => "utp".into(),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(_) => "memory".into(),
         }
     }
 }
@@ -186,6 +389,8 @@ impl AsyncRead for CombinedStream {
             CombinedStream::Tcp(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
             #[cfg(feature = "transport_utp")]
             CombinedStream::Utp(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
         }
     }
 }
@@ -200,6 +405,24 @@ impl AsyncWrite for CombinedStream {
             CombinedStream::Tcp(ref mut stream) => Pin::new(stream).poll_write(cx, buf),
             #[cfg(feature = "transport_utp")]
             CombinedStream::Utp(ref mut stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(ref mut stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            CombinedStream::Tcp(ref mut stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "transport_utp")]
+            CombinedStream::Utp(ref mut stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(ref mut stream) => {
+                Pin::new(stream).poll_write_vectored(cx, bufs)
+            }
         }
     }
 
@@ -208,6 +431,8 @@ impl AsyncWrite for CombinedStream {
             CombinedStream::Tcp(ref mut stream) => Pin::new(stream).poll_flush(cx),
             #[cfg(feature = "transport_utp")]
             CombinedStream::Utp(ref mut stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(ref mut stream) => Pin::new(stream).poll_flush(cx),
         }
     }
 
@@ -216,12 +441,71 @@ impl AsyncWrite for CombinedStream {
             CombinedStream::Tcp(ref mut stream) => Pin::new(stream).poll_close(cx),
             #[cfg(feature = "transport_utp")]
             CombinedStream::Utp(ref mut stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(feature = "sim")]
+            CombinedStream::Memory(ref mut stream) => Pin::new(stream).poll_close(cx),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_fair_polling() -> std::io::Result<()> {
+        use super::*;
+        use crate::sim::Simulation;
+        use futures::{future, StreamExt};
+
+        let sim = Simulation::new(1);
+        sim.run(async {
+            let mut lanes = (sim.network().bind(), sim.network().bind());
+            let mut dialer = sim.network().dial_only();
+            for _ in 0..4 {
+                dialer.connect(lanes.0.local_addr());
+            }
+            for _ in 0..6 {
+                dialer.connect(lanes.1.local_addr());
+            }
+            // Once all dials are answered, both lanes have their connections
+            // waiting.
+            let mut outgoing = vec![];
+            for _ in 0..10 {
+                outgoing.push(dialer.next().await.unwrap()?);
+            }
+
+            type Lanes = (MemoryTransport, MemoryTransport);
+            fn accept(lane: &mut MemoryTransport, cx: &mut Context<'_>) -> bool {
+                matches!(Pin::new(lane).poll_next(cx), Poll::Ready(Some(Ok(_))))
+            }
+            fn first(lanes: &mut Lanes, cx: &mut Context<'_>) -> Option<&'static str> {
+                Some("first").filter(|_| accept(&mut lanes.0, cx))
+            }
+            fn second(lanes: &mut Lanes, cx: &mut Context<'_>) -> Option<&'static str> {
+                Some("second").filter(|_| accept(&mut lanes.1, cx))
+            }
+
+            let mut first_first = false;
+            let mut order = vec![];
+            for _ in 0..10 {
+                let lane = future::poll_fn(|cx| {
+                    Poll::Ready(poll_alternating(
+                        &mut lanes,
+                        cx,
+                        &mut first_first,
+                        first,
+                        second,
+                    ))
+                })
+                .await;
+                order.push(lane.unwrap());
+            }
+            let mut expected = vec!["second", "first"].repeat(4);
+            expected.extend(vec!["second"; 2]);
+            assert_eq!(order, expected);
+            Ok(())
+        })
+    }
+
     // use std::net::{IpAddr, Ipv4Addr};
     // use super::*;
     // use async_std::stream::StreamExt;
@@ -0,0 +1,361 @@
+//! The handshake securing new connections.
+//!
+//! The swarm runs a [`Handshaker`] over every new connection before the
+//! firewall sees it. The default one runs the Noise handshake; others, e.g.
+//! for other Noise patterns or TLS-PSK, can be set with
+//! `Config::set_handshaker`. A handshaker returns a [`Session`] whose
+//! [`SessionCipher`] then encrypts all frames of the connection.
+//!
+//! Frames are prefixed with their length as a little endian u24, so a cipher
+//! only transforms single messages of up to 64KiB. An empty message tells the
+//! remote that the sender rekeyed, see `RekeyPolicy`.
+
+use async_trait::async_trait;
+use blake2_rfc::blake2b::blake2b;
+use futures::io::{AsyncRead, AsyncWrite, IoSlice};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::config::RekeyPolicy;
+use crate::discovery::Topic;
+use crate::runtime;
+
+/// Frames are at most this long, without the header.
+pub const MAX_MESSAGE: usize = 65535;
+pub(crate) const HEADER_LEN: usize = 3;
+const READ_CHUNK: usize = 4096;
+
+/// The stream a handshake runs over.
+pub trait HandshakeStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> HandshakeStream for T where T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized {}
+
+/// A new connection to secure.
+#[derive(Debug, Clone)]
+pub struct HandshakeRequest {
+    pub is_initiator: bool,
+    pub peer_addr: SocketAddr,
+    /// Keys of private topics, see `Hyperswarm::configure_private`. Both
+    /// sides prove that they know them and check the proofs of the remote.
+    pub topic_keys: Vec<(Topic, [u8; 32])>,
+}
+
+/// The outcome of a completed handshake.
+pub struct Session {
+    pub cipher: Box<dyn SessionCipher>,
+    /// The key the remote authenticated with.
+    pub remote_key: [u8; 32],
+    /// Private topics of `HandshakeRequest::topic_keys` the remote proved to
+    /// know.
+    pub topics: Vec<Topic>,
+    /// The application payload of the remote, see `HandshakePolicy::payload`.
+    pub payload: Vec<u8>,
+    /// A hash unique to the session, see `Connection::handshake_hash`.
+    pub hash: Vec<u8>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("remote_key", &pretty_hash::fmt(&self.remote_key).unwrap())
+            .field("topics", &self.topics.len())
+            .field("payload", &self.payload.len())
+            .finish()
+    }
+}
+
+/// Encrypts and decrypts the messages of an established session.
+pub trait SessionCipher: Send + Sync {
+    /// Bytes a message grows by when encrypted.
+    fn overhead(&self) -> usize;
+
+    /// Encrypt a message of up to `MAX_MESSAGE - overhead()` bytes.
+    fn encrypt(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>>;
+
+    fn decrypt(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Switch to a new key for sending. Ciphers that cannot rekey keep the
+    /// current one.
+    fn rekey_outgoing(&mut self) {}
+
+    /// Switch to a new key for receiving, after the remote rekeyed.
+    fn rekey_incoming(&mut self) {}
+}
+
+/// Authenticates the remote and agrees on a session cipher.
+///
+/// Handshakes run under `HandshakePolicy::timeout`. Failing counts as a
+/// `Misbehavior::FailedHandshake` of the remote.
+#[async_trait]
+pub trait Handshaker: fmt::Debug + Send + Sync {
+    async fn handshake(
+        &self,
+        stream: &mut dyn HandshakeStream,
+        request: HandshakeRequest,
+    ) -> io::Result<Session>;
+}
+
+pub(crate) fn frame_header(len: usize) -> [u8; HEADER_LEN] {
+    let bytes = (len as u32).to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+pub(crate) fn frame_len(header: &[u8]) -> usize {
+    u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize
+}
+
+/// A namespaced id like `crypto.namespace(name, count)[index]` of
+/// hypercore-crypto, which JS hyperswarm and hypercore derive keys with.
+pub(crate) fn namespace(name: &[u8], index: u8) -> [u8; 32] {
+    let mut input = blake2b(32, &[], name).as_bytes().to_vec();
+    input.push(index);
+    let mut id = [0u8; 32];
+    id.copy_from_slice(blake2b(32, &[], &input).as_bytes());
+    id
+}
+
+fn frame_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Frames the data of an established connection and runs it through the
+/// session cipher.
+pub(crate) struct Cipher {
+    session: Box<dyn SessionCipher>,
+    rekey: Option<RekeyPolicy>,
+    // Bytes sent and the time since the last rekey.
+    sent: u64,
+    keyed_at: Instant,
+    read_buf: Vec<u8>,
+    plain: Vec<u8>,
+    plain_pos: usize,
+    write_buf: Vec<u8>,
+    max_message: usize,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("buffered_read", &self.read_buf.len())
+            .field("buffered_write", &self.write_buf.len())
+            .finish()
+    }
+}
+
+impl Cipher {
+    pub fn new(session: Box<dyn SessionCipher>, rekey: Option<RekeyPolicy>) -> Self {
+        Self {
+            session,
+            rekey,
+            sent: 0,
+            keyed_at: runtime::now(),
+            read_buf: vec![],
+            plain: vec![],
+            plain_pos: 0,
+            write_buf: vec![],
+            max_message: MAX_MESSAGE,
+        }
+    }
+
+    /// Cap encrypted frames below `MAX_MESSAGE`. Frames always have room
+    /// for some plaintext besides the cipher's overhead.
+    pub fn set_max_message(&mut self, max: usize) {
+        let min = self.session.overhead() + 1;
+        self.max_message = max.max(min).min(MAX_MESSAGE);
+    }
+
+    fn poll_read<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncRead + Unpin + ?Sized,
+    {
+        loop {
+            if self.plain_pos < self.plain.len() {
+                let n = (self.plain.len() - self.plain_pos).min(buf.len());
+                buf[..n].copy_from_slice(&self.plain[self.plain_pos..self.plain_pos + n]);
+                self.plain_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+            if self.read_buf.len() >= HEADER_LEN {
+                let len = frame_len(&self.read_buf);
+                if len > MAX_MESSAGE {
+                    return Poll::Ready(Err(frame_error("frame too large")));
+                }
+                if self.read_buf.len() >= HEADER_LEN + len {
+                    let frame = &self.read_buf[HEADER_LEN..HEADER_LEN + len];
+                    self.plain = self.session.decrypt(frame)?;
+                    self.plain_pos = 0;
+                    self.read_buf.drain(..HEADER_LEN + len);
+                    if self.plain.is_empty() {
+                        self.session.rekey_incoming();
+                    }
+                    continue;
+                }
+            }
+            let mut chunk = [0u8; READ_CHUNK];
+            let n = futures::ready!(Pin::new(&mut *inner).poll_read(cx, &mut chunk))?;
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            self.read_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn poll_write<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        futures::ready!(self.poll_drain(inner, cx))?;
+        // Empty messages are reserved for rekeying.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if self.rekey_due() {
+            self.push_message(&[])?;
+            self.session.rekey_outgoing();
+            self.sent = 0;
+            self.keyed_at = runtime::now();
+        }
+        let n = buf.len().min(self.max_plaintext());
+        let message = self.session.encrypt(&buf[..n])?;
+        if message.len() > MAX_MESSAGE {
+            return Poll::Ready(Err(frame_error("frame too large")));
+        }
+        self.sent += n as u64;
+        let header = frame_header(message.len());
+        if !self.write_buf.is_empty() {
+            self.write_buf.extend_from_slice(&header);
+            self.write_buf.extend_from_slice(&message);
+            let _ = self.poll_drain(inner, cx)?;
+            return Poll::Ready(Ok(n));
+        }
+        // Written from the encrypted message, only buffering what the
+        // transport did not take.
+        let bufs = [IoSlice::new(&header), IoSlice::new(&message)];
+        let written = match Pin::new(&mut *inner).poll_write_vectored(cx, &bufs)? {
+            Poll::Ready(written) => written,
+            Poll::Pending => 0,
+        };
+        if written < HEADER_LEN {
+            self.write_buf.extend_from_slice(&header[written..]);
+        }
+        let written = written.saturating_sub(HEADER_LEN).min(message.len());
+        self.write_buf.extend_from_slice(&message[written..]);
+        Poll::Ready(Ok(n))
+    }
+
+    /// The most plaintext a single message carries.
+    fn max_plaintext(&self) -> usize {
+        self.max_message - self.session.overhead()
+    }
+
+    fn rekey_due(&self) -> bool {
+        self.rekey.as_ref().map_or(false, |policy| {
+            self.sent >= policy.max_bytes || runtime::elapsed(self.keyed_at) >= policy.interval
+        })
+    }
+
+    fn push_message(&mut self, plaintext: &[u8]) -> io::Result<()> {
+        let message = self.session.encrypt(plaintext)?;
+        if message.len() > MAX_MESSAGE {
+            return Err(frame_error("frame too large"));
+        }
+        self.write_buf
+            .extend_from_slice(&frame_header(message.len()));
+        self.write_buf.extend_from_slice(&message);
+        Ok(())
+    }
+
+    fn poll_drain<T>(&mut self, inner: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        while !self.write_buf.is_empty() {
+            let n = futures::ready!(Pin::new(&mut *inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A stream seen through its cipher.
+pub(crate) struct Encrypted<'a, T: ?Sized> {
+    pub inner: &'a mut T,
+    pub cipher: &'a mut Cipher,
+}
+
+impl<T> AsyncRead for Encrypted<'_, T>
+where
+    T: AsyncRead + Unpin + ?Sized,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.cipher.poll_read(this.inner, cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Encrypted<'_, T>
+where
+    T: AsyncWrite + Unpin + ?Sized,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.cipher.poll_write(this.inner, cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if let Some(buf) = bufs.iter().find(|buf| buf.len() == total) {
+            return this.cipher.poll_write(this.inner, cx, buf);
+        }
+        // Messages are encrypted whole, so gather them into one.
+        let max = total.min(this.cipher.max_plaintext());
+        let mut message = Vec::with_capacity(max);
+        for buf in bufs {
+            let room = max - message.len();
+            message.extend_from_slice(&buf[..buf.len().min(room)]);
+        }
+        this.cipher.poll_write(this.inner, cx, &message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.cipher.poll_drain(this.inner, cx))?;
+        Pin::new(&mut *this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.cipher.poll_drain(this.inner, cx))?;
+        Pin::new(&mut *this.inner).poll_close(cx)
+    }
+}
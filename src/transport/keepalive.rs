@@ -0,0 +1,274 @@
+use futures::io::{AsyncRead, AsyncWrite, IoSlice};
+use futures::stream::Stream;
+use log::*;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::config::KeepalivePolicy;
+use crate::runtime::{wheel, TimerWheel};
+
+const DATA: u8 = 0;
+const PING: u8 = 1;
+const PONG: u8 = 2;
+const HEADER_LEN: usize = 3;
+const READ_CHUNK: usize = 4096;
+/// Data frames at least this large are written from the caller's buffer,
+/// instead of being copied into the write buffer first.
+const DIRECT_WRITE: usize = 8 * 1024;
+
+/// Framing that interleaves pings and pongs with application data, see
+/// `KeepalivePolicy`.
+///
+/// Every frame is a kind byte and a big endian u16 payload length. Pings and
+/// pongs have no payload. A ping is sent on every tick without anything read
+/// since the previous one, and the peer is declared dead after too many
+/// ticks in a row without.
+///
+/// Large data frames are written straight from the caller's buffer, see
+/// `DIRECT_WRITE`. If the transport takes only part of one, the frame stays
+/// open and pings and pongs wait until the caller wrote the rest of it.
+#[derive(Debug)]
+pub(crate) struct Keepalive {
+    policy: KeepalivePolicy,
+    timer: wheel::Interval,
+    missed: u32,
+    received: bool,
+    read_buf: Vec<u8>,
+    // Payload bytes of the current data frame not yet read.
+    data_left: usize,
+    write_buf: Vec<u8>,
+    max_frame: usize,
+    // The header of the open direct data frame, the bytes of it not written
+    // yet, and the payload bytes it still expects.
+    header: [u8; HEADER_LEN],
+    header_left: usize,
+    frame_left: usize,
+}
+
+impl Keepalive {
+    pub fn new(policy: KeepalivePolicy, timers: &TimerWheel) -> Self {
+        Self {
+            timer: timers.interval(policy.interval),
+            policy,
+            missed: 0,
+            received: false,
+            read_buf: vec![],
+            data_left: 0,
+            write_buf: vec![],
+            max_frame: u16::MAX as usize,
+            header: [0; HEADER_LEN],
+            header_left: 0,
+            frame_left: 0,
+        }
+    }
+
+    /// Cap the payload of data frames below the u16 maximum.
+    pub fn set_max_frame(&mut self, max: usize) {
+        self.max_frame = max.min(u16::MAX as usize);
+    }
+
+    pub fn poll_read<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    {
+        self.poll_timer(cx)?;
+        let _ = self.poll_drain(inner, cx)?;
+        loop {
+            if self.data_left > 0 && !self.read_buf.is_empty() {
+                let n = self.data_left.min(self.read_buf.len()).min(buf.len());
+                buf[..n].copy_from_slice(&self.read_buf[..n]);
+                self.read_buf.drain(..n);
+                self.data_left -= n;
+                return Poll::Ready(Ok(n));
+            }
+            if self.data_left == 0 && self.read_buf.len() >= HEADER_LEN {
+                self.on_header(inner, cx)?;
+                continue;
+            }
+            let mut chunk = [0u8; READ_CHUNK];
+            let n = futures::ready!(Pin::new(&mut *inner).poll_read(cx, &mut chunk))?;
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            self.received = true;
+            self.read_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    pub fn poll_write<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if self.frame_left > 0 {
+            return self.poll_write_direct(inner, cx, buf);
+        }
+        futures::ready!(self.poll_drain(inner, cx))?;
+        let n = buf.len().min(self.max_frame);
+        if n >= DIRECT_WRITE {
+            let len = (n as u16).to_be_bytes();
+            self.header = [DATA, len[0], len[1]];
+            self.header_left = HEADER_LEN;
+            self.frame_left = n;
+            return self.poll_write_direct(inner, cx, buf);
+        }
+        self.push_frame(DATA, &buf[..n]);
+        let _ = self.poll_drain(inner, cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    // Write the payload of the open data frame from `buf`, after what is
+    // left of its header.
+    fn poll_write_direct<T>(
+        &mut self,
+        inner: &mut T,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        let payload = &buf[..buf.len().min(self.frame_left)];
+        let mut written = 0;
+        while written == 0 {
+            let n = if self.header_left > 0 {
+                let header = &self.header[HEADER_LEN - self.header_left..];
+                let bufs = [IoSlice::new(header), IoSlice::new(payload)];
+                let n = futures::ready!(Pin::new(&mut *inner).poll_write_vectored(cx, &bufs))?;
+                let of_header = n.min(self.header_left);
+                self.header_left -= of_header;
+                written = n - of_header;
+                n
+            } else {
+                written = futures::ready!(Pin::new(&mut *inner).poll_write(cx, payload))?;
+                written
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+        }
+        self.frame_left -= written;
+        if self.frame_left == 0 {
+            // Pings and pongs queued while the frame was open.
+            let _ = self.poll_drain(inner, cx)?;
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    /// Write out buffered frames. They wait while a direct data frame is
+    /// open.
+    pub fn poll_drain<T>(&mut self, inner: &mut T, cx: &mut Context<'_>) -> Poll<io::Result<()>>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        if self.frame_left > 0 {
+            return Poll::Ready(Ok(()));
+        }
+        while !self.write_buf.is_empty() {
+            let n = futures::ready!(Pin::new(&mut *inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn on_header<T>(&mut self, inner: &mut T, cx: &mut Context<'_>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        let kind = self.read_buf[0];
+        let len = u16::from_be_bytes([self.read_buf[1], self.read_buf[2]]) as usize;
+        self.read_buf.drain(..HEADER_LEN);
+        match (kind, len) {
+            (DATA, len) => self.data_left = len,
+            (PING, 0) => {
+                self.push_frame(PONG, &[]);
+                let _ = self.poll_drain(inner, cx)?;
+            }
+            (PONG, 0) => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid keepalive frame",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_timer(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while let Poll::Ready(Some(())) = Pin::new(&mut self.timer).poll_next(cx) {
+            if self.received {
+                self.missed = 0;
+            } else {
+                self.missed += 1;
+            }
+            self.received = false;
+            if self.missed > self.policy.max_missed {
+                debug!("peer missed {} keepalive pings", self.missed);
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "peer stopped answering keepalive pings",
+                ));
+            }
+            if self.missed > 0 {
+                self.push_frame(PING, &[]);
+            }
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, kind: u8, payload: &[u8]) {
+        self.write_buf.push(kind);
+        self.write_buf
+            .extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        self.write_buf.extend_from_slice(payload);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::io::Cursor;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_frames() {
+        let policy = KeepalivePolicy::default().set_interval(Duration::from_secs(60));
+        let mut keepalive = Keepalive::new(policy, &TimerWheel::default());
+        let mut inner = Cursor::new(vec![PING, 0, 0, DATA, 0, 2, b'h', b'i']);
+        let mut buf = [0u8; 8];
+        let n = futures::future::poll_fn(|cx| keepalive.poll_read(&mut inner, cx, &mut buf))
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n], b"hi");
+        // The pong was written after the frames read.
+        assert_eq!(&inner.get_ref()[8..], &[PONG, 0, 0]);
+
+        // Large frames keep their header in front of the payload.
+        let mut inner = Cursor::new(vec![]);
+        let payload = vec![7u8; 2 * DIRECT_WRITE];
+        let n = futures::future::poll_fn(|cx| keepalive.poll_write(&mut inner, cx, &payload))
+            .await
+            .unwrap();
+        assert_eq!(n, payload.len());
+        let written = inner.into_inner();
+        assert_eq!(&written[..HEADER_LEN], &[DATA, 0x40, 0]);
+        assert_eq!(&written[HEADER_LEN..], &payload[..]);
+    }
+}
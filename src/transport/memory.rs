@@ -0,0 +1,750 @@
+use futures::stream::FuturesUnordered;
+use futures_lite::{AsyncRead, AsyncWrite, Future, Stream};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use super::{Connection, Transport};
+use crate::discovery::Topic;
+use crate::error::ConnectError;
+use crate::runtime::{self, Sleep};
+
+type ConnectFut =
+    Pin<Box<dyn Future<Output = (SocketAddr, io::Result<MemoryStream>)> + Send + 'static>>;
+
+const PROTOCOL: &str = "memory";
+
+/// Hosts get addresses in 10.0.0.0/8, all on this port.
+const PORT: u16 = 49152;
+
+/// Bytes written but not yet read, per direction, before writes wait.
+const DEFAULT_WINDOW: usize = 64 * 1024;
+
+/// Writes are split into packets of at most this many bytes, each shaped
+/// on its own.
+const PACKET_SIZE: usize = 1400;
+
+/// A lost packet is sent again after this long, doubling with every loss.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A packet lost this many times in a row resets its connection.
+const MAX_RETRANSMITS: u32 = 8;
+
+/// A lost connect is tried again after this long, doubling with every loss,
+/// until the connect times out after this many attempts.
+const CONNECT_RETRY: Duration = Duration::from_secs(1);
+const CONNECT_ATTEMPTS: u32 = 4;
+
+/// A one-way latency, fixed or drawn from a distribution for every packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// Uniformly distributed between a minimum and a maximum.
+    Uniform(Duration, Duration),
+    /// Normally distributed around a mean, cut off at zero.
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+    },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Fixed(Duration::default())
+    }
+}
+
+impl Latency {
+    fn sample(&self) -> Duration {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform(min, max) if max > min => min + (max - min).mul_f64(random_unit()),
+            Latency::Uniform(min, _) => min,
+            Latency::Normal { mean, std_dev } => {
+                // Box-Muller transform of two uniform samples.
+                let (u, v) = (1.0 - random_unit(), random_unit());
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+                let latency = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+                Duration::from_secs_f64(latency.max(0.0))
+            }
+        }
+    }
+}
+
+/// How data travels in one direction between two hosts of a
+/// `MemoryNetwork`.
+///
+/// Connections on the network are reliable and ordered like TCP, so shaping
+/// shows up as delays: a lost packet arrives after a retransmit timeout of
+/// 200ms, doubling with every further loss, and resets the connection after
+/// 8 losses in a row. A reordered packet takes a second latency sample
+/// longer and holds up the packets behind it until it arrived. Lost connects
+/// are retried after 1s, 2s and 4s before they time out. Randomness comes
+/// from the seed within a simulation.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkConfig {
+    pub latency: Latency,
+    /// The share of packets that are lost, from 0 to 1.
+    pub loss: f64,
+    /// The share of packets that are reordered, from 0 to 1.
+    pub reorder: f64,
+    /// Bytes per second each connection can send, unlimited if `None`.
+    pub bandwidth: Option<u64>,
+}
+
+impl LinkConfig {
+    pub fn set_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn set_loss(mut self, loss: f64) -> Self {
+        self.loss = loss.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn set_reorder(mut self, reorder: f64) -> Self {
+        self.reorder = reorder.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn set_bandwidth(mut self, bandwidth: Option<u64>) -> Self {
+        self.bandwidth = bandwidth.map(|bandwidth| bandwidth.max(1));
+        self
+    }
+
+    // The time a packet sent at `sent` arrives, or `None` if it was lost
+    // too often.
+    fn arrival(&self, mut sent: Instant) -> Option<Instant> {
+        let mut timeout = RETRANSMIT_TIMEOUT;
+        for _ in 0..=MAX_RETRANSMITS {
+            if !chance(self.loss) {
+                let mut at = sent + self.latency.sample();
+                if chance(self.reorder) {
+                    at += self.latency.sample();
+                }
+                return Some(at);
+            }
+            sent += timeout;
+            timeout *= 2;
+        }
+        None
+    }
+
+    // The time a packet of `len` bytes is sent, once the packets before it
+    // left at the bandwidth of the link.
+    fn departure(&self, len: usize, now: Instant, busy_until: &mut Option<Instant>) -> Instant {
+        match self.bandwidth {
+            Some(bandwidth) => {
+                let start = busy_until.map_or(now, |busy| busy.max(now));
+                let sent = start + Duration::from_secs_f64(len as f64 / bandwidth as f64);
+                *busy_until = Some(sent);
+                sent
+            }
+            None => now,
+        }
+    }
+}
+
+// A random number in [0, 1).
+fn random_unit() -> f64 {
+    (runtime::random() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn chance(probability: f64) -> bool {
+    probability > 0.0 && random_unit() < probability
+}
+
+/// A network of in-memory transports, for tests and simulations.
+///
+/// Every transport bound on the network gets an address of its own and can
+/// dial the others. Connecting takes one round trip, and data is carried as
+/// configured by the `LinkConfig` between the two hosts, by default without
+/// any delay or loss. Dialing an address nothing is bound to is refused. The
+/// network also keeps the topics its hosts announce, see `MemoryDiscovery`.
+///
+/// Clones share the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork(Arc<Mutex<NetworkState>>);
+
+#[derive(Default)]
+struct NetworkState {
+    hosts: HashMap<SocketAddr, Arc<Mutex<Incoming>>>,
+    announced: HashMap<Topic, BTreeSet<SocketAddr>>,
+    next_host: u32,
+    link: LinkConfig,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConfig>,
+    window: Option<usize>,
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.lock().unwrap();
+        f.debug_struct("MemoryNetwork")
+            .field("hosts", &state.hosts.len())
+            .field("link", &state.link)
+            .field("links", &state.links.len())
+            .finish()
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a fixed one-way latency for all links, see `set_link`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.0.lock().unwrap().link.latency = Latency::Fixed(latency);
+    }
+
+    /// Shape all links without one of their own. Applies to open
+    /// connections too, for data written from now on.
+    pub fn set_link(&self, link: LinkConfig) {
+        self.0.lock().unwrap().link = link;
+    }
+
+    /// Shape the link from one host to another, e.g. for an asymmetric or a
+    /// single slow path. The opposite direction is configured on its own.
+    pub fn set_link_between(&self, from: SocketAddr, to: SocketAddr, link: LinkConfig) {
+        self.0.lock().unwrap().links.insert((from, to), link);
+    }
+
+    /// The link from one host to another.
+    pub fn link(&self, from: SocketAddr, to: SocketAddr) -> LinkConfig {
+        let state = self.0.lock().unwrap();
+        state.links.get(&(from, to)).unwrap_or(&state.link).clone()
+    }
+
+    /// Set how many bytes a connection buffers per direction before writes
+    /// wait for the reader, 64 KiB by default. Applies to new connections.
+    pub fn set_window(&self, window: usize) {
+        self.0.lock().unwrap().window = Some(window.max(1));
+    }
+
+    /// Bind a transport at the next free address.
+    pub fn bind(&self) -> MemoryTransport {
+        let addr = self.next_addr();
+        self.bind_addr(addr)
+            .expect("next address of the network is free")
+    }
+
+    /// Bind a transport at an address, e.g. to restart a host where it was.
+    pub fn bind_addr(&self, addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let incoming = Arc::new(Mutex::new(Incoming::default()));
+        let mut state = self.0.lock().unwrap();
+        if state.hosts.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        state.hosts.insert(addr, incoming.clone());
+        drop(state);
+        Ok(MemoryTransport::new(self.clone(), addr, Some(incoming)))
+    }
+
+    /// A transport with an address of its own that accepts no connections.
+    pub fn dial_only(&self) -> MemoryTransport {
+        MemoryTransport::new(self.clone(), self.next_addr(), None)
+    }
+
+    fn next_addr(&self) -> SocketAddr {
+        let mut state = self.0.lock().unwrap();
+        loop {
+            state.next_host += 1;
+            let ip = Ipv4Addr::from(0x0a00_0000 + state.next_host);
+            let addr = SocketAddr::from((ip, PORT));
+            if !state.hosts.contains_key(&addr) {
+                return addr;
+            }
+        }
+    }
+
+    // Open a connection, taking one round trip: the request reaches the
+    // listener after one latency, and the answer comes back after another.
+    async fn connect(&self, from: SocketAddr, to: SocketAddr) -> io::Result<MemoryStream> {
+        let mut retry = CONNECT_RETRY;
+        for _ in 0..CONNECT_ATTEMPTS {
+            let request = self.link(from, to);
+            let answer = self.link(to, from);
+            if chance(request.loss) || chance(answer.loss) {
+                runtime::sleep(retry).await;
+                retry *= 2;
+                continue;
+            }
+            runtime::sleep(request.latency.sample()).await;
+            let res = self.accept(from, to);
+            runtime::sleep(answer.latency.sample()).await;
+            return res;
+        }
+        Err(io::ErrorKind::TimedOut.into())
+    }
+
+    fn accept(&self, from: SocketAddr, to: SocketAddr) -> io::Result<MemoryStream> {
+        let state = self.0.lock().unwrap();
+        let incoming = state
+            .hosts
+            .get(&to)
+            .ok_or(io::ErrorKind::ConnectionRefused)?;
+        let window = state.window.unwrap_or(DEFAULT_WINDOW);
+        let (local, remote) = MemoryStream::pair(self.clone(), from, to, window);
+        incoming.lock().unwrap().push(remote);
+        Ok(local)
+    }
+
+    fn unbind(&self, addr: SocketAddr, incoming: &Arc<Mutex<Incoming>>) {
+        let mut state = self.0.lock().unwrap();
+        if state
+            .hosts
+            .get(&addr)
+            .map_or(false, |bound| Arc::ptr_eq(bound, incoming))
+        {
+            state.hosts.remove(&addr);
+        }
+    }
+
+    pub(crate) fn announce(&self, topic: Topic, addr: SocketAddr) {
+        let mut state = self.0.lock().unwrap();
+        state.announced.entry(topic).or_default().insert(addr);
+    }
+
+    pub(crate) fn unannounce(&self, topic: &Topic, addr: &SocketAddr) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(addrs) = state.announced.get_mut(topic) {
+            addrs.remove(addr);
+            if addrs.is_empty() {
+                state.announced.remove(topic);
+            }
+        }
+    }
+
+    /// Addresses announced for a topic, in order.
+    pub(crate) fn announced(&self, topic: &Topic) -> Vec<SocketAddr> {
+        let state = self.0.lock().unwrap();
+        state
+            .announced
+            .get(topic)
+            .map(|addrs| addrs.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+// Connections waiting to be accepted by a transport.
+#[derive(Default)]
+struct Incoming {
+    streams: VecDeque<MemoryStream>,
+    waker: Option<Waker>,
+}
+
+impl Incoming {
+    fn push(&mut self, stream: MemoryStream) {
+        self.streams.push_back(stream);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A transport on a `MemoryNetwork`.
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    incoming: Option<Arc<Mutex<Incoming>>>,
+    pending_connects: FuturesUnordered<ConnectFut>,
+    failed_connects: VecDeque<(SocketAddr, ConnectError)>,
+}
+
+impl fmt::Debug for MemoryTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryTransport")
+            .field("addr", &self.addr)
+            .field("listening", &self.incoming.is_some())
+            .field("pending_connects", &self.pending_connects.len())
+            .finish()
+    }
+}
+
+impl MemoryTransport {
+    fn new(
+        network: MemoryNetwork,
+        addr: SocketAddr,
+        incoming: Option<Arc<Mutex<Incoming>>>,
+    ) -> Self {
+        Self {
+            network,
+            addr,
+            incoming,
+            pending_connects: FuturesUnordered::new(),
+            failed_connects: VecDeque::new(),
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// Outgoing connections that failed since the last call.
+    pub fn take_failed_connects(&mut self) -> Vec<(SocketAddr, ConnectError)> {
+        self.failed_connects.drain(..).collect()
+    }
+
+    /// Abort all outgoing connections that are still being established.
+    pub fn cancel_connects(&mut self) {
+        self.pending_connects = FuturesUnordered::new();
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if let Some(incoming) = self.incoming.as_ref() {
+            self.network.unbind(self.addr, incoming);
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Connection = MemoryStream;
+
+    fn connect(&mut self, peer_addr: SocketAddr) {
+        let network = self.network.clone();
+        let local_addr = self.addr;
+        let fut = async move { (peer_addr, network.connect(local_addr, peer_addr).await) };
+        self.pending_connects.push(Box::pin(fut));
+    }
+}
+
+impl Stream for MemoryTransport {
+    type Item = io::Result<Connection<<Self as Transport>::Connection>>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(incoming) = self.incoming.as_ref() {
+            let mut incoming = incoming.lock().unwrap();
+            match incoming.streams.pop_front() {
+                Some(stream) => {
+                    let peer_addr = stream.peer_addr();
+                    let conn = Connection::new(stream, peer_addr, false, PROTOCOL.into());
+                    return Poll::Ready(Some(Ok(conn)));
+                }
+                None => incoming.waker = Some(cx.waker().clone()),
+            }
+        }
+
+        match Pin::new(&mut self.pending_connects).poll_next(cx) {
+            Poll::Ready(Some((peer_addr, Ok(stream)))) => {
+                let conn = Connection::new(stream, peer_addr, true, PROTOCOL.into());
+                Poll::Ready(Some(Ok(conn)))
+            }
+            Poll::Ready(Some((peer_addr, Err(e)))) => {
+                self.failed_connects.push_back((peer_addr, (&e).into()));
+                Poll::Ready(Some(Err(e)))
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+// One direction of a connection.
+#[derive(Default)]
+struct Pipe {
+    // Written data with the time it arrives.
+    chunks: VecDeque<(Instant, Vec<u8>)>,
+    buffered: usize,
+    // When the close of the writer arrives.
+    closed_at: Option<Instant>,
+    // When the reader sees a reset, after a packet was lost too often.
+    lost_at: Option<Instant>,
+    // Until when the link is busy sending earlier packets.
+    busy_until: Option<Instant>,
+    // Whether the reader is gone.
+    reset: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    // Send a packet over a link, or the close of the writer if `None`.
+    // False if it was lost too often and the connection is reset.
+    fn send(&mut self, link: &LinkConfig, now: Instant, packet: Option<&[u8]>) -> bool {
+        let len = packet.map_or(0, <[u8]>::len);
+        let sent = link.departure(len, now, &mut self.busy_until);
+        let arrival = link.arrival(sent);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        match (arrival, packet) {
+            (None, _) => {
+                self.lost_at.get_or_insert(sent);
+                false
+            }
+            (Some(at), Some(packet)) => {
+                self.chunks.push_back((at, packet.to_vec()));
+                self.buffered += len;
+                true
+            }
+            (Some(at), None) => {
+                self.closed_at.get_or_insert(at);
+                true
+            }
+        }
+    }
+}
+
+/// A connection on a `MemoryNetwork`.
+pub struct MemoryStream {
+    network: MemoryNetwork,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    window: usize,
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+    // Wakes the reader once the next chunk arrives.
+    read_timer: Option<(Instant, Sleep)>,
+}
+
+impl fmt::Debug for MemoryStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStream")
+            .field("local_addr", &self.local_addr)
+            .field("peer_addr", &self.peer_addr)
+            .finish()
+    }
+}
+
+impl MemoryStream {
+    fn pair(network: MemoryNetwork, a: SocketAddr, b: SocketAddr, window: usize) -> (Self, Self) {
+        let a_to_b = Arc::new(Mutex::new(Pipe::default()));
+        let b_to_a = Arc::new(Mutex::new(Pipe::default()));
+        let stream_a = Self {
+            network: network.clone(),
+            local_addr: a,
+            peer_addr: b,
+            window,
+            read: b_to_a.clone(),
+            write: a_to_b.clone(),
+            read_timer: None,
+        };
+        let stream_b = Self {
+            network,
+            local_addr: b,
+            peer_addr: a,
+            window,
+            read: a_to_b,
+            write: b_to_a,
+            read_timer: None,
+        };
+        (stream_a, stream_b)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    fn link(&self) -> LinkConfig {
+        self.network.link(self.local_addr, self.peer_addr)
+    }
+
+    fn close(&self) {
+        let link = self.link();
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed_at.is_none() && pipe.lost_at.is_none() {
+            pipe.send(&link, runtime::now(), None);
+        }
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let now = runtime::now();
+            let next = {
+                let mut pipe = this.read.lock().unwrap();
+                pipe.read_waker = Some(cx.waker().clone());
+                let lost_at = pipe.lost_at;
+                if lost_at.map_or(false, |at| at <= now) {
+                    return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+                }
+                // Chunks are read in order, so a delayed one holds up those
+                // behind it.
+                let next = match pipe.chunks.front_mut() {
+                    Some((at, chunk)) if *at <= now => {
+                        let n = buf.len().min(chunk.len());
+                        buf[..n].copy_from_slice(&chunk[..n]);
+                        chunk.drain(..n);
+                        if chunk.is_empty() {
+                            pipe.chunks.pop_front();
+                        }
+                        pipe.buffered -= n;
+                        if let Some(waker) = pipe.write_waker.take() {
+                            waker.wake();
+                        }
+                        return Poll::Ready(Ok(n));
+                    }
+                    Some((at, _)) => Some(*at),
+                    None => match pipe.closed_at {
+                        Some(at) if at <= now => return Poll::Ready(Ok(0)),
+                        at => at,
+                    },
+                };
+                match next.into_iter().chain(lost_at).min() {
+                    Some(next) => next,
+                    None => return Poll::Pending,
+                }
+            };
+            match this.read_timer.as_ref() {
+                Some((deadline, _)) if *deadline == next => {}
+                _ => {
+                    let sleep = runtime::sleep(next.saturating_duration_since(now));
+                    this.read_timer = Some((next, sleep));
+                }
+            }
+            let (_, timer) = this.read_timer.as_mut().unwrap();
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.read_timer = None;
+        }
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let link = self.link();
+        let now = runtime::now();
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.reset || pipe.closed_at.is_some() || pipe.lost_at.is_some() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let room = self.window.saturating_sub(pipe.buffered);
+        if room == 0 {
+            pipe.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = buf.len().min(room);
+        for packet in buf[..n].chunks(PACKET_SIZE) {
+            if !pipe.send(&link, now, Some(packet)) {
+                break;
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.close();
+        let mut read = self.read.lock().unwrap();
+        read.reset = true;
+        if let Some(waker) = read.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use futures::StreamExt;
+
+    #[async_std::test]
+    async fn test_memory_transport() -> io::Result<()> {
+        let network = MemoryNetwork::new();
+        let mut a = network.bind();
+        let mut b = network.bind();
+        assert_ne!(a.local_addr(), b.local_addr());
+
+        a.connect(b.local_addr());
+        let (conn_a, conn_b) = futures::join!(a.next(), b.next());
+        let (mut conn_a, mut conn_b) = (conn_a.unwrap()?, conn_b.unwrap()?);
+        assert!(conn_a.is_initiator() && !conn_b.is_initiator());
+        assert_eq!(conn_b.peer_addr(), a.local_addr());
+
+        conn_a.write_all(b"hello").await?;
+        conn_a.close().await?;
+        let mut buf = vec![];
+        conn_b.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"hello");
+
+        let addr = b.local_addr();
+        drop(b);
+        a.connect(addr);
+        assert!(a.next().await.unwrap().is_err());
+        assert_eq!(
+            a.take_failed_connects(),
+            vec![(addr, ConnectError::Refused)]
+        );
+        Ok(())
+    }
+
+    // Send data over a shaped link, returning the virtual time it took.
+    fn transfer(seed: u64, data: &[u8]) -> io::Result<Duration> {
+        let sim = crate::sim::Simulation::new(seed);
+        let link = LinkConfig::default()
+            .set_latency(Latency::Uniform(
+                Duration::from_millis(20),
+                Duration::from_millis(80),
+            ))
+            .set_loss(0.2)
+            .set_reorder(0.3)
+            .set_bandwidth(Some(100 * 1024));
+        sim.network().set_link(link);
+        sim.run(async {
+            let mut a = sim.network().bind();
+            let mut b = sim.network().bind();
+            a.connect(b.local_addr());
+            let (conn_a, conn_b) = futures::join!(a.next(), b.next());
+            let (mut conn_a, mut conn_b) = (conn_a.unwrap()?, conn_b.unwrap()?);
+            let write = async {
+                conn_a.write_all(data).await?;
+                conn_a.close().await
+            };
+            let mut buf = vec![];
+            let (written, read) = futures::join!(write, conn_b.read_to_end(&mut buf));
+            written?;
+            read?;
+            assert_eq!(buf, data);
+            Ok(sim.clock().elapsed())
+        })
+    }
+
+    #[test]
+    fn test_link_shaping() -> io::Result<()> {
+        let data: Vec<u8> = (0..50 * 1024).map(|i| i as u8).collect();
+        let elapsed = transfer(3, &data)?;
+        // 50 KiB at 100 KiB/s take half a second to send alone.
+        assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+        assert_eq!(transfer(3, &data)?, elapsed);
+        Ok(())
+    }
+}
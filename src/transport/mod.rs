@@ -1,13 +1,43 @@
-use futures::io::{AsyncRead, AsyncWrite};
+use futures::io::{AsyncRead, AsyncWrite, IoSlice};
 use futures::stream::Stream;
-use std::fmt::Debug;
+use futures::task::AtomicWaker;
+use std::fmt::{self, Debug};
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "noise")]
+use crate::config::RekeyPolicy;
+use crate::config::{CoalescePolicy, KeepalivePolicy};
+use crate::data::ConnectionData;
+use crate::error::ConnectError;
+use crate::metrics::Metrics;
+use crate::peer::PeerInfo;
+use crate::runtime::{self, TimerWheel};
+use coalesce::Coalesce;
+#[cfg(feature = "noise")]
+use handshake::{Cipher, SessionCipher};
+use keepalive::Keepalive;
+
+mod coalesce;
 pub mod combined;
+#[cfg(feature = "noise")]
+pub mod handshake;
+mod keepalive;
+#[cfg(feature = "sim")]
+pub mod memory;
+pub mod mux;
+#[cfg(feature = "noise")]
+pub(crate) mod noise;
+#[cfg(feature = "noise")]
+mod secretstream;
 pub mod tcp;
+#[cfg(feature = "noise")]
+pub(crate) mod tickets;
 
 #[cfg(feature = "transport_utp")]
 pub mod utp;
@@ -23,6 +53,183 @@ pub trait Transport:
     // ) -> Poll<Option<io::Result<Connection<Self::Connection>>>>;
 }
 
+/// Why a connection was closed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CloseReason {
+    /// The remote end closed the connection.
+    Remote,
+    /// The connection was closed locally.
+    Local,
+    /// The connection was dropped without being closed.
+    Dropped,
+    /// Reading or writing failed.
+    Error(ConnectError),
+    /// The swarm closed the connection to make room for another one.
+    Evicted,
+}
+
+type CloseHook = Box<dyn FnOnce(CloseReason) + Send + Sync>;
+
+// Runs the close hook at most once, at the latest when dropped. Dropping
+// also marks the connection closed, so the swarm frees its connection slot
+// and dedup entry however the connection ends.
+struct CloseGuard {
+    hook: Option<CloseHook>,
+    control: ConnectionControl,
+}
+
+impl CloseGuard {
+    fn fire(&mut self, reason: CloseReason) {
+        if let Some(hook) = self.hook.take() {
+            hook(reason);
+        }
+    }
+}
+
+impl Debug for CloseGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CloseGuard")
+            .field(&self.hook.is_some())
+            .finish()
+    }
+}
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        self.control.set_closed();
+        self.fire(CloseReason::Dropped);
+    }
+}
+
+#[derive(Debug)]
+struct ControlState {
+    // Touched on every read and write, so kept lock-free: the time of the last
+    // activity in nanoseconds since `opened`.
+    opened: Instant,
+    last_active: AtomicU64,
+    closed: AtomicBool,
+    closing: AtomicBool,
+    evicted: AtomicBool,
+    // Whether the last read is pending, so that the reader sees a close.
+    reading: AtomicBool,
+    waker: AtomicWaker,
+    data: ConnectionData,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// Shared with a connection to see whether it is still open and idle, and to
+/// evict it.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionControl(Arc<ControlState>);
+
+impl Default for ConnectionControl {
+    fn default() -> Self {
+        Self(Arc::new(ControlState {
+            opened: runtime::now(),
+            last_active: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            closing: AtomicBool::new(false),
+            evicted: AtomicBool::new(false),
+            reading: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            data: ConnectionData::default(),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }))
+    }
+}
+
+impl ConnectionControl {
+    /// Whether the connection was closed, or dropped so that this is the
+    /// last reference.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Ordering::SeqCst) || Arc::strong_count(&self.0) == 1
+    }
+
+    pub fn data(&self) -> &ConnectionData {
+        &self.0.data
+    }
+
+    /// Time since data was last read or written.
+    pub fn idle_for(&self) -> Duration {
+        let last_active = Duration::from_nanos(self.0.last_active.load(Ordering::Relaxed));
+        runtime::elapsed(self.0.opened).saturating_sub(last_active)
+    }
+
+    /// Bytes read and written so far.
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.0.bytes_read.load(Ordering::Relaxed),
+            self.0.bytes_written.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Make the next read or write fail, waking a pending reader.
+    pub fn evict(&self) {
+        self.0.evicted.store(true, Ordering::SeqCst);
+        self.0.closed.store(true, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+
+    /// Close the connection on its next read, which then returns EOF.
+    pub fn close(&self) {
+        self.0.closing.store(true, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+
+    /// Whether a read of the connection is pending, so that a close takes
+    /// effect without waiting for the application to read again.
+    pub fn is_reading(&self) -> bool {
+        self.0.reading.load(Ordering::SeqCst)
+    }
+
+    fn is_closing(&self) -> bool {
+        self.0.closing.load(Ordering::SeqCst)
+    }
+
+    fn is_evicted(&self) -> bool {
+        self.0.evicted.load(Ordering::SeqCst)
+    }
+
+    fn touch(&self) {
+        let now = runtime::elapsed(self.0.opened).as_nanos() as u64;
+        self.0.last_active.store(now, Ordering::Relaxed);
+    }
+
+    fn on_read(&self, bytes: usize) {
+        self.0.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn on_written(&self, bytes: usize) {
+        self.0
+            .bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn set_closed(&self) {
+        self.0.closed.store(true, Ordering::SeqCst);
+    }
+
+    fn set_reading(&self, reading: bool) {
+        self.0.reading.store(reading, Ordering::SeqCst);
+    }
+
+    fn set_waker(&self, waker: &Waker) {
+        self.0.waker.register(waker);
+    }
+}
+
+// The stream a connection reads and writes, either the inner one or seen
+// through its cipher.
+trait RawStream: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T> RawStream for T where T: AsyncRead + AsyncWrite + Unpin + ?Sized {}
+
+fn evicted_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "connection evicted")
+}
+
 #[derive(Debug)]
 pub struct Connection<T>
 where
@@ -32,6 +239,18 @@ where
     peer_addr: SocketAddr,
     is_initiator: bool,
     protocol: String,
+    on_close: CloseGuard,
+    control: ConnectionControl,
+    info: PeerInfo,
+    keepalive: Option<Keepalive>,
+    coalesce: Option<Coalesce>,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "noise")]
+    cipher: Option<Cipher>,
+    #[cfg(feature = "noise")]
+    handshake_hash: Option<Vec<u8>>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl<T> Connection<T>
@@ -39,14 +258,48 @@ where
     T: Debug + AsyncRead + AsyncWrite + Unpin,
 {
     pub fn new(inner: T, peer_addr: SocketAddr, is_initiator: bool, protocol: String) -> Self {
+        let info = PeerInfo::new(peer_addr, is_initiator, protocol.clone());
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "connection",
+            peer = %crate::redact::redact(peer_addr),
+            initiator = is_initiator,
+            protocol = %protocol
+        );
+        let control = ConnectionControl::default();
         Self {
             inner,
             peer_addr,
             is_initiator,
             protocol,
+            on_close: CloseGuard {
+                hook: None,
+                control: control.clone(),
+            },
+            control,
+            info,
+            keepalive: None,
+            coalesce: None,
+            metrics: None,
+            #[cfg(feature = "noise")]
+            cipher: None,
+            #[cfg(feature = "noise")]
+            handshake_hash: None,
+            #[cfg(feature = "tracing")]
+            span,
         }
     }
 
+    /// Set a function to be called once when the connection closes, fails
+    /// or is dropped.
+    pub fn set_close_hook<F>(&mut self, hook: F)
+    where
+        F: FnOnce(CloseReason) + Send + Sync + 'static,
+    {
+        self.on_close.fire(CloseReason::Dropped);
+        self.on_close.hook = Some(Box::new(hook));
+    }
+
     pub fn peer_addr(&self) -> SocketAddr {
         self.peer_addr
     }
@@ -59,41 +312,318 @@ where
         &self.protocol
     }
 
-    pub fn into_parts(self) -> (T, SocketAddr, bool, String) {
+    /// Topics, addresses and discovery details of the remote peer.
+    pub fn peer_info(&self) -> &PeerInfo {
+        &self.info
+    }
+
+    /// Bytes read from the connection so far, after decryption.
+    pub fn bytes_read(&self) -> u64 {
+        self.control.bytes().0
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.control.bytes().1
+    }
+
+    /// Values attached to the connection by the application, see
+    /// `Hyperswarm::connection_data`.
+    pub fn data(&self) -> &ConnectionData {
+        self.control.data()
+    }
+
+    /// The span covering the connection's lifetime, entered while it is
+    /// read or written. Instrument tasks serving the connection with it to
+    /// correlate their events.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    pub(crate) fn peer_info_mut(&mut self) -> &mut PeerInfo {
+        &mut self.info
+    }
+
+    pub(crate) fn control(&self) -> ConnectionControl {
+        self.control.clone()
+    }
+
+    /// Frame all data to interleave keepalive pings, see `KeepalivePolicy`.
+    /// Both ends have to enable it before anything is sent.
+    pub(crate) fn set_keepalive(&mut self, policy: KeepalivePolicy, timers: &TimerWheel) {
+        self.keepalive = Some(Keepalive::new(policy, timers));
+    }
+
+    /// Buffer small writes to the transport, see `CoalescePolicy`.
+    pub(crate) fn set_coalesce(&mut self, policy: CoalescePolicy, timers: &TimerWheel) {
+        self.coalesce = Some(Coalesce::new(policy, timers));
+    }
+
+    /// Split writes into frames of at most `max` bytes, so no buffer of the
+    /// connection grows larger, see `ResourceLimits::max_write_buffer`.
+    pub(crate) fn set_max_write_buffer(&mut self, max: usize) {
+        let max = max.max(1);
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.set_max_frame(max);
+        }
+        if let Some(coalesce) = self.coalesce.as_mut() {
+            coalesce.set_max_bytes(max);
+        }
+        #[cfg(feature = "noise")]
+        if let Some(cipher) = self.cipher.as_mut() {
+            cipher.set_max_message(max);
+        }
+    }
+
+    /// Count the bytes read and written in the swarm's metrics.
+    pub(crate) fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Encrypt all data from now on, after a handshake.
+    #[cfg(feature = "noise")]
+    pub(crate) fn set_cipher(
+        &mut self,
+        cipher: Box<dyn SessionCipher>,
+        handshake_hash: Vec<u8>,
+        rekey: Option<RekeyPolicy>,
+    ) {
+        self.cipher = Some(Cipher::new(cipher, rekey));
+        self.handshake_hash = Some(handshake_hash);
+    }
+
+    /// The hash of the handshake that encrypted the connection, unique to
+    /// the session. Protocols on top can bind capabilities to it.
+    #[cfg(feature = "noise")]
+    pub fn handshake_hash(&self) -> Option<&[u8]> {
+        self.handshake_hash.as_deref()
+    }
+
+    fn with_io<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut dyn RawStream, Option<&mut Keepalive>) -> R,
+    {
+        let keepalive = self.keepalive.as_mut();
+        // Coalescing sits right on the transport, so encrypted and keepalive
+        // frames are collected too.
+        let mut coalesced;
+        let inner: &mut dyn RawStream = match self.coalesce.as_mut() {
+            Some(coalesce) => {
+                coalesced = coalesce::Coalesced {
+                    inner: &mut self.inner,
+                    coalesce,
+                    reading: self.control.is_reading(),
+                };
+                &mut coalesced
+            }
+            None => &mut self.inner,
+        };
+        #[cfg(feature = "noise")]
+        {
+            if let Some(cipher) = self.cipher.as_mut() {
+                let mut encrypted = handshake::Encrypted { inner, cipher };
+                return f(&mut encrypted, keepalive);
+            }
+        }
+        f(inner, keepalive)
+    }
+
+    fn on_write(&mut self, res: &Poll<io::Result<usize>>) {
+        match res {
+            Poll::Ready(Ok(n)) => {
+                self.control.touch();
+                self.control.on_written(*n);
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.on_written(*n);
+                }
+            }
+            Poll::Ready(Err(e)) => self.on_closed(CloseReason::Error(e.into())),
+            Poll::Pending => {}
+        }
+    }
+
+    fn on_closed(&mut self, reason: CloseReason) {
+        self.control.set_closed();
+        self.on_close.fire(reason);
+    }
+
+    /// Split the connection into its parts. A close hook is discarded
+    /// without being called, and the swarm no longer counts the connection.
+    pub fn into_parts(mut self) -> (T, SocketAddr, bool, String) {
+        self.on_close.hook = None;
         (self.inner, self.peer_addr, self.is_initiator, self.protocol)
     }
 }
 
+#[cfg(feature = "hypercore")]
+impl<T> Connection<T>
+where
+    T: Debug + AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    /// Hand the connection to hypercore-protocol for replication.
+    ///
+    /// hypercore-protocol checks channel capabilities against the state of
+    /// its own handshake and cannot take the swarm's, so its handshake always
+    /// runs, within the swarm's session. Only its encryption is left out once
+    /// the swarm encrypts the connection. For capabilities bound to the
+    /// swarm's handshake itself, see `capability`.
+    pub fn into_protocol(self) -> hypercore_protocol::Protocol<Self> {
+        let encrypted = self.cipher.is_none();
+        hypercore_protocol::ProtocolBuilder::new(self.is_initiator)
+            .set_handshake(true)
+            .set_encrypted(encrypted)
+            .connect(self)
+    }
+
+    /// The capability proving that this side knows a hypercore key, as
+    /// hypercore 10 derives it from the handshake hash. The hash covers the
+    /// static keys of both sides, so the capability only verifies within
+    /// this session with the authenticated remote. None in plaintext mode.
+    pub fn capability(&self, key: &[u8; 32]) -> Option<[u8; 32]> {
+        let hash = self.handshake_hash.as_ref()?;
+        Some(replicate_capability(self.is_initiator, key, hash))
+    }
+
+    /// Whether the remote proved to know a hypercore key with `capability`.
+    /// Always false in plaintext mode.
+    pub fn verify_remote_capability(&self, key: &[u8; 32], capability: &[u8]) -> bool {
+        self.handshake_hash.as_ref().map_or(false, |hash| {
+            let expected = replicate_capability(!self.is_initiator, key, hash);
+            capability.len() == expected.len()
+                && capability
+                    .iter()
+                    .zip(expected.iter())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        })
+    }
+}
+
+// `caps.replicate` of hypercore 10.
+#[cfg(feature = "hypercore")]
+fn replicate_capability(is_initiator: bool, key: &[u8; 32], hash: &[u8]) -> [u8; 32] {
+    let index = if is_initiator { 1 } else { 2 };
+    let mut input = handshake::namespace(b"hypercore", index).to_vec();
+    input.extend_from_slice(key);
+    let mut capability = [0u8; 32];
+    capability.copy_from_slice(blake2_rfc::blake2b::blake2b(32, hash, &input).as_bytes());
+    capability
+}
+
 impl<T> AsyncRead for Connection<T>
 where
-    T: AsyncRead + Unpin + Debug,
+    T: AsyncRead + AsyncWrite + Unpin + Debug,
 {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.inner).poll_read(cx, buf)
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        if self.control.is_evicted() {
+            self.on_close.fire(CloseReason::Evicted);
+            return Poll::Ready(Err(evicted_error()));
+        }
+        if self.control.is_closing() {
+            futures::ready!(self.with_io(|io, _| Pin::new(io).poll_close(cx)))?;
+            self.on_closed(CloseReason::Local);
+            return Poll::Ready(Ok(0));
+        }
+        let res = self.with_io(|io, keepalive| match keepalive {
+            Some(keepalive) => keepalive.poll_read(io, cx, buf),
+            None => Pin::new(io).poll_read(cx, buf),
+        });
+        self.control.set_reading(res.is_pending());
+        match &res {
+            Poll::Ready(Ok(0)) if !buf.is_empty() => self.on_closed(CloseReason::Remote),
+            Poll::Ready(Ok(n)) => {
+                self.control.touch();
+                self.control.on_read(*n);
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.on_read(*n);
+                }
+            }
+            Poll::Ready(Err(e)) => self.on_closed(CloseReason::Error(e.into())),
+            Poll::Pending => self.control.set_waker(cx.waker()),
+        }
+        res
     }
 }
 
 impl<T> AsyncWrite for Connection<T>
 where
-    T: AsyncWrite + Unpin + Debug,
+    T: AsyncRead + AsyncWrite + Unpin + Debug,
 {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.inner).poll_write(cx, buf)
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        if self.control.is_evicted() {
+            self.on_close.fire(CloseReason::Evicted);
+            return Poll::Ready(Err(evicted_error()));
+        }
+        if self.control.is_closing() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "connection is closing",
+            )));
+        }
+        let res = self.with_io(|io, keepalive| match keepalive {
+            Some(keepalive) => keepalive.poll_write(io, cx, buf),
+            None => Pin::new(io).poll_write(cx, buf),
+        });
+        self.on_write(&res);
+        res
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if self.keepalive.is_some() || self.control.is_evicted() || self.control.is_closing() {
+            let buf = bufs
+                .iter()
+                .find(|buf| !buf.is_empty())
+                .map_or(&[][..], |buf| &**buf);
+            return self.poll_write(cx, buf);
+        }
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        let res = self.with_io(|io, _| Pin::new(io).poll_write_vectored(cx, bufs));
+        self.on_write(&res);
+        res
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.inner).poll_flush(cx)
+        self.with_io(|io, keepalive| {
+            if let Some(keepalive) = keepalive {
+                futures::ready!(keepalive.poll_drain(&mut *io, cx))?;
+            }
+            Pin::new(io).poll_flush(cx)
+        })
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.inner).poll_close(cx)
+        let res = self.with_io(|io, keepalive| {
+            if let Some(keepalive) = keepalive {
+                futures::ready!(keepalive.poll_drain(&mut *io, cx))?;
+            }
+            Pin::new(io).poll_close(cx)
+        });
+        if res.is_ready() {
+            self.on_closed(CloseReason::Local);
+        }
+        res
     }
 }
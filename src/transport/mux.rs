@@ -0,0 +1,420 @@
+//! Protocol negotiation and channel multiplexing over one connection.
+//!
+//! After the handshake, both ends can agree on the application protocols
+//! they share with [`negotiate`], and then run each of them on its own
+//! [`Channel`] of a [`Mux`].
+
+use async_std::channel;
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream};
+use log::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::runtime;
+
+const MAX_PROTOCOLS: usize = 64;
+// Channels the remote end may have open at once.
+const MAX_REMOTE_CHANNELS: usize = 64;
+// Frames received for a channel that was not read yet.
+const MAX_QUEUED_FRAMES: usize = 16;
+
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CLOSE: u8 = 2;
+// Channel id, kind and payload length.
+const HEADER_LEN: usize = 7;
+
+/// A protocol name and the highest version of it spoken locally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub name: String,
+    pub version: u32,
+}
+
+impl Protocol {
+    pub fn new(name: impl Into<String>, version: u32) -> Self {
+        Self {
+            name: name.into(),
+            version,
+        }
+    }
+}
+
+/// Exchange supported protocols with the remote end. Returns the protocols
+/// both ends speak, each at the lower of the two versions.
+pub async fn negotiate<T>(conn: &mut T, protocols: &[Protocol]) -> io::Result<Vec<Protocol>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    if protocols.len() > MAX_PROTOCOLS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many protocols",
+        ));
+    }
+    let mut msg = vec![protocols.len() as u8];
+    for protocol in protocols {
+        let name = protocol.name.as_bytes();
+        if name.is_empty() || name.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "protocol names must be 1 to 255 bytes",
+            ));
+        }
+        msg.push(name.len() as u8);
+        msg.extend_from_slice(name);
+        msg.extend_from_slice(&protocol.version.to_be_bytes());
+    }
+    conn.write_all(&msg).await?;
+    conn.flush().await?;
+
+    let mut count = [0u8; 1];
+    conn.read_exact(&mut count).await?;
+    if count[0] as usize > MAX_PROTOCOLS {
+        return Err(invalid_data("too many protocols"));
+    }
+    let mut common = vec![];
+    for _ in 0..count[0] {
+        let mut len = [0u8; 1];
+        conn.read_exact(&mut len).await?;
+        let mut name = vec![0u8; len[0] as usize];
+        conn.read_exact(&mut name).await?;
+        let mut version = [0u8; 4];
+        conn.read_exact(&mut version).await?;
+        let name = String::from_utf8(name).map_err(|_| invalid_data("invalid protocol name"))?;
+        let version = u32::from_be_bytes(version);
+        if let Some(local) = protocols.iter().find(|p| p.name == name) {
+            common.push(Protocol::new(name, version.min(local.version)));
+        }
+    }
+    Ok(common)
+}
+
+type Channels = Arc<Mutex<HashMap<u32, channel::Sender<Vec<u8>>>>>;
+
+/// Runs channels over a connection, each a separate byte stream.
+///
+/// Both ends open channels by protocol name, and receive the channels opened
+/// by the other end from `accept`. Closing or dropping a channel ends it in
+/// both directions. Writes are queued without limit, so protocols should not
+/// send far ahead of what the remote reads. Received frames are queued up
+/// to a limit per channel, after which reading the connection waits for the
+/// channel to be read, stalling the other channels too.
+pub struct Mux {
+    outgoing: channel::Sender<Vec<u8>>,
+    channels: Channels,
+    next_id: AtomicU32,
+    accepted: channel::Receiver<Channel>,
+}
+
+impl fmt::Debug for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mux")
+            .field("channels", &self.channels.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Mux {
+    /// Start multiplexing a connection, spawning tasks that read and write
+    /// it. Both ends have to pass opposite `is_initiator` values, e.g. from
+    /// `Connection::is_initiator`.
+    pub fn new<T>(conn: T, is_initiator: bool) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut reader, mut writer) = futures_lite::io::split(conn);
+        let (outgoing, outgoing_rx) = channel::unbounded::<Vec<u8>>();
+        let (accepted_tx, accepted) = channel::bounded(MAX_REMOTE_CHANNELS);
+        let channels: Channels = Arc::default();
+
+        runtime::spawn("hyperswarm::mux-writer", async move {
+            while let Ok(frame) = outgoing_rx.recv().await {
+                if let Err(e) = writer.write_all(&frame).await {
+                    debug!("mux write failed: {}", e);
+                    break;
+                }
+                if outgoing_rx.is_empty() {
+                    let _ = writer.flush().await;
+                }
+            }
+            let _ = writer.close().await;
+        });
+
+        let reader_channels = channels.clone();
+        let reader_outgoing = outgoing.clone();
+        runtime::spawn("hyperswarm::mux-reader", async move {
+            // Channels opened by the remote end have the other parity.
+            let remote_parity = if is_initiator { 0 } else { 1 };
+            let res = read_frames(&mut reader, &reader_channels, remote_parity, |id, name| {
+                let channel = Channel::new(id, name, &reader_channels, &reader_outgoing);
+                accepted_tx.try_send(channel).is_ok()
+            })
+            .await;
+            if let Err(e) = res {
+                debug!("mux read failed: {}", e);
+            }
+            // Ends all channels.
+            reader_channels.lock().unwrap().clear();
+        });
+
+        Self {
+            outgoing,
+            channels,
+            // Initiators use odd ids, so that both ends can open channels
+            // without agreeing on ids.
+            next_id: AtomicU32::new(if is_initiator { 1 } else { 2 }),
+            accepted,
+        }
+    }
+
+    /// Open a channel for a protocol.
+    pub fn open(&self, protocol: &str) -> Channel {
+        let id = self.next_id.fetch_add(2, Ordering::SeqCst);
+        let channel = Channel::new(id, protocol.to_string(), &self.channels, &self.outgoing);
+        let _ = self.outgoing.try_send(frame(id, OPEN, protocol.as_bytes()));
+        channel
+    }
+
+    /// The next channel opened by the remote end, or None once the
+    /// connection closed.
+    pub async fn accept(&self) -> Option<Channel> {
+        self.accepted.recv().await.ok()
+    }
+}
+
+async fn read_frames<R, F>(
+    reader: &mut R,
+    channels: &Channels,
+    remote_parity: u32,
+    mut on_open: F,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(u32, String) -> bool,
+{
+    let mut header = [0u8; HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header).await {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            res => res?,
+        }
+        let id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = header[4];
+        let len = u16::from_be_bytes([header[5], header[6]]) as usize;
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await?;
+        match kind {
+            OPEN => {
+                {
+                    let channels = channels.lock().unwrap();
+                    if id % 2 != remote_parity || channels.contains_key(&id) {
+                        return Err(invalid_data("invalid channel id"));
+                    }
+                    let remote = channels.keys().filter(|id| *id % 2 == remote_parity);
+                    if remote.count() >= MAX_REMOTE_CHANNELS {
+                        return Err(invalid_data("too many channels"));
+                    }
+                }
+                let name = String::from_utf8(payload)
+                    .map_err(|_| invalid_data("invalid protocol name"))?;
+                if !on_open(id, name) {
+                    return Ok(());
+                }
+            }
+            DATA => {
+                let tx = channels.lock().unwrap().get(&id).cloned();
+                if let Some(tx) = tx {
+                    let _ = tx.send(payload).await;
+                }
+            }
+            CLOSE => {
+                channels.lock().unwrap().remove(&id);
+            }
+            _ => return Err(invalid_data("invalid mux frame")),
+        }
+    }
+}
+
+fn frame(id: u32, kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// One protocol's byte stream within a `Mux`.
+pub struct Channel {
+    id: u32,
+    protocol: String,
+    incoming: channel::Receiver<Vec<u8>>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    outgoing: channel::Sender<Vec<u8>>,
+    channels: Channels,
+    closed: bool,
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("id", &self.id)
+            .field("protocol", &self.protocol)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl Channel {
+    fn new(
+        id: u32,
+        protocol: String,
+        channels: &Channels,
+        outgoing: &channel::Sender<Vec<u8>>,
+    ) -> Self {
+        let (tx, incoming) = channel::bounded(MAX_QUEUED_FRAMES);
+        channels.lock().unwrap().insert(id, tx);
+        Self {
+            id,
+            protocol,
+            incoming,
+            read_buf: vec![],
+            read_pos: 0,
+            outgoing: outgoing.clone(),
+            channels: channels.clone(),
+            closed: false,
+        }
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.channels.lock().unwrap().remove(&self.id);
+            let _ = self.outgoing.try_send(frame(self.id, CLOSE, &[]));
+        }
+    }
+
+    fn closed_error() -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "channel is closed")
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl AsyncRead for Channel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.read_pos >= self.read_buf.len() {
+            match futures::ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(data) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                }
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let n = (self.read_buf.len() - self.read_pos).min(buf.len());
+        let pos = self.read_pos;
+        buf[..n].copy_from_slice(&self.read_buf[pos..pos + n]);
+        self.read_pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Channel {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.closed {
+            return Poll::Ready(Err(Self::closed_error()));
+        }
+        let n = buf.len().min(u16::MAX as usize);
+        match self.outgoing.try_send(frame(self.id, DATA, &buf[..n])) {
+            Ok(()) => Poll::Ready(Ok(n)),
+            Err(_) => Poll::Ready(Err(Self::closed_error())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::os::unix::net::UnixStream;
+    use async_std::task;
+
+    #[async_std::test]
+    async fn test_negotiate_and_mux() -> io::Result<()> {
+        let (mut a, mut b) = UnixStream::pair()?;
+        let remote = task::spawn(async move {
+            let protocols = [Protocol::new("chat", 1), Protocol::new("files", 3)];
+            let common = negotiate(&mut b, &protocols).await.unwrap();
+            let mux = Mux::new(b, false);
+            let mut channel = mux.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            channel.read_exact(&mut buf).await.unwrap();
+            (common, channel.protocol().to_string(), buf)
+        });
+
+        let protocols = [Protocol::new("files", 2), Protocol::new("video", 1)];
+        let common = negotiate(&mut a, &protocols).await?;
+        assert_eq!(common, vec![Protocol::new("files", 2)]);
+        let mux = Mux::new(a, true);
+        let mut channel = mux.open("files");
+        channel.write_all(b"hello").await?;
+
+        let (common, protocol, buf) = remote.await;
+        assert_eq!(common, vec![Protocol::new("files", 2)]);
+        assert_eq!(protocol, "files");
+        assert_eq!(&buf, b"hello");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_max_remote_channels() -> io::Result<()> {
+        let (mut a, b) = UnixStream::pair()?;
+        let mux = Mux::new(b, false);
+        for i in 0..=MAX_REMOTE_CHANNELS as u32 {
+            a.write_all(&frame(2 * i + 1, OPEN, b"chat")).await?;
+        }
+        // Reading stops once one channel too many is opened.
+        let mut channels = vec![];
+        while let Some(channel) = mux.accept().await {
+            channels.push(channel);
+        }
+        assert_eq!(channels.len(), MAX_REMOTE_CHANNELS);
+        Ok(())
+    }
+}
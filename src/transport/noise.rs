@@ -0,0 +1,654 @@
+//! Noise XX handshake, compatible with hyperswarm's secret-stream.
+//!
+//! Handshake messages and all later frames are prefixed with their length as
+//! a little endian u24. The handshake runs
+//! `Noise_XX_Ed25519_ChaChaPoly_BLAKE2b` with the Ed25519 identity keys of
+//! both swarms, carrying topic proofs and application payloads. Afterwards
+//! both sides send the header of a libsodium secretstream, which encrypts
+//! every later frame, see [`secretstream`](super::secretstream).
+
+use async_trait::async_trait;
+use blake2_rfc::blake2b::{blake2b, Blake2b};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::traits::IsIdentity;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use log::*;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use super::handshake::{
+    frame_header, frame_len, HandshakeRequest, HandshakeStream, Handshaker, Session, HEADER_LEN,
+    MAX_MESSAGE,
+};
+use super::secretstream::SecretStream;
+use super::tickets::{Ticket, Tickets, TICKET_ID_LEN};
+use crate::discovery::Topic;
+use crate::keys::Keypair;
+use crate::redact::redact;
+
+const PATTERN: &str = "Noise_XX_Ed25519_ChaChaPoly_BLAKE2b";
+const HASH_LEN: usize = 64;
+const BLOCK_LEN: usize = 128;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const MAX_TOPIC_PROOFS: usize = 16;
+// Starts the initiator's first payload when it resumes a session, followed
+// by the ticket id.
+const RESUME: u8 = 1;
+/// Application payloads are limited to this, well below `MAX_MESSAGE` to
+/// leave room for topic proofs.
+pub(crate) const MAX_PAYLOAD: usize = 16 * 1024;
+
+/// The local side of a handshake.
+#[derive(Debug, Clone)]
+pub(crate) struct HandshakeOptions {
+    pub keypair: Keypair,
+    /// Bytes read from the remote at most.
+    pub max_bytes: usize,
+    pub tickets: Arc<Mutex<Tickets>>,
+    /// Keys of private topics. Both sides prove that they know them, with a
+    /// MAC of each over the handshake hash after the key exchange, and check
+    /// the MACs they receive against them.
+    pub topic_keys: Vec<(Topic, [u8; 32])>,
+    /// Sent to the remote, up to `MAX_PAYLOAD` bytes.
+    pub payload: Vec<u8>,
+}
+
+/// The default handshaker, running the Noise handshake with the swarm's
+/// keypair.
+#[derive(Debug)]
+pub(crate) struct NoiseHandshaker {
+    pub options: HandshakeOptions,
+}
+
+#[async_trait]
+impl Handshaker for NoiseHandshaker {
+    async fn handshake(
+        &self,
+        mut stream: &mut dyn HandshakeStream,
+        request: HandshakeRequest,
+    ) -> io::Result<Session> {
+        let options = HandshakeOptions {
+            topic_keys: request.topic_keys,
+            ..self.options.clone()
+        };
+        handshake(
+            &mut stream,
+            request.is_initiator,
+            request.peer_addr,
+            &options,
+        )
+        .await
+    }
+}
+
+/// Run the handshake over a fresh stream.
+///
+/// An initiator holding a ticket for the address asks to resume in the
+/// payload of its first message, see `Tickets`. A responder knowing the
+/// ticket answers `<- e, ee, psk` keyed with the ticket's secret instead of
+/// its static key, otherwise the full handshake continues as if no ticket
+/// was sent. The topic proofs and application payload of each side are the
+/// payload of its last message, so proofs are bound to both ephemeral keys
+/// and cannot be replayed into another session. Peers that send no payload,
+/// like JS hyperswarm, prove no topics and never resume.
+pub(crate) async fn handshake<T>(
+    stream: &mut T,
+    is_initiator: bool,
+    peer_addr: SocketAddr,
+    options: &HandshakeOptions,
+) -> io::Result<Session>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut budget = options.max_bytes;
+    let mut state = SymmetricState::new(PATTERN);
+    // An empty prologue, like secret-stream.
+    state.mix_hash(&[]);
+    let (remote_key, topics, payload) = if is_initiator {
+        initiate(stream, &mut state, peer_addr, options, &mut budget).await?
+    } else {
+        respond(stream, &mut state, peer_addr, options, &mut budget).await?
+    };
+    let hash = state.h.to_vec();
+    let ticket = Ticket::derive(&hash, remote_key);
+    if is_initiator {
+        options
+            .tickets
+            .lock()
+            .unwrap()
+            .insert_addr(peer_addr, ticket);
+    } else {
+        options.tickets.lock().unwrap().insert_id(ticket);
+    }
+    let (tx, rx) = state.split(is_initiator);
+    let (cipher, header) = SecretStream::new(tx, rx, &hash, is_initiator);
+    send_frame(stream, &header).await?;
+    Ok(Session {
+        cipher: Box::new(cipher),
+        remote_key,
+        topics,
+        payload,
+        hash,
+    })
+}
+
+// The remote key, proven topics and the remote payload.
+type Completed = ([u8; 32], Vec<Topic>, Vec<u8>);
+
+async fn initiate<T>(
+    stream: &mut T,
+    state: &mut SymmetricState,
+    peer_addr: SocketAddr,
+    options: &HandshakeOptions,
+    budget: &mut usize,
+) -> io::Result<Completed>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let ephemeral = Keypair::generate();
+    let ticket = options.tickets.lock().unwrap().take_addr(&peer_addr);
+    // -> e
+    let mut message = ephemeral.public_key().to_vec();
+    state.mix_hash(&ephemeral.public_key());
+    let resume = match &ticket {
+        Some(ticket) => [&[RESUME][..], &ticket.id].concat(),
+        None => vec![],
+    };
+    message.extend(state.encrypt_and_hash(&resume)?);
+    send_frame(stream, &message).await?;
+    // <- e, ee, and either psk or s, es
+    let message = recv_frame(stream, budget).await?;
+    let (remote_ephemeral, rest) = split_message(&message, KEY_LEN)?;
+    state.mix_hash(remote_ephemeral);
+    state.mix_key(&dh(&ephemeral, remote_ephemeral)?);
+    if let Some(ticket) = ticket {
+        // Only a responder knowing the ticket encrypts under its secret.
+        let mut resumed = state.clone();
+        resumed.mix_key_and_hash(&ticket.psk);
+        let hash = resumed.h;
+        if let Ok(message) = resumed.decrypt_and_hash(rest) {
+            let (topics, payload) = check_topic_proofs(&hash, &message, &options.topic_keys)?;
+            *state = resumed;
+            // -> payload
+            let proofs = handshake_payload(&state.h, options);
+            let message = state.encrypt_and_hash(&proofs)?;
+            send_frame(stream, &message).await?;
+            debug!("resumed session with {}", redact(peer_addr));
+            return Ok((ticket.remote_key, topics, payload));
+        }
+    }
+    let (remote_static, rest) = split_message(rest, KEY_LEN + TAG_LEN)?;
+    let remote_key = public_key(&state.decrypt_and_hash(remote_static)?)?;
+    state.mix_key(&dh(&ephemeral, &remote_key)?);
+    let hash = state.h;
+    let message = state.decrypt_and_hash(rest)?;
+    let (topics, payload) = check_topic_proofs(&hash, &message, &options.topic_keys)?;
+    // -> s, se
+    let mut message = state.encrypt_and_hash(&options.keypair.public_key())?;
+    state.mix_key(&dh(&options.keypair, remote_ephemeral)?);
+    let proofs = handshake_payload(&state.h, options);
+    message.extend(state.encrypt_and_hash(&proofs)?);
+    send_frame(stream, &message).await?;
+    Ok((remote_key, topics, payload))
+}
+
+async fn respond<T>(
+    stream: &mut T,
+    state: &mut SymmetricState,
+    peer_addr: SocketAddr,
+    options: &HandshakeOptions,
+    budget: &mut usize,
+) -> io::Result<Completed>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let ephemeral = Keypair::generate();
+    // -> e
+    let message = recv_frame(stream, budget).await?;
+    let (remote_ephemeral, rest) = split_message(&message, KEY_LEN)?;
+    let remote_ephemeral = public_key(remote_ephemeral)?;
+    state.mix_hash(&remote_ephemeral);
+    let resume = state.decrypt_and_hash(rest)?;
+    let ticket = match resume.split_first() {
+        Some((&RESUME, id)) if id.len() == TICKET_ID_LEN => {
+            let mut ticket_id = [0u8; TICKET_ID_LEN];
+            ticket_id.copy_from_slice(id);
+            options.tickets.lock().unwrap().take_id(&ticket_id)
+        }
+        _ => None,
+    };
+    // <- e, ee
+    let mut message = ephemeral.public_key().to_vec();
+    state.mix_hash(&ephemeral.public_key());
+    state.mix_key(&dh(&ephemeral, &remote_ephemeral)?);
+    if let Some(ticket) = ticket {
+        // psk
+        state.mix_key_and_hash(&ticket.psk);
+        let proofs = handshake_payload(&state.h, options);
+        message.extend(state.encrypt_and_hash(&proofs)?);
+        send_frame(stream, &message).await?;
+        // -> payload
+        let message = recv_frame(stream, budget).await?;
+        let hash = state.h;
+        let message = state.decrypt_and_hash(&message)?;
+        let (topics, payload) = check_topic_proofs(&hash, &message, &options.topic_keys)?;
+        debug!("resumed session with {}", redact(peer_addr));
+        return Ok((ticket.remote_key, topics, payload));
+    }
+    // s, es
+    message.extend(state.encrypt_and_hash(&options.keypair.public_key())?);
+    state.mix_key(&dh(&options.keypair, &remote_ephemeral)?);
+    let proofs = handshake_payload(&state.h, options);
+    message.extend(state.encrypt_and_hash(&proofs)?);
+    send_frame(stream, &message).await?;
+    // -> s, se
+    let message = recv_frame(stream, budget).await?;
+    let (remote_static, rest) = split_message(&message, KEY_LEN + TAG_LEN)?;
+    let remote_key = public_key(&state.decrypt_and_hash(remote_static)?)?;
+    state.mix_key(&dh(&ephemeral, &remote_key)?);
+    let hash = state.h;
+    let message = state.decrypt_and_hash(rest)?;
+    let (topics, payload) = check_topic_proofs(&hash, &message, &options.topic_keys)?;
+    Ok((remote_key, topics, payload))
+}
+
+/// The symmetric state of a Noise handshake, see section 5.2 of the Noise
+/// specification.
+#[derive(Clone)]
+struct SymmetricState {
+    ck: [u8; HASH_LEN],
+    h: [u8; HASH_LEN],
+    k: Option<[u8; KEY_LEN]>,
+    n: u64,
+}
+
+impl SymmetricState {
+    fn new(protocol: &str) -> Self {
+        let mut h = [0u8; HASH_LEN];
+        h[..protocol.len()].copy_from_slice(protocol.as_bytes());
+        Self {
+            ck: h,
+            h,
+            k: None,
+            n: 0,
+        }
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hash = Blake2b::new(HASH_LEN);
+        hash.update(&self.h);
+        hash.update(data);
+        self.h.copy_from_slice(hash.finalize().as_bytes());
+    }
+
+    fn mix_key(&mut self, input: &[u8]) {
+        let [ck, k, _] = hkdf(&self.ck, input);
+        self.ck = ck;
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&k[..KEY_LEN]);
+        self.k = Some(key);
+        self.n = 0;
+    }
+
+    // Mixes in a pre-shared key, like the `psk` token.
+    fn mix_key_and_hash(&mut self, input: &[u8]) {
+        let [ck, h, k] = hkdf(&self.ck, input);
+        self.ck = ck;
+        self.mix_hash(&h);
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&k[..KEY_LEN]);
+        self.k = Some(key);
+        self.n = 0;
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let ciphertext = match self.k {
+            Some(key) => {
+                let payload = Payload {
+                    msg: plaintext,
+                    aad: &self.h,
+                };
+                let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+                    .encrypt(&nonce(self.n), payload)
+                    .map_err(|_| noise_error("encryption failed"))?;
+                self.n += 1;
+                ciphertext
+            }
+            None => plaintext.to_vec(),
+        };
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let plaintext = match self.k {
+            Some(key) => {
+                let payload = Payload {
+                    msg: ciphertext,
+                    aad: &self.h,
+                };
+                let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+                    .decrypt(&nonce(self.n), payload)
+                    .map_err(|_| noise_error("decryption failed"))?;
+                self.n += 1;
+                plaintext
+            }
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    // The sending and receiving keys of the transport.
+    fn split(&self, is_initiator: bool) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+        let [k1, k2, _] = hkdf(&self.ck, &[]);
+        let mut initiator = [0u8; KEY_LEN];
+        initiator.copy_from_slice(&k1[..KEY_LEN]);
+        let mut responder = [0u8; KEY_LEN];
+        responder.copy_from_slice(&k2[..KEY_LEN]);
+        if is_initiator {
+            (initiator, responder)
+        } else {
+            (responder, initiator)
+        }
+    }
+}
+
+// Noise's ChaChaPoly nonce, 32 zero bits and the little endian counter.
+fn nonce(n: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&n.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut block = [0u8; BLOCK_LEN];
+    // Keys are at most HASH_LEN long here, so never hashed first.
+    block[..key.len()].copy_from_slice(key);
+    let mut inner = Blake2b::new(HASH_LEN);
+    inner.update(&block.iter().map(|byte| byte ^ 0x36).collect::<Vec<_>>());
+    for data in data {
+        inner.update(data);
+    }
+    let mut outer = Blake2b::new(HASH_LEN);
+    outer.update(&block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<_>>());
+    outer.update(inner.finalize().as_bytes());
+    let mut out = [0u8; HASH_LEN];
+    out.copy_from_slice(outer.finalize().as_bytes());
+    out
+}
+
+fn hkdf(chaining_key: &[u8], input: &[u8]) -> [[u8; HASH_LEN]; 3] {
+    let temp = hmac(chaining_key, &[input]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    let third = hmac(&temp, &[&second, &[3]]);
+    [first, second, third]
+}
+
+// Ed25519 Diffie-Hellman like `noise-curve-ed`: the clamped scalar of the
+// local key times the remote point, refusing small order points.
+fn dh(local: &Keypair, remote: &[u8]) -> io::Result<[u8; KEY_LEN]> {
+    let point = CompressedEdwardsY::from_slice(remote)
+        .decompress()
+        .filter(|point| !point.is_small_order())
+        .ok_or_else(|| noise_error("invalid public key"))?;
+    let shared = point * local.scalar();
+    if shared.is_identity() {
+        return Err(noise_error("invalid public key"));
+    }
+    Ok(shared.compress().to_bytes())
+}
+
+fn public_key(key: &[u8]) -> io::Result<[u8; KEY_LEN]> {
+    if key.len() != KEY_LEN {
+        return Err(noise_error("invalid public key"));
+    }
+    let mut public = [0u8; KEY_LEN];
+    public.copy_from_slice(key);
+    Ok(public)
+}
+
+fn split_message(message: &[u8], len: usize) -> io::Result<(&[u8], &[u8])> {
+    if message.len() < len {
+        return Err(noise_error("handshake message too short"));
+    }
+    Ok(message.split_at(len))
+}
+
+fn topic_proof(hash: &[u8], key: &[u8; 32]) -> [u8; 32] {
+    let mut proof = [0u8; 32];
+    proof.copy_from_slice(blake2b(32, key, hash).as_bytes());
+    proof
+}
+
+fn topic_proofs(hash: &[u8], topic_keys: &[(Topic, [u8; 32])]) -> Vec<u8> {
+    let keys = &topic_keys[..topic_keys.len().min(MAX_TOPIC_PROOFS)];
+    let mut payload = vec![keys.len() as u8];
+    for (_, key) in keys {
+        payload.extend_from_slice(&topic_proof(hash, key));
+    }
+    payload
+}
+
+// The topic proofs followed by the application payload. Without either,
+// the payload is empty, as JS hyperswarm sends it.
+fn handshake_payload(hash: &[u8], options: &HandshakeOptions) -> Vec<u8> {
+    if options.topic_keys.is_empty() && options.payload.is_empty() {
+        return vec![];
+    }
+    let mut payload = topic_proofs(hash, &options.topic_keys);
+    payload.extend_from_slice(&options.payload);
+    payload
+}
+
+// Returns the proven topics and the application payload following the
+// proofs. An empty payload carries neither.
+fn check_topic_proofs(
+    hash: &[u8],
+    message: &[u8],
+    topic_keys: &[(Topic, [u8; 32])],
+) -> io::Result<(Vec<Topic>, Vec<u8>)> {
+    let count = match message.first() {
+        Some(count) => *count as usize,
+        None => return Ok((vec![], vec![])),
+    };
+    let proofs = match message.get(1..1 + count * 32) {
+        Some(proofs) if count <= MAX_TOPIC_PROOFS => proofs,
+        _ => return Err(noise_error("invalid topic proofs")),
+    };
+    let proofs: Vec<&[u8]> = proofs.chunks(32).collect();
+    let topics = topic_keys
+        .iter()
+        .filter(|(_, key)| {
+            let expected = topic_proof(hash, key);
+            proofs
+                .iter()
+                .any(|proof| constant_time_eq(proof, &expected))
+        })
+        .map(|(topic, _)| *topic)
+        .collect();
+    Ok((topics, message[1 + count * 32..].to_vec()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn send_frame<T>(stream: &mut T, message: &[u8]) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    if message.len() > MAX_MESSAGE {
+        return Err(noise_error("handshake message too large"));
+    }
+    stream.write_all(&frame_header(message.len())).await?;
+    stream.write_all(message).await?;
+    stream.flush().await
+}
+
+async fn recv_frame<T>(stream: &mut T, budget: &mut usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut header = [0u8; HEADER_LEN];
+    read_limited(stream, &mut header, budget).await?;
+    let len = frame_len(&header);
+    if len > MAX_MESSAGE {
+        return Err(noise_error("handshake message too large"));
+    }
+    let mut message = vec![0u8; len];
+    read_limited(stream, &mut message, budget).await?;
+    Ok(message)
+}
+
+// Read exactly `buf.len()` bytes, failing if that exceeds the budget.
+async fn read_limited<T>(stream: &mut T, buf: &mut [u8], budget: &mut usize) -> io::Result<()>
+where
+    T: AsyncRead + Unpin,
+{
+    if buf.len() > *budget {
+        return Err(noise_error("handshake exceeded its byte limit"));
+    }
+    *budget -= buf.len();
+    stream.read_exact(buf).await
+}
+
+fn noise_error(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("noise: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RekeyPolicy;
+    use crate::transport::handshake::{Cipher, Encrypted};
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::task;
+    use std::time::Duration;
+
+    fn options(keys: &Keypair, tickets: &Arc<Mutex<Tickets>>) -> HandshakeOptions {
+        HandshakeOptions {
+            keypair: keys.clone(),
+            max_bytes: 1024,
+            tickets: tickets.clone(),
+            topic_keys: vec![([1u8; 32], [2u8; 32]), ([3u8; 32], [4u8; 32])],
+            payload: keys.public_key().to_vec(),
+        }
+    }
+
+    async fn pair() -> io::Result<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = async { listener.accept().await.map(|(stream, _)| stream) };
+        futures::try_join!(TcpStream::connect(addr), accept)
+    }
+
+    async fn connect(
+        a_keys: &Keypair,
+        b_keys: &Keypair,
+        a_tickets: &Arc<Mutex<Tickets>>,
+        b_tickets: &Arc<Mutex<Tickets>>,
+    ) -> io::Result<()> {
+        let (mut a, mut b) = pair().await?;
+        let addr = "10.0.0.2:4000".parse().unwrap();
+        let mut a_options = options(a_keys, a_tickets);
+        a_options.topic_keys.truncate(1);
+        let b_options = options(b_keys, b_tickets);
+        let responder = task::spawn(async move {
+            let session = handshake(&mut b, false, addr, &b_options).await.unwrap();
+            assert_eq!(session.topics, vec![[1u8; 32]]);
+            assert_eq!(session.payload, session.remote_key.to_vec());
+            let mut stream = Encrypted {
+                inner: &mut b,
+                cipher: &mut Cipher::new(session.cipher, None),
+            };
+            let mut buf = [0u8; 10];
+            stream.read_exact(&mut buf).await.unwrap();
+            (session.remote_key, session.hash, buf)
+        });
+        let session = handshake(&mut a, true, addr, &a_options).await?;
+        assert_eq!(session.topics, vec![[1u8; 32]]);
+        assert_eq!(session.remote_key, b_keys.public_key());
+        assert_eq!(session.payload, b_keys.public_key().to_vec());
+        let hash = session.hash.clone();
+        // Rekeys after every write.
+        let rekey = RekeyPolicy::default().set_max_bytes(1);
+        let mut stream = Encrypted {
+            inner: &mut a,
+            cipher: &mut Cipher::new(session.cipher, Some(rekey)),
+        };
+        stream.write_all(b"hello").await?;
+        stream.write_all(b"world").await?;
+        stream.flush().await?;
+        let (remote_key, remote_hash, buf) = responder.await;
+        assert_eq!(remote_key, a_keys.public_key());
+        assert_eq!(remote_hash, hash);
+        assert_eq!(&buf, b"helloworld");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_handshake() -> io::Result<()> {
+        let a_keys = Keypair::generate();
+        let b_keys = Keypair::generate();
+        let ttl = Some(Duration::from_secs(60));
+        let a_tickets = Arc::new(Mutex::new(Tickets::new(ttl)));
+        let b_tickets = Arc::new(Mutex::new(Tickets::new(ttl)));
+        connect(&a_keys, &b_keys, &a_tickets, &b_tickets).await?;
+        // Resumes with the ticket of the first session.
+        connect(&a_keys, &b_keys, &a_tickets, &b_tickets).await?;
+        // Falls back to a full handshake for an unknown ticket.
+        let b_tickets = Arc::new(Mutex::new(Tickets::new(ttl)));
+        connect(&a_keys, &b_keys, &a_tickets, &b_tickets).await
+    }
+
+    #[async_std::test]
+    async fn test_empty_payload() -> io::Result<()> {
+        let tickets = Arc::new(Mutex::new(Tickets::new(None)));
+        let (mut a, mut b) = pair().await?;
+        let addr = "10.0.0.2:4000".parse().unwrap();
+        let a_options = options(&Keypair::generate(), &tickets);
+        let mut b_options = options(&Keypair::generate(), &tickets);
+        // Sends neither topic proofs nor a payload, like JS hyperswarm.
+        b_options.topic_keys.clear();
+        b_options.payload.clear();
+        let responder = task::spawn(async move {
+            let session = handshake(&mut b, false, addr, &b_options).await?;
+            io::Result::Ok((session.topics, session.payload))
+        });
+        let session = handshake(&mut a, true, addr, &a_options).await?;
+        assert!(session.topics.is_empty());
+        assert!(session.payload.is_empty());
+        let (topics, payload) = responder.await?;
+        assert!(topics.is_empty());
+        assert_eq!(payload, a_options.payload);
+        assert_eq!(check_topic_proofs(&[], &[], &[])?, (vec![], vec![]));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_responder_without_topic_key() -> io::Result<()> {
+        let tickets = Arc::new(Mutex::new(Tickets::new(None)));
+        let (mut a, mut b) = pair().await?;
+        let addr = "10.0.0.2:4000".parse().unwrap();
+        let a_options = options(&Keypair::generate(), &tickets);
+        let mut b_options = options(&Keypair::generate(), &tickets);
+        // Knows the topic, but not its key.
+        b_options.topic_keys = vec![([1u8; 32], [5u8; 32])];
+        let responder = task::spawn(async move {
+            let session = handshake(&mut b, false, addr, &b_options).await?;
+            io::Result::Ok(session.topics)
+        });
+        let session = handshake(&mut a, true, addr, &a_options).await?;
+        assert!(session.topics.is_empty());
+        assert!(responder.await?.is_empty());
+        Ok(())
+    }
+}
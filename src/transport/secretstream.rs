@@ -0,0 +1,349 @@
+//! libsodium's `crypto_secretstream_xchacha20poly1305`, the transport
+//! encryption of hyperswarm's secret-stream.
+//!
+//! After the handshake each side sends a header frame of 56 bytes: a stream
+//! id derived from the handshake hash, so both ends know they completed the
+//! same handshake, and the header of its secretstream. Every later frame is
+//! one secretstream message, a tag byte and the encrypted data followed by a
+//! MAC. The stream rekeys itself through message tags, so both ends can
+//! rekey without a separate signal.
+
+use blake2_rfc::blake2b::blake2b;
+use poly1305::universal_hash::NewUniversalHash;
+use poly1305::Poly1305;
+use rand::Rng;
+use std::io;
+
+use super::handshake::{namespace, SessionCipher};
+
+pub(crate) const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = 24;
+const ID_LEN: usize = 32;
+/// The header frame sent first, the stream id and the secretstream header.
+pub(crate) const HEADER_FRAME_LEN: usize = ID_LEN + HEADER_LEN;
+/// Bytes a message grows by, the tag and the MAC.
+const OVERHEAD: usize = 17;
+const MAC_LEN: usize = 16;
+
+const TAG_MESSAGE: u8 = 0;
+const TAG_REKEY: u8 = 2;
+
+/// The transport encryption of a session, in both directions.
+pub(crate) struct SecretStream {
+    is_initiator: bool,
+    hash: Vec<u8>,
+    push: State,
+    rx: [u8; KEY_LEN],
+    // Set up with the remote's header frame.
+    pull: Option<State>,
+}
+
+impl SecretStream {
+    /// The session with the keys and hash of a handshake, and the header
+    /// frame to send before any other frame.
+    pub fn new(
+        tx: [u8; KEY_LEN],
+        rx: [u8; KEY_LEN],
+        hash: &[u8],
+        is_initiator: bool,
+    ) -> (Self, [u8; HEADER_FRAME_LEN]) {
+        let mut header = [0u8; HEADER_LEN];
+        rand::thread_rng().fill(&mut header);
+        let mut frame = [0u8; HEADER_FRAME_LEN];
+        frame[..ID_LEN].copy_from_slice(&stream_id(hash, is_initiator));
+        frame[ID_LEN..].copy_from_slice(&header);
+        let stream = Self {
+            is_initiator,
+            hash: hash.to_vec(),
+            push: State::new(&tx, &header),
+            rx,
+            pull: None,
+        };
+        (stream, frame)
+    }
+}
+
+impl SessionCipher for SecretStream {
+    fn overhead(&self) -> usize {
+        OVERHEAD
+    }
+
+    // Empty messages ask for a rekey, which the tag carries to the remote.
+    fn encrypt(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let tag = if plaintext.is_empty() {
+            TAG_REKEY
+        } else {
+            TAG_MESSAGE
+        };
+        Ok(self.push.push(plaintext, tag))
+    }
+
+    fn decrypt(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.pull.as_mut() {
+            Some(pull) => pull.pull(ciphertext),
+            None => {
+                if ciphertext.len() != HEADER_FRAME_LEN
+                    || ciphertext[..ID_LEN] != stream_id(&self.hash, !self.is_initiator)
+                {
+                    return Err(stream_error("invalid header"));
+                }
+                let mut header = [0u8; HEADER_LEN];
+                header.copy_from_slice(&ciphertext[ID_LEN..]);
+                self.pull = Some(State::new(&self.rx, &header));
+                Ok(vec![])
+            }
+        }
+    }
+}
+
+fn stream_error(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("secretstream: {}", message),
+    )
+}
+
+/// The id each side of a handshake sends in its header frame.
+fn stream_id(hash: &[u8], is_initiator: bool) -> [u8; ID_LEN] {
+    let index = if is_initiator { 0 } else { 1 };
+    let namespace = namespace(b"hyperswarm/secret-stream", index);
+    let mut id = [0u8; ID_LEN];
+    id.copy_from_slice(blake2b(ID_LEN, hash, &namespace).as_bytes());
+    id
+}
+
+/// One direction of a secretstream.
+struct State {
+    key: [u8; KEY_LEN],
+    // A 32 bit counter and the 64 bit inner nonce.
+    nonce: [u8; 12],
+}
+
+impl State {
+    fn new(key: &[u8; KEY_LEN], header: &[u8; HEADER_LEN]) -> Self {
+        let mut input = [0u8; 16];
+        input.copy_from_slice(&header[..16]);
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&header[16..]);
+        let mut state = Self {
+            key: hchacha20(key, &input),
+            nonce,
+        };
+        state.reset_counter();
+        state
+    }
+
+    fn push(&mut self, message: &[u8], tag: u8) -> Vec<u8> {
+        let mut block = [0u8; 64];
+        block[0] = tag;
+        xor_keystream(&self.key, &self.nonce, 1, &mut block);
+        let mut out = Vec::with_capacity(message.len() + OVERHEAD);
+        out.push(block[0]);
+        out.extend_from_slice(message);
+        xor_keystream(&self.key, &self.nonce, 2, &mut out[1..]);
+        let mac = self.mac(&block, &out[1..]);
+        out.extend_from_slice(&mac);
+        self.advance(&mac, tag);
+        out
+    }
+
+    fn pull(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        if ciphertext.len() < OVERHEAD {
+            return Err(stream_error("message too short"));
+        }
+        let (body, mac) = ciphertext.split_at(ciphertext.len() - MAC_LEN);
+        let mut block = [0u8; 64];
+        block[0] = body[0];
+        xor_keystream(&self.key, &self.nonce, 1, &mut block);
+        let tag = block[0];
+        block[0] = body[0];
+        let expected = self.mac(&block, &body[1..]);
+        if !constant_time_eq(&expected, mac) {
+            return Err(stream_error("invalid MAC"));
+        }
+        let mut message = body[1..].to_vec();
+        xor_keystream(&self.key, &self.nonce, 2, &mut message);
+        self.advance(&expected, tag);
+        Ok(message)
+    }
+
+    // The MAC over the encrypted tag block and message, padded like
+    // libsodium does it.
+    fn mac(&self, block: &[u8; 64], ciphertext: &[u8]) -> [u8; MAC_LEN] {
+        let mut mac_key = [0u8; 64];
+        xor_keystream(&self.key, &self.nonce, 0, &mut mac_key);
+        let mut data = Vec::with_capacity(block.len() + ciphertext.len() + 32);
+        data.extend_from_slice(block);
+        data.extend_from_slice(ciphertext);
+        data.resize(data.len() + ciphertext.len() % 16, 0);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&((64 + ciphertext.len()) as u64).to_le_bytes());
+        let poly = Poly1305::new(poly1305::Key::from_slice(&mac_key[..32]));
+        let mut mac = [0u8; MAC_LEN];
+        mac.copy_from_slice(&poly.compute_unpadded(&data).into_bytes());
+        mac
+    }
+
+    fn advance(&mut self, mac: &[u8; MAC_LEN], tag: u8) {
+        for (nonce, mac) in self.nonce[4..].iter_mut().zip(mac) {
+            *nonce ^= mac;
+        }
+        let counter =
+            u32::from_le_bytes([self.nonce[0], self.nonce[1], self.nonce[2], self.nonce[3]]);
+        let counter = counter.wrapping_add(1);
+        self.nonce[..4].copy_from_slice(&counter.to_le_bytes());
+        if tag & TAG_REKEY != 0 || counter == 0 {
+            self.rekey();
+        }
+    }
+
+    fn rekey(&mut self) {
+        let mut next = [0u8; KEY_LEN + 8];
+        next[..KEY_LEN].copy_from_slice(&self.key);
+        next[KEY_LEN..].copy_from_slice(&self.nonce[4..]);
+        xor_keystream(&self.key, &self.nonce, 0, &mut next);
+        self.key.copy_from_slice(&next[..KEY_LEN]);
+        self.nonce[4..].copy_from_slice(&next[KEY_LEN..]);
+        self.reset_counter();
+    }
+
+    fn reset_counter(&mut self) {
+        self.nonce[..4].copy_from_slice(&1u32.to_le_bytes());
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn rounds(state: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(state, 0, 4, 8, 12);
+        quarter_round(state, 1, 5, 9, 13);
+        quarter_round(state, 2, 6, 10, 14);
+        quarter_round(state, 3, 7, 11, 15);
+        quarter_round(state, 0, 5, 10, 15);
+        quarter_round(state, 1, 6, 11, 12);
+        quarter_round(state, 2, 7, 8, 13);
+        quarter_round(state, 3, 4, 9, 14);
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn initial_state(key: &[u8; KEY_LEN], input: &[u8; 16]) -> [u32; 16] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&SIGMA);
+    for (word, value) in state[4..].iter_mut().zip(words(key).chain(words(input))) {
+        *word = value;
+    }
+    state
+}
+
+/// The ChaCha20 block of RFC 8439 at a counter.
+fn chacha20_block(key: &[u8; KEY_LEN], nonce: &[u8; 12], counter: u32) -> [u8; 64] {
+    let mut input = [0u8; 16];
+    input[..4].copy_from_slice(&counter.to_le_bytes());
+    input[4..].copy_from_slice(nonce);
+    let initial = initial_state(key, &input);
+    let mut state = initial;
+    rounds(&mut state);
+    let mut block = [0u8; 64];
+    for (i, (word, initial)) in state.iter().zip(initial.iter()).enumerate() {
+        block[i * 4..i * 4 + 4].copy_from_slice(&word.wrapping_add(*initial).to_le_bytes());
+    }
+    block
+}
+
+/// XOR data with the ChaCha20 keystream, starting at a block counter.
+fn xor_keystream(key: &[u8; KEY_LEN], nonce: &[u8; 12], counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block = chacha20_block(key, nonce, counter.wrapping_add(i as u32));
+        for (byte, key) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= key;
+        }
+    }
+}
+
+/// HChaCha20, which derives the subkey of XChaCha20 from a key and the first
+/// 16 bytes of the nonce.
+fn hchacha20(key: &[u8; KEY_LEN], input: &[u8; 16]) -> [u8; KEY_LEN] {
+    let mut state = initial_state(key, input);
+    rounds(&mut state);
+    let mut out = [0u8; KEY_LEN];
+    for (i, word) in state[..4].iter().chain(state[12..].iter()).enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key() -> [u8; KEY_LEN] {
+        let mut key = [0u8; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn test_chacha20() {
+        // Test vectors of RFC 8439 and the XChaCha20 draft.
+        let nonce = [0, 0, 0, 9, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        assert_eq!(
+            hex::encode(&chacha20_block(&key(), &nonce, 1)[..32]),
+            "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e"
+        );
+        let input = [
+            0, 0, 0, 9, 0, 0, 0, 0x4a, 0, 0, 0, 0, 0x31, 0x41, 0x59, 0x27,
+        ];
+        assert_eq!(
+            hex::encode(hchacha20(&key(), &input)),
+            "82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc"
+        );
+    }
+
+    #[test]
+    fn test_secret_stream() {
+        let (a_tx, b_tx) = ([1u8; KEY_LEN], [2u8; KEY_LEN]);
+        let hash = [3u8; 64];
+        let (mut a, a_header) = SecretStream::new(a_tx, b_tx, &hash, true);
+        let (mut b, b_header) = SecretStream::new(b_tx, a_tx, &hash, false);
+        assert!(a.decrypt(&a_header).is_err());
+        assert_eq!(a.decrypt(&b_header).unwrap(), b"");
+        assert_eq!(b.decrypt(&a_header).unwrap(), b"");
+
+        let message = a.encrypt(b"hello").unwrap();
+        assert_eq!(message.len(), 5 + OVERHEAD);
+        assert_eq!(b.decrypt(&message).unwrap(), b"hello");
+        // Rekeys both ends.
+        let rekey = a.encrypt(b"").unwrap();
+        assert_eq!(b.decrypt(&rekey).unwrap(), b"");
+        let message = a.encrypt(b"world").unwrap();
+        assert_eq!(b.decrypt(&message).unwrap(), b"world");
+
+        let mut tampered = a.encrypt(b"hello").unwrap();
+        tampered[3] ^= 1;
+        assert!(b.decrypt(&tampered).is_err());
+    }
+}
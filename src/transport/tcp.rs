@@ -1,8 +1,8 @@
-pub use async_std::net::TcpStream;
-use async_std::net::{SocketAddr, TcpListener};
+use async_std::net::SocketAddr;
 use async_std::stream::Stream;
 use futures::stream::FuturesUnordered;
 use futures_lite::{ready, Future};
+use std::collections::VecDeque;
 use std::fmt;
 use std::io;
 use std::net::ToSocketAddrs;
@@ -10,16 +10,21 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use super::{Connection, Transport};
+use crate::error::ConnectError;
+use crate::runtime::TcpListener;
+pub use crate::runtime::TcpStream;
 
-pub type ConnectFut = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send + 'static>>;
+pub type ConnectFut =
+    Pin<Box<dyn Future<Output = (SocketAddr, io::Result<TcpStream>)> + Send + 'static>>;
 
 const PROTOCOL: &'static str = "tcp";
 
 #[derive(Debug)]
 pub struct TcpTransport {
     addr: SocketAddr,
-    incoming: TcpIncoming,
+    incoming: Option<TcpIncoming>,
     pending_connects: FuturesUnordered<ConnectFut>,
+    failed_connects: VecDeque<(SocketAddr, ConnectError)>,
 }
 
 impl TcpTransport {
@@ -33,21 +38,42 @@ impl TcpTransport {
         let incoming = TcpIncoming::new(listener)?;
         Ok(Self {
             addr,
-            incoming,
+            incoming: Some(incoming),
             pending_connects: FuturesUnordered::new(),
+            failed_connects: VecDeque::new(),
         })
     }
 
+    /// A transport without a listener, that only makes outgoing connections.
+    pub fn dial_only() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            incoming: None,
+            pending_connects: FuturesUnordered::new(),
+            failed_connects: VecDeque::new(),
+        }
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.addr
     }
+
+    /// Outgoing connections that failed since the last call.
+    pub fn take_failed_connects(&mut self) -> Vec<(SocketAddr, ConnectError)> {
+        self.failed_connects.drain(..).collect()
+    }
+
+    /// Abort all outgoing connections that are still being established.
+    pub fn cancel_connects(&mut self) {
+        self.pending_connects = FuturesUnordered::new();
+    }
 }
 
 impl Transport for TcpTransport {
     type Connection = TcpStream;
 
     fn connect(&mut self, peer_addr: SocketAddr) {
-        let fut = TcpStream::connect(peer_addr);
+        let fut = async move { (peer_addr, TcpStream::connect(peer_addr).await) };
         // let fut = connect_delayed(peer_addr);
         self.pending_connects.push(Box::pin(fut));
     }
@@ -56,12 +82,22 @@ impl Transport for TcpTransport {
 impl Stream for TcpTransport {
     type Item = io::Result<Connection<<Self as Transport>::Connection>>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let incoming = Pin::new(&mut self.incoming).poll_next(cx);
-        if let Some(conn) = into_connection(incoming, false) {
-            return Poll::Ready(Some(conn));
+        if let Some(incoming) = self.incoming.as_mut() {
+            let incoming = Pin::new(incoming).poll_next(cx);
+            if let Some(conn) = into_connection(incoming, false) {
+                return Poll::Ready(Some(conn));
+            }
         }
 
-        let connect = Pin::new(&mut self.pending_connects).poll_next(cx);
+        let connect = match Pin::new(&mut self.pending_connects).poll_next(cx) {
+            Poll::Ready(Some((peer_addr, Err(e)))) => {
+                self.failed_connects.push_back((peer_addr, (&e).into()));
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(Some((_, res))) => Poll::Ready(Some(res)),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        };
         if let Some(conn) = into_connection(connect, true) {
             return Poll::Ready(Some(conn));
         }
@@ -0,0 +1,110 @@
+use blake2_rfc::blake2b::blake2b;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::runtime;
+
+pub(crate) const TICKET_ID_LEN: usize = 16;
+const MAX_TICKETS: usize = 1024;
+
+/// A resumption ticket, derived by both ends from the hash of a completed
+/// handshake.
+#[derive(Clone)]
+pub(crate) struct Ticket {
+    pub id: [u8; TICKET_ID_LEN],
+    pub psk: [u8; 32],
+    pub remote_key: [u8; 32],
+}
+
+impl fmt::Debug for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ticket")
+            .field("id", &hex::encode(self.id))
+            .finish()
+    }
+}
+
+impl Ticket {
+    pub fn derive(handshake_hash: &[u8], remote_key: [u8; 32]) -> Self {
+        let mut id = [0u8; TICKET_ID_LEN];
+        id.copy_from_slice(
+            blake2b(TICKET_ID_LEN, handshake_hash, b"hyperswarm ticket id").as_bytes(),
+        );
+        let mut psk = [0u8; 32];
+        psk.copy_from_slice(blake2b(32, handshake_hash, b"hyperswarm resumption").as_bytes());
+        Self {
+            id,
+            psk,
+            remote_key,
+        }
+    }
+}
+
+/// Tickets of recent sessions, see `HandshakePolicy::resumption`.
+///
+/// The initiator of a session keeps its ticket by the dialed address, the
+/// responder by ticket id. Tickets are used once, and the resumed session
+/// issues the next one. Resumed sessions still run an ephemeral key exchange
+/// and keep forward secrecy, but the peer is authenticated by the ticket
+/// instead of its static key: anyone who obtains a ticket can impersonate the
+/// peer until it expires.
+#[derive(Debug, Default)]
+pub(crate) struct Tickets {
+    ttl: Option<Duration>,
+    by_addr: HashMap<SocketAddr, (Ticket, Instant)>,
+    by_id: HashMap<[u8; TICKET_ID_LEN], (Ticket, Instant)>,
+}
+
+impl Tickets {
+    /// Tickets expiring after `ttl`, or none issued if None.
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            ..Self::default()
+        }
+    }
+
+    pub fn insert_addr(&mut self, addr: SocketAddr, ticket: Ticket) {
+        if self.ttl.is_some() {
+            insert(&mut self.by_addr, addr, ticket);
+        }
+    }
+
+    pub fn insert_id(&mut self, ticket: Ticket) {
+        if self.ttl.is_some() {
+            insert(&mut self.by_id, ticket.id, ticket);
+        }
+    }
+
+    pub fn take_addr(&mut self, addr: &SocketAddr) -> Option<Ticket> {
+        let (ticket, issued) = self.by_addr.remove(addr)?;
+        self.fresh(issued).then(|| ticket)
+    }
+
+    pub fn take_id(&mut self, id: &[u8; TICKET_ID_LEN]) -> Option<Ticket> {
+        let (ticket, issued) = self.by_id.remove(id)?;
+        self.fresh(issued).then(|| ticket)
+    }
+
+    fn fresh(&self, issued: Instant) -> bool {
+        self.ttl.map_or(false, |ttl| runtime::elapsed(issued) < ttl)
+    }
+}
+
+fn insert<K>(tickets: &mut HashMap<K, (Ticket, Instant)>, key: K, ticket: Ticket)
+where
+    K: std::hash::Hash + Eq + Copy,
+{
+    if tickets.len() >= MAX_TICKETS {
+        let oldest = tickets
+            .iter()
+            .min_by_key(|(_, (_, issued))| *issued)
+            .map(|(key, _)| *key);
+        if let Some(oldest) = oldest {
+            tickets.remove(&oldest);
+        }
+    }
+    tickets.insert(key, (ticket, runtime::now()));
+}
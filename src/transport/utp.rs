@@ -38,6 +38,11 @@ impl UtpTransport {
             pending_connects: FuturesUnordered::new(),
         })
     }
+
+    /// Abort all outgoing connections that are still being established.
+    pub fn cancel_connects(&mut self) {
+        self.pending_connects = FuturesUnordered::new();
+    }
 }
 
 impl Transport for UtpTransport {
@@ -0,0 +1,59 @@
+//! Swarms with `Config::set_redact_logs` log no peer addresses. A test
+//! binary of its own, as it installs the process wide logger.
+
+#![cfg(feature = "sim")]
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::StreamExt;
+use hyperswarm::sim::Simulation;
+use hyperswarm::{Hyperswarm, TopicConfig};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io;
+use std::sync::Mutex;
+
+struct Capture(Mutex<Vec<String>>);
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_redacted_logs() -> io::Result<()> {
+    let capture: &'static Capture = Box::leak(Box::new(Capture(Mutex::new(vec![]))));
+    log::set_logger(capture).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let sim = Simulation::new(5);
+    let topic = [1u8; 32];
+    let addrs = sim.run(async {
+        let config = sim.config().set_redact_logs(true);
+        let mut server = Hyperswarm::bind(config.clone()).await?;
+        let mut client = Hyperswarm::bind(config).await?;
+        server.configure(topic, TopicConfig::server());
+        client.configure(topic, TopicConfig::client());
+        let (conn_a, conn_b) = futures::join!(server.next(), client.next());
+        let (mut conn_a, mut conn_b) = (conn_a.unwrap()?, conn_b.unwrap()?);
+        conn_b.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        conn_a.read_exact(&mut buf).await?;
+        io::Result::Ok([conn_a.peer_addr(), conn_b.peer_addr()])
+    })?;
+
+    let lines = capture.0.lock().unwrap();
+    assert!(!lines.is_empty());
+    for addr in addrs.iter() {
+        let ip = addr.ip().to_string();
+        for line in lines.iter() {
+            assert!(!line.contains(&ip), "{} in {:?}", addr, line);
+        }
+    }
+    Ok(())
+}